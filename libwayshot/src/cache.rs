@@ -0,0 +1,146 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use image::DynamicImage;
+use wayland_client::protocol::wl_output::WlOutput;
+
+use crate::output::OutputInfo;
+
+/// A cached capture of a single output.
+#[derive(Debug, Clone)]
+struct CacheEntry {
+    image: DynamicImage,
+    cursor_overlay: bool,
+    captured_at: Instant,
+    generation: u64,
+}
+
+/// In-memory cache of the last screenshot taken of each output.
+///
+/// Every output has a generation counter. An entry is only served while the
+/// generation it was captured at is still current and it is younger than the
+/// requested maximum age.
+///
+/// The generation is bumped by [`ScreenshotCache::invalidate`], which the
+/// connection calls when the output resolution changes, when the output was
+/// damaged since the last lookup, and from
+/// [`crate::WayshotConnection::invalidate_screenshot_cache`]. On compositors
+/// that don't report damage a cached image is served within the maximum age
+/// even if the output changed.
+///
+/// Enable it with [`crate::WayshotConnection::enable_screenshot_cache`].
+///
+/// With a byte budget set, the oldest images are evicted once the cached
+/// images take up more than the budget.
+#[derive(Debug, Default)]
+pub struct ScreenshotCache {
+    entries: HashMap<WlOutput, CacheEntry>,
+    generations: HashMap<WlOutput, u64>,
+    byte_budget: Option<u64>,
+}
+
+impl ScreenshotCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Current generation of the given output.
+    pub fn generation(&self, output: &WlOutput) -> u64 {
+        self.generations.get(output).copied().unwrap_or_default()
+    }
+
+    /// Mark the content of the output as changed, making any cached image stale.
+    pub fn invalidate(&mut self, output: &WlOutput) {
+        *self.generations.entry(output.clone()).or_default() += 1;
+    }
+
+    /// Return the cached image if it is from the current generation and not older than `max_age`.
+    pub fn get(
+        &self,
+        output: &WlOutput,
+        cursor_overlay: bool,
+        max_age: Duration,
+    ) -> Option<&DynamicImage> {
+        let entry = self.entries.get(output)?;
+        if entry.cursor_overlay != cursor_overlay
+            || entry.generation != self.generation(output)
+            || entry.captured_at.elapsed() > max_age
+        {
+            return None;
+        }
+        Some(&entry.image)
+    }
+
+    /// Store a freshly captured image for the output at its current generation.
+    ///
+    /// An image larger than the byte budget on its own is not stored.
+    pub fn insert(&mut self, output: &WlOutput, cursor_overlay: bool, image: DynamicImage) {
+        let generation = self.generation(output);
+        self.entries.insert(
+            output.clone(),
+            CacheEntry {
+                image,
+                cursor_overlay,
+                captured_at: Instant::now(),
+                generation,
+            },
+        );
+        self.evict_over_budget();
+    }
+
+    /// Limit the cached images to `max_bytes`, `None` for no limit.
+    pub fn set_byte_budget(&mut self, max_bytes: Option<u64>) {
+        self.byte_budget = max_bytes;
+        self.evict_over_budget();
+    }
+
+    /// Bytes of pixel data of all cached images.
+    pub fn byte_size(&self) -> u64 {
+        self.entries
+            .values()
+            .map(|entry| entry.image.as_bytes().len() as u64)
+            .sum()
+    }
+
+    fn evict_over_budget(&mut self) {
+        let Some(byte_budget) = self.byte_budget else {
+            return;
+        };
+        while self.byte_size() > byte_budget {
+            let Some(oldest) = self
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.captured_at)
+                .map(|(output, _)| output.clone())
+            else {
+                break;
+            };
+            tracing::debug!("Evicting cached screenshot to stay within {byte_budget} bytes");
+            self.entries.remove(&oldest);
+        }
+    }
+
+    /// Evict every output that is not part of `outputs`.
+    pub fn retain_outputs(&mut self, outputs: &[OutputInfo]) {
+        self.entries
+            .retain(|wl_output, _| outputs.iter().any(|o| o.wl_output == *wl_output));
+        self.generations
+            .retain(|wl_output, _| outputs.iter().any(|o| o.wl_output == *wl_output));
+    }
+
+    /// Number of cached images.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Drop all cached images.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
@@ -0,0 +1,130 @@
+//! Ids of captures, to correlate the captures of tools that run at the same
+//! time, e.g. their log lines, metadata and files.
+
+use std::{
+    fmt::{self, Display},
+    process,
+    str::FromStr,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+/// Identifies one frame captured by a [`WayshotConnection`](crate::WayshotConnection),
+/// written as `<process>-<generation>`, e.g. `4242-3`.
+///
+/// The generation counts the captures of the connection from 1, so the ids of
+/// a process are ordered and the process tells concurrently running tools apart.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct CaptureId {
+    pub process: u32,
+    pub generation: u64,
+}
+
+impl Display for CaptureId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.process, self.generation)
+    }
+}
+
+impl FromStr for CaptureId {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let invalid = || format!("'{s}' is not a capture id like 4242-3");
+        let (process, generation) = s.split_once('-').ok_or_else(invalid)?;
+        Ok(Self {
+            process: process.parse().map_err(|_| invalid())?,
+            generation: generation.parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for CaptureId {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for CaptureId {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let id = <std::borrow::Cow<'de, str>>::deserialize(deserializer)?;
+        id.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// Hands out the [`CaptureId`]s of a connection, see
+/// [`WayshotConnection::set_capture_ids`](crate::WayshotConnection::set_capture_ids).
+#[derive(Debug)]
+pub struct CaptureIds {
+    process: u32,
+    first: u64,
+    /// Generation of the next id.
+    next: AtomicU64,
+}
+
+impl Default for CaptureIds {
+    /// Ids of this process, starting at generation 1.
+    fn default() -> Self {
+        Self::new(process::id(), 1)
+    }
+}
+
+impl CaptureIds {
+    /// Ids of `process` starting at generation `first`, e.g. fixed ones for
+    /// reproducible ids in tests.
+    pub fn new(process: u32, first: u64) -> Self {
+        Self {
+            process,
+            first,
+            next: AtomicU64::new(first),
+        }
+    }
+
+    /// The id of the next capture.
+    pub fn next_id(&self) -> CaptureId {
+        CaptureId {
+            process: self.process,
+            generation: self.next.fetch_add(1, Ordering::Relaxed),
+        }
+    }
+
+    /// The id handed out last, none before the first capture.
+    pub fn last_id(&self) -> Option<CaptureId> {
+        let next = self.next.load(Ordering::Relaxed);
+        (next > self.first).then(|| CaptureId {
+            process: self.process,
+            generation: next - 1,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ids_count_up_from_the_first_generation() {
+        let ids = CaptureIds::new(4242, 1);
+        assert_eq!(ids.last_id(), None);
+        assert_eq!(ids.next_id().to_string(), "4242-1");
+        assert_eq!(ids.next_id().to_string(), "4242-2");
+        assert_eq!(ids.last_id().map(|id| id.generation), Some(2));
+
+        let resumed = CaptureIds::new(7, 10);
+        assert_eq!(resumed.last_id(), None);
+        assert_eq!(resumed.next_id().generation, 10);
+        assert_eq!(CaptureIds::default().next_id().process, process::id());
+    }
+
+    #[test]
+    fn ids_parse_their_display() {
+        let id = CaptureId {
+            process: 4242,
+            generation: 3,
+        };
+        assert_eq!("4242-3".parse(), Ok(id));
+        assert!("4242".parse::<CaptureId>().is_err());
+        assert!("4242-x".parse::<CaptureId>().is_err());
+    }
+}
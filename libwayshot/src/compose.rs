@@ -0,0 +1,1058 @@
+//! Cropping and stitching of frames without a compositor connection.
+
+use std::thread;
+
+use image::{DynamicImage, GenericImageView, GrayImage, Luma, Rgba, RgbaImage, imageops::replace};
+
+use crate::{
+    Error, Result,
+    image_util::{self, FrameView},
+    region::{LogicalRegion, Position, Region, Size},
+    screencopy::{ColorHandling, FrameCopy, FrameFormat, FrameMeta},
+};
+
+/// A captured frame detached from the Wayland connection, e.g. one stored
+/// during a test run to be composited later, see [`FrameCopy::detach`].
+///
+/// With the `serde` feature it can be serialized and replayed.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct OfflineFrame {
+    pub meta: FrameMeta,
+    /// Raw pixel data as laid out by `meta.frame_format`.
+    pub data: Vec<u8>,
+}
+
+impl OfflineFrame {
+    /// Build a frame from its raw parts.
+    ///
+    /// `logical_region` is where the frame is in the logical coordinate space
+    /// and `transform` the transform of the output it was captured from.
+    pub fn new(
+        frame_format: FrameFormat,
+        transform: wayland_client::protocol::wl_output::Transform,
+        logical_region: LogicalRegion,
+        data: Vec<u8>,
+    ) -> Self {
+        Self {
+            meta: FrameMeta::new(frame_format, transform, logical_region),
+            data,
+        }
+    }
+
+    /// The raw data if it can be pasted as it is at `scale`, see [`image_util::rotate_is_noop`].
+    fn as_raw(&self, scale: f64) -> Result<Option<FrameView<'_>>> {
+        let FrameMeta {
+            frame_format,
+            transform,
+            logical_region,
+            ..
+        } = self.meta;
+        let Size { width, height } = logical_region.inner.size;
+        // Frames with a higher scale still need to be downscaled.
+        let unscaled = frame_format.size.width <= (width as f64 * scale).round() as u32
+            && frame_format.size.height <= (height as f64 * scale).round() as u32;
+        if !(unscaled
+            && image_util::rotate_is_noop(
+                transform,
+                frame_format.size,
+                logical_region.inner.size,
+                scale,
+            ))
+        {
+            return Ok(None);
+        }
+        FrameView::new(&self.data, frame_format).map(Some)
+    }
+
+    /// Convert the raw data to an image in the logical orientation, at `scale`
+    /// times the logical size.
+    fn to_image(&self, scale: f64) -> Result<DynamicImage> {
+        let image = FrameView::new(&self.data, self.meta.frame_format)?.to_rgba();
+        let image = image_util::rotate_image_buffer(
+            image.into(),
+            self.meta.transform,
+            self.meta.logical_region.inner.size,
+            scale,
+        );
+
+        // Only upscaling is done when rotating, downscale frames with a higher scale here.
+        let Size { width, height } = self.meta.logical_region.inner.size;
+        let (width, height) = (
+            (width as f64 * scale).round() as u32,
+            (height as f64 * scale).round() as u32,
+        );
+        if image.width() <= width {
+            return Ok(image);
+        }
+        tracing::debug!("Downscaling frame to {width}x{height}");
+        Ok(image.resize_exact(width, height, image::imageops::FilterType::Triangle))
+    }
+}
+
+impl TryFrom<&FrameCopy> for OfflineFrame {
+    type Error = Error;
+
+    fn try_from(frame_copy: &FrameCopy) -> Result<Self> {
+        frame_copy.to_offline()
+    }
+}
+
+/// Options for [`compose`].
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ComposeOptions {
+    scale: Option<f64>,
+    pack_outputs: bool,
+    coverage_mask: bool,
+    output_borders: Option<OutputBorders>,
+}
+
+/// See [`ComposeOptions::draw_output_borders`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct OutputBorders {
+    color: Rgba<u8>,
+    thickness: u32,
+    label: bool,
+}
+
+impl ComposeOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Physical pixels per logical pixel of the composed image. Defaults to the
+    /// highest scale among the frames, like a live capture.
+    pub fn scale(mut self, scale: f64) -> Self {
+        self.scale = Some(scale);
+        self
+    }
+
+    /// Place the frames side by side, ordered by their logical position,
+    /// instead of where they are in the logical coordinate space. Off by default.
+    ///
+    /// Use this for outputs that are far apart, e.g. a projector placed at
+    /// `x=10000`, so the gap between them doesn't become part of the image.
+    /// The capture region then only selects which frames are composed.
+    pub fn pack_outputs(mut self, pack_outputs: bool) -> Self {
+        self.pack_outputs = pack_outputs;
+        self
+    }
+
+    /// Also return a [`Composite::coverage_mask`] of which pixels came from a
+    /// frame. Off by default.
+    pub fn with_coverage_mask(mut self, coverage_mask: bool) -> Self {
+        self.coverage_mask = coverage_mask;
+        self
+    }
+
+    /// Outline where each frame was placed in `color`, `thickness` physical
+    /// pixels wide on the inside of its logical region, to show where the
+    /// outputs meet in the composed image. Off by default.
+    ///
+    /// `label` asks for the outputs to be named next to their outlines, which
+    /// isn't supported yet; the outlines are drawn without names for now.
+    pub fn draw_output_borders(mut self, color: Rgba<u8>, thickness: u32, label: bool) -> Self {
+        self.output_borders = Some(OutputBorders {
+            color,
+            thickness,
+            label,
+        });
+        self
+    }
+}
+
+/// The result of [`compose_with_layout`].
+#[derive(Debug, Clone)]
+pub struct Composite {
+    pub image: RgbaImage,
+    /// Where each composed frame was placed, in the order of the frames.
+    pub layout: Vec<OutputPlacement>,
+    /// With [`ComposeOptions::with_coverage_mask`], a mask of the size of
+    /// `image` that is 255 where a frame was pasted and 0 where the image is
+    /// transparent fill, e.g. gaps between outputs or outputs left out.
+    pub coverage_mask: Option<GrayImage>,
+    /// What was done to the colour values of the composed frames, see
+    /// [`ColorHandling::combine`].
+    pub color_handling: ColorHandling,
+}
+
+/// Where a frame ended up in a composed image, see [`compose_with_layout`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutputPlacement {
+    /// The frame's logical region.
+    pub source: LogicalRegion,
+    /// The logical region it covers in the composed image, which differs from
+    /// `source` with [`ComposeOptions::pack_outputs`].
+    pub placed: LogicalRegion,
+}
+
+/// Move `regions` next to each other from left to right, in the order of their
+/// position, starting at the top left of their bounding box.
+fn pack_regions(regions: &[LogicalRegion]) -> Vec<LogicalRegion> {
+    let Some(origin) = regions
+        .iter()
+        .map(|region| region.inner.position)
+        .reduce(|a, b| Position {
+            x: a.x.min(b.x),
+            y: a.y.min(b.y),
+        })
+    else {
+        return Vec::new();
+    };
+    let mut order: Vec<usize> = (0..regions.len()).collect();
+    order.sort_by_key(|&i| {
+        let Position { x, y } = regions[i].inner.position;
+        (x, y)
+    });
+
+    let mut packed = regions.to_vec();
+    let mut x = origin.x;
+    for i in order {
+        packed[i].inner.position = Position { x, y: origin.y };
+        x += packed[i].inner.size.width as i32;
+    }
+    packed
+}
+
+/// The smallest region containing all of `regions`.
+fn bounding_region(regions: &[LogicalRegion]) -> Result<LogicalRegion> {
+    let (x1, y1, x2, y2) = regions
+        .iter()
+        .map(|region| {
+            let Region { position, size } = region.inner;
+            (
+                position.x,
+                position.y,
+                position.x + size.width as i32,
+                position.y + size.height as i32,
+            )
+        })
+        .reduce(|a, b| (a.0.min(b.0), a.1.min(b.1), a.2.max(b.2), a.3.max(b.3)))
+        .ok_or(Error::NoOutputs)?;
+    Ok(LogicalRegion {
+        inner: Region {
+            position: Position { x: x1, y: y1 },
+            size: Size {
+                width: (x2 - x1) as u32,
+                height: (y2 - y1) as u32,
+            },
+        },
+    })
+}
+
+/// Crop and stitch `frames` into an image of `region`, the same way a capture
+/// of that region is composited, but without a compositor.
+///
+/// Frames that do not overlap `region` are ignored. Returns
+/// [`Error::NoOutputs`] if none do.
+pub fn compose(
+    frames: &[OfflineFrame],
+    region: LogicalRegion,
+    options: ComposeOptions,
+) -> Result<RgbaImage> {
+    compose_with_layout(frames, region, options).map(|composite| composite.image)
+}
+
+/// Like [`compose`], also returning where each composed frame was placed and,
+/// if enabled in `options`, which pixels they cover.
+pub fn compose_with_layout(
+    frames: &[OfflineFrame],
+    region: LogicalRegion,
+    options: ComposeOptions,
+) -> Result<Composite> {
+    let frames: Vec<_> = frames
+        .iter()
+        .filter(|frame| frame.meta.logical_region.overlaps(&region))
+        .collect();
+    let scale = options.scale.unwrap_or_else(|| {
+        frames
+            .iter()
+            .map(|frame| frame.meta.scale())
+            .fold(1.0, f64::max)
+    });
+    if !(scale.is_finite() && scale > 0.0) {
+        return Err(Error::Unsupported(format!("compose scale {scale}")));
+    }
+    let sources: Vec<_> = frames
+        .iter()
+        .map(|frame| frame.meta.logical_region)
+        .collect();
+    let (placed, region) = if options.pack_outputs {
+        let placed = pack_regions(&sources);
+        let region = bounding_region(&placed)?;
+        (placed, region)
+    } else {
+        (sources.clone(), region)
+    };
+
+    let images = thread::scope(|scope| {
+        frames
+            .iter()
+            .map(|frame| {
+                scope.spawn(move || match frame.as_raw(scale)? {
+                    Some(_) => Ok(None),
+                    None => frame.to_image(scale).map(Some),
+                })
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+            .map(|handle| handle.join().expect("compose thread panicked"))
+            .collect::<Result<Vec<_>>>()
+    })?;
+    let blit_sources = images
+        .iter()
+        .zip(&frames)
+        .zip(&placed)
+        .map(|((image, frame), placed)| {
+            let source = match image {
+                Some(image) => BlitSource::Image(image),
+                None => BlitSource::Raw(FrameView::new(&frame.data, frame.meta.frame_format)?),
+            };
+            Ok((source, *placed))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let (image, coverage_mask) = blit(blit_sources, region, scale, options.coverage_mask)?;
+    let mut image = image.into_rgba8();
+    if let Some(borders) = options.output_borders {
+        if borders.label {
+            tracing::debug!("Output labels aren't supported yet, drawing plain borders");
+        }
+        for placed in &placed {
+            let relative = Region {
+                position: Position {
+                    x: placed.inner.position.x - region.inner.position.x,
+                    y: placed.inner.position.y - region.inner.position.y,
+                },
+                size: placed.inner.size,
+            };
+            image_util::draw_rect_outline(
+                &mut image,
+                relative.to_physical(scale),
+                borders.color,
+                borders.thickness,
+            );
+        }
+    }
+    let layout = sources
+        .into_iter()
+        .zip(placed)
+        .map(|(source, placed)| OutputPlacement { source, placed })
+        .collect();
+    let color_handling = frames
+        .iter()
+        .map(|frame| frame.meta.color_handling)
+        .fold(ColorHandling::Passthrough, ColorHandling::combine);
+    Ok(Composite {
+        image,
+        layout,
+        coverage_mask,
+        color_handling,
+    })
+}
+
+/// A frame pasted by [`blit`].
+pub(crate) enum BlitSource<'a> {
+    /// A frame converted to an image in the logical orientation and final scale.
+    Image(&'a DynamicImage),
+    /// A frame whose raw buffer already is in the logical orientation and
+    /// final scale, copied without converting it to an image first.
+    Raw(FrameView<'a>),
+}
+
+/// Paste the images, each covering its logical region at `scale`, onto a
+/// transparent image of `capture_region`. With `coverage_mask` also return a
+/// mask of the pasted pixels, see [`Composite::coverage_mask`].
+pub(crate) fn blit<'a>(
+    images: impl IntoIterator<Item = (BlitSource<'a>, LogicalRegion)>,
+    capture_region: LogicalRegion,
+    scale: f64,
+    coverage_mask: bool,
+) -> Result<(DynamicImage, Option<GrayImage>)> {
+    // Map everything relative to the capture region, see `Region::to_physical`
+    // for how fractional scales are rounded.
+    let relative = |logical_region: LogicalRegion| Region {
+        position: Position {
+            x: logical_region.inner.position.x - capture_region.inner.position.x,
+            y: logical_region.inner.position.y - capture_region.inner.position.y,
+        },
+        size: logical_region.inner.size,
+    };
+    let Size { width, height } = relative(capture_region).to_physical(scale).size;
+    let mut mask = coverage_mask.then(|| GrayImage::new(width, height));
+    images
+        .into_iter()
+        .fold(
+            None,
+            |composite_image: Option<DynamicImage>, (image, logical_region)| {
+                // Default to a transparent image.
+                let mut composite_image =
+                    composite_image.unwrap_or_else(|| DynamicImage::new_rgba8(width, height));
+                let Position { x, y } = relative(logical_region).to_physical(scale).position;
+                tracing::span!(
+                    tracing::Level::DEBUG,
+                    "replace",
+                    frame_copy_region = format!("{}", logical_region),
+                    capture_region = format!("{}", capture_region),
+                    x = x,
+                    y = y,
+                )
+                .in_scope(|| {
+                    tracing::debug!("Replacing parts of the final image");
+                    let (x, y) = (x as i64, y as i64);
+                    let dimensions = match image {
+                        BlitSource::Image(image) => {
+                            replace(&mut composite_image, image, x, y);
+                            image.dimensions()
+                        }
+                        BlitSource::Raw(raw) => {
+                            raw.blit(
+                                composite_image
+                                    .as_mut_rgba8()
+                                    .expect("composite is created as rgba8"),
+                                x,
+                                y,
+                            );
+                            raw.dimensions()
+                        }
+                    };
+                    if let Some(mask) = &mut mask {
+                        mark_covered(mask, x, y, dimensions);
+                    }
+                });
+                Some(composite_image)
+            },
+        )
+        .map(|composite_image| (composite_image, mask))
+        .ok_or_else(|| {
+            tracing::error!("Provided capture region doesn't intersect with any outputs!");
+            Error::NoOutputs
+        })
+}
+
+/// Set the pixels of `mask` covered by an image of `width` by `height` pasted
+/// at (`x`, `y`), clipped to the mask.
+fn mark_covered(mask: &mut GrayImage, x: i64, y: i64, (width, height): (u32, u32)) {
+    let x0 = x.clamp(0, mask.width() as i64) as u32;
+    let y0 = y.clamp(0, mask.height() as i64) as u32;
+    let x1 = (x + width as i64).clamp(0, mask.width() as i64) as u32;
+    let y1 = (y + height as i64).clamp(0, mask.height() as i64) as u32;
+    for y in y0..y1 {
+        for x in x0..x1 {
+            mask.put_pixel(x, y, Luma([255]));
+        }
+    }
+}
+
+/// Serialize Wayland enums such as `wl_shm::Format` as their protocol value.
+#[cfg(feature = "serde")]
+pub(crate) mod wl_enum_serde {
+    use serde::{Deserialize, Deserializer, Serializer, de::Error as _};
+
+    pub fn serialize<T, S>(value: &T, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        T: Copy + Into<u32>,
+        S: Serializer,
+    {
+        serializer.serialize_u32((*value).into())
+    }
+
+    pub fn deserialize<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+    where
+        T: TryFrom<u32>,
+        D: Deserializer<'de>,
+    {
+        let value = u32::deserialize(deserializer)?;
+        T::try_from(value).map_err(|_| D::Error::custom(format!("unknown enum value {value}")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wayland_client::protocol::{wl_output::Transform, wl_shm::Format};
+
+    /// An Xrgb8888 frame filled with `rgb`, with two bytes of row padding.
+    fn solid_frame(
+        size: Size,
+        transform: Transform,
+        region: LogicalRegion,
+        [r, g, b]: [u8; 3],
+    ) -> OfflineFrame {
+        let stride = size.width * 4 + 2;
+        let mut data = vec![0xee; (stride * size.height) as usize];
+        for row in data.chunks_mut(stride as usize) {
+            for pixel in row[..size.width as usize * 4].chunks_exact_mut(4) {
+                pixel.copy_from_slice(&[b, g, r, 0]);
+            }
+        }
+        let frame_format = FrameFormat {
+            format: Format::Xrgb8888,
+            size,
+            stride,
+        };
+        OfflineFrame::new(frame_format, transform, region, data)
+    }
+
+    #[test]
+    fn compose_stitches_and_crops_side_by_side_frames() {
+        let size = Size {
+            width: 4,
+            height: 2,
+        };
+        let frames = [
+            solid_frame(
+                size,
+                Transform::Normal,
+                LogicalRegion::new(0, 0, 4, 2),
+                [255, 0, 0],
+            ),
+            solid_frame(
+                size,
+                Transform::Normal,
+                LogicalRegion::new(4, 0, 4, 2),
+                [0, 0, 255],
+            ),
+        ];
+
+        let image = compose(
+            &frames,
+            LogicalRegion::new(2, 0, 4, 2),
+            ComposeOptions::new(),
+        )
+        .expect("compose");
+
+        assert_eq!(image.dimensions(), (4, 2));
+        assert_eq!(image.get_pixel(1, 1).0, [255, 0, 0, 255]);
+        assert_eq!(image.get_pixel(2, 0).0, [0, 0, 255, 255]);
+    }
+
+    #[test]
+    fn output_borders_are_clipped_to_the_composed_region() {
+        let size = Size {
+            width: 4,
+            height: 4,
+        };
+        let frames = [
+            solid_frame(
+                size,
+                Transform::Normal,
+                LogicalRegion::new(0, 0, 4, 4),
+                [255, 0, 0],
+            ),
+            solid_frame(
+                size,
+                Transform::Normal,
+                LogicalRegion::new(4, 0, 4, 4),
+                [0, 0, 255],
+            ),
+        ];
+        let green = Rgba([0, 255, 0, 255]);
+
+        let image = compose(
+            &frames,
+            LogicalRegion::new(2, 0, 4, 4),
+            ComposeOptions::new().draw_output_borders(green, 1, true),
+        )
+        .expect("compose");
+
+        assert_eq!(image.dimensions(), (4, 4));
+        // The left edge of the first output and the right one of the second are cropped.
+        let row: Vec<_> = (0..4).map(|x| image.get_pixel(x, 1).0).collect();
+        assert_eq!(row, [[255, 0, 0, 255], green.0, green.0, [0, 0, 255, 255]]);
+        assert!((0..4).all(|x| *image.get_pixel(x, 0) == green && *image.get_pixel(x, 3) == green));
+    }
+
+    /// A 4x2 output at `x=0` and a 2x2 one far to its right.
+    fn disjoint_frames() -> [OfflineFrame; 2] {
+        [
+            solid_frame(
+                Size {
+                    width: 2,
+                    height: 2,
+                },
+                Transform::Normal,
+                LogicalRegion::new(1000, 0, 2, 2),
+                [0, 0, 255],
+            ),
+            solid_frame(
+                Size {
+                    width: 4,
+                    height: 2,
+                },
+                Transform::Normal,
+                LogicalRegion::new(0, 0, 4, 2),
+                [255, 0, 0],
+            ),
+        ]
+    }
+
+    #[test]
+    fn compose_keeps_the_gap_between_disjoint_frames_by_default() {
+        let frames = disjoint_frames();
+
+        let Composite { image, layout, .. } = compose_with_layout(
+            &frames,
+            LogicalRegion::new(0, 0, 1002, 2),
+            ComposeOptions::new(),
+        )
+        .expect("compose");
+
+        assert_eq!(image.dimensions(), (1002, 2));
+        assert_eq!(image.get_pixel(500, 0).0, [0, 0, 0, 0]);
+        assert_eq!(image.get_pixel(1001, 1).0, [0, 0, 255, 255]);
+        assert!(
+            layout
+                .iter()
+                .all(|placement| placement.source == placement.placed)
+        );
+    }
+
+    #[test]
+    fn compose_packs_disjoint_frames_in_position_order() {
+        let frames = disjoint_frames();
+
+        let Composite { image, layout, .. } = compose_with_layout(
+            &frames,
+            LogicalRegion::new(0, 0, 1002, 2),
+            ComposeOptions::new().pack_outputs(true),
+        )
+        .expect("compose");
+
+        assert_eq!(image.dimensions(), (6, 2));
+        assert_eq!(image.get_pixel(3, 1).0, [255, 0, 0, 255]);
+        assert_eq!(image.get_pixel(4, 0).0, [0, 0, 255, 255]);
+        assert_eq!(
+            layout,
+            [
+                OutputPlacement {
+                    source: LogicalRegion::new(1000, 0, 2, 2),
+                    placed: LogicalRegion::new(4, 0, 2, 2),
+                },
+                OutputPlacement {
+                    source: LogicalRegion::new(0, 0, 4, 2),
+                    placed: LogicalRegion::new(0, 0, 4, 2),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn coverage_mask_marks_pasted_pixels_of_both_blit_paths() {
+        let size = Size {
+            width: 4,
+            height: 2,
+        };
+        // An L of three outputs, the one to the bottom right failed and is
+        // left out. The rotated output goes through the image path.
+        let frames = [
+            solid_frame(
+                size,
+                Transform::Normal,
+                LogicalRegion::new(0, 0, 4, 2),
+                [255, 0, 0],
+            ),
+            solid_frame(
+                size,
+                Transform::Normal,
+                LogicalRegion::new(4, 0, 4, 2),
+                [0, 255, 0],
+            ),
+            solid_frame(
+                Size {
+                    width: 2,
+                    height: 4,
+                },
+                Transform::_90,
+                LogicalRegion::new(0, 2, 4, 2),
+                [0, 0, 255],
+            ),
+        ];
+
+        let Composite {
+            image,
+            coverage_mask,
+            ..
+        } = compose_with_layout(
+            &frames,
+            LogicalRegion::new(0, 0, 8, 4),
+            ComposeOptions::new().with_coverage_mask(true),
+        )
+        .expect("compose");
+        let mask = coverage_mask.expect("coverage mask");
+
+        assert_eq!(mask.dimensions(), image.dimensions());
+        for (x, y, coverage) in mask.enumerate_pixels() {
+            let covered = y < 2 || x < 4;
+            assert_eq!(coverage.0, [if covered { 255 } else { 0 }], "({x}, {y})");
+            assert_eq!(image.get_pixel(x, y).0[3] == 255, covered, "({x}, {y})");
+        }
+        assert!(
+            compose_with_layout(
+                &frames,
+                LogicalRegion::new(0, 0, 8, 4),
+                ComposeOptions::new()
+            )
+            .expect("compose")
+            .coverage_mask
+            .is_none()
+        );
+    }
+
+    #[test]
+    fn compose_rotates_and_scales_to_the_highest_scale() {
+        // A portrait output with scale 2, captured in landscape buffer orientation.
+        let rotated = solid_frame(
+            Size {
+                width: 4,
+                height: 2,
+            },
+            Transform::_90,
+            LogicalRegion::new(0, 0, 1, 2),
+            [0, 255, 0],
+        );
+        let unscaled = solid_frame(
+            Size {
+                width: 1,
+                height: 2,
+            },
+            Transform::Normal,
+            LogicalRegion::new(1, 0, 1, 2),
+            [255, 255, 255],
+        );
+
+        let image = compose(
+            &[rotated, unscaled],
+            LogicalRegion::new(0, 0, 2, 2),
+            ComposeOptions::new(),
+        )
+        .expect("compose");
+
+        assert_eq!(image.dimensions(), (4, 4));
+        assert_eq!(image.get_pixel(1, 3).0, [0, 255, 0, 255]);
+        assert_eq!(image.get_pixel(3, 3).0, [255, 255, 255, 255]);
+    }
+
+    #[test]
+    fn compose_fractional_scales_crops_at_odd_offsets_without_seams() {
+        for (scale, logical_size, physical_size) in [
+            (
+                1.25,
+                Size {
+                    width: 8,
+                    height: 4,
+                },
+                Size {
+                    width: 10,
+                    height: 5,
+                },
+            ),
+            (
+                1.6,
+                Size {
+                    width: 5,
+                    height: 5,
+                },
+                Size {
+                    width: 8,
+                    height: 8,
+                },
+            ),
+        ] {
+            let Size { width, height } = logical_size;
+            let frames = [
+                solid_frame(
+                    physical_size,
+                    Transform::Normal,
+                    LogicalRegion::new(0, 0, width, height),
+                    [255, 0, 0],
+                ),
+                solid_frame(
+                    physical_size,
+                    Transform::Normal,
+                    LogicalRegion::new(width as i32, 0, width, height),
+                    [0, 0, 255],
+                ),
+            ];
+            let region = LogicalRegion::new(3, 1, width * 2 - 4, height - 2);
+
+            let image = compose(&frames, region, ComposeOptions::new()).expect("compose");
+
+            // The composite's pixel grid starts at the region's top left.
+            let expected =
+                LogicalRegion::new(0, 0, region.inner.size.width, region.inner.size.height)
+                    .inner
+                    .to_physical(scale)
+                    .size;
+            assert_eq!(image.dimensions(), (expected.width, expected.height));
+            let seam = ((width as f64 - 3.0) * scale).round() as u32;
+            for (x, y, pixel) in image.enumerate_pixels() {
+                let color = if x < seam {
+                    [255, 0, 0, 255]
+                } else {
+                    [0, 0, 255, 255]
+                };
+                assert_eq!(pixel.0, color, "pixel ({x}, {y}) at scale {scale}");
+            }
+        }
+    }
+
+    #[test]
+    fn compose_selection_on_an_output_border_has_no_seam() {
+        for (scale, physical_width) in [(1.0, 8), (1.25, 10), (2.0, 16)] {
+            let size = Size {
+                width: physical_width,
+                height: physical_width / 2,
+            };
+            let frames = [
+                solid_frame(
+                    size,
+                    Transform::Normal,
+                    LogicalRegion::new(0, 0, 8, 4),
+                    [255, 0, 0],
+                ),
+                solid_frame(
+                    size,
+                    Transform::Normal,
+                    LogicalRegion::new(8, 0, 8, 4),
+                    [0, 0, 255],
+                ),
+            ];
+            // Crossing the border, starting right on it and ending right on it.
+            for region in [
+                LogicalRegion::new(5, 0, 6, 4),
+                LogicalRegion::new(8, 0, 3, 4),
+                LogicalRegion::new(5, 0, 3, 4),
+            ] {
+                let image =
+                    compose(&frames, region, ComposeOptions::new().scale(scale)).expect("compose");
+
+                let border = ((8 - region.inner.position.x) as f64 * scale).round() as i64;
+                for (x, y, pixel) in image.enumerate_pixels() {
+                    let color = if (x as i64) < border {
+                        [255, 0, 0, 255]
+                    } else {
+                        [0, 0, 255, 255]
+                    };
+                    assert_eq!(
+                        pixel.0, color,
+                        "pixel ({x}, {y}) of {region} at scale {scale}"
+                    );
+                }
+            }
+        }
+    }
+
+    const GREY: [u8; 4] = [128, 128, 128, 255];
+    const RED: [u8; 4] = [255, 0, 0, 255];
+    const GREEN: [u8; 4] = [0, 255, 0, 255];
+    const BLUE: [u8; 4] = [0, 0, 255, 255];
+    const WHITE: [u8; 4] = [255, 255, 255, 255];
+
+    /// A grey Xrgb8888 frame with a red, green, blue and white pixel in its
+    /// top left, top right, bottom left and bottom right buffer corner.
+    fn marked_frame(size: Size, transform: Transform, region: LogicalRegion) -> OfflineFrame {
+        let mut frame = solid_frame(size, transform, region, [128, 128, 128]);
+        let stride = frame.meta.frame_format.stride as usize;
+        let (right, bottom) = (size.width as usize - 1, size.height as usize - 1);
+        for (x, y, [r, g, b, _]) in [
+            (0, 0, RED),
+            (right, 0, GREEN),
+            (0, bottom, BLUE),
+            (right, bottom, WHITE),
+        ] {
+            let offset = y * stride + x * 4;
+            frame.data[offset..offset + 4].copy_from_slice(&[b, g, r, 0]);
+        }
+        frame
+    }
+
+    #[test]
+    fn compose_places_corner_markers_for_every_transform_and_scale() {
+        // Where the top left, top right, bottom left and bottom right buffer
+        // corners end up on the output, as (right, bottom) corner flags.
+        const TL: (bool, bool) = (false, false);
+        const TR: (bool, bool) = (true, false);
+        const BL: (bool, bool) = (false, true);
+        const BR: (bool, bool) = (true, true);
+        let transforms = [
+            (Transform::Normal, [TL, TR, BL, BR]),
+            // Turned clockwise.
+            (Transform::_90, [TR, BR, TL, BL]),
+            (Transform::_180, [BR, BL, TR, TL]),
+            (Transform::_270, [BL, TL, BR, TR]),
+            // Mirrored left to right, then turned clockwise.
+            (Transform::Flipped, [TR, TL, BR, BL]),
+            (Transform::Flipped90, [BR, TR, BL, TL]),
+            (Transform::Flipped180, [BL, BR, TL, TR]),
+            (Transform::Flipped270, [TL, BL, TR, BR]),
+        ];
+        // An 8x4 logical output at (16, 8).
+        let output = LogicalRegion::new(16, 8, 8, 4);
+        let placements = [
+            ("fully inside", output),
+            ("straddling the left edge", LogicalRegion::new(14, 8, 5, 4)),
+            (
+                "straddling the bottom right corner",
+                LogicalRegion::new(20, 10, 6, 4),
+            ),
+        ];
+
+        for (transform, corners) in transforms {
+            let colors = [RED, GREEN, BLUE, WHITE];
+            let marker = |corner: (bool, bool)| {
+                colors[corners.iter().position(|c| *c == corner).expect("corner")]
+            };
+            for scale in [1.0, 1.25, 2.0] {
+                let (width, height) = ((8.0 * scale) as u32, (4.0 * scale) as u32);
+                let buffer_size = if matches!(
+                    transform,
+                    Transform::_90 | Transform::_270 | Transform::Flipped90 | Transform::Flipped270
+                ) {
+                    Size {
+                        width: height,
+                        height: width,
+                    }
+                } else {
+                    Size { width, height }
+                };
+                let frames = [marked_frame(buffer_size, transform, output)];
+
+                for (placement, region) in placements {
+                    let image = compose(&frames, region, ComposeOptions::new()).expect("compose");
+
+                    // The output's top left in the image, rounded like `Region::to_physical`.
+                    let offset = |output: i32, region: i32| {
+                        ((output - region) as f64 * scale).round() as i64
+                    };
+                    let left = offset(output.inner.position.x, region.inner.position.x);
+                    let top = offset(output.inner.position.y, region.inner.position.y);
+                    for (x, y, pixel) in image.enumerate_pixels() {
+                        let (x, y) = (x as i64 - left, y as i64 - top);
+                        let expected = if !(0..width as i64).contains(&x)
+                            || !(0..height as i64).contains(&y)
+                        {
+                            [0, 0, 0, 0]
+                        } else {
+                            let right = x == width as i64 - 1;
+                            let bottom = y == height as i64 - 1;
+                            match (x == 0 || right, y == 0 || bottom) {
+                                (true, true) => marker((right, bottom)),
+                                _ => GREY,
+                            }
+                        };
+                        assert_eq!(
+                            pixel.0, expected,
+                            "output pixel ({x}, {y}), {transform:?} at scale {scale}, {placement}"
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn compose_pastes_unrotated_frames_like_converted_ones() {
+        let gradient = |size: Size, region: LogicalRegion| {
+            let mut frame = solid_frame(size, Transform::Normal, region, [0, 0, 0]);
+            let stride = frame.meta.frame_format.stride as usize;
+            for (y, row) in frame.data.chunks_mut(stride).enumerate() {
+                for (x, pixel) in row[..size.width as usize * 4]
+                    .chunks_exact_mut(4)
+                    .enumerate()
+                {
+                    pixel[..3].copy_from_slice(&[x as u8 * 9, y as u8 * 13, (x + y) as u8]);
+                }
+            }
+            frame
+        };
+        let frames = [
+            // Scale 2, pasted from its raw buffer.
+            gradient(
+                Size {
+                    width: 16,
+                    height: 12,
+                },
+                LogicalRegion::new(0, 0, 8, 6),
+            ),
+            // Scale 1, upscaled and therefore converted.
+            gradient(
+                Size {
+                    width: 8,
+                    height: 6,
+                },
+                LogicalRegion::new(8, 2, 8, 6),
+            ),
+        ];
+
+        for region in [
+            LogicalRegion::new(0, 0, 16, 8),
+            LogicalRegion::new(3, 1, 9, 6),
+            LogicalRegion::new(-2, -2, 20, 12),
+        ] {
+            let scale = 2.0;
+            assert!(frames[0].as_raw(scale).unwrap().is_some());
+            assert!(frames[1].as_raw(scale).unwrap().is_none());
+
+            let composed = compose(&frames, region, ComposeOptions::new()).expect("compose");
+
+            let images = frames
+                .iter()
+                .map(|frame| frame.to_image(scale).expect("image"))
+                .collect::<Vec<_>>();
+            let converted = blit(
+                images
+                    .iter()
+                    .zip(&frames)
+                    .map(|(image, frame)| (BlitSource::Image(image), frame.meta.logical_region)),
+                region,
+                scale,
+                false,
+            )
+            .expect("blit")
+            .0
+            .into_rgba8();
+            assert_eq!(composed, converted, "region {region}");
+        }
+    }
+
+    #[test]
+    fn compose_without_overlapping_frames_fails() {
+        let frame = solid_frame(
+            Size {
+                width: 2,
+                height: 2,
+            },
+            Transform::Normal,
+            LogicalRegion::new(0, 0, 2, 2),
+            [0, 0, 0],
+        );
+
+        let result = compose(
+            &[frame],
+            LogicalRegion::new(10, 10, 2, 2),
+            ComposeOptions::new(),
+        );
+
+        assert!(matches!(result, Err(Error::NoOutputs)));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn offline_frame_serde_round_trip() {
+        let frame = solid_frame(
+            Size {
+                width: 2,
+                height: 1,
+            },
+            Transform::Flipped90,
+            LogicalRegion::new(-5, 3, 1, 2),
+            [1, 2, 3],
+        );
+
+        let json = serde_json::to_string(&frame).expect("serialize");
+        let restored: OfflineFrame = serde_json::from_str(&json).expect("deserialize");
+
+        assert_eq!(restored, frame);
+    }
+}
@@ -1,32 +1,85 @@
+//! Conversion of the compositor's shm formats to the pixels of an image.
+
+use std::ops::Range;
+
 use image::ColorType;
 use wayland_client::protocol::wl_shm;
 
-pub trait Convert {
-    /// Convert raw image data into output type, return said type
-    fn convert_inplace(&self, data: &mut [u8]) -> ColorType;
+use crate::screencopy::ColorHandling;
+
+pub(crate) trait Convert {
+    /// Convert raw image data into [`Self::color_type`].
+    fn convert_inplace(&self, data: &mut [u8]);
+
+    /// The colour type of the converted pixels.
+    fn color_type(&self) -> ColorType {
+        ColorType::Rgba8
+    }
+
+    /// Convert only the bytes `columns` of the `rows` of a frame with `stride`
+    /// bytes per row, e.g. the part of a frame a region capture crops out. The
+    /// other pixels are left as they are.
+    fn convert_rows(
+        &self,
+        data: &mut [u8],
+        stride: usize,
+        rows: Range<usize>,
+        columns: Range<usize>,
+    ) {
+        for row in data.chunks_mut(stride).take(rows.end).skip(rows.start) {
+            if let Some(pixels) = row.get_mut(columns.clone()) {
+                self.convert_inplace(pixels);
+            }
+        }
+    }
+
+    /// What the conversion does to the colour values.
+    fn color_handling(&self) -> ColorHandling {
+        ColorHandling::Passthrough
+    }
 }
 
 #[derive(Default)]
 struct ConvertBGR10 {}
 
+/// Abgr8888, RGBA in memory, un-premultiplied.
 #[derive(Default)]
 struct ConvertNone {}
 
+/// Xbgr8888, whose padding byte becomes opaque alpha.
+#[derive(Default)]
+struct ConvertOpaque {}
+
+/// Argb8888, BGRA in memory, un-premultiplied.
 #[derive(Default)]
 struct ConvertRGB8 {}
 
+/// Xrgb8888, whose padding byte becomes opaque alpha.
+#[derive(Default)]
+struct ConvertRGB8Opaque {}
+
 #[derive(Default)]
 struct ConvertBGR888 {}
 
 const SHIFT10BITS_1: u32 = 20;
 const SHIFT10BITS_2: u32 = 10;
 
+/// Whether wayshot can convert frames of `format`, e.g. to check a format
+/// before forcing it. Every format wayshot selects for a capture is one of these.
+pub fn is_supported(format: wl_shm::Format) -> bool {
+    create_converter(format).is_some()
+}
+
 /// Creates format converter based of input format, return None if conversion
 /// isn't possible. Conversion is happening inplace.
-pub fn create_converter(format: wl_shm::Format) -> Option<Box<dyn Convert>> {
+pub(crate) fn create_converter(format: wl_shm::Format) -> Option<Box<dyn Convert>> {
     match format {
-        wl_shm::Format::Xbgr8888 | wl_shm::Format::Abgr8888 => Some(Box::<ConvertNone>::default()),
-        wl_shm::Format::Xrgb8888 | wl_shm::Format::Argb8888 => Some(Box::<ConvertRGB8>::default()),
+        // Formats with alpha keep it, e.g. for transparent captures, the padding
+        // byte of the others is whatever the compositor left there.
+        wl_shm::Format::Abgr8888 => Some(Box::<ConvertNone>::default()),
+        wl_shm::Format::Xbgr8888 => Some(Box::<ConvertOpaque>::default()),
+        wl_shm::Format::Argb8888 => Some(Box::<ConvertRGB8>::default()),
+        wl_shm::Format::Xrgb8888 => Some(Box::<ConvertRGB8Opaque>::default()),
         wl_shm::Format::Xbgr2101010 | wl_shm::Format::Abgr2101010 => {
             Some(Box::<ConvertBGR10>::default())
         }
@@ -36,27 +89,66 @@ pub fn create_converter(format: wl_shm::Format) -> Option<Box<dyn Convert>> {
 }
 
 impl Convert for ConvertNone {
-    fn convert_inplace(&self, _data: &mut [u8]) -> ColorType {
-        ColorType::Rgba8
+    fn convert_inplace(&self, data: &mut [u8]) {
+        for chunk in data.chunks_exact_mut(4) {
+            let [r, g, b, a] = [chunk[0], chunk[1], chunk[2], chunk[3]];
+            chunk.copy_from_slice(&unpremultiplied([r, g, b, a]));
+        }
+    }
+}
+
+impl Convert for ConvertOpaque {
+    fn convert_inplace(&self, data: &mut [u8]) {
+        for chunk in data.chunks_exact_mut(4) {
+            chunk[3] = 255;
+        }
     }
 }
 
 impl Convert for ConvertRGB8 {
-    fn convert_inplace(&self, data: &mut [u8]) -> ColorType {
+    fn convert_inplace(&self, data: &mut [u8]) {
+        for chunk in data.chunks_exact_mut(4) {
+            let [b, g, r, a] = [chunk[0], chunk[1], chunk[2], chunk[3]];
+            chunk.copy_from_slice(&unpremultiplied([r, g, b, a]));
+        }
+    }
+}
+
+impl Convert for ConvertRGB8Opaque {
+    fn convert_inplace(&self, data: &mut [u8]) {
         for chunk in data.chunks_exact_mut(4) {
             chunk.swap(0, 2);
+            chunk[3] = 255;
         }
-        ColorType::Rgba8
     }
 }
 
+/// The straight RGBA pixel images expect of a premultiplied one. Fully
+/// transparent pixels become transparent black.
+#[inline(always)]
+pub(crate) fn unpremultiplied([r, g, b, a]: [u8; 4]) -> [u8; 4] {
+    let straight = |channel: u8| match a {
+        0 => 0,
+        255 => channel,
+        a => (channel as u32 * 255 / a as u32).min(255) as u8,
+    };
+    [straight(r), straight(g), straight(b), a]
+}
+
+/// The premultiplied pixel of the straight RGBA pixel, the inverse of
+/// [`unpremultiplied`] up to rounding.
+pub(crate) fn premultiplied([r, g, b, a]: [u8; 4]) -> [u8; 4] {
+    let premultiply = |channel: u8| ((channel as u32 * a as u32 + 127) / 255) as u8;
+    [premultiply(r), premultiply(g), premultiply(b), a]
+}
+
 /// Simple conversion from 10 to 8 bits for one channel
 fn convert10_to_8(color: u32) -> u8 {
     ((color >> 2) & 255) as u8
 }
 
 impl Convert for ConvertBGR10 {
-    fn convert_inplace(&self, data: &mut [u8]) -> ColorType {
+    fn convert_inplace(&self, data: &mut [u8]) {
         for chunk in data.chunks_exact_mut(4) {
             let pixel = ((chunk[3] as u32) << 24)
                 | ((chunk[2] as u32) << 16)
@@ -70,12 +162,17 @@ impl Convert for ConvertBGR10 {
             chunk[2] = r;
             chunk[3] = 255;
         }
-        ColorType::Rgba8
+    }
+
+    fn color_handling(&self) -> ColorHandling {
+        ColorHandling::ReducedDepth
     }
 }
 
 impl Convert for ConvertBGR888 {
-    fn convert_inplace(&self, _data: &mut [u8]) -> ColorType {
+    fn convert_inplace(&self, _data: &mut [u8]) {}
+
+    fn color_type(&self) -> ColorType {
         ColorType::Rgb8
     }
 }
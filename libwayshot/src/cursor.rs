@@ -0,0 +1,668 @@
+use std::{collections::HashSet, env, fs, slice};
+
+use image::{Pixel, RgbaImage, imageops::FilterType};
+use xcursor::{CursorTheme, parser::Image as XcursorImage};
+
+use crate::{LogicalRegion, OutputInfo, convert, region::Position};
+
+/// Cursor size used when `XCURSOR_SIZE` is unset, matching libwayland-cursor.
+const DEFAULT_CURSOR_SIZE: u32 = 24;
+
+/// Outline of the built-in arrow, designed on a 24x24 grid with the tip at the origin.
+const ARROW_OUTLINE: [(f64, f64); 7] = [
+    (0.0, 0.0),
+    (0.0, 17.0),
+    (4.5, 13.0),
+    (7.5, 20.0),
+    (10.0, 19.0),
+    (7.0, 12.0),
+    (13.0, 12.0),
+];
+
+/// How the cursor is drawn when capturing several outputs into one image.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CursorMode {
+    /// Capture without the cursor.
+    #[default]
+    Hidden,
+    /// Let the compositor draw the cursor into every output capture. Mirrored
+    /// or overlapping outputs can show the cursor more than once.
+    PerOutput,
+    /// Capture the outputs without the cursor and draw the cursor on the
+    /// stitched image afterwards, covering every logical pixel at most once.
+    ///
+    /// The cursor position and image are read from the cursor sessions of
+    /// ext-image-copy-capture. If the compositor reports the position but the
+    /// image can't be copied, the image of the
+    /// [`CursorSource`](crate::WayshotConnection::set_cursor_source) is drawn
+    /// instead. Compositors without ext-image-copy-capture draw the cursor
+    /// into every output like [`Self::PerOutput`].
+    ComposeOnce,
+}
+
+impl From<bool> for CursorMode {
+    fn from(cursor_overlay: bool) -> Self {
+        if cursor_overlay {
+            Self::PerOutput
+        } else {
+            Self::Hidden
+        }
+    }
+}
+
+/// Result of [`WayshotConnection::check_cursor_overlay`].
+///
+/// [`WayshotConnection::check_cursor_overlay`]: crate::WayshotConnection::check_cursor_overlay
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CursorOverlayCheck {
+    /// A capture with the cursor differed from one without, so hiding the
+    /// cursor works.
+    Honored,
+    /// The captures with and without the cursor were identical. Either the
+    /// cursor is not on the checked outputs, or the compositor ignores
+    /// `overlay_cursor` and draws the cursor always or never. A cursor the
+    /// compositor always draws cannot be told apart from the content below it
+    /// and is not removed.
+    Unverified,
+}
+
+/// Cursor pixels of one output, positioned in the stitched image.
+#[derive(Debug, Clone)]
+pub(crate) struct CursorLayer {
+    pub x: i64,
+    pub y: i64,
+    pub image: RgbaImage,
+}
+
+impl CursorLayer {
+    /// The bounding box of the pixels that differ between the captures with and
+    /// without the cursor, or `None` if the cursor is not on this output.
+    ///
+    /// `origin` is the position of the captures in the stitched image. Pixels
+    /// inside the box that did not change are left transparent.
+    pub fn extract(
+        with_cursor: &RgbaImage,
+        without_cursor: &RgbaImage,
+        origin: (i64, i64),
+    ) -> Option<Self> {
+        if with_cursor.dimensions() != without_cursor.dimensions() {
+            tracing::warn!("Cursor captures differ in size, ignoring the cursor");
+            return None;
+        }
+        let changed = |x: u32, y: u32| {
+            with_cursor.get_pixel(x, y).to_rgb() != without_cursor.get_pixel(x, y).to_rgb()
+        };
+
+        let (mut x1, mut y1, mut x2, mut y2) = (u32::MAX, u32::MAX, 0, 0);
+        for (x, y, _) in with_cursor.enumerate_pixels() {
+            if changed(x, y) {
+                x1 = x1.min(x);
+                y1 = y1.min(y);
+                x2 = x2.max(x);
+                y2 = y2.max(y);
+            }
+        }
+        if x1 > x2 {
+            return None;
+        }
+
+        let image = RgbaImage::from_fn(x2 - x1 + 1, y2 - y1 + 1, |x, y| {
+            let (x, y) = (x1 + x, y1 + y);
+            if changed(x, y) {
+                let mut pixel = *with_cursor.get_pixel(x, y);
+                pixel.0[3] = 255;
+                pixel
+            } else {
+                image::Rgba([0, 0, 0, 0])
+            }
+        });
+        Some(Self {
+            x: origin.0 + x1 as i64,
+            y: origin.1 + y1 as i64,
+            image,
+        })
+    }
+}
+
+/// The cursor over an output as the compositor reports it, see
+/// [`CursorMode::ComposeOnce`].
+#[derive(Debug, Clone)]
+pub(crate) struct CursorPlane {
+    /// Position of the hotspot in pixels of the output's buffer, may lie
+    /// outside of it when only part of the cursor is over the output.
+    pub position: (i32, i32),
+    pub cursor: CursorImage,
+    /// Pixels of `cursor` per logical pixel.
+    pub scale: f64,
+}
+
+impl CursorPlane {
+    /// The cursor image at `scale` pixels per logical pixel, with its hotspot.
+    fn scaled(&self, scale: f64) -> (RgbaImage, (f64, f64)) {
+        let factor = scale / self.scale;
+        let CursorImage { image, hotspot } = &self.cursor;
+        let image = if factor == 1.0 {
+            image.clone()
+        } else {
+            let size = |length: u32| ((length as f64 * factor).round() as u32).max(1);
+            image::imageops::resize(
+                image,
+                size(image.width()),
+                size(image.height()),
+                FilterType::Triangle,
+            )
+        };
+        (
+            image,
+            (hotspot.0 as f64 * factor, hotspot.1 as f64 * factor),
+        )
+    }
+}
+
+impl CursorLayer {
+    /// The layer of `plane` over `output_info`, placed in a composite of
+    /// `capture_region` with `max_scale` pixels per logical pixel.
+    pub fn from_plane(
+        plane: &CursorPlane,
+        output_info: &OutputInfo,
+        capture_region: LogicalRegion,
+        max_scale: f64,
+    ) -> Self {
+        let (x, y) = output_info
+            .physical_to_logical()
+            .apply((plane.position.0 as f64, plane.position.1 as f64));
+        let (image, (hotspot_x, hotspot_y)) = plane.scaled(max_scale);
+        let origin = capture_region.inner.position;
+        Self {
+            x: ((x - origin.x as f64) * max_scale - hotspot_x).round() as i64,
+            y: ((y - origin.y as f64) * max_scale - hotspot_y).round() as i64,
+            image,
+        }
+    }
+}
+
+/// The cursor over a frame of
+/// [`WayshotConnection::capture_on_damage_excluding_cursor`], which captures
+/// the frames without it. See [`Self::draw`].
+///
+/// [`WayshotConnection::capture_on_damage_excluding_cursor`]: crate::WayshotConnection::capture_on_damage_excluding_cursor
+#[derive(Debug, Clone)]
+pub struct FrameCursor {
+    /// Position of the hotspot in the global logical coordinate space.
+    pub position: (f64, f64),
+    /// The cursor in the buffer pixels of the frame.
+    layer: CursorLayer,
+}
+
+impl FrameCursor {
+    /// The cursor of `plane` over a capture of `output_info`.
+    pub(crate) fn new(plane: &CursorPlane, output_info: &OutputInfo) -> Self {
+        let position = output_info
+            .physical_to_logical()
+            .apply((plane.position.0 as f64, plane.position.1 as f64));
+        let (image, (hotspot_x, hotspot_y)) = plane.scaled(output_info.scale());
+        Self {
+            position,
+            layer: CursorLayer {
+                x: (plane.position.0 as f64 - hotspot_x).round() as i64,
+                y: (plane.position.1 as f64 - hotspot_y).round() as i64,
+                image,
+            },
+        }
+    }
+
+    /// Blend the cursor onto `image`, the pixels of its frame in the
+    /// orientation of the buffer, as [`FrameCopy::image`] gives them.
+    ///
+    /// [`FrameCopy::image`]: crate::FrameCopy::image
+    pub fn draw(&self, image: &mut RgbaImage) {
+        compose_cursor_layers(image, slice::from_ref(&self.layer));
+    }
+}
+
+/// `damage` without the rectangles that lie within `radius` logical pixels of
+/// one of the `hotspots`, e.g. where the cursor was and where it is now.
+pub(crate) fn damage_outside_cursor(
+    damage: &[LogicalRegion],
+    hotspots: &[(f64, f64)],
+    radius: u32,
+) -> Vec<LogicalRegion> {
+    let radius = radius as f64;
+    let zones: Vec<LogicalRegion> = hotspots
+        .iter()
+        .map(|&(x, y)| {
+            LogicalRegion::from_corners(
+                Position {
+                    x: (x - radius).floor() as i32,
+                    y: (y - radius).floor() as i32,
+                },
+                Position {
+                    x: (x + radius).ceil() as i32,
+                    y: (y + radius).ceil() as i32,
+                },
+            )
+        })
+        .collect();
+    damage
+        .iter()
+        .filter(|rect| {
+            !zones
+                .iter()
+                .any(|zone| rect.intersection(zone) == Some(**rect))
+        })
+        .copied()
+        .collect()
+}
+
+/// Draw the cursor layers onto the stitched image, every pixel at most once.
+///
+/// Layers of mirrored outputs land on the same pixels and are drawn once,
+/// while the halves of a cursor straddling two outputs add up to one cursor.
+pub(crate) fn compose_cursor_layers(composite: &mut RgbaImage, layers: &[CursorLayer]) {
+    let mut painted = HashSet::new();
+    for layer in layers {
+        for (x, y, pixel) in layer.image.enumerate_pixels() {
+            if pixel.0[3] == 0 {
+                continue;
+            }
+            let (target_x, target_y) = (layer.x + x as i64, layer.y + y as i64);
+            let (Ok(target_x), Ok(target_y)) = (u32::try_from(target_x), u32::try_from(target_y))
+            else {
+                continue;
+            };
+            if target_x >= composite.width()
+                || target_y >= composite.height()
+                || !painted.insert((target_x, target_y))
+            {
+                continue;
+            }
+            composite.get_pixel_mut(target_x, target_y).blend(pixel);
+        }
+    }
+}
+
+/// Where a cursor drawn by wayshot itself gets its image from.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CursorSource {
+    /// The `default` (or `left_ptr`) cursor of the theme named by
+    /// `XCURSOR_THEME` at `XCURSOR_SIZE`, or the built-in arrow if that theme
+    /// or icon cannot be loaded.
+    #[default]
+    Theme,
+    /// Always use the built-in arrow.
+    BuiltIn,
+}
+
+/// A cursor image with its hotspot, the pixel that points at the cursor position.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CursorImage {
+    pub image: RgbaImage,
+    pub hotspot: (u32, u32),
+}
+
+impl CursorImage {
+    /// Load the cursor image from `source`, at the size of `XCURSOR_SIZE`.
+    pub fn load(source: CursorSource) -> Self {
+        let size = env::var("XCURSOR_SIZE")
+            .ok()
+            .and_then(|size| size.parse().ok())
+            .filter(|size| *size > 0)
+            .unwrap_or(DEFAULT_CURSOR_SIZE);
+        match source {
+            CursorSource::Theme => {
+                let theme = env::var("XCURSOR_THEME").unwrap_or_else(|_| "default".to_string());
+                Self::from_theme(&theme, size).unwrap_or_else(|| {
+                    tracing::debug!(
+                        "Could not load cursor theme {theme:?}, using the built-in cursor"
+                    );
+                    Self::built_in(size)
+                })
+            }
+            CursorSource::BuiltIn => Self::built_in(size),
+        }
+    }
+
+    /// The default cursor of the cursor theme `theme`, closest to `size`.
+    pub fn from_theme(theme: &str, size: u32) -> Option<Self> {
+        let theme = CursorTheme::load(theme);
+        let path = ["default", "left_ptr"]
+            .into_iter()
+            .find_map(|icon| theme.load_icon(icon))?;
+        let content = fs::read(&path)
+            .inspect_err(|e| tracing::debug!("Failed to read cursor {}: {e}", path.display()))
+            .ok()?;
+        Self::from_xcursor_images(&xcursor::parser::parse_xcursor(&content)?, size)
+    }
+
+    /// Pick the first frame of the image whose nominal size is closest to `size`.
+    fn from_xcursor_images(images: &[XcursorImage], size: u32) -> Option<Self> {
+        let closest = images
+            .iter()
+            .min_by_key(|image| image.size.abs_diff(size))?;
+        // Xcursor pixels are premultiplied ARGB in native (little) endian order.
+        let pixels = closest
+            .pixels_rgba
+            .chunks_exact(4)
+            .flat_map(|pixel| convert::unpremultiplied([pixel[2], pixel[1], pixel[0], pixel[3]]))
+            .collect();
+        Some(Self {
+            image: RgbaImage::from_raw(closest.width, closest.height, pixels)?,
+            hotspot: (closest.xhot, closest.yhot),
+        })
+    }
+
+    /// A white arrow with a black outline of `size`x`size` pixels, hotspot at its tip.
+    pub fn built_in(size: u32) -> Self {
+        let scale = size as f64 / DEFAULT_CURSOR_SIZE as f64;
+        let inside = |x: i64, y: i64| {
+            point_in_polygon(
+                ((x as f64 + 0.5) / scale, (y as f64 + 0.5) / scale),
+                &ARROW_OUTLINE,
+            )
+        };
+        let image = RgbaImage::from_fn(size, size, |x, y| {
+            let (x, y) = (x as i64, y as i64);
+            if !inside(x, y) {
+                image::Rgba([0, 0, 0, 0])
+            } else if [(-1, 0), (1, 0), (0, -1), (0, 1)]
+                .iter()
+                .all(|(dx, dy)| inside(x + dx, y + dy))
+            {
+                image::Rgba([255, 255, 255, 255])
+            } else {
+                image::Rgba([0, 0, 0, 255])
+            }
+        });
+        Self {
+            image,
+            hotspot: (0, 0),
+        }
+    }
+}
+
+/// Even-odd rule point in polygon test.
+fn point_in_polygon((x, y): (f64, f64), polygon: &[(f64, f64)]) -> bool {
+    let mut inside = false;
+    let mut previous = polygon[polygon.len() - 1];
+    for &current in polygon {
+        let ((x1, y1), (x2, y2)) = (previous, current);
+        if (y1 > y) != (y2 > y) && x < x1 + (y - y1) * (x2 - x1) / (y2 - y1) {
+            inside = !inside;
+        }
+        previous = current;
+    }
+    inside
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::region::{Position, Region, Size};
+    use image::Rgba;
+
+    const BACKGROUND: Rgba<u8> = Rgba([10, 20, 30, 255]);
+    const CURSOR: Rgba<u8> = Rgba([255, 255, 255, 255]);
+
+    /// An output capture with a cursor of `width`x`height` at `(x, y)`, if any.
+    fn capture(size: (u32, u32), cursor: Option<(u32, u32, u32, u32)>) -> RgbaImage {
+        RgbaImage::from_fn(size.0, size.1, |px, py| match cursor {
+            Some((x, y, width, height))
+                if (x..x + width).contains(&px) && (y..y + height).contains(&py) =>
+            {
+                CURSOR
+            }
+            _ => BACKGROUND,
+        })
+    }
+
+    fn cursor_pixels(image: &RgbaImage) -> usize {
+        image.pixels().filter(|pixel| **pixel == CURSOR).count()
+    }
+
+    #[test]
+    fn extract_returns_bounding_box_of_changed_pixels() {
+        let without = capture((20, 10), None);
+        let with = capture((20, 10), Some((4, 2, 3, 5)));
+
+        let layer = CursorLayer::extract(&with, &without, (100, 50)).expect("cursor found");
+
+        assert_eq!((layer.x, layer.y), (104, 52));
+        assert_eq!(layer.image.dimensions(), (3, 5));
+        assert!(layer.image.pixels().all(|pixel| *pixel == CURSOR));
+    }
+
+    #[test]
+    fn extract_returns_none_without_cursor() {
+        let without = capture((20, 10), None);
+
+        assert!(CursorLayer::extract(&without, &without, (0, 0)).is_none());
+    }
+
+    #[test]
+    fn compose_once_draws_mirrored_cursor_once() {
+        // Two mirrored outputs both show the cursor at the same logical spot.
+        let with = capture((20, 10), Some((5, 5, 4, 4)));
+        let without = capture((20, 10), None);
+        let first = CursorLayer::extract(&with, &without, (0, 0)).expect("cursor found");
+        let mut second = first.clone();
+        // Tint the second layer so drawing it would be visible.
+        second.image.pixels_mut().for_each(|pixel| pixel.0[0] = 0);
+        let mut composite = capture((20, 10), None);
+
+        compose_cursor_layers(&mut composite, &[first, second]);
+
+        assert_eq!(composite, with);
+    }
+
+    #[test]
+    fn compose_once_joins_cursor_straddling_an_output_boundary() {
+        // Outputs side by side at x 0 and 10, a 4x4 cursor at logical x 8..12.
+        let left_with = capture((10, 10), Some((8, 3, 2, 4)));
+        let right_with = capture((10, 10), Some((0, 3, 2, 4)));
+        let without = capture((10, 10), None);
+        let layers: Vec<_> = [
+            CursorLayer::extract(&left_with, &without, (0, 0)),
+            CursorLayer::extract(&right_with, &without, (10, 0)),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        let mut composite = capture((20, 10), None);
+
+        compose_cursor_layers(&mut composite, &layers);
+
+        assert_eq!(composite, capture((20, 10), Some((8, 3, 4, 4))));
+        assert_eq!(cursor_pixels(&composite), 16);
+    }
+
+    #[test]
+    fn compose_clips_layers_outside_the_composite() {
+        let with = capture((10, 10), Some((0, 0, 4, 4)));
+        let without = capture((10, 10), None);
+        let layer = CursorLayer::extract(&with, &without, (-2, 8)).expect("cursor found");
+        let mut composite = capture((10, 10), None);
+
+        compose_cursor_layers(&mut composite, &[layer]);
+
+        assert_eq!(cursor_pixels(&composite), 4);
+    }
+
+    /// An output of `width`x`height` logical pixels at logical `x`, `scale`
+    /// pixels per logical pixel.
+    fn output(x: i32, width: u32, height: u32, scale: u32) -> OutputInfo {
+        OutputInfo::detached(
+            "DP-1",
+            Size {
+                width: width * scale,
+                height: height * scale,
+            },
+            LogicalRegion {
+                inner: Region {
+                    position: Position { x, y: 0 },
+                    size: Size { width, height },
+                },
+            },
+        )
+    }
+
+    /// A solid `size`x`size` cursor image with the hotspot in its top left corner.
+    fn plane(position: (i32, i32), size: u32, scale: f64) -> CursorPlane {
+        CursorPlane {
+            position,
+            cursor: CursorImage {
+                image: RgbaImage::from_pixel(size, size, CURSOR),
+                hotspot: (0, 0),
+            },
+            scale,
+        }
+    }
+
+    #[test]
+    fn planes_of_a_cursor_straddling_outputs_of_different_scales_are_drawn_once() {
+        // A 1x output at x 0..10 and a 2x output at x 10..20, the cursor is a
+        // 4x4 logical square at logical (8, 3).
+        let (left, right) = (output(0, 10, 10, 1), output(10, 10, 10, 2));
+        let layers = [
+            CursorLayer::from_plane(
+                &plane((8, 3), 4, 1.0),
+                &left,
+                LogicalRegion::new(0, 0, 20, 10),
+                2.0,
+            ),
+            CursorLayer::from_plane(
+                &plane((-4, 6), 8, 2.0),
+                &right,
+                LogicalRegion::new(0, 0, 20, 10),
+                2.0,
+            ),
+        ];
+        let mut composite = capture((40, 20), None);
+
+        compose_cursor_layers(&mut composite, &layers);
+
+        assert_eq!(composite, capture((40, 20), Some((16, 6, 8, 8))));
+    }
+
+    #[test]
+    fn planes_of_mirrored_outputs_are_drawn_once() {
+        let (first, second) = (output(0, 20, 10, 1), output(0, 20, 10, 1));
+        let first = CursorLayer::from_plane(
+            &plane((5, 5), 4, 1.0),
+            &first,
+            LogicalRegion::new(0, 0, 20, 10),
+            1.0,
+        );
+        let mut second = CursorLayer::from_plane(
+            &plane((5, 5), 4, 1.0),
+            &second,
+            LogicalRegion::new(0, 0, 20, 10),
+            1.0,
+        );
+        // Tint the second layer so drawing it would be visible.
+        second.image.pixels_mut().for_each(|pixel| pixel.0[0] = 0);
+        let mut composite = capture((20, 10), None);
+
+        compose_cursor_layers(&mut composite, &[first, second]);
+
+        assert_eq!(composite, capture((20, 10), Some((5, 5, 4, 4))));
+    }
+
+    #[test]
+    fn plane_is_placed_by_its_hotspot() {
+        let mut cursor = plane((10, 6), 4, 1.0);
+        cursor.cursor.hotspot = (2, 1);
+
+        let layer = CursorLayer::from_plane(
+            &cursor,
+            &output(0, 20, 10, 1),
+            LogicalRegion::new(0, 0, 20, 10),
+            1.0,
+        );
+
+        assert_eq!((layer.x, layer.y), (8, 5));
+    }
+
+    fn xcursor_image(size: u32, argb: u32) -> XcursorImage {
+        let pixels_rgba: Vec<u8> = (0..size * size).flat_map(|_| argb.to_le_bytes()).collect();
+        XcursorImage {
+            size,
+            width: size,
+            height: size,
+            xhot: size / 4,
+            yhot: size / 2,
+            delay: 0,
+            pixels_argb: pixels_rgba.clone(),
+            pixels_rgba,
+        }
+    }
+
+    #[test]
+    fn xcursor_picks_closest_size_and_unpremultiplies() {
+        // Half transparent red, premultiplied.
+        let images = [
+            xcursor_image(16, 0x8080_0000),
+            xcursor_image(32, 0x8080_0000),
+        ];
+
+        let cursor = CursorImage::from_xcursor_images(&images, 30).expect("cursor");
+
+        assert_eq!(cursor.image.dimensions(), (32, 32));
+        assert_eq!(cursor.hotspot, (8, 16));
+        assert_eq!(*cursor.image.get_pixel(0, 0), Rgba([255, 0, 0, 128]));
+    }
+
+    #[test]
+    fn missing_theme_is_none() {
+        assert!(CursorImage::from_theme("wayshot-no-such-theme", 24).is_none());
+    }
+
+    #[test]
+    fn built_in_arrow_scales_with_size() {
+        for size in [24, 48] {
+            let cursor = CursorImage::built_in(size);
+
+            assert_eq!(cursor.image.dimensions(), (size, size));
+            assert_eq!(cursor.hotspot, (0, 0));
+            // Tip is opaque, the opposite corner is transparent.
+            assert_eq!(cursor.image.get_pixel(0, 0).0[3], 255);
+            assert_eq!(cursor.image.get_pixel(size - 1, 0).0[3], 0);
+            let white = cursor
+                .image
+                .pixels()
+                .filter(|pixel| **pixel == Rgba([255, 255, 255, 255]))
+                .count();
+            assert!(white > 0);
+        }
+    }
+
+    #[test]
+    fn frame_cursors_are_drawn_in_buffer_pixels() {
+        // A 4x4 logical cursor over a scale 2 output covers 8x8 of its pixels.
+        let cursor = FrameCursor::new(&plane((6, 4), 4, 1.0), &output(0, 10, 10, 2));
+        let mut frame = capture((20, 20), None);
+
+        cursor.draw(&mut frame);
+
+        assert_eq!(cursor.position, (3.0, 2.0));
+        assert_eq!(frame, capture((20, 20), Some((6, 4, 8, 8))));
+    }
+
+    #[test]
+    fn damage_around_the_cursor_is_ignored() {
+        let rect = |x, y, size| LogicalRegion::new(x, y, size, size);
+        let (old_cursor, new_cursor, straddling, elsewhere) = (
+            rect(45, 45, 10),
+            rect(98, 98, 4),
+            rect(60, 60, 20),
+            rect(200, 0, 4),
+        );
+
+        let damage = damage_outside_cursor(
+            &[old_cursor, new_cursor, straddling, elsewhere],
+            &[(50.0, 50.0), (100.0, 100.0)],
+            16,
+        );
+
+        assert_eq!(damage, [straddling, elsewhere]);
+    }
+}
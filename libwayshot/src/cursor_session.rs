@@ -0,0 +1,281 @@
+//! The cursor over an output as the compositor draws it, read with the cursor
+//! sessions of ext-image-copy-capture. See [`CursorMode::ComposeOnce`].
+//!
+//! [`CursorMode::ComposeOnce`]: crate::CursorMode::ComposeOnce
+
+use std::fs::File;
+
+use image::RgbaImage;
+use wayland_client::{
+    Connection, Dispatch, EventQueue, Proxy, QueueHandle, WEnum, delegate_noop,
+    protocol::{
+        wl_pointer::WlPointer,
+        wl_seat::{self, WlSeat},
+    },
+};
+use wayland_protocols::ext::{
+    image_capture_source::v1::client::{
+        ext_image_capture_source_v1::ExtImageCaptureSourceV1,
+        ext_output_image_capture_source_manager_v1::ExtOutputImageCaptureSourceManagerV1,
+    },
+    image_copy_capture::v1::client::{
+        ext_image_copy_capture_cursor_session_v1::{self, ExtImageCopyCaptureCursorSessionV1},
+        ext_image_copy_capture_manager_v1::ExtImageCopyCaptureManagerV1,
+    },
+};
+
+use crate::{
+    CursorImage, Error, OutputInfo, Result, WayshotConnection, buffer_frame_copy, convert,
+    cursor::CursorPlane,
+    dispatch::CaptureFrameState,
+    environment::{MIN_IMAGE_CAPTURE_SOURCE_VERSION, MIN_IMAGE_COPY_CAPTURE_VERSION, has_global},
+    screencopy::{create_shm_fd, map_shm_frame, seal_shm_size},
+};
+
+/// `wl_pointer.release` is only available from this `wl_seat` version on.
+const MIN_POINTER_RELEASE_VERSION: u32 = 3;
+/// `wl_seat.release` is only available from this version on.
+const MIN_SEAT_RELEASE_VERSION: u32 = 5;
+
+#[derive(Debug, Default)]
+struct CursorSessionState {
+    /// The bound seats, with whether they have a pointer.
+    seats: Vec<(WlSeat, bool)>,
+    /// Whether the cursor image is over the output.
+    entered: bool,
+    position: Option<(i32, i32)>,
+    hotspot: Option<(u32, u32)>,
+}
+
+impl WayshotConnection {
+    /// Whether the compositor offers cursor sessions for outputs.
+    pub(crate) fn cursor_sessions_supported(&self) -> bool {
+        self.image_copy_support
+            && has_global::<ExtOutputImageCaptureSourceManagerV1>(
+                &self.globals,
+                MIN_IMAGE_CAPTURE_SOURCE_VERSION,
+            )
+    }
+
+    /// The cursor of the first seat with a pointer over `output_info`, none
+    /// if it isn't over the output or there is no pointer.
+    ///
+    /// If the cursor image can't be copied, the image of
+    /// [`Self::set_cursor_source`] is used.
+    pub(crate) fn cursor_plane(&self, output_info: &OutputInfo) -> Result<Option<CursorPlane>> {
+        self.with_cursor_session(output_info, |event_queue, state, cursor_session| {
+            self.read_cursor_plane(event_queue, state, cursor_session, output_info)
+        })
+        .map(Option::flatten)
+    }
+
+    /// Whether the pointer of the first seat with one is over `output_info`.
+    pub(crate) fn pointer_is_over(&self, output_info: &OutputInfo) -> Result<bool> {
+        self.with_cursor_session(output_info, |event_queue, state, _| {
+            // Where the cursor is gets sent right after the session is created.
+            event_queue.roundtrip(state)?;
+            Ok(state.entered)
+        })
+        .map(|entered| entered.unwrap_or(false))
+    }
+
+    /// Run `f` with a cursor session for the pointer of the first seat with
+    /// one on `output_info`, none if no seat has a pointer.
+    fn with_cursor_session<T>(
+        &self,
+        output_info: &OutputInfo,
+        f: impl FnOnce(
+            &mut EventQueue<CursorSessionState>,
+            &mut CursorSessionState,
+            &ExtImageCopyCaptureCursorSessionV1,
+        ) -> Result<T>,
+    ) -> Result<Option<T>> {
+        let mut event_queue = self.conn.new_event_queue::<CursorSessionState>();
+        let qh = event_queue.handle();
+        let manager = self.globals.bind::<ExtImageCopyCaptureManagerV1, _, _>(
+            &qh,
+            MIN_IMAGE_COPY_CAPTURE_VERSION..=MIN_IMAGE_COPY_CAPTURE_VERSION,
+            (),
+        )?;
+        let source_manager = self
+            .globals
+            .bind::<ExtOutputImageCaptureSourceManagerV1, _, _>(
+                &qh,
+                MIN_IMAGE_CAPTURE_SOURCE_VERSION..=MIN_IMAGE_CAPTURE_SOURCE_VERSION,
+                (),
+            )?;
+        let mut state = CursorSessionState::default();
+        let registry = self.globals.registry();
+        for global in self.globals.contents().clone_list() {
+            if global.interface == WlSeat::interface().name {
+                let version = global.version.min(WlSeat::interface().version);
+                let wl_seat = registry.bind::<WlSeat, _, _>(global.name, version, &qh, ());
+                state.seats.push((wl_seat, false));
+            }
+        }
+        // The capabilities are sent right after binding.
+        let result = event_queue.roundtrip(&mut state).map_err(Error::from);
+
+        let seat = state
+            .seats
+            .iter()
+            .find(|(_, pointer)| *pointer)
+            .map(|(wl_seat, _)| wl_seat.clone());
+        let result = match (result, seat) {
+            (Err(e), _) => Err(e),
+            (Ok(_), None) => {
+                tracing::debug!("No seat has a pointer, there is no cursor session");
+                Ok(None)
+            }
+            (Ok(_), Some(wl_seat)) => {
+                let pointer = wl_seat.get_pointer(&qh, ());
+                let source = source_manager.create_source(&output_info.wl_output, &qh, ());
+                let cursor_session =
+                    manager.create_pointer_cursor_session(&source, &pointer, &qh, ());
+                let result = f(&mut event_queue, &mut state, &cursor_session);
+                cursor_session.destroy();
+                source.destroy();
+                if pointer.version() >= MIN_POINTER_RELEASE_VERSION {
+                    pointer.release();
+                }
+                result.map(Some)
+            }
+        };
+        for (wl_seat, _) in &state.seats {
+            if wl_seat.version() >= MIN_SEAT_RELEASE_VERSION {
+                wl_seat.release();
+            }
+        }
+        source_manager.destroy();
+        manager.destroy();
+        self.conn.flush().ok();
+        result
+    }
+
+    fn read_cursor_plane(
+        &self,
+        event_queue: &mut EventQueue<CursorSessionState>,
+        state: &mut CursorSessionState,
+        cursor_session: &ExtImageCopyCaptureCursorSessionV1,
+        output_info: &OutputInfo,
+    ) -> Result<Option<CursorPlane>> {
+        // Where the cursor is gets sent right after the session is created.
+        event_queue.roundtrip(state)?;
+        let Some(position) = state.position.filter(|_| state.entered) else {
+            return Ok(None);
+        };
+        let image = match self.capture_cursor_image(cursor_session) {
+            Ok(image) => image,
+            Err(e) => {
+                tracing::debug!("Failed to copy the cursor image, drawing our own: {e}");
+                return Ok(Some(CursorPlane {
+                    position,
+                    cursor: CursorImage::load(self.cursor_source),
+                    // `XCURSOR_SIZE` is in logical pixels.
+                    scale: 1.0,
+                }));
+            }
+        };
+        // The hotspot is sent along with the first captured image.
+        event_queue.roundtrip(state)?;
+        Ok(Some(CursorPlane {
+            position,
+            cursor: CursorImage {
+                image,
+                hotspot: state.hotspot.unwrap_or_default(),
+            },
+            // Compositors draw the cursor at the scale of the output it is on.
+            scale: output_info.scale(),
+        }))
+    }
+
+    /// Copy the image of the cursor of `cursor_session`.
+    fn capture_cursor_image(
+        &self,
+        cursor_session: &ExtImageCopyCaptureCursorSessionV1,
+    ) -> Result<RgbaImage> {
+        let mut state = CaptureFrameState::new(false);
+        let mut event_queue = self.conn.new_event_queue::<CaptureFrameState>();
+        let qh = event_queue.handle();
+        let session = cursor_session.get_capture_session(&qh, ());
+        let frame = session.create_frame(&qh, ());
+        let result = (|| {
+            while !state.session_done {
+                self.dispatch_capture(&mut event_queue, &mut state)?;
+            }
+            let frame_format = state
+                .formats
+                .iter()
+                .find(|format| convert::is_supported(format.format))
+                .copied()
+                .ok_or_else(|| {
+                    Error::no_usable_format(state.formats.iter().map(|format| format.format))
+                })?;
+            let frame_format = self.align_stride(frame_format);
+            let mem_file = File::from(create_shm_fd()?);
+            mem_file.set_len(frame_format.byte_size())?;
+            seal_shm_size(&mem_file);
+            let _frame_guard = self.ext_image_copy_frame_inner(
+                state,
+                event_queue,
+                frame,
+                frame_format,
+                &mem_file,
+            )?;
+            let frame_mmap = map_shm_frame(&mem_file, frame_format)?;
+            buffer_frame_copy(frame_format, frame_mmap, self.next_capture_id()).get_image()
+        })();
+        session.destroy();
+        Ok(result?.into_rgba8())
+    }
+}
+
+impl Dispatch<WlSeat, ()> for CursorSessionState {
+    fn event(
+        state: &mut Self,
+        proxy: &WlSeat,
+        event: wl_seat::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        if let wl_seat::Event::Capabilities {
+            capabilities: WEnum::Value(capabilities),
+        } = event
+            && let Some((_, pointer)) = state.seats.iter_mut().find(|(seat, _)| seat == proxy)
+        {
+            *pointer = capabilities.contains(wl_seat::Capability::Pointer);
+        }
+    }
+}
+
+impl Dispatch<ExtImageCopyCaptureCursorSessionV1, ()> for CursorSessionState {
+    fn event(
+        state: &mut Self,
+        _: &ExtImageCopyCaptureCursorSessionV1,
+        event: ext_image_copy_capture_cursor_session_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        match event {
+            ext_image_copy_capture_cursor_session_v1::Event::Enter => state.entered = true,
+            ext_image_copy_capture_cursor_session_v1::Event::Leave => {
+                state.entered = false;
+                state.position = None;
+            }
+            ext_image_copy_capture_cursor_session_v1::Event::Position { x, y } => {
+                state.position = Some((x, y))
+            }
+            ext_image_copy_capture_cursor_session_v1::Event::Hotspot { x, y } => {
+                state.hotspot = Some((x.max(0) as u32, y.max(0) as u32))
+            }
+            _ => {}
+        }
+    }
+}
+
+delegate_noop!(CursorSessionState: ignore WlPointer);
+delegate_noop!(CursorSessionState: ignore ExtImageCopyCaptureManagerV1);
+delegate_noop!(CursorSessionState: ignore ExtOutputImageCaptureSourceManagerV1);
+delegate_noop!(CursorSessionState: ignore ExtImageCaptureSourceV1);
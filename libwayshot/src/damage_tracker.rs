@@ -0,0 +1,208 @@
+//! Damage tracking for the screenshot cache, see
+//! [`crate::WayshotConnection::screenshot_single_output_cached`].
+//!
+//! Every tracked output has a wlr-screencopy frame copied with damage pending.
+//! The compositor only completes it once the output changed, so checking for
+//! damage is reading the events that arrived, and the idle cost is a buffer of
+//! the output size.
+
+use std::{
+    collections::hash_map::Entry,
+    fmt,
+    fs::File,
+    os::fd::AsFd,
+    sync::{PoisonError, atomic::Ordering},
+    time::Duration,
+};
+
+use wayland_client::{EventQueue, protocol::wl_shm::WlShm};
+use wayland_protocols_wlr::screencopy::v1::client::{
+    zwlr_screencopy_frame_v1::ZwlrScreencopyFrameV1,
+    zwlr_screencopy_manager_v1::ZwlrScreencopyManagerV1,
+};
+
+use crate::{
+    Error, OutputInfo, Result, WayshotConnection,
+    dispatch::CaptureFrameState,
+    dispatch_with_timeout,
+    environment::{MIN_SCREENCOPY_FOR_DAMAGE, MIN_SCREENCOPY_VERSION},
+    screencopy::{FrameFormat, FrameGuard, ResidentBytes, create_shm_buffer, create_shm_fd},
+};
+
+/// A screencopy session of one output with a copy pending until the output
+/// is damaged.
+pub(crate) struct DamageTracker {
+    output_info: OutputInfo,
+    /// Whether the cursor is part of the copies, so that its moves are damage.
+    cursor_overlay: bool,
+    event_queue: EventQueue<CaptureFrameState>,
+    state: CaptureFrameState,
+    /// Compositors track the damage per manager, so all frames share one.
+    manager: ZwlrScreencopyManagerV1,
+    shm: WlShm,
+    mem_file: File,
+    buffer: Option<(FrameFormat, FrameGuard)>,
+    frame: Option<ZwlrScreencopyFrameV1>,
+}
+
+impl fmt::Debug for DamageTracker {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DamageTracker")
+            .field("output", &self.output_info.name)
+            .finish_non_exhaustive()
+    }
+}
+
+impl WayshotConnection {
+    /// Whether `output_info` was damaged since the last call, `None` if the
+    /// compositor can't tell, see [`Self::capture_on_damage`]. With
+    /// `cursor_overlay`, moving the cursor over the output is damage too.
+    ///
+    /// The first call starts tracking the output and counts as damage, since
+    /// nothing is known about the time before it.
+    pub(crate) fn take_damage(
+        &self,
+        output_info: &OutputInfo,
+        cursor_overlay: bool,
+    ) -> Option<bool> {
+        let mut trackers = self
+            .damage_trackers
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let key = (output_info.wl_output.clone(), cursor_overlay);
+        let tracker = match trackers.entry(key.clone()) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                return match self.track_damage(output_info, cursor_overlay) {
+                    Ok(tracker) => {
+                        entry.insert(tracker);
+                        Some(true)
+                    }
+                    Err(error) => {
+                        tracing::debug!("Not tracking the damage of {output_info}: {error}");
+                        None
+                    }
+                };
+            }
+        };
+        match tracker.take_damage(self) {
+            Ok(damaged) => Some(damaged),
+            Err(error) => {
+                tracing::debug!("Stopped tracking the damage of {output_info}: {error}");
+                trackers.remove(&key);
+                Some(true)
+            }
+        }
+    }
+
+    /// Stop tracking the damage of outputs that are not part of `outputs`.
+    pub(crate) fn retain_damage_trackers(&self, outputs: &[OutputInfo]) {
+        self.damage_trackers
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .retain(|(wl_output, _), _| outputs.iter().any(|o| o.wl_output == *wl_output));
+    }
+
+    fn track_damage(
+        &self,
+        output_info: &OutputInfo,
+        cursor_overlay: bool,
+    ) -> Result<DamageTracker> {
+        let event_queue = self.conn.new_event_queue::<CaptureFrameState>();
+        let manager = self.bind_screencopy_manager(
+            &event_queue.handle(),
+            MIN_SCREENCOPY_VERSION.max(MIN_SCREENCOPY_FOR_DAMAGE),
+            "tracking damage",
+        )?;
+        let mut tracker = DamageTracker {
+            output_info: output_info.clone(),
+            cursor_overlay,
+            event_queue,
+            state: CaptureFrameState::new(false),
+            manager,
+            shm: self.capture_shm()?,
+            mem_file: File::from(create_shm_fd()?),
+            buffer: None,
+            frame: None,
+        };
+        // The first copy of a session completes right away, the next one
+        // waits for damage.
+        tracker.copy_with_damage(self)?;
+        while tracker.state.state.is_none() {
+            self.dispatch_capture(&mut tracker.event_queue, &mut tracker.state)?;
+        }
+        tracker.copy_with_damage(self)?;
+        Ok(tracker)
+    }
+}
+
+impl DamageTracker {
+    /// Whether the pending copy completed, copying again if it did.
+    fn take_damage(&mut self, wayshot: &WayshotConnection) -> Result<bool> {
+        dispatch_with_timeout(&mut self.event_queue, &mut self.state, Duration::ZERO)?;
+        if self.state.state.is_none() {
+            return Ok(false);
+        }
+        // A failed copy is damage too, e.g. when the mode of the output changed.
+        self.copy_with_damage(wayshot)?;
+        Ok(true)
+    }
+
+    /// Start a copy of the output that completes once it is damaged.
+    fn copy_with_damage(&mut self, wayshot: &WayshotConnection) -> Result<()> {
+        if let Some(frame) = self.frame.take() {
+            frame.destroy();
+        }
+        self.state = CaptureFrameState::new(false);
+        let qh = self.event_queue.handle();
+        let frame = self.manager.capture_output(
+            self.cursor_overlay as i32,
+            &self.output_info.wl_output,
+            &qh,
+            (),
+        );
+        while !self.state.buffer_done.load(Ordering::SeqCst) {
+            wayshot.dispatch_capture(&mut self.event_queue, &mut self.state)?;
+        }
+        let frame_format =
+            FrameFormat::select_shm(&self.state.formats, Some(self.output_info.physical_size))
+                .ok_or_else(|| {
+                    Error::no_usable_format(self.state.formats.iter().map(|format| format.format))
+                })?;
+        let frame_guard = match self.buffer {
+            Some((format, ref frame_guard)) if format == frame_format => frame_guard,
+            _ => {
+                // Drop the previous buffer before its memory is resized.
+                self.buffer = None;
+                // The shm file is sealed against shrinking, so only ever grow it.
+                if self.mem_file.metadata()?.len() < frame_format.byte_size() {
+                    self.mem_file.set_len(frame_format.byte_size())?;
+                }
+                let (shm_pool, buffer) =
+                    create_shm_buffer(&self.shm, self.mem_file.as_fd(), &frame_format, &qh)?;
+                let frame_guard = FrameGuard {
+                    buffer,
+                    shm_pool,
+                    size: frame_format.size,
+                    _resident: ResidentBytes::new(
+                        &wayshot.resident_capture_bytes,
+                        frame_format.byte_size(),
+                    ),
+                };
+                &self.buffer.insert((frame_format, frame_guard)).1
+            }
+        };
+        frame.copy_with_damage(&frame_guard.buffer);
+        self.frame = Some(frame);
+        Ok(())
+    }
+}
+
+impl Drop for DamageTracker {
+    fn drop(&mut self) {
+        if let Some(frame) = self.frame.take() {
+            frame.destroy();
+        }
+        self.manager.destroy();
+    }
+}
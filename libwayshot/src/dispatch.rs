@@ -1,8 +1,8 @@
-use drm::node::DrmNode;
+use drm::node::{DrmNode, NodeType};
 use std::{
     collections::HashSet,
     os::fd::{AsFd, BorrowedFd},
-    path::Path,
+    path::{Path, PathBuf},
     sync::atomic::{AtomicBool, Ordering},
 };
 use wayland_client::{
@@ -12,9 +12,12 @@ use wayland_client::{
     globals::GlobalListContents,
     protocol::{
         wl_buffer::WlBuffer,
+        wl_callback::{self, WlCallback},
         wl_compositor::WlCompositor,
         wl_output::{self, WlOutput},
+        wl_region::WlRegion,
         wl_registry::{self, WlRegistry},
+        wl_seat::{self, WlSeat},
         wl_shm::WlShm,
         wl_shm_pool::WlShmPool,
         wl_surface::WlSurface,
@@ -40,6 +43,7 @@ use wayland_protocols::{
     wp::{
         linux_dmabuf::zv1::client::{
             zwp_linux_buffer_params_v1::{self, ZwpLinuxBufferParamsV1},
+            zwp_linux_dmabuf_feedback_v1::{self, ZwpLinuxDmabufFeedbackV1},
             zwp_linux_dmabuf_v1::{self, ZwpLinuxDmabufV1},
         },
         viewporter::client::{wp_viewport::WpViewport, wp_viewporter::WpViewporter},
@@ -61,14 +65,22 @@ use wayland_protocols_wlr::{
 };
 
 use crate::{
+    Error, Result,
+    environment::MIN_WL_OUTPUT_VERSION,
     output::OutputInfo,
-    region::{LogicalRegion, Position, Size, TopLevel},
+    region::{LogicalRegion, Position, Region, Size, TopLevel},
     screencopy::{DMAFrameFormat, FrameFormat},
+    seat::SeatInfo,
 };
 
-#[derive(Debug)]
-pub struct OutputCaptureState {
-    pub outputs: Vec<OutputInfo>,
+#[derive(Debug, Default)]
+pub(crate) struct OutputCaptureState {
+    pub(crate) outputs: Vec<OutputInfo>,
+    /// Outputs that received `wl_output.done` after their logical region was known.
+    pub(crate) complete_outputs: HashSet<WlOutput>,
+    /// When set, an xdg_output is requested for every output as soon as it is bound.
+    pub(crate) xdg_output_manager: Option<ZxdgOutputManagerV1>,
+    pub(crate) xdg_outputs: Vec<ZxdgOutputV1>,
 }
 
 impl Dispatch<WlRegistry, ()> for OutputCaptureState {
@@ -92,20 +104,40 @@ impl Dispatch<WlRegistry, ()> for OutputCaptureState {
         } = event
             && interface == "wl_output"
         {
-            if version >= 4 {
-                let output = wl_registry.bind::<wl_output::WlOutput, _, _>(name, 4, qh, ());
-                state.outputs.push(OutputInfo {
-                    wl_output: output,
-                    name: "".to_string(),
-                    description: String::new(),
-                    transform: wl_output::Transform::Normal,
-                    physical_size: Size::default(),
-                    logical_region: LogicalRegion::default(),
-                });
-            } else {
-                tracing::error!("Ignoring a wl_output with version < 4.");
-            }
+            state.bind_output(wl_registry, name, version, qh);
+        }
+    }
+}
+
+impl OutputCaptureState {
+    /// Bind the `wl_output` global `name` and request its xdg_output right
+    /// away, so both answer in the same roundtrip.
+    pub(crate) fn bind_output(
+        &mut self,
+        wl_registry: &WlRegistry,
+        name: u32,
+        version: u32,
+        qh: &QueueHandle<Self>,
+    ) {
+        if version < MIN_WL_OUTPUT_VERSION {
+            tracing::error!("Ignoring a wl_output with version < {MIN_WL_OUTPUT_VERSION}.");
+            return;
+        }
+        let output =
+            wl_registry.bind::<wl_output::WlOutput, _, _>(name, MIN_WL_OUTPUT_VERSION, qh, ());
+        if let Some(xdg_output_manager) = &self.xdg_output_manager {
+            let index = self.outputs.len();
+            self.xdg_outputs
+                .push(xdg_output_manager.get_xdg_output(&output, qh, index));
         }
+        self.outputs.push(OutputInfo {
+            wl_output: output,
+            name: "".to_string(),
+            description: String::new(),
+            transform: wl_output::Transform::Normal,
+            physical_size: Size::default(),
+            logical_region: LogicalRegion::default(),
+        });
     }
 }
 
@@ -150,6 +182,10 @@ impl Dispatch<WlOutput, ()> for OutputCaptureState {
                 output.transform = transform;
             }
             wl_output::Event::Scale { .. } => {}
+            // xdg_output v3 sends its logical region right before wl_output.done.
+            wl_output::Event::Done if output.logical_region.inner.size != Size::default() => {
+                state.complete_outputs.insert(wl_output.clone());
+            }
             wl_output::Event::Done => {}
             _ => {}
         }
@@ -198,7 +234,7 @@ impl Dispatch<ZxdgOutputV1, usize> for OutputCaptureState {
 
 /// State of the frame after attempting to copy it's data to a wl_buffer.
 #[derive(Debug, Copy, Clone, PartialEq)]
-pub enum FrameState {
+pub(crate) enum FrameState {
     /// Compositor returned a failed event on calling `frame.copy`.
     Failed,
     FailedWithReason(WEnum<FailureReason>),
@@ -206,14 +242,19 @@ pub enum FrameState {
     Finished,
 }
 
-pub struct CaptureFrameState {
-    pub formats: Vec<FrameFormat>,
-    pub dmabuf_formats: Vec<DMAFrameFormat>,
-    pub state: Option<FrameState>,
-    pub buffer_done: AtomicBool,
-    pub toplevels: Vec<TopLevel>,
+pub(crate) struct CaptureFrameState {
+    pub(crate) formats: Vec<FrameFormat>,
+    pub(crate) dmabuf_formats: Vec<DMAFrameFormat>,
+    pub(crate) state: Option<FrameState>,
+    pub(crate) buffer_done: AtomicBool,
+    /// Damaged regions of a wlr-screencopy frame copied with damage, in buffer coordinates.
+    pub(crate) damage: Vec<Region>,
+    pub(crate) toplevels: Vec<TopLevel>,
     pub(crate) session_done: bool,
-    pub(crate) gbm: Option<gbm::Device<Card>>,
+    /// Buffer size of an ext-image-copy session, which applies to all of its formats.
+    buffer_size: Size,
+    /// The device advertised by ext-image-copy and a GBM device opened on it.
+    pub(crate) gbm: Option<(PathBuf, gbm::Device<Card>)>,
     find_gbm: bool,
 }
 
@@ -224,12 +265,26 @@ impl CaptureFrameState {
             dmabuf_formats: Vec::new(),
             state: None,
             buffer_done: AtomicBool::new(false),
+            damage: Vec::new(),
             toplevels: Vec::new(),
             session_done: false,
+            buffer_size: Size::default(),
             gbm: None,
             find_gbm,
         }
     }
+
+    /// The first advertised dmabuf format.
+    ///
+    /// Returns [`Error::NoFormatsAdvertised`] if no formats were advertised at
+    /// all, or [`Error::NoSupportedBufferFormat`] naming the shm formats if
+    /// only those were.
+    pub(crate) fn first_dmabuf_format(&self) -> Result<DMAFrameFormat> {
+        self.dmabuf_formats
+            .first()
+            .copied()
+            .ok_or_else(|| Error::no_usable_format(self.formats.iter().map(|format| format.format)))
+    }
 }
 
 impl Dispatch<ZwpLinuxDmabufV1, ()> for CaptureFrameState {
@@ -289,21 +344,13 @@ impl Dispatch<ExtImageCopyCaptureSessionV1, ()> for CaptureFrameState {
         _conn: &Connection,
         _qhandle: &QueueHandle<Self>,
     ) {
-        if state.formats.is_empty() {
-            state.formats.push(FrameFormat {
-                format: wayland_client::protocol::wl_shm::Format::Rgb888A8,
-                size: Size {
-                    width: 0,
-                    height: 0,
-                },
-                stride: 0,
-            });
-        }
         match event {
             ext_image_copy_capture_session_v1::Event::BufferSize { width, height } => {
-                let format = state.formats.first_mut().unwrap();
-                format.size = Size { width, height };
-                format.stride = 4 * width;
+                state.buffer_size = Size { width, height };
+                for format in &mut state.formats {
+                    format.size = state.buffer_size;
+                    format.stride = 4 * width;
+                }
                 for DMAFrameFormat {
                     size:
                         Size {
@@ -320,8 +367,11 @@ impl Dispatch<ExtImageCopyCaptureSessionV1, ()> for CaptureFrameState {
             ext_image_copy_capture_session_v1::Event::ShmFormat {
                 format: WEnum::Value(format),
             } => {
-                let set_format = state.formats.first_mut().unwrap();
-                set_format.format = format;
+                state.formats.push(FrameFormat {
+                    format,
+                    size: state.buffer_size,
+                    stride: 4 * state.buffer_size.width,
+                });
             }
             ext_image_copy_capture_session_v1::Event::DmabufDevice { device } => {
                 if !state.find_gbm {
@@ -331,25 +381,18 @@ impl Dispatch<ExtImageCopyCaptureSessionV1, ()> for CaptureFrameState {
                 let Ok(node) = DrmNode::from_dev_id(device) else {
                     return;
                 };
-                let Some(pa) = node.dev_path() else {
+                let Some(pa) = render_node_path(&node) else {
                     return;
                 };
-                let Ok(gbm) = gbm::Device::new(Card::open(pa)) else {
+                let Ok(gbm) = Card::open(&pa).and_then(gbm::Device::new) else {
                     return;
                 };
-                state.gbm = Some(gbm);
+                state.gbm = Some((pa, gbm));
             }
             ext_image_copy_capture_session_v1::Event::DmabufFormat { format, .. } => {
-                let mut width = 0;
-                let mut height = 0;
-                if !state.formats.is_empty() {
-                    let format = state.formats[0];
-                    width = format.size.width;
-                    height = format.size.height;
-                }
                 state.dmabuf_formats.push(DMAFrameFormat {
                     format,
-                    size: Size { width, height },
+                    size: state.buffer_size,
                 });
             }
             ext_image_copy_capture_session_v1::Event::Done => {
@@ -444,7 +487,18 @@ impl Dispatch<ZwlrScreencopyFrameV1, ()> for CaptureFrameState {
             zwlr_screencopy_frame_v1::Event::Failed => {
                 frame.state.replace(FrameState::Failed);
             }
-            zwlr_screencopy_frame_v1::Event::Damage { .. } => {}
+            zwlr_screencopy_frame_v1::Event::Damage {
+                x,
+                y,
+                width,
+                height,
+            } => frame.damage.push(Region {
+                position: Position {
+                    x: x as i32,
+                    y: y as i32,
+                },
+                size: Size { width, height },
+            }),
             zwlr_screencopy_frame_v1::Event::LinuxDmabuf {
                 format,
                 width,
@@ -477,7 +531,7 @@ delegate_noop!(CaptureFrameState: ignore ExtForeignToplevelImageCaptureSourceMan
 
 // TODO: Create a xdg-shell surface, check for the enter event, grab the output from it.
 
-pub struct WayshotState {}
+pub(crate) struct WayshotState {}
 delegate_noop!(WayshotState: ignore ZwpLinuxDmabufV1);
 impl wayland_client::Dispatch<wl_registry::WlRegistry, GlobalListContents> for WayshotState {
     fn event(
@@ -491,8 +545,63 @@ impl wayland_client::Dispatch<wl_registry::WlRegistry, GlobalListContents> for W
     }
 }
 
-pub struct LayerShellState {
-    pub configured_outputs: HashSet<WlOutput>,
+/// Collects the name a `wl_seat` announces after being bound.
+#[derive(Debug, Default)]
+pub(crate) struct SeatNameState {
+    pub(crate) name: Option<String>,
+}
+
+impl Dispatch<WlSeat, ()> for SeatNameState {
+    fn event(
+        state: &mut Self,
+        _: &WlSeat,
+        event: wl_seat::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        if let wl_seat::Event::Name { name } = event {
+            state.name = Some(name);
+        }
+    }
+}
+
+/// Collects the name and capabilities of every bound `wl_seat`.
+#[derive(Debug, Default)]
+pub(crate) struct SeatListState {
+    pub(crate) seats: Vec<(WlSeat, SeatInfo)>,
+}
+
+impl Dispatch<WlSeat, ()> for SeatListState {
+    fn event(
+        state: &mut Self,
+        proxy: &WlSeat,
+        event: wl_seat::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        let Some((_, seat)) = state.seats.iter_mut().find(|(wl_seat, _)| wl_seat == proxy) else {
+            return;
+        };
+        match event {
+            wl_seat::Event::Name { name } => seat.name = name,
+            wl_seat::Event::Capabilities {
+                capabilities: Value(capabilities),
+            } => {
+                seat.pointer = capabilities.contains(wl_seat::Capability::Pointer);
+                seat.keyboard = capabilities.contains(wl_seat::Capability::Keyboard);
+            }
+            _ => {}
+        }
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct LayerShellState {
+    pub(crate) configured_outputs: HashSet<WlOutput>,
+    /// Number of `wl_surface.frame` callbacks that are done.
+    pub(crate) frames_done: usize,
 }
 
 delegate_noop!(LayerShellState: ignore WlCompositor);
@@ -501,9 +610,25 @@ delegate_noop!(LayerShellState: ignore WlShmPool);
 delegate_noop!(LayerShellState: ignore WlBuffer);
 delegate_noop!(LayerShellState: ignore ZwlrLayerShellV1);
 delegate_noop!(LayerShellState: ignore WlSurface);
+delegate_noop!(LayerShellState: ignore WlRegion);
 delegate_noop!(LayerShellState: ignore WpViewport);
 delegate_noop!(LayerShellState: ignore WpViewporter);
 
+impl wayland_client::Dispatch<WlCallback, ()> for LayerShellState {
+    fn event(
+        state: &mut Self,
+        _proxy: &WlCallback,
+        event: wl_callback::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qhandle: &QueueHandle<Self>,
+    ) {
+        if let wl_callback::Event::Done { .. } = event {
+            state.frames_done += 1;
+        }
+    }
+}
+
 impl wayland_client::Dispatch<ZwlrLayerSurfaceV1, WlOutput> for LayerShellState {
     // No need to instrument here, span from lib.rs is automatically used.
     fn event(
@@ -545,15 +670,99 @@ impl AsFd for Card {
 impl drm::Device for Card {}
 /// Simple helper methods for opening a `Card`.
 impl Card {
-    pub fn open<P: AsRef<Path>>(path: P) -> Self {
+    pub(crate) fn open<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
         let mut options = std::fs::OpenOptions::new();
         options.read(true);
         options.write(true);
-        Card(options.open(path).unwrap())
+        options.open(path).map(Card)
     }
 }
 #[derive(Debug)]
 pub(crate) struct DMABUFState {
-    pub linux_dmabuf: ZwpLinuxDmabufV1,
-    pub gbmdev: gbm::Device<Card>,
+    pub(crate) linux_dmabuf: ZwpLinuxDmabufV1,
+    pub(crate) gbmdev: gbm::Device<Card>,
+    /// Render node the buffers are allocated on.
+    pub(crate) device: PathBuf,
+    /// Render node of the compositor's main device, if it advertised one.
+    pub(crate) compositor_device: Option<PathBuf>,
+}
+
+impl DMABUFState {
+    /// Explain a failed DMA-BUF capture by the device mismatch, if there is one.
+    pub(crate) fn import_error(&self, error: Error) -> Error {
+        match &self.compositor_device {
+            Some(compositor_device) if *compositor_device != self.device => {
+                Error::DmabufDeviceMismatch {
+                    device: self.device.clone(),
+                    compositor_device: compositor_device.clone(),
+                    source: Box::new(error),
+                }
+            }
+            _ => error,
+        }
+    }
+}
+
+/// Render node of the DRM device `node`, or `node` itself if it has none.
+pub(crate) fn render_node_path(node: &DrmNode) -> Option<PathBuf> {
+    node.dev_path_with_type(NodeType::Render)
+        .or_else(|| node.dev_path())
+}
+
+/// Collects the main device of the compositor from the default dmabuf feedback.
+#[derive(Debug, Default)]
+pub(crate) struct DmabufFeedbackState {
+    pub(crate) main_device: Option<DrmNode>,
+    pub(crate) done: bool,
+}
+
+impl Dispatch<ZwpLinuxDmabufFeedbackV1, ()> for DmabufFeedbackState {
+    fn event(
+        state: &mut Self,
+        _proxy: &ZwpLinuxDmabufFeedbackV1,
+        event: zwp_linux_dmabuf_feedback_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        match event {
+            zwp_linux_dmabuf_feedback_v1::Event::MainDevice { device } => {
+                let Ok(device) = device.try_into().map(u64::from_ne_bytes) else {
+                    tracing::warn!("Compositor sent a malformed dmabuf main device");
+                    return;
+                };
+                state.main_device = DrmNode::from_dev_id(device).ok();
+            }
+            zwp_linux_dmabuf_feedback_v1::Event::Done => state.done = true,
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use wayland_client::protocol::wl_shm;
+
+    use super::*;
+
+    #[test]
+    fn dmabuf_capture_without_dmabuf_formats_names_the_shm_formats() {
+        let mut state = CaptureFrameState::new(false);
+        assert!(matches!(
+            state.first_dmabuf_format(),
+            Err(Error::NoFormatsAdvertised)
+        ));
+
+        state.formats.push(FrameFormat {
+            format: wl_shm::Format::Xrgb8888,
+            size: Size::default(),
+            stride: 0,
+        });
+        match state.first_dmabuf_format() {
+            Err(Error::NoSupportedBufferFormat { advertised }) => {
+                assert_eq!(advertised, "Xrgb8888")
+            }
+            result => panic!("expected NoSupportedBufferFormat, got {result:?}"),
+        }
+    }
 }
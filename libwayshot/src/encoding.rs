@@ -0,0 +1,1253 @@
+use std::{
+    borrow::Cow,
+    fmt::{self, Display},
+    hash::{DefaultHasher, Hash, Hasher},
+    io::{self, Seek, SeekFrom, Write},
+    str::FromStr,
+};
+
+use image::{DynamicImage, ImageFormat, RgbImage, RgbaImage};
+use sha2::Digest;
+
+use crate::{CaptureId, Error, FrameImage, Result, region::Size};
+
+/// zlib level used for deterministic PNG output.
+#[cfg(feature = "png")]
+const DETERMINISTIC_PNG_COMPRESSION: u8 = 6;
+/// Quality used for deterministic JPEG output.
+#[cfg(feature = "jpeg")]
+const DETERMINISTIC_JPEG_QUALITY: u8 = 90;
+/// Quality used for JPEG output otherwise, the default of `image`'s encoder.
+#[cfg(feature = "jpeg")]
+const DEFAULT_JPEG_QUALITY: u8 = 75;
+/// Keyword of the PNG `tEXt` chunk holding [`EncodingOptions::capture_id`].
+pub const PNG_CAPTURE_ID_KEYWORD: &str = "wayshot:capture-id";
+/// Rows compared by [`VerifyMode::Sampled`], spread evenly over the image.
+const VERIFY_SAMPLED_ROWS: u32 = 16;
+
+/// Options for encoding a captured image.
+///
+/// # Example usage
+///
+/// ```ignore
+/// use libwayshot::EncodingOptions;
+/// let mut png = Vec::new();
+/// EncodingOptions::new()
+///     .deterministic(true)
+///     .encode(&image, image::ImageFormat::Png, &mut std::io::Cursor::new(&mut png))?;
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EncodingOptions {
+    deterministic: bool,
+    pixel_format: Option<PixelFormat>,
+    jpeg_subsampling: JpegSubsampling,
+    capture_id: Option<CaptureId>,
+}
+
+/// Channel layout the encoded image is converted to, for tools that only accept one.
+///
+/// This is independent of the shm format negotiated with the compositor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PixelFormat {
+    Rgba8,
+    /// RGB without alpha, the alpha channel is dropped.
+    Rgb8,
+    Bgra8,
+    /// BGR without alpha, the alpha channel is dropped.
+    Bgr8,
+}
+
+impl PixelFormat {
+    pub fn has_alpha(self) -> bool {
+        matches!(self, Self::Rgba8 | Self::Bgra8)
+    }
+
+    fn is_bgr(self) -> bool {
+        matches!(self, Self::Bgra8 | Self::Bgr8)
+    }
+
+    /// Whether `format` can be encoded with these pixels.
+    ///
+    /// Alpha needs a format that stores it, and the swapped BGR channels only
+    /// survive lossless formats that write samples as they are.
+    pub fn is_supported_by(self, format: ImageFormat) -> bool {
+        let alpha = matches!(
+            format,
+            ImageFormat::Png
+                | ImageFormat::Qoi
+                | ImageFormat::WebP
+                | ImageFormat::Avif
+                | ImageFormat::Tiff
+                | ImageFormat::Tga
+        );
+        let verbatim = matches!(
+            format,
+            ImageFormat::Png
+                | ImageFormat::Pnm
+                | ImageFormat::Qoi
+                | ImageFormat::WebP
+                | ImageFormat::Tiff
+        );
+        (alpha || !self.has_alpha()) && (verbatim || !self.is_bgr())
+    }
+
+    /// Convert `image` to these pixels.
+    pub fn convert(self, image: &DynamicImage) -> DynamicImage {
+        match self {
+            Self::Rgba8 => image.to_rgba8().into(),
+            Self::Rgb8 => image.to_rgb8().into(),
+            Self::Bgra8 => {
+                let mut image = image.to_rgba8();
+                image.pixels_mut().for_each(|pixel| pixel.0.swap(0, 2));
+                image.into()
+            }
+            Self::Bgr8 => {
+                let mut image = image.to_rgb8();
+                image.pixels_mut().for_each(|pixel| pixel.0.swap(0, 2));
+                image.into()
+            }
+        }
+    }
+
+    /// Build an image from tightly packed `data` in these pixels, e.g. raw
+    /// frames piped in from another tool.
+    ///
+    /// Fails with [`Error::RawImageSize`] if `data` doesn't hold exactly
+    /// `width` x `height` pixels.
+    pub fn image_from_raw(
+        self,
+        width: u32,
+        height: u32,
+        mut data: Vec<u8>,
+    ) -> Result<DynamicImage> {
+        let channels = if self.has_alpha() { 4 } else { 3 };
+        let expected = width as u64 * height as u64 * channels;
+        if data.len() as u64 != expected {
+            return Err(Error::RawImageSize {
+                width,
+                height,
+                expected,
+                got: data.len() as u64,
+            });
+        }
+        if self.is_bgr() {
+            data.chunks_exact_mut(channels as usize)
+                .for_each(|pixel| pixel.swap(0, 2));
+        }
+        let image = if self.has_alpha() {
+            RgbaImage::from_raw(width, height, data).map(DynamicImage::from)
+        } else {
+            RgbImage::from_raw(width, height, data).map(DynamicImage::from)
+        };
+        // The length was checked above.
+        Ok(image.expect("raw image length matches its size"))
+    }
+}
+
+impl Display for PixelFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Rgba8 => "rgba8",
+            Self::Rgb8 => "rgb8",
+            Self::Bgra8 => "bgra8",
+            Self::Bgr8 => "bgr8",
+        })
+    }
+}
+
+impl FromStr for PixelFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        Ok(match s {
+            "rgba8" | "rgba" => Self::Rgba8,
+            "rgb8" | "rgb" => Self::Rgb8,
+            "bgra8" | "bgra" => Self::Bgra8,
+            "bgr8" | "bgr" => Self::Bgr8,
+            _ => {
+                return Err(format!(
+                    "unknown pixel format '{s}', expected rgba8, rgb8, bgra8 or bgr8"
+                ));
+            }
+        })
+    }
+}
+
+/// How much of a decoded file [`EncodingOptions::verify`] compares against the
+/// image it was encoded from.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum VerifyMode {
+    /// Compare the dimensions and a hash of a few evenly spaced rows.
+    #[default]
+    Sampled,
+    /// Compare the dimensions and every row.
+    Full,
+}
+
+impl Display for VerifyMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Sampled => "sampled",
+            Self::Full => "full",
+        })
+    }
+}
+
+impl FromStr for VerifyMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        Ok(match s {
+            "sampled" => Self::Sampled,
+            "full" => Self::Full,
+            _ => {
+                return Err(format!(
+                    "unknown verify mode '{s}', expected sampled or full"
+                ));
+            }
+        })
+    }
+}
+
+impl VerifyMode {
+    fn rows(self, height: u32) -> impl Iterator<Item = u32> {
+        let step = match self {
+            Self::Sampled => height.div_ceil(VERIFY_SAMPLED_ROWS).max(1),
+            Self::Full => 1,
+        } as usize;
+        (0..height).step_by(step)
+    }
+}
+
+/// How much of the colour a JPEG keeps, see [`EncodingOptions::jpeg_subsampling`].
+///
+/// JPEG stores brightness at full resolution and can store colour at a lower
+/// one. That saves a third to half of the size of photos, but smears the
+/// edges of coloured text and thin lines, which screenshots are full of.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum JpegSubsampling {
+    /// Colour at full resolution, the sharpest and largest.
+    #[default]
+    Yuv444,
+    /// Colour at half the horizontal resolution.
+    Yuv422,
+    /// Colour at half the horizontal and vertical resolution, the default of
+    /// most photo encoders.
+    Yuv420,
+}
+
+impl Display for JpegSubsampling {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Yuv444 => "444",
+            Self::Yuv422 => "422",
+            Self::Yuv420 => "420",
+        })
+    }
+}
+
+impl FromStr for JpegSubsampling {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        Ok(match s {
+            "444" | "4:4:4" => Self::Yuv444,
+            "422" | "4:2:2" => Self::Yuv422,
+            "420" | "4:2:0" => Self::Yuv420,
+            _ => {
+                return Err(format!(
+                    "unknown chroma subsampling '{s}', expected 444, 422 or 420"
+                ));
+            }
+        })
+    }
+}
+
+#[cfg(feature = "jpeg")]
+impl From<JpegSubsampling> for jpeg_encoder::SamplingFactor {
+    fn from(subsampling: JpegSubsampling) -> Self {
+        match subsampling {
+            JpegSubsampling::Yuv444 => Self::R_4_4_4,
+            JpegSubsampling::Yuv422 => Self::R_4_2_2,
+            JpegSubsampling::Yuv420 => Self::R_4_2_0,
+        }
+    }
+}
+
+/// Algorithm of the digest a [`HashingWriter`] computes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChecksumAlgorithm {
+    Sha256,
+    Blake3,
+}
+
+impl Display for ChecksumAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Sha256 => "sha256",
+            Self::Blake3 => "blake3",
+        })
+    }
+}
+
+impl FromStr for ChecksumAlgorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        Ok(match s {
+            "sha256" => Self::Sha256,
+            "blake3" => Self::Blake3,
+            _ => {
+                return Err(format!(
+                    "unknown checksum algorithm '{s}', expected sha256 or blake3"
+                ));
+            }
+        })
+    }
+}
+
+impl ChecksumAlgorithm {
+    /// Hex digest of `bytes`, as printed by `sha256sum` and `b3sum`.
+    pub fn digest(self, bytes: &[u8]) -> String {
+        let mut writer = HashingWriter::new(io::sink(), self);
+        writer.update(bytes);
+        writer.finish().1
+    }
+}
+
+enum DigestState {
+    Sha256(Box<sha2::Sha256>),
+    Blake3(Box<blake3::Hasher>),
+}
+
+/// Passes writes on to a writer while hashing them, so the digest of a file
+/// is known once it is written without reading it back.
+///
+/// Seeking is limited to asking for the position, which is all the encoders
+/// writing the formats wayshot saves need. Other seeks fail with
+/// [`io::ErrorKind::Unsupported`] since they would rewrite hashed bytes.
+pub struct HashingWriter<W> {
+    inner: W,
+    state: DigestState,
+    position: u64,
+}
+
+impl<W> HashingWriter<W> {
+    pub fn new(inner: W, algorithm: ChecksumAlgorithm) -> Self {
+        let state = match algorithm {
+            ChecksumAlgorithm::Sha256 => DigestState::Sha256(Box::default()),
+            ChecksumAlgorithm::Blake3 => DigestState::Blake3(Box::default()),
+        };
+        Self {
+            inner,
+            state,
+            position: 0,
+        }
+    }
+
+    fn update(&mut self, bytes: &[u8]) {
+        match &mut self.state {
+            DigestState::Sha256(hasher) => hasher.update(bytes),
+            DigestState::Blake3(hasher) => {
+                hasher.update(bytes);
+            }
+        }
+        self.position += bytes.len() as u64;
+    }
+
+    /// The inner writer and the hex digest of everything written to it.
+    pub fn finish(self) -> (W, String) {
+        let digest = match self.state {
+            DigestState::Sha256(hasher) => hasher
+                .finalize()
+                .iter()
+                .map(|byte| format!("{byte:02x}"))
+                .collect(),
+            DigestState::Blake3(hasher) => hasher.finalize().to_hex().to_string(),
+        };
+        (self.inner, digest)
+    }
+}
+
+impl<W> fmt::Debug for HashingWriter<W> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HashingWriter")
+            .field("position", &self.position)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<W: Write> Seek for HashingWriter<W> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        // Writes only append, so the end is the current position.
+        let target = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(offset) | SeekFrom::End(offset) => {
+                self.position.checked_add_signed(offset)
+            }
+        };
+        if target != Some(self.position) {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "seeking while hashing the written bytes",
+            ));
+        }
+        Ok(self.position)
+    }
+}
+
+/// Encode `image` as a JPEG with `subsampling`, which `image`'s own encoder
+/// can't do. Alpha is dropped and grey images stay grey, like `image` does.
+#[cfg(feature = "jpeg")]
+fn encode_jpeg<W: Write>(
+    image: &DynamicImage,
+    quality: u8,
+    subsampling: JpegSubsampling,
+    writer: &mut W,
+) -> Result<()> {
+    use jpeg_encoder::ColorType;
+
+    let (data, color_type) = match image {
+        DynamicImage::ImageLuma8(image) => (Cow::Borrowed(image.as_raw()), ColorType::Luma),
+        DynamicImage::ImageLumaA8(_) | DynamicImage::ImageLuma16(_) => {
+            (Cow::Owned(image.to_luma8().into_raw()), ColorType::Luma)
+        }
+        DynamicImage::ImageRgb8(image) => (Cow::Borrowed(image.as_raw()), ColorType::Rgb),
+        DynamicImage::ImageRgba8(image) => (Cow::Borrowed(image.as_raw()), ColorType::Rgba),
+        _ => (Cow::Owned(image.to_rgb8().into_raw()), ColorType::Rgb),
+    };
+    encode_jpeg_raw(
+        &data,
+        (image.width(), image.height()),
+        color_type,
+        quality,
+        subsampling,
+        writer,
+    )
+}
+
+/// Encode tightly packed `data` of `dimensions` as a JPEG, see [`encode_jpeg`].
+#[cfg(feature = "jpeg")]
+fn encode_jpeg_raw<W: Write>(
+    data: &[u8],
+    (width, height): (u32, u32),
+    color_type: jpeg_encoder::ColorType,
+    quality: u8,
+    subsampling: JpegSubsampling,
+    writer: &mut W,
+) -> Result<()> {
+    use image::error::{EncodingError, ImageError, ImageFormatHint};
+    use jpeg_encoder::Encoder;
+
+    let encoding_error = |error: Box<dyn std::error::Error + Send + Sync>| {
+        ImageError::Encoding(EncodingError::new(
+            ImageFormatHint::Exact(ImageFormat::Jpeg),
+            error,
+        ))
+    };
+    let (Ok(width), Ok(height)) = (u16::try_from(width), u16::try_from(height)) else {
+        return Err(encoding_error(
+            format!("{width}x{height} is larger than a JPEG can be").into(),
+        )
+        .into());
+    };
+    let mut encoder = Encoder::new(writer, quality);
+    encoder.set_sampling_factor(subsampling.into());
+    encoder
+        .encode(data, width, height, color_type)
+        .map_err(|error| encoding_error(error.into()))?;
+    Ok(())
+}
+
+/// Length of the PNG signature and the IHDR chunk that follows it.
+const PNG_HEADER_LEN: usize = 8 + 12 + 13;
+
+/// Write a PNG `tEXt` chunk of `keyword` and `text`, both Latin-1.
+fn write_png_text<W: Write>(writer: &mut W, keyword: &str, text: &str) -> io::Result<()> {
+    let mut chunk = b"tEXt".to_vec();
+    chunk.extend_from_slice(keyword.as_bytes());
+    chunk.push(0);
+    chunk.extend_from_slice(text.as_bytes());
+    writer.write_all(&(chunk.len() as u32 - 4).to_be_bytes())?;
+    writer.write_all(&chunk)?;
+    writer.write_all(&crc32(&chunk).to_be_bytes())
+}
+
+/// The CRC-32 PNG chunks end with, over their type and data.
+fn crc32(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!0, |crc, &byte| {
+        (0..8).fold(crc ^ byte as u32, |crc, _| {
+            (crc >> 1) ^ (0xedb8_8320 & (crc & 1).wrapping_neg())
+        })
+    })
+}
+
+/// Hash row `y` of `image`, leaving out alpha unless `alpha` is set.
+fn hash_row(image: &DynamicImage, y: u32, alpha: bool) -> u64 {
+    use image::GenericImageView;
+
+    let mut hasher = DefaultHasher::new();
+    for x in 0..image.width() {
+        let pixel = image.get_pixel(x, y).0;
+        let channels = if alpha { &pixel[..] } else { &pixel[..3] };
+        channels.hash(&mut hasher);
+    }
+    hasher.finish()
+}
+
+impl EncodingOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Make identical pixels always produce identical bytes.
+    ///
+    /// The PNG filter strategy, compression level and JPEG quality are pinned
+    /// instead of following the encoder defaults, and no timestamps or other
+    /// metadata chunks are written.
+    pub fn deterministic(mut self, deterministic: bool) -> Self {
+        self.deterministic = deterministic;
+        self
+    }
+
+    pub fn is_deterministic(&self) -> bool {
+        self.deterministic
+    }
+
+    /// Convert the image to `pixel_format` before encoding it, instead of
+    /// keeping the captured layout.
+    pub fn pixel_format(mut self, pixel_format: Option<PixelFormat>) -> Self {
+        self.pixel_format = pixel_format;
+        self
+    }
+
+    pub fn get_pixel_format(&self) -> Option<PixelFormat> {
+        self.pixel_format
+    }
+
+    /// Chroma subsampling of JPEG output, full resolution colour (4:4:4) by
+    /// default as that keeps coloured text sharp.
+    pub fn jpeg_subsampling(mut self, jpeg_subsampling: JpegSubsampling) -> Self {
+        self.jpeg_subsampling = jpeg_subsampling;
+        self
+    }
+
+    pub fn get_jpeg_subsampling(&self) -> JpegSubsampling {
+        self.jpeg_subsampling
+    }
+
+    /// Write `capture_id` into PNG images, as a `tEXt` chunk with the keyword
+    /// [`PNG_CAPTURE_ID_KEYWORD`], so a file can be traced back to the log
+    /// lines of its capture. Other formats are written without it.
+    pub fn capture_id(mut self, capture_id: Option<CaptureId>) -> Self {
+        self.capture_id = capture_id;
+        self
+    }
+
+    pub fn get_capture_id(&self) -> Option<CaptureId> {
+        self.capture_id
+    }
+
+    /// Encode as PNG with `encode`, adding the capture id chunk after the header.
+    fn with_capture_id<W: Write>(
+        &self,
+        writer: &mut W,
+        encode: impl FnOnce(&Self, &mut io::Cursor<Vec<u8>>) -> Result<()>,
+    ) -> Result<()> {
+        let mut png = io::Cursor::new(Vec::new());
+        encode(&self.capture_id(None), &mut png)?;
+        let png = png.into_inner();
+        let Some(capture_id) = self.capture_id else {
+            return Ok(writer.write_all(&png)?);
+        };
+        // The signature and IHDR, which has to be the first chunk.
+        let (header, chunks) = png.split_at(PNG_HEADER_LEN.min(png.len()));
+        writer.write_all(header)?;
+        write_png_text(writer, PNG_CAPTURE_ID_KEYWORD, &capture_id.to_string())?;
+        writer.write_all(chunks)?;
+        Ok(())
+    }
+
+    /// Encode `image` as `format` into `writer`.
+    ///
+    /// Fails with [`Error::UnsupportedPixelFormat`] if `format` can't hold the
+    /// requested pixel format.
+    pub fn encode<W: Write + Seek>(
+        &self,
+        image: &DynamicImage,
+        format: ImageFormat,
+        writer: &mut W,
+    ) -> Result<()> {
+        if format == ImageFormat::Png && self.capture_id.is_some() {
+            return self.with_capture_id(writer, |options, png| options.encode(image, format, png));
+        }
+        let image = self.convert(image, format)?;
+        let image = image.as_ref();
+        #[cfg(feature = "jpeg")]
+        if format == ImageFormat::Jpeg {
+            let quality = match self.deterministic {
+                true => DETERMINISTIC_JPEG_QUALITY,
+                false => DEFAULT_JPEG_QUALITY,
+            };
+            return encode_jpeg(image, quality, self.jpeg_subsampling, writer);
+        }
+        if !self.deterministic {
+            image.write_to(writer, format)?;
+            return Ok(());
+        }
+        match format {
+            #[cfg(feature = "png")]
+            ImageFormat::Png => {
+                use image::codecs::png::{CompressionType, FilterType, PngEncoder};
+                image.write_with_encoder(PngEncoder::new_with_quality(
+                    writer,
+                    CompressionType::Level(DETERMINISTIC_PNG_COMPRESSION),
+                    FilterType::Paeth,
+                ))?;
+            }
+            // The remaining encoders have no tunables or timestamps.
+            _ => image.write_to(writer, format)?,
+        }
+        Ok(())
+    }
+
+    /// Like [`Self::encode`] for an image borrowing the pixels of a frame, see
+    /// [`FrameCopy::image`](crate::FrameCopy::image). The pixels are encoded
+    /// where they are, without copying them into an image first, unless they
+    /// have to be converted to a pixel format.
+    pub fn encode_frame_image<W: Write + Seek>(
+        &self,
+        image: &FrameImage,
+        format: ImageFormat,
+        writer: &mut W,
+    ) -> Result<()> {
+        if self.pixel_format.is_some() {
+            return self.encode(&image.to_image(), format, writer);
+        }
+        if format == ImageFormat::Png && self.capture_id.is_some() {
+            return self.with_capture_id(writer, |options, png| {
+                options.encode_frame_image(image, format, png)
+            });
+        }
+        let (width, height) = image.dimensions();
+        let data = image.as_bytes();
+        #[cfg(feature = "jpeg")]
+        if format == ImageFormat::Jpeg {
+            let quality = match self.deterministic {
+                true => DETERMINISTIC_JPEG_QUALITY,
+                false => DEFAULT_JPEG_QUALITY,
+            };
+            let color_type = match image {
+                FrameImage::Rgb8(_) => jpeg_encoder::ColorType::Rgb,
+                FrameImage::Rgba8(_) => jpeg_encoder::ColorType::Rgba,
+            };
+            return encode_jpeg_raw(
+                data,
+                (width, height),
+                color_type,
+                quality,
+                self.jpeg_subsampling,
+                writer,
+            );
+        }
+        let color_type = image.color_type().into();
+        match format {
+            #[cfg(feature = "png")]
+            ImageFormat::Png if self.deterministic => {
+                use image::{
+                    ImageEncoder,
+                    codecs::png::{CompressionType, FilterType, PngEncoder},
+                };
+                PngEncoder::new_with_quality(
+                    writer,
+                    CompressionType::Level(DETERMINISTIC_PNG_COMPRESSION),
+                    FilterType::Paeth,
+                )
+                .write_image(data, width, height, color_type)?;
+            }
+            _ => image::write_buffer_with_format(writer, data, width, height, color_type, format)?,
+        }
+        Ok(())
+    }
+
+    /// Decode `encoded` and check that it holds the pixels [`Self::encode`]
+    /// wrote for `image` as `format`, e.g. after reading a saved file back.
+    ///
+    /// Lossy formats (JPEG, AVIF) only have their dimensions compared. Fails
+    /// with [`Error::VerificationFailed`] on a mismatch or undecodable data.
+    pub fn verify(
+        &self,
+        image: &DynamicImage,
+        format: ImageFormat,
+        encoded: &[u8],
+        mode: VerifyMode,
+    ) -> Result<()> {
+        if !format.reading_enabled() {
+            return Err(Error::Unsupported(format!(
+                "decoding {format:?} to verify it"
+            )));
+        }
+        let expected = self.convert(image, format)?;
+        let decoded = image::load_from_memory_with_format(encoded, format).map_err(|e| {
+            Error::VerificationFailed(format!("can't decode the {format:?} image: {e}"))
+        })?;
+        let (expected_size, decoded_size) = (
+            (expected.width(), expected.height()),
+            (decoded.width(), decoded.height()),
+        );
+        if expected_size != decoded_size {
+            return Err(Error::VerificationFailed(format!(
+                "decoded {}x{} image, expected {}x{}",
+                decoded_size.0, decoded_size.1, expected_size.0, expected_size.1
+            )));
+        }
+        if matches!(format, ImageFormat::Jpeg | ImageFormat::Avif) {
+            return Ok(());
+        }
+
+        let alpha = decoded.color().has_alpha();
+        match mode
+            .rows(decoded.height())
+            .find(|&y| hash_row(&expected, y, alpha) != hash_row(&decoded, y, alpha))
+        {
+            Some(y) => Err(Error::VerificationFailed(format!(
+                "row {y} of the decoded image differs"
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    /// `image` converted to the pixel format, if one is set and `format` supports it.
+    fn convert<'a>(
+        &self,
+        image: &'a DynamicImage,
+        format: ImageFormat,
+    ) -> Result<Cow<'a, DynamicImage>> {
+        Ok(match self.pixel_format {
+            Some(pixel_format) if !pixel_format.is_supported_by(format) => {
+                return Err(Error::UnsupportedPixelFormat {
+                    pixel_format,
+                    format,
+                });
+            }
+            Some(pixel_format) => Cow::Owned(pixel_format.convert(image)),
+            None => Cow::Borrowed(image),
+        })
+    }
+}
+
+/// QOI opcodes, see <https://qoiformat.org/qoi-specification.pdf>.
+const QOI_OP_INDEX: u8 = 0x00;
+const QOI_OP_DIFF: u8 = 0x40;
+const QOI_OP_LUMA: u8 = 0x80;
+const QOI_OP_RUN: u8 = 0xc0;
+const QOI_OP_RGB: u8 = 0xfe;
+const QOI_OP_RGBA: u8 = 0xff;
+/// Longest run a single `QOI_OP_RUN` can encode.
+const QOI_MAX_RUN: u8 = 62;
+const QOI_END: [u8; 8] = [0, 0, 0, 0, 0, 0, 0, 1];
+
+/// Streaming QOI encoder fed one RGBA pixel at a time, so frames can be
+/// encoded straight from their raw buffer without an image in between.
+pub(crate) struct QoiEncoder<'a, W: Write> {
+    writer: &'a mut W,
+    index: [u32; 64],
+    previous: u32,
+    /// Index position of `previous` once it has been stored there.
+    previous_position: Option<u8>,
+    run: u8,
+    pending: Vec<u8>,
+}
+
+impl<'a, W: Write> QoiEncoder<'a, W> {
+    /// Write the header of a `size` image with 3 (RGB) or 4 (RGBA) `channels`.
+    pub fn new(writer: &'a mut W, size: Size, channels: u8) -> Result<Self> {
+        writer.write_all(b"qoif")?;
+        writer.write_all(&size.width.to_be_bytes())?;
+        writer.write_all(&size.height.to_be_bytes())?;
+        // sRGB with linear alpha.
+        writer.write_all(&[channels, 0])?;
+        Ok(Self {
+            writer,
+            index: [0; 64],
+            previous: u32::from_ne_bytes([0, 0, 0, 255]),
+            previous_position: None,
+            run: 0,
+            pending: Vec::with_capacity(size.width as usize * 5 + QOI_END.len()),
+        })
+    }
+
+    #[inline(always)]
+    pub fn push(&mut self, pixel: [u8; 4]) {
+        let packed = u32::from_ne_bytes(pixel);
+        if packed == self.previous {
+            self.run += 1;
+            if self.run == QOI_MAX_RUN {
+                self.pending.push(QOI_OP_RUN | (QOI_MAX_RUN - 1));
+                self.run = 0;
+            }
+            return;
+        }
+        if self.run > 0 {
+            // A single repeat is as short as an index hit, which is what the
+            // reference encoder in the `qoi` crate emits.
+            let op = match self.previous_position {
+                Some(position) if self.run == 1 => QOI_OP_INDEX | position,
+                _ => QOI_OP_RUN | (self.run - 1),
+            };
+            self.pending.push(op);
+            self.run = 0;
+        }
+
+        let [r, g, b, a] = pixel;
+        // Wrapping at 256 keeps the result modulo 64 the same.
+        let position = (r.wrapping_mul(3))
+            .wrapping_add(g.wrapping_mul(5))
+            .wrapping_add(b.wrapping_mul(7))
+            .wrapping_add(a.wrapping_mul(11))
+            % 64;
+        let slot = &mut self.index[position as usize];
+        if *slot == packed {
+            self.pending.push(QOI_OP_INDEX | position);
+        } else {
+            *slot = packed;
+            let [pr, pg, pb, pa] = self.previous.to_ne_bytes();
+            if a != pa {
+                self.pending.extend_from_slice(&[QOI_OP_RGBA, r, g, b, a]);
+            } else {
+                let (dr, dg, db) = (
+                    r.wrapping_sub(pr) as i8,
+                    g.wrapping_sub(pg) as i8,
+                    b.wrapping_sub(pb) as i8,
+                );
+                let (dr_dg, db_dg) = (dr.wrapping_sub(dg), db.wrapping_sub(dg));
+                if (-2..2).contains(&dr) && (-2..2).contains(&dg) && (-2..2).contains(&db) {
+                    self.pending.push(
+                        QOI_OP_DIFF
+                            | ((dr + 2) as u8) << 4
+                            | ((dg + 2) as u8) << 2
+                            | (db + 2) as u8,
+                    );
+                } else if (-32..32).contains(&dg)
+                    && (-8..8).contains(&dr_dg)
+                    && (-8..8).contains(&db_dg)
+                {
+                    self.pending.extend_from_slice(&[
+                        QOI_OP_LUMA | (dg + 32) as u8,
+                        ((dr_dg + 8) as u8) << 4 | (db_dg + 8) as u8,
+                    ]);
+                } else {
+                    self.pending.extend_from_slice(&[QOI_OP_RGB, r, g, b]);
+                }
+            }
+        }
+        self.previous = packed;
+        self.previous_position = Some(position);
+    }
+
+    /// Write out what has been encoded so far, e.g. after every row.
+    pub fn flush(&mut self) -> Result<()> {
+        self.writer.write_all(&self.pending)?;
+        self.pending.clear();
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<()> {
+        if self.run > 0 {
+            self.pending.push(QOI_OP_RUN | (self.run - 1));
+        }
+        self.pending.extend_from_slice(&QOI_END);
+        self.flush()
+    }
+}
+
+#[cfg(all(test, feature = "png"))]
+mod tests {
+    use super::*;
+    use image::{Rgba, RgbaImage};
+    use std::{
+        env, fs,
+        io::Cursor,
+        process::{Command, Stdio},
+    };
+
+    const CHILD_OUTPUT_ENV: &str = "LIBWAYSHOT_ENCODING_TEST_OUTPUT";
+
+    fn gradient() -> DynamicImage {
+        RgbaImage::from_fn(64, 48, |x, y| {
+            Rgba([(x * 4) as u8, (y * 5) as u8, ((x + y) * 2) as u8, 255])
+        })
+        .into()
+    }
+
+    fn encode_png(options: EncodingOptions) -> Vec<u8> {
+        let mut cursor = Cursor::new(Vec::new());
+        options
+            .encode(&gradient(), ImageFormat::Png, &mut cursor)
+            .expect("encode png");
+        cursor.into_inner()
+    }
+
+    fn hash(bytes: &[u8]) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        bytes.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// Encodes the test image when run as a child of
+    /// `deterministic_png_is_identical_across_processes`, no-op otherwise.
+    #[test]
+    fn encode_deterministic_png_child() {
+        if let Ok(path) = env::var(CHILD_OUTPUT_ENV) {
+            fs::write(path, encode_png(EncodingOptions::new().deterministic(true)))
+                .expect("write encoded png");
+        }
+    }
+
+    #[test]
+    fn deterministic_png_is_identical_across_processes() {
+        let dir = env::temp_dir();
+        let hashes: Vec<u64> = (0..2)
+            .map(|run| {
+                let path = dir.join(format!(
+                    "libwayshot-encoding-{}-{run}.png",
+                    std::process::id()
+                ));
+                let status = Command::new(env::current_exe().expect("test binary"))
+                    .args([
+                        "encoding::tests::encode_deterministic_png_child",
+                        "--exact",
+                        "--test-threads=1",
+                    ])
+                    .env(CHILD_OUTPUT_ENV, &path)
+                    .stdout(Stdio::null())
+                    .status()
+                    .expect("spawn child test");
+                assert!(status.success());
+                let bytes = fs::read(&path).expect("read encoded png");
+                fs::remove_file(&path).ok();
+                hash(&bytes)
+            })
+            .collect();
+
+        assert_eq!(hashes[0], hashes[1]);
+        assert_eq!(
+            hashes[0],
+            hash(&encode_png(EncodingOptions::new().deterministic(true)))
+        );
+    }
+
+    #[test]
+    fn deterministic_png_round_trips() {
+        let bytes = encode_png(EncodingOptions::new().deterministic(true));
+        let decoded =
+            image::load_from_memory_with_format(&bytes, ImageFormat::Png).expect("decode png");
+
+        assert_eq!(decoded.to_rgba8(), gradient().to_rgba8());
+    }
+
+    #[test]
+    fn pixel_format_converts_before_encoding() {
+        let image: DynamicImage = RgbaImage::from_pixel(2, 2, Rgba([10, 20, 30, 40])).into();
+        let mut cursor = Cursor::new(Vec::new());
+        EncodingOptions::new()
+            .pixel_format(Some(PixelFormat::Bgr8))
+            .encode(&image, ImageFormat::Png, &mut cursor)
+            .expect("encode png");
+        let decoded = image::load_from_memory_with_format(cursor.get_ref(), ImageFormat::Png)
+            .expect("decode png");
+
+        assert_eq!(decoded.color(), image::ColorType::Rgb8);
+        assert_eq!(decoded.to_rgb8().get_pixel(1, 1).0, [30, 20, 10]);
+        assert_eq!(
+            PixelFormat::Bgra8
+                .convert(&image)
+                .to_rgba8()
+                .get_pixel(0, 0)
+                .0,
+            [30, 20, 10, 40]
+        );
+    }
+
+    #[test]
+    fn pixel_format_is_validated_against_the_encoder() {
+        assert!(PixelFormat::Rgb8.is_supported_by(ImageFormat::Jpeg));
+        assert!(!PixelFormat::Rgba8.is_supported_by(ImageFormat::Jpeg));
+        assert!(!PixelFormat::Bgr8.is_supported_by(ImageFormat::Jpeg));
+        assert!(PixelFormat::Bgr8.is_supported_by(ImageFormat::Pnm));
+        assert!(!PixelFormat::Bgra8.is_supported_by(ImageFormat::Pnm));
+        assert!(PixelFormat::Bgra8.is_supported_by(ImageFormat::Qoi));
+
+        let result = EncodingOptions::new()
+            .pixel_format(Some(PixelFormat::Rgba8))
+            .encode(&gradient(), ImageFormat::Jpeg, &mut Cursor::new(Vec::new()));
+        assert!(matches!(
+            result,
+            Err(Error::UnsupportedPixelFormat {
+                pixel_format: PixelFormat::Rgba8,
+                format: ImageFormat::Jpeg,
+            })
+        ));
+    }
+
+    #[cfg(feature = "jpeg")]
+    #[test]
+    fn jpeg_subsampling_trades_colour_edges_for_size() {
+        // One pixel wide red and blue columns, the worst case for subsampling.
+        let stripes: DynamicImage = RgbaImage::from_fn(64, 64, |x, _| match x % 2 {
+            0 => Rgba([255, 0, 0, 255]),
+            _ => Rgba([0, 0, 255, 255]),
+        })
+        .into();
+        let encode = |subsampling| {
+            let mut cursor = Cursor::new(Vec::new());
+            EncodingOptions::new()
+                .jpeg_subsampling(subsampling)
+                .encode(&stripes, ImageFormat::Jpeg, &mut cursor)
+                .expect("encode jpeg");
+            cursor.into_inner()
+        };
+        let error = |encoded: &[u8]| {
+            let decoded = image::load_from_memory(encoded)
+                .expect("decode")
+                .into_rgb8();
+            decoded
+                .pixels()
+                .zip(stripes.to_rgb8().pixels())
+                .map(|(a, b)| {
+                    a.0.iter()
+                        .zip(b.0)
+                        .map(|(a, b)| a.abs_diff(b) as u64)
+                        .sum::<u64>()
+                })
+                .sum::<u64>()
+        };
+
+        let full = encode(JpegSubsampling::Yuv444);
+        let half = encode(JpegSubsampling::Yuv420);
+        assert!(error(&full) * 4 < error(&half));
+        assert!(half.len() < full.len());
+        assert_eq!(
+            EncodingOptions::new().get_jpeg_subsampling(),
+            JpegSubsampling::Yuv444
+        );
+    }
+
+    #[test]
+    fn capture_ids_are_written_into_png_text_chunks() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+
+        let capture_id = CaptureId {
+            process: 4242,
+            generation: 3,
+        };
+        let without = encode_png(EncodingOptions::new().deterministic(true));
+        let with = encode_png(
+            EncodingOptions::new()
+                .deterministic(true)
+                .capture_id(Some(capture_id)),
+        );
+
+        // The chunk follows the header and the rest is the same.
+        let chunk = [
+            &[0, 0, 0, 25][..],
+            b"tEXtwayshot:capture-id\x004242-3",
+            &crc32(b"tEXtwayshot:capture-id\x004242-3").to_be_bytes(),
+        ]
+        .concat();
+        assert_eq!(with[..PNG_HEADER_LEN], without[..PNG_HEADER_LEN]);
+        assert_eq!(with[PNG_HEADER_LEN..][..chunk.len()], chunk);
+        assert_eq!(
+            with[PNG_HEADER_LEN + chunk.len()..],
+            without[PNG_HEADER_LEN..]
+        );
+        let decoded = image::load_from_memory_with_format(&with, ImageFormat::Png).expect("decode");
+        assert_eq!(decoded.to_rgba8(), gradient().to_rgba8());
+    }
+
+    #[test]
+    fn jpeg_subsampling_parses_its_display() {
+        for subsampling in [
+            JpegSubsampling::Yuv444,
+            JpegSubsampling::Yuv422,
+            JpegSubsampling::Yuv420,
+        ] {
+            assert_eq!(subsampling.to_string().parse(), Ok(subsampling));
+        }
+        assert_eq!("4:2:0".parse(), Ok(JpegSubsampling::Yuv420));
+        assert!("411".parse::<JpegSubsampling>().is_err());
+    }
+
+    #[test]
+    fn pixel_format_parses_its_display() {
+        for format in [
+            PixelFormat::Rgba8,
+            PixelFormat::Rgb8,
+            PixelFormat::Bgra8,
+            PixelFormat::Bgr8,
+        ] {
+            assert_eq!(format.to_string().parse(), Ok(format));
+        }
+        assert!("argb".parse::<PixelFormat>().is_err());
+        assert_eq!("bgra".parse(), Ok(PixelFormat::Bgra8));
+    }
+
+    #[test]
+    fn raw_pixels_are_read_in_their_layout() {
+        let image = PixelFormat::Bgr8
+            .image_from_raw(2, 1, vec![3, 2, 1, 6, 5, 4])
+            .expect("bgr image");
+        assert_eq!(image.to_rgb8().into_raw(), [1, 2, 3, 4, 5, 6]);
+
+        let image = PixelFormat::Rgba8
+            .image_from_raw(1, 2, vec![1, 2, 3, 4, 5, 6, 7, 8])
+            .expect("rgba image");
+        assert_eq!(image.to_rgba8().into_raw(), [1, 2, 3, 4, 5, 6, 7, 8]);
+
+        assert!(matches!(
+            PixelFormat::Rgba8.image_from_raw(2, 2, vec![0; 15]),
+            Err(Error::RawImageSize {
+                expected: 16,
+                got: 15,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn verify_reads_back_a_written_file() {
+        let path = env::temp_dir().join(format!(
+            "libwayshot-verify-{}-intact.png",
+            std::process::id()
+        ));
+        fs::write(&path, encode_png(EncodingOptions::new())).expect("write png");
+        let bytes = fs::read(&path).expect("read png");
+        fs::remove_file(&path).ok();
+
+        for mode in [VerifyMode::Sampled, VerifyMode::Full] {
+            EncodingOptions::new()
+                .verify(&gradient(), ImageFormat::Png, &bytes, mode)
+                .expect("verify intact png");
+        }
+    }
+
+    #[test]
+    fn verify_detects_a_corrupted_file() {
+        let path = env::temp_dir().join(format!(
+            "libwayshot-verify-{}-corrupt.png",
+            std::process::id()
+        ));
+        let mut bytes = encode_png(EncodingOptions::new());
+        let middle = bytes.len() / 2;
+        bytes[middle] ^= 0xff;
+        fs::write(&path, &bytes).expect("write png");
+        let corrupted = fs::read(&path).expect("read png");
+        fs::write(&path, &bytes[..middle]).expect("write png");
+        let truncated = fs::read(&path).expect("read png");
+        fs::remove_file(&path).ok();
+
+        for bytes in [corrupted, truncated] {
+            assert!(matches!(
+                EncodingOptions::new().verify(
+                    &gradient(),
+                    ImageFormat::Png,
+                    &bytes,
+                    VerifyMode::Sampled
+                ),
+                Err(Error::VerificationFailed(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn verify_compares_dimensions_and_rows() {
+        let bytes = encode_png(EncodingOptions::new());
+        let smaller = gradient().crop_imm(0, 0, 64, 47);
+        assert!(matches!(
+            EncodingOptions::new().verify(&smaller, ImageFormat::Png, &bytes, VerifyMode::Sampled),
+            Err(Error::VerificationFailed(_))
+        ));
+
+        // Row 1 sits between the sampled rows, only a full compare sees it.
+        let mut changed = gradient().to_rgba8();
+        changed.put_pixel(5, 1, Rgba([1, 2, 3, 255]));
+        let changed = DynamicImage::from(changed);
+        EncodingOptions::new()
+            .verify(&changed, ImageFormat::Png, &bytes, VerifyMode::Sampled)
+            .expect("row 1 isn't sampled");
+        assert!(matches!(
+            EncodingOptions::new().verify(&changed, ImageFormat::Png, &bytes, VerifyMode::Full),
+            Err(Error::VerificationFailed(_))
+        ));
+    }
+
+    #[test]
+    fn verify_mode_parses_its_display() {
+        for mode in [VerifyMode::Sampled, VerifyMode::Full] {
+            assert_eq!(mode.to_string().parse(), Ok(mode));
+        }
+        assert!("none".parse::<VerifyMode>().is_err());
+    }
+
+    #[test]
+    fn hashing_writer_matches_reference_digests() {
+        // Test vectors from FIPS 180-2 and the BLAKE3 reference implementation.
+        assert_eq!(
+            ChecksumAlgorithm::Sha256.digest(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            ChecksumAlgorithm::Blake3.digest(b""),
+            "af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262"
+        );
+
+        // Written in uneven chunks, spanning several blocks and BLAKE3 chunks.
+        let mut writer = HashingWriter::new(Vec::new(), ChecksumAlgorithm::Sha256);
+        for chunk in vec![b'a'; 1_000_000].chunks(999) {
+            writer.write_all(chunk).expect("write");
+        }
+        let (written, digest) = writer.finish();
+        assert_eq!(written.len(), 1_000_000);
+        assert_eq!(
+            digest,
+            "cdc76e5c9914fb9281a1c7e284d73e67f1809a48a497200e046d39ccc7112cd0"
+        );
+
+        // Streaming an encoder through it hashes the bytes it ends up with.
+        let png = encode_png(EncodingOptions::new());
+        for algorithm in [ChecksumAlgorithm::Sha256, ChecksumAlgorithm::Blake3] {
+            let mut writer = HashingWriter::new(Vec::new(), algorithm);
+            EncodingOptions::new()
+                .encode(&gradient(), ImageFormat::Png, &mut writer)
+                .expect("encode");
+            let (written, digest) = writer.finish();
+            assert_eq!(written, png);
+            assert_eq!(digest, algorithm.digest(&png));
+            assert_eq!(algorithm.to_string().parse(), Ok(algorithm));
+        }
+    }
+
+    #[test]
+    fn hashing_writer_refuses_to_seek_back() {
+        let mut writer = HashingWriter::new(Vec::new(), ChecksumAlgorithm::Blake3);
+        writer.write_all(b"abc").expect("write");
+        assert_eq!(writer.stream_position().expect("position"), 3);
+        assert_eq!(writer.seek(SeekFrom::End(0)).expect("end"), 3);
+        assert_eq!(
+            writer.seek(SeekFrom::Start(0)).map_err(|e| e.kind()),
+            Err(io::ErrorKind::Unsupported)
+        );
+    }
+}
@@ -0,0 +1,320 @@
+use std::{env, fmt::Display, time::Duration};
+
+use wayland_client::{Connection, Proxy, globals::GlobalList, protocol::wl_seat::WlSeat};
+
+use crate::dispatch::SeatNameState;
+
+// Protocol versions libwayshot needs for its features. Embedders can compare
+// them with `WayshotConnection::global_version` to detect support up front.
+
+/// `wl_output` version needed to list outputs, the first with the `name` event.
+pub const MIN_WL_OUTPUT_VERSION: u32 = 4;
+/// `zxdg_output_manager_v1` version needed for the logical output layout.
+pub const MIN_XDG_OUTPUT_VERSION: u32 = 3;
+/// `zwlr_screencopy_manager_v1` version needed to capture outputs and regions.
+pub const MIN_SCREENCOPY_VERSION: u32 = 3;
+/// `zwlr_screencopy_manager_v1` version adding `copy_with_damage`, which
+/// damage-tracked recording needs. Implied by [`MIN_SCREENCOPY_VERSION`].
+pub const MIN_SCREENCOPY_FOR_DAMAGE: u32 = 2;
+/// `ext_image_copy_capture_manager_v1` version needed to capture through
+/// ext-image-copy-capture, including toplevels.
+pub const MIN_IMAGE_COPY_CAPTURE_VERSION: u32 = 1;
+/// Version of the `ext_*_image_capture_source_manager_v1` globals needed to
+/// capture outputs or toplevels through ext-image-copy-capture.
+pub const MIN_IMAGE_CAPTURE_SOURCE_VERSION: u32 = 1;
+/// `ext_foreign_toplevel_list_v1` version needed to list toplevels.
+pub const MIN_FOREIGN_TOPLEVEL_LIST_VERSION: u32 = 1;
+/// `wl_compositor` version needed for overlays such as the freeze selection.
+pub const MIN_COMPOSITOR_VERSION: u32 = 3;
+/// `zwlr_layer_shell_v1` version needed for overlays such as the freeze selection.
+pub const MIN_LAYER_SHELL_VERSION: u32 = 1;
+/// `zwlr_output_manager_v1` version needed to enable outputs for a capture.
+pub const MIN_OUTPUT_MANAGER_VERSION: u32 = 1;
+/// `ext_workspace_manager_v1` version needed to read the active workspaces.
+pub const MIN_WORKSPACE_VERSION: u32 = 1;
+/// `ext_session_lock_manager_v1` version that lets refused captures be reported as a locked session.
+pub const MIN_SESSION_LOCK_VERSION: u32 = 1;
+/// `zwp_linux_dmabuf_v1` version needed for dmabuf captures.
+pub const MIN_LINUX_DMABUF_VERSION: u32 = 4;
+
+/// Details about the Wayland session wayshot runs in, meant to be included in bug reports.
+///
+/// Do not instantiate, instead use [`crate::WayshotConnection::environment_info`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EnvironmentInfo {
+    /// Version of the advertised `wl_compositor` global.
+    pub wl_compositor_version: Option<u32>,
+    /// Version of the advertised `wl_shm` global.
+    pub wl_shm_version: Option<u32>,
+    /// Value of `XDG_CURRENT_DESKTOP`.
+    pub current_desktop: Option<String>,
+    /// Display the connection was made to, falling back to `WAYLAND_DISPLAY`.
+    pub wayland_display: Option<String>,
+    /// Name of the first seat, only announced by `wl_seat` version 2 and up.
+    pub seat_name: Option<String>,
+}
+
+impl EnvironmentInfo {
+    pub(crate) fn query(
+        conn: &Connection,
+        globals: &GlobalList,
+        wayland_display: Option<&str>,
+    ) -> Self {
+        Self {
+            wl_compositor_version: global_version(globals, "wl_compositor"),
+            wl_shm_version: global_version(globals, "wl_shm"),
+            current_desktop: env::var("XDG_CURRENT_DESKTOP").ok(),
+            wayland_display: wayland_display
+                .map(str::to_string)
+                .or_else(|| env::var("WAYLAND_DISPLAY").ok()),
+            seat_name: query_seat_name(conn, globals),
+        }
+    }
+}
+
+impl Display for EnvironmentInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        fn or_unknown<T: Display>(value: &Option<T>) -> &dyn Display {
+            match value {
+                Some(value) => value,
+                None => &"unknown",
+            }
+        }
+
+        writeln!(
+            f,
+            "wl_compositor version: {}",
+            or_unknown(&self.wl_compositor_version)
+        )?;
+        writeln!(f, "wl_shm version: {}", or_unknown(&self.wl_shm_version))?;
+        writeln!(
+            f,
+            "XDG_CURRENT_DESKTOP: {}",
+            or_unknown(&self.current_desktop)
+        )?;
+        writeln!(f, "WAYLAND_DISPLAY: {}", or_unknown(&self.wayland_display))?;
+        write!(f, "seat: {}", or_unknown(&self.seat_name))
+    }
+}
+
+/// How long the phases of connecting to the compositor took, to find out
+/// where the time before the first capture goes.
+///
+/// Do not instantiate, instead use [`crate::WayshotConnection::startup_timings`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StartupTimings {
+    /// Opening the Wayland socket, zero for connections made by the caller.
+    pub connect: Duration,
+    /// Receiving the advertised globals.
+    pub globals: Duration,
+    /// Binding the outputs and receiving their names and logical regions.
+    pub outputs: Duration,
+    /// Receiving the toplevels, zero if they weren't listed.
+    pub toplevels: Duration,
+}
+
+impl StartupTimings {
+    /// The phases with their names, in the order they run.
+    pub fn phases(&self) -> [(&'static str, Duration); 4] {
+        [
+            ("connect", self.connect),
+            ("globals", self.globals),
+            ("outputs", self.outputs),
+            ("toplevels", self.toplevels),
+        ]
+    }
+}
+
+/// Best guess of the running compositor and the versions of the protocols wayshot uses.
+///
+/// Do not instantiate, instead use [`crate::WayshotConnection::compositor_info`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CompositorInfo {
+    /// Compositor name, guessed from compositor specific globals and falling
+    /// back to `XDG_CURRENT_DESKTOP`.
+    pub name: Option<String>,
+    pub screencopy_version: Option<u32>,
+    pub image_copy_capture_version: Option<u32>,
+    pub layer_shell_version: Option<u32>,
+    pub xdg_output_version: Option<u32>,
+}
+
+impl CompositorInfo {
+    pub(crate) fn query(globals: &GlobalList) -> Self {
+        let name = globals
+            .contents()
+            .with_list(|list| {
+                detect_compositor(list.iter().map(|global| global.interface.as_str()))
+            })
+            .map(str::to_string)
+            .or_else(|| env::var("XDG_CURRENT_DESKTOP").ok());
+        Self {
+            name,
+            screencopy_version: global_version(globals, "zwlr_screencopy_manager_v1"),
+            image_copy_capture_version: global_version(
+                globals,
+                "ext_image_copy_capture_manager_v1",
+            ),
+            layer_shell_version: global_version(globals, "zwlr_layer_shell_v1"),
+            xdg_output_version: global_version(globals, "zxdg_output_manager_v1"),
+        }
+    }
+}
+
+impl Display for CompositorInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "compositor: {}",
+            self.name.as_deref().unwrap_or("unknown")
+        )?;
+        for (protocol, version) in [
+            ("screencopy", self.screencopy_version),
+            ("image-copy-capture", self.image_copy_capture_version),
+            ("layer-shell", self.layer_shell_version),
+            ("xdg-output", self.xdg_output_version),
+        ] {
+            match version {
+                Some(version) => write!(f, ", {protocol} v{version}")?,
+                None => write!(f, ", no {protocol}")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Guess the compositor from globals only it advertises.
+fn detect_compositor<'a>(mut interfaces: impl Iterator<Item = &'a str>) -> Option<&'static str> {
+    const KNOWN_PREFIXES: &[(&str, &str)] = &[
+        ("hyprland_", "Hyprland"),
+        ("river_", "river"),
+        ("org_kde_kwin_", "KWin"),
+        ("gtk_shell1", "Mutter"),
+        ("zwf_", "Wayfire"),
+        ("zcosmic_", "COSMIC"),
+        ("treeland_", "Treeland"),
+        ("weston_", "Weston"),
+    ];
+    interfaces.find_map(|interface| {
+        KNOWN_PREFIXES
+            .iter()
+            .find(|(prefix, _)| interface.starts_with(prefix))
+            .map(|(_, name)| *name)
+    })
+}
+
+/// Version of the `I` global libwayshot binds, the advertised one capped at
+/// the highest version it implements, or `None` if it isn't advertised.
+pub(crate) fn negotiated_version<I: Proxy>(globals: &GlobalList) -> Option<u32> {
+    global_version(globals, I::interface().name)
+        .map(|advertised| advertised.min(I::interface().version))
+}
+
+/// Version of the first advertised global with the given interface name.
+pub(crate) fn global_version(globals: &GlobalList, interface: &str) -> Option<u32> {
+    globals.contents().with_list(|list| {
+        list.iter()
+            .find(|global| global.interface == interface)
+            .map(|global| global.version)
+    })
+}
+
+/// Whether a global of the `I` interface is advertised in at least `version`,
+/// without binding it.
+pub(crate) fn has_global<I: Proxy>(globals: &GlobalList, version: u32) -> bool {
+    global_version(globals, I::interface().name).is_some_and(|advertised| advertised >= version)
+}
+
+fn query_seat_name(conn: &Connection, globals: &GlobalList) -> Option<String> {
+    let mut state = SeatNameState::default();
+    let mut event_queue = conn.new_event_queue::<SeatNameState>();
+    let qh = event_queue.handle();
+
+    let seat = match globals.bind::<WlSeat, _, _>(&qh, 2..=WlSeat::interface().version, ()) {
+        Ok(seat) => seat,
+        Err(e) => {
+            tracing::debug!("Could not bind wl_seat to read its name: {e}");
+            return None;
+        }
+    };
+    if let Err(e) = event_queue.roundtrip(&mut state) {
+        tracing::debug!("Failed to receive the wl_seat name: {e}");
+    }
+    if seat.version() >= 5 {
+        seat.release();
+    }
+    state.name
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display_lists_every_field() {
+        let info = EnvironmentInfo {
+            wl_compositor_version: Some(6),
+            wl_shm_version: Some(2),
+            current_desktop: Some("sway".to_string()),
+            wayland_display: Some("wayland-1".to_string()),
+            seat_name: Some("seat0".to_string()),
+        };
+
+        assert_eq!(
+            info.to_string(),
+            "wl_compositor version: 6\n\
+             wl_shm version: 2\n\
+             XDG_CURRENT_DESKTOP: sway\n\
+             WAYLAND_DISPLAY: wayland-1\n\
+             seat: seat0"
+        );
+    }
+
+    #[test]
+    fn detect_compositor_from_specific_globals() {
+        assert_eq!(
+            detect_compositor(["wl_compositor", "hyprland_toplevel_export_manager_v1"].into_iter()),
+            Some("Hyprland")
+        );
+        assert_eq!(
+            detect_compositor(["wl_shm", "river_control_unstable_v1"].into_iter()),
+            Some("river")
+        );
+        assert_eq!(
+            detect_compositor(["wl_compositor", "zwlr_screencopy_manager_v1"].into_iter()),
+            None
+        );
+    }
+
+    #[test]
+    fn compositor_info_display_lists_protocols() {
+        let info = CompositorInfo {
+            name: Some("sway".to_string()),
+            screencopy_version: Some(3),
+            image_copy_capture_version: None,
+            layer_shell_version: Some(4),
+            xdg_output_version: Some(3),
+        };
+
+        assert_eq!(
+            info.to_string(),
+            "compositor: sway, screencopy v3, no image-copy-capture, layer-shell v4, xdg-output v3"
+        );
+    }
+
+    #[test]
+    fn display_marks_missing_fields_unknown() {
+        let info = EnvironmentInfo::default();
+
+        assert_eq!(
+            info.to_string(),
+            "wl_compositor version: unknown\n\
+             wl_shm version: unknown\n\
+             XDG_CURRENT_DESKTOP: unknown\n\
+             WAYLAND_DISPLAY: unknown\n\
+             seat: unknown"
+        );
+    }
+}
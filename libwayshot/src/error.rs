@@ -1,10 +1,16 @@
-use std::{io, result};
+use std::{fmt::Debug, io, path::PathBuf, result};
 
 use drm::buffer::UnrecognizedFourcc;
 use gbm::InvalidFdError;
 use thiserror::Error;
+
+use crate::{
+    encoding::PixelFormat,
+    region::{LogicalRegion, Region, Size},
+};
 use wayland_client::{
     ConnectError, DispatchError, WEnum,
+    backend::WaylandError,
     globals::{BindError, GlobalError},
 };
 use wayland_protocols::ext::image_copy_capture::v1::client::ext_image_copy_capture_frame_v1::FailureReason;
@@ -12,37 +18,102 @@ use wayland_protocols::ext::image_copy_capture::v1::client::ext_image_copy_captu
 pub type Result<T, E = Error> = result::Result<T, E>;
 
 #[derive(Error, Debug)]
+#[non_exhaustive]
 pub enum Error {
     #[error("no outputs supplied")]
     NoOutputs,
+    #[error("timed out waiting for {expected} output(s), {found} ready")]
+    OutputWaitTimeout { expected: usize, found: usize },
     #[error("image buffer is not big enough")]
     BufferTooSmall,
+    #[error("a {size} frame with a stride of {stride} bytes is too large for a shm buffer")]
+    FrameTooLarge { size: Size, stride: u32 },
+    #[error("shm buffer holds {got} bytes, but the frame needs {expected}")]
+    BufferSizeMismatch { expected: u64, got: u64 },
     #[error("image color type not supported")]
     InvalidColor,
     #[error("IO error: {0}")]
     Io(#[from] io::Error),
     #[error("dispatch error: {0}")]
-    Dispatch(#[from] DispatchError),
+    Dispatch(DispatchError),
     #[error("bind error: {0}")]
     Bind(#[from] BindError),
     #[error("global error: {0}")]
-    Global(#[from] GlobalError),
+    Global(GlobalError),
+    /// The socket to the compositor was closed, usually because the compositor
+    /// exited or crashed. Long-running callers can reconnect on this error.
+    #[error("connection to the compositor lost: {0}")]
+    ConnectionLost(io::Error),
+    /// The reader of a stream such as
+    /// `WayshotConnection::stream_single_output_ppm` closed it while a frame
+    /// was written. Streaming callers can stop on this error.
+    #[error("stream closed by its reader: {0}")]
+    StreamClosed(io::Error),
     #[error("connect error: {0}")]
     Connect(#[from] ConnectError),
     #[error("framecopy failed")]
     FramecopyFailed,
     #[error("framecopy failed with reason {0:?}")]
     FramecopyFailedWithReason(WEnum<FailureReason>),
-    #[error("No supported buffer format")]
-    NoSupportedBufferFormat,
+    /// The compositor finished describing the buffers of a capture without
+    /// advertising a single format.
+    #[error(
+        "the compositor advertised no buffer formats, the output may be disabled or the compositor buggy"
+    )]
+    NoFormatsAdvertised,
+    #[error("No supported buffer format, the compositor advertised {advertised}")]
+    NoSupportedBufferFormat { advertised: String },
     #[error("Cannot find required wayland protocol")]
     ProtocolNotFound(String),
     #[error("error occurred in freeze callback")]
     FreezeCallbackError(String),
+    /// Every capture attempt failed on a compositor supporting
+    /// ext-session-lock, and so did a probe capture of every output, which
+    /// compositors do while the session is locked.
+    #[error("session is locked, capture refused by compositor")]
+    SessionLocked,
+    /// The user dismissed the region selection, e.g. by pressing escape.
+    #[error("selection cancelled")]
+    SelectionCancelled,
+    /// The selected region has no area, e.g. after a click without a drag.
+    /// Callers may treat this as a cancelled selection.
+    #[error("selected region is empty")]
+    EmptyRegion,
+    /// The selected region is entirely outside of every output. Callers may
+    /// treat this as a cancelled selection.
+    #[error("selected region {0} is not on any output")]
+    RegionNotVisible(LogicalRegion),
     #[error(
         "dmabuf configuration not initialized. Did you not use Wayshot::from_connection_with_dmabuf()?"
     )]
     NoDMAStateError,
+    /// The DRM device given for DMA-BUF capture could not be opened.
+    #[error("cannot open DRM device {}: {source}", device.display())]
+    DrmDevice {
+        device: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    /// No compositor is listening on the socket given to `WayshotConnection::connect_to`.
+    #[error("cannot connect to Wayland display {}: {source}", socket.display())]
+    DisplayConnect {
+        socket: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    /// A DMA-BUF capture failed while the buffers were allocated on a different
+    /// device than the one the compositor renders on.
+    #[error(
+        "DMA-BUF import failed, buffers are allocated on {} but the compositor uses {}: {source}",
+        device.display(),
+        compositor_device.display()
+    )]
+    DmabufDeviceMismatch {
+        device: PathBuf,
+        compositor_device: PathBuf,
+        #[source]
+        source: Box<Error>,
+    },
     #[error("dmabuf color format provided by compositor is invalid")]
     UnrecognizedColorCode(#[from] UnrecognizedFourcc),
     #[error("dmabuf device has been destroyed")]
@@ -51,20 +122,131 @@ pub enum Error {
     EGLImageToTexProcNotFoundError,
     #[error("Capture failed: {0}")]
     CaptureFailed(String),
+    /// The compositor didn't apply an output configuration, see
+    /// `WayshotConnection::screenshot_disabled_output`.
+    #[error("output configuration failed: {0}")]
+    OutputConfiguration(String),
+    /// No seat has the name given to [`crate::SeatSelector::Named`].
+    #[error("no seat named {name}, available seats: {available}")]
+    SeatNotFound { name: String, available: String },
+    #[error("no seat with a pointer matches {selector}, available seats: {available}")]
+    SeatWithoutPointer { selector: String, available: String },
+    /// The pointer isn't over any output, see
+    /// [`crate::WayshotConnection::focused_output`].
+    #[error("the pointer is not over any output, the focused output is unknown")]
+    FocusedOutputUnknown,
     #[error("Unsupported for some reason: {0}")]
     Unsupported(String),
     #[error("Fd does not exist")]
     InvalidFd(#[from] InvalidFdError),
+    #[error("region {region} is outside of the output bounds {bounds}")]
+    RegionOutOfBounds { region: Region, bounds: Size },
+    /// No output has the name given to e.g. [`crate::capture_output_png`].
+    #[error("no output named {0}")]
+    OutputNotFound(String),
+    #[error("invalid state file {path:?}: {reason}")]
+    InvalidStateFile { path: PathBuf, reason: String },
+    #[error("invalid tile layout: {0}")]
+    InvalidTileLayout(String),
+    #[error("encoding error: {0}")]
+    Encoding(#[from] image::ImageError),
+    /// A file read back after encoding doesn't hold the encoded image.
+    #[error("raw {width}x{height} image needs {expected} bytes, got {got}")]
+    RawImageSize {
+        width: u32,
+        height: u32,
+        expected: u64,
+        got: u64,
+    },
+    #[error("verification failed: {0}")]
+    VerificationFailed(String),
+    #[error("{format:?} can't be encoded with {pixel_format} pixels")]
+    UnsupportedPixelFormat {
+        pixel_format: PixelFormat,
+        format: image::ImageFormat,
+    },
+}
+
+/// Whether an IO error of `kind` means the other end of a socket is gone.
+fn is_disconnect(kind: io::ErrorKind) -> bool {
+    matches!(
+        kind,
+        io::ErrorKind::BrokenPipe
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::NotConnected
+            | io::ErrorKind::UnexpectedEof
+    )
+}
+
+/// Returns the underlying IO error if the wayland error means the socket is gone.
+fn connection_lost(error: &WaylandError) -> Option<io::Error> {
+    match error {
+        WaylandError::Io(io_error) if is_disconnect(io_error.kind()) => {
+            Some(io::Error::new(io_error.kind(), io_error.to_string()))
+        }
+        _ => None,
+    }
+}
+
+impl Error {
+    /// The error of a capture that found no usable one among the `advertised`
+    /// formats, [`Error::NoFormatsAdvertised`] if there are none.
+    pub(crate) fn no_usable_format<F: Debug>(advertised: impl IntoIterator<Item = F>) -> Self {
+        let advertised: Vec<_> = advertised
+            .into_iter()
+            .map(|format| format!("{format:?}"))
+            .collect();
+        if advertised.is_empty() {
+            return Error::NoFormatsAdvertised;
+        }
+        Error::NoSupportedBufferFormat {
+            advertised: advertised.join(", "),
+        }
+    }
+
+    /// Turn an error writing to a stream into [`Error::StreamClosed`] if the
+    /// reader went away.
+    pub(crate) fn on_stream(self) -> Self {
+        match self {
+            Error::Io(e) if is_disconnect(e.kind()) => Error::StreamClosed(e),
+            error => error,
+        }
+    }
+}
+
+impl From<DispatchError> for Error {
+    fn from(error: DispatchError) -> Self {
+        match &error {
+            DispatchError::Backend(wayland_error) => connection_lost(wayland_error)
+                .map(Error::ConnectionLost)
+                .unwrap_or(Error::Dispatch(error)),
+            _ => Error::Dispatch(error),
+        }
+    }
+}
+
+impl From<GlobalError> for Error {
+    fn from(error: GlobalError) -> Self {
+        match &error {
+            GlobalError::Backend(wayland_error) => connection_lost(wayland_error)
+                .map(Error::ConnectionLost)
+                .unwrap_or(Error::Global(error)),
+            _ => Error::Global(error),
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::region::Position;
     use drm::buffer::UnrecognizedFourcc;
     use wayland_client::{
         ConnectError, DispatchError,
         backend::{InvalidId, ObjectId, WaylandError, protocol::ProtocolError},
         globals::{BindError, GlobalError},
+        protocol::wl_shm,
     };
 
     #[test]
@@ -132,6 +314,55 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_from_dispatch_error_broken_pipe_is_connection_lost() {
+        let io_error = std::io::Error::from(std::io::ErrorKind::BrokenPipe);
+        let dispatch_error = DispatchError::Backend(WaylandError::Io(io_error));
+
+        let wayshot_error: Error = dispatch_error.into();
+
+        match wayshot_error {
+            Error::ConnectionLost(e) => assert_eq!(e.kind(), std::io::ErrorKind::BrokenPipe),
+            _ => panic!("Expected Error::ConnectionLost(...)"),
+        }
+    }
+
+    #[test]
+    fn stream_write_errors_become_stream_closed_when_the_reader_left() {
+        let closed = Error::Io(std::io::ErrorKind::ConnectionReset.into()).on_stream();
+        assert!(
+            matches!(closed, Error::StreamClosed(e) if e.kind() == std::io::ErrorKind::ConnectionReset)
+        );
+        let full = Error::Io(std::io::ErrorKind::StorageFull.into()).on_stream();
+        assert!(matches!(full, Error::Io(_)));
+    }
+
+    #[test]
+    fn test_from_dispatch_error_other_io_stays_dispatch() {
+        let io_error = std::io::Error::from(std::io::ErrorKind::PermissionDenied);
+        let dispatch_error = DispatchError::Backend(WaylandError::Io(io_error));
+
+        let wayshot_error: Error = dispatch_error.into();
+
+        match wayshot_error {
+            Error::Dispatch(DispatchError::Backend(WaylandError::Io(_))) => {}
+            _ => panic!("Expected Error::Dispatch(DispatchError::Backend(WaylandError::Io(...)))"),
+        }
+    }
+
+    #[test]
+    fn test_from_global_error_connection_reset_is_connection_lost() {
+        let io_error = std::io::Error::from(std::io::ErrorKind::ConnectionReset);
+        let global_error = GlobalError::Backend(WaylandError::Io(io_error));
+
+        let wayshot_error: Error = global_error.into();
+
+        match wayshot_error {
+            Error::ConnectionLost(e) => assert_eq!(e.kind(), std::io::ErrorKind::ConnectionReset),
+            _ => panic!("Expected Error::ConnectionLost(...)"),
+        }
+    }
+
     #[test]
     fn test_from_bind_error_uv() {
         let bind_error = BindError::UnsupportedVersion;
@@ -228,8 +459,18 @@ mod tests {
 
     #[test]
     fn test_display_no_supported_buffer_format() {
-        let err = Error::NoSupportedBufferFormat;
-        assert_eq!(err.to_string(), "No supported buffer format");
+        let err = Error::no_usable_format([wl_shm::Format::Rgb565, wl_shm::Format::Yuyv]);
+        assert_eq!(
+            err.to_string(),
+            "No supported buffer format, the compositor advertised Rgb565, Yuyv"
+        );
+    }
+
+    #[test]
+    fn missing_formats_are_not_reported_as_unsupported() {
+        let err = Error::no_usable_format(Vec::<wl_shm::Format>::new());
+        assert!(matches!(err, Error::NoFormatsAdvertised));
+        assert!(err.to_string().contains("the output may be disabled"));
     }
 
     #[test]
@@ -244,6 +485,60 @@ mod tests {
         assert_eq!(err.to_string(), "error occurred in freeze callback");
     }
 
+    #[test]
+    fn test_display_focused_output_unknown() {
+        let err = Error::FocusedOutputUnknown;
+        assert_eq!(
+            err.to_string(),
+            "the pointer is not over any output, the focused output is unknown"
+        );
+    }
+
+    #[test]
+    fn test_display_selection_cancelled() {
+        let err = Error::SelectionCancelled;
+        assert_eq!(err.to_string(), "selection cancelled");
+    }
+
+    #[test]
+    fn test_display_region_not_visible() {
+        let err = Error::RegionNotVisible(LogicalRegion {
+            inner: Region {
+                position: Position { x: -100, y: 0 },
+                size: Size {
+                    width: 50,
+                    height: 20,
+                },
+            },
+        });
+        assert_eq!(
+            err.to_string(),
+            "selected region (-100, 0) (50x20) is not on any output"
+        );
+    }
+
+    #[test]
+    fn test_display_session_locked() {
+        let err = Error::SessionLocked;
+        assert_eq!(
+            err.to_string(),
+            "session is locked, capture refused by compositor"
+        );
+    }
+
+    #[test]
+    fn test_display_dmabuf_device_mismatch() {
+        let err = Error::DmabufDeviceMismatch {
+            device: "/dev/dri/renderD129".into(),
+            compositor_device: "/dev/dri/renderD128".into(),
+            source: Box::new(Error::FramecopyFailed),
+        };
+        assert_eq!(
+            err.to_string(),
+            "DMA-BUF import failed, buffers are allocated on /dev/dri/renderD129 but the compositor uses /dev/dri/renderD128: framecopy failed"
+        );
+    }
+
     #[test]
     fn test_display_no_dma_state_error() {
         let err = Error::NoDMAStateError;
@@ -272,4 +567,75 @@ mod tests {
             _ => panic!("Expected Error::EGLError(khronos_egl::Error::ContextLost)"),
         }
     }
+
+    /// Lists the variants of [`Error`] as `VARIANTS`, in a match without a
+    /// wildcard so that a new variant doesn't build until it is listed.
+    macro_rules! error_variants {
+        ($($variant:ident),* $(,)?) => {
+            const VARIANTS: &[&str] = &[$(stringify!($variant)),*];
+
+            fn variant_name(error: &Error) -> &'static str {
+                match error {
+                    $(Error::$variant { .. } => stringify!($variant),)*
+                }
+            }
+        };
+    }
+
+    error_variants! {
+        NoOutputs,
+        OutputWaitTimeout,
+        BufferTooSmall,
+        FrameTooLarge,
+        BufferSizeMismatch,
+        InvalidColor,
+        Io,
+        Dispatch,
+        Bind,
+        Global,
+        ConnectionLost,
+        StreamClosed,
+        Connect,
+        FramecopyFailed,
+        FramecopyFailedWithReason,
+        NoFormatsAdvertised,
+        NoSupportedBufferFormat,
+        ProtocolNotFound,
+        FreezeCallbackError,
+        SessionLocked,
+        SelectionCancelled,
+        EmptyRegion,
+        RegionNotVisible,
+        NoDMAStateError,
+        DrmDevice,
+        DisplayConnect,
+        DmabufDeviceMismatch,
+        UnrecognizedColorCode,
+        EGLError,
+        EGLImageToTexProcNotFoundError,
+        CaptureFailed,
+        OutputConfiguration,
+        SeatNotFound,
+        SeatWithoutPointer,
+        FocusedOutputUnknown,
+        Unsupported,
+        InvalidFd,
+        RegionOutOfBounds,
+        OutputNotFound,
+        InvalidStateFile,
+        InvalidTileLayout,
+        Encoding,
+        RawImageSize,
+        VerificationFailed,
+        UnsupportedPixelFormat,
+    }
+
+    #[test]
+    fn error_variants_are_listed() {
+        // Read by the error report of the wayshot binary, which gives every
+        // variant its own kind.
+        let listed: Vec<&str> = include_str!("../error-variants.txt").lines().collect();
+        assert_eq!(listed, VARIANTS, "update libwayshot/error-variants.txt");
+        assert_eq!(variant_name(&Error::NoOutputs), "NoOutputs");
+    }
 }
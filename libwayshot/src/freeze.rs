@@ -0,0 +1,304 @@
+//! How the outputs look while they are frozen for a selection, see
+//! [`WayshotConnection::screenshot_freeze_with_options`](crate::WayshotConnection::screenshot_freeze_with_options).
+
+use image::{Rgba, RgbaImage, imageops};
+
+use crate::{image_util::FrameView, output::OutputInfo, region::Region};
+
+/// Appearance of the frozen outputs while the freeze callback runs.
+///
+/// The default shows the frozen frames as they were captured. Blurring,
+/// dimming and tinting only change what is shown, never the captured image.
+/// They apply outside of the selection a selector reports while dragging with
+/// [`WayshotConnection::set_freeze_selection`](crate::WayshotConnection::set_freeze_selection),
+/// or everywhere until it does. Dimming and tinting are cheap, blurring costs
+/// a blur of every frame when freezing.
+///
+/// # Example usage
+///
+/// ```ignore
+/// use libwayshot::{FreezeOptions, FreezeScope};
+/// let options = FreezeOptions::new()
+///     .blur(8.0)
+///     .dim(0.3)
+///     .tint(Some(image::Rgba([0, 0, 64, 40])))
+///     .show_on(FreezeScope::InvolvedOutputs(vec!["DP-1".to_string()]));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FreezeOptions {
+    dim: f32,
+    tint: Option<Rgba<u8>>,
+    blur: f32,
+    show_on: FreezeScope,
+}
+
+/// Which outputs are frozen, see [`FreezeOptions::show_on`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum FreezeScope {
+    /// Freeze every output and let the selection span all of them.
+    #[default]
+    AllOutputs,
+    /// Only capture and freeze the outputs with these names, e.g. the one a
+    /// picker runs on. The other outputs stay live and a selection on them
+    /// fails with [`Error::RegionNotVisible`](crate::Error::RegionNotVisible).
+    InvolvedOutputs(Vec<String>),
+}
+
+impl FreezeOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Darken the frozen frames by `dim`, from 0 (unchanged, the default) to 1 (black).
+    pub fn dim(mut self, dim: f32) -> Self {
+        self.dim = if dim.is_nan() {
+            0.0
+        } else {
+            dim.clamp(0.0, 1.0)
+        };
+        self
+    }
+
+    /// Blur the frozen frames with a gaussian of `sigma` buffer pixels, 0 (the
+    /// default) to keep them sharp.
+    pub fn blur(mut self, sigma: f32) -> Self {
+        self.blur = if sigma.is_nan() { 0.0 } else { sigma.max(0.0) };
+        self
+    }
+
+    /// Blend `tint` over the frozen frames, by its alpha. None by default.
+    pub fn tint(mut self, tint: Option<Rgba<u8>>) -> Self {
+        self.tint = tint;
+        self
+    }
+
+    /// Which outputs to freeze, all of them by default.
+    pub fn show_on(mut self, show_on: FreezeScope) -> Self {
+        self.show_on = show_on;
+        self
+    }
+
+    pub fn get_dim(&self) -> f32 {
+        self.dim
+    }
+
+    pub fn get_tint(&self) -> Option<Rgba<u8>> {
+        self.tint
+    }
+
+    pub fn get_blur(&self) -> f32 {
+        self.blur
+    }
+
+    pub fn get_show_on(&self) -> &FreezeScope {
+        &self.show_on
+    }
+
+    /// Whether `output_info` is frozen.
+    pub(crate) fn shows(&self, output_info: &OutputInfo) -> bool {
+        match &self.show_on {
+            FreezeScope::AllOutputs => true,
+            FreezeScope::InvolvedOutputs(names) => names.contains(&output_info.name),
+        }
+    }
+
+    /// Whether the frozen frames are shown as captured, so their own buffers
+    /// can be attached without a copy.
+    pub(crate) fn is_unshaded(&self) -> bool {
+        self.dim == 0.0
+            && self.blur == 0.0
+            && self.tint.is_none_or(|Rgba([_, _, _, alpha])| alpha == 0)
+    }
+
+    /// Dim and tint a row of RGBA pixels in place, leaving it opaque.
+    ///
+    /// Both are one multiply-add per channel: dimming scales by `1 - dim` and
+    /// the tint is blended over that by its alpha.
+    pub(crate) fn shade_row(&self, row: &mut [u8]) {
+        let Rgba([r, g, b, alpha]) = self.tint.unwrap_or(Rgba([0, 0, 0, 0]));
+        let alpha = alpha as f32 / 255.0;
+        let factor = (1.0 - self.dim) * (1.0 - alpha);
+        let offsets = [r, g, b].map(|channel| channel as f32 * alpha);
+        for pixel in row.chunks_exact_mut(4) {
+            for (channel, offset) in pixel.iter_mut().zip(offsets) {
+                *channel = (*channel as f32 * factor + offset).round().min(255.0) as u8;
+            }
+            pixel[3] = 255;
+        }
+    }
+
+    /// The frozen frame in `view` as it is shown, with and without blurring
+    /// and shading.
+    pub(crate) fn overlay_frame(&self, view: &FrameView) -> OverlayFrame {
+        let (width, height) = view.dimensions();
+        let mut canvas = RgbaImage::new(width, height);
+        view.blit(&mut canvas, 0, 0);
+        let mut shaded = if self.blur > 0.0 {
+            imageops::fast_blur(&canvas, self.blur).into_raw()
+        } else {
+            canvas.as_raw().clone()
+        };
+        let mut sharp = canvas.into_raw();
+        for row in shaded.chunks_exact_mut(width as usize * 4) {
+            self.shade_row(row);
+        }
+        // Xrgb8888 is stored as BGRX.
+        for pixels in [&mut sharp, &mut shaded] {
+            pixels
+                .chunks_exact_mut(4)
+                .for_each(|pixel| pixel.swap(0, 2));
+        }
+        OverlayFrame {
+            width: width as usize,
+            sharp,
+            shaded,
+        }
+    }
+}
+
+/// A frozen frame in both of the looks it is shown in, as tightly packed
+/// `Xrgb8888` rows for a shm buffer.
+pub(crate) struct OverlayFrame {
+    width: usize,
+    sharp: Vec<u8>,
+    shaded: Vec<u8>,
+}
+
+impl std::fmt::Debug for OverlayFrame {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("OverlayFrame")
+            .field("width", &self.width)
+            .finish_non_exhaustive()
+    }
+}
+
+impl OverlayFrame {
+    /// The pixels to show with `selection`, in buffer pixels, kept sharp and
+    /// the rest blurred and shaded.
+    pub(crate) fn pixels(&self, selection: Option<Region>) -> Vec<u8> {
+        let mut pixels = self.shaded.clone();
+        if let Some(Region { position, size }) = selection {
+            let row_len = self.width * 4;
+            let (left, top) = (position.x as usize, position.y as usize);
+            let columns = left * 4..(left + size.width as usize).min(self.width) * 4;
+            for (row, sharp_row) in pixels
+                .chunks_exact_mut(row_len)
+                .zip(self.sharp.chunks_exact(row_len))
+                .skip(top)
+                .take(size.height as usize)
+            {
+                row[columns.clone()].copy_from_slice(&sharp_row[columns.clone()]);
+            }
+        }
+        pixels
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use wayland_client::protocol::wl_shm::Format;
+
+    use super::*;
+    use crate::{region::Size, screencopy::FrameFormat};
+
+    fn shaded(options: &FreezeOptions, pixel: [u8; 4]) -> [u8; 4] {
+        let mut row = pixel;
+        options.shade_row(&mut row);
+        row
+    }
+
+    #[test]
+    fn dim_scales_every_channel() {
+        let options = FreezeOptions::new().dim(0.5);
+        assert_eq!(shaded(&options, [200, 100, 1, 0]), [100, 50, 1, 255]);
+        assert_eq!(
+            shaded(&FreezeOptions::new().dim(1.0), [200, 100, 1, 255]),
+            [0, 0, 0, 255]
+        );
+        // Out of range amounts are clamped.
+        assert_eq!(FreezeOptions::new().dim(3.0).get_dim(), 1.0);
+        assert_eq!(FreezeOptions::new().dim(f32::NAN).get_dim(), 0.0);
+    }
+
+    #[test]
+    fn tint_blends_by_its_alpha_after_dimming() {
+        let red = FreezeOptions::new().tint(Some(Rgba([255, 0, 0, 51])));
+        // 20% red over white and black.
+        assert_eq!(shaded(&red, [255, 255, 255, 255]), [255, 204, 204, 255]);
+        assert_eq!(shaded(&red, [0, 0, 0, 255]), [51, 0, 0, 255]);
+
+        let both = FreezeOptions::new()
+            .dim(0.5)
+            .tint(Some(Rgba([0, 0, 255, 51])));
+        assert_eq!(shaded(&both, [100, 100, 100, 255]), [40, 40, 91, 255]);
+    }
+
+    #[test]
+    fn default_options_leave_frames_unshaded() {
+        assert!(FreezeOptions::new().is_unshaded());
+        assert!(
+            FreezeOptions::new()
+                .tint(Some(Rgba([255, 0, 0, 0])))
+                .is_unshaded()
+        );
+        assert!(!FreezeOptions::new().dim(0.1).is_unshaded());
+    }
+
+    #[test]
+    fn overlay_pixels_are_shaded_xrgb_rows() {
+        // Two Xrgb8888 pixels, stored as BGRX, with padding after the row.
+        let data = [10, 20, 30, 0, 40, 50, 60, 0, 9, 9];
+        let frame_format = FrameFormat {
+            format: Format::Xrgb8888,
+            size: Size {
+                width: 2,
+                height: 1,
+            },
+            stride: 10,
+        };
+        let view = FrameView::new(&data, frame_format).expect("view");
+
+        let frame = FreezeOptions::new().dim(0.5).overlay_frame(&view);
+        assert_eq!(frame.pixels(None), [5, 10, 15, 255, 20, 25, 30, 255]);
+    }
+
+    #[test]
+    fn overlay_keeps_the_selection_sharp() {
+        // A 4x2 Xrgb8888 frame with one bright column.
+        let mut data = [40; 32];
+        for row in data.chunks_exact_mut(16) {
+            row[8..12].fill(240);
+        }
+        let frame_format = FrameFormat {
+            format: Format::Xrgb8888,
+            size: Size {
+                width: 4,
+                height: 2,
+            },
+            stride: 16,
+        };
+        let view = FrameView::new(&data, frame_format).expect("view");
+        let options = FreezeOptions::new().blur(1.0).dim(0.5);
+        assert!(!FreezeOptions::new().blur(1.0).is_unshaded());
+        assert_eq!(FreezeOptions::new().blur(-2.0).get_blur(), 0.0);
+
+        let frame = options.overlay_frame(&view);
+        let selection = Region {
+            position: crate::region::Position { x: 2, y: 1 },
+            size: Size {
+                width: 5,
+                height: 1,
+            },
+        };
+        let pixels = frame.pixels(Some(selection));
+
+        let pixel = |x: usize, y: usize| &pixels[(y * 4 + x) * 4..][..3];
+        // Inside of the selection, clipped to the frame, the frame is as captured.
+        assert_eq!(pixel(2, 1), [240; 3]);
+        assert_eq!(pixel(3, 1), [40; 3]);
+        // Outside of it the bright column is blurred into its neighbours and dimmed.
+        assert!(pixel(2, 0)[0] < 120);
+        assert!(pixel(1, 0)[0] > 20);
+        assert!(pixel(1, 0)[0] < pixel(2, 0)[0]);
+    }
+}
@@ -1,7 +1,26 @@
-use image::DynamicImage;
-use wayland_client::protocol::wl_output::Transform;
+use std::{
+    fmt,
+    hash::{DefaultHasher, Hash, Hasher},
+    io::Write,
+    mem,
+    num::NonZeroUsize,
+    str::FromStr,
+    thread,
+};
 
-use crate::region::Size;
+use image::{ColorType, DynamicImage, GenericImageView, Rgba, Rgba32FImage, RgbaImage};
+use wayland_client::protocol::{wl_output::Transform, wl_shm::Format};
+
+use crate::{
+    Error, Result, convert,
+    encoding::QoiEncoder,
+    region::{Position, Region, Size},
+    screencopy::{FrameCopy, FrameData, FrameFormat},
+};
+
+/// Maximum number of source rows averaged per thumbnail row, the rows in
+/// between are skipped when downscaling by a large ratio.
+const THUMBNAIL_MAX_SAMPLED_ROWS: u32 = 4;
 
 #[tracing::instrument(skip(image))]
 pub(crate) fn rotate_image_buffer(
@@ -10,15 +29,67 @@ pub(crate) fn rotate_image_buffer(
     logical_size: Size,
     max_scale: f64,
 ) -> DynamicImage {
-    // TODO Better document whether width and height are before or after the transform.
-    // Perhaps this should be part of a cleanup of the FrameCopy struct.
-    let (logical_width, _logical_height) = match transform {
-        Transform::_90 | Transform::_270 | Transform::Flipped90 | Transform::Flipped270 => {
-            (logical_size.height, logical_size.width)
-        }
-        _ => (logical_size.width, logical_size.height),
-    };
-    let rotated_image = match transform {
+    let rotated_image = apply_transform(image, transform);
+
+    // `logical_size` is after the transform, like the rotated image.
+    let scale = rotated_image.width() as f64 / logical_size.width as f64;
+    // The amount of scaling left to perform.
+    let scaling_left = max_scale / scale;
+    if scaling_left <= 1.0 {
+        tracing::debug!("No scaling left to do");
+        return rotated_image;
+    }
+
+    tracing::debug!("Scaling left to do: {scaling_left}");
+    // Size from the logical size like `Region::to_physical`, so fractional scales
+    // round the same way as the offsets the image is placed at.
+    let new_width = (logical_size.width as f64 * max_scale).round() as u32;
+    let new_height = (logical_size.height as f64 * max_scale).round() as u32;
+    tracing::debug!("Resizing image to {new_width}x{new_height}");
+    image::imageops::resize(
+        &rotated_image,
+        new_width,
+        new_height,
+        image::imageops::FilterType::Gaussian,
+    )
+    .into()
+}
+
+/// `crop`, in physical pixels of an output, clipped to the `width` by
+/// `height` image of it, as rounding fractional scales can overshoot by a pixel.
+pub(crate) fn clamp_crop(crop: Region, width: u32, height: u32) -> Region {
+    let Region { position, size } = crop;
+    let (x, y) = (
+        (position.x as u32).min(width),
+        (position.y as u32).min(height),
+    );
+    Region {
+        position: Position {
+            x: x as i32,
+            y: y as i32,
+        },
+        size: Size {
+            width: size.width.min(width - x),
+            height: size.height.min(height - y),
+        },
+    }
+}
+
+/// Whether [`rotate_image_buffer`] would return a frame of `frame_size` as it is,
+/// so it can be pasted from its raw buffer with [`FrameView::blit`] instead.
+pub(crate) fn rotate_is_noop(
+    transform: Transform,
+    frame_size: Size,
+    logical_size: Size,
+    max_scale: f64,
+) -> bool {
+    transform == Transform::Normal
+        && frame_size.width as f64 / logical_size.width as f64 >= max_scale
+}
+
+/// Rotate and flip the image according to the output transform.
+pub(crate) fn apply_transform(image: DynamicImage, transform: Transform) -> DynamicImage {
+    match transform {
         Transform::_90 => image::imageops::rotate90(&image).into(),
         Transform::_180 => image::imageops::rotate180(&image).into(),
         Transform::_270 => image::imageops::rotate270(&image).into(),
@@ -36,25 +107,1777 @@ pub(crate) fn rotate_image_buffer(
             image::imageops::rotate270(&flipped_buffer).into()
         }
         _ => image,
+    }
+}
+
+/// Layout of the bytes a [`FrameView`] reads its pixels from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PixelLayout {
+    /// As copied by the compositor.
+    Raw(Format),
+    /// Converted in place for an image, see [`FrameCopy::converted`].
+    Rgba8,
+    Rgb8,
+}
+
+/// Bounds checked, stride aware access to the pixels of a frame in shared
+/// memory, see [`FrameCopy::view`].
+///
+/// Rows are returned without their padding. Pixels are read as RGBA, whether
+/// the frame still holds the format the compositor copied it in or was
+/// already converted to an image.
+#[derive(Debug, Clone, Copy)]
+pub struct FrameView<'a> {
+    /// Starts at the first pixel of the view and ends after its last one.
+    data: &'a [u8],
+    layout: PixelLayout,
+    size: Size,
+    stride: usize,
+    bytes_per_pixel: usize,
+}
+
+impl<'a> FrameView<'a> {
+    /// View the pixel data of a shm backed frame, checking it is big enough for its format.
+    pub(crate) fn from_frame(frame: &'a FrameCopy) -> Result<Self> {
+        let FrameData::Mmap(data) = &frame.frame_data else {
+            return Err(Error::InvalidColor);
+        };
+        let view = Self::new(data, frame.frame_format)?;
+        if !frame.converted {
+            return Ok(view);
+        }
+        let (layout, bytes_per_pixel) = match frame.frame_color_type {
+            ColorType::Rgba8 => (PixelLayout::Rgba8, 4),
+            ColorType::Rgb8 => (PixelLayout::Rgb8, 3),
+            _ => return Err(Error::InvalidColor),
+        };
+        // Conversion keeps the size of every pixel.
+        if bytes_per_pixel != view.bytes_per_pixel {
+            return Err(Error::InvalidColor);
+        }
+        Ok(Self { layout, ..view })
+    }
+
+    /// View the pixels of an 8 bit RGB or RGBA `image`.
+    pub(crate) fn from_image(image: &'a DynamicImage) -> Result<Self> {
+        let (data, layout, bytes_per_pixel): (&[u8], _, _) = match image {
+            DynamicImage::ImageRgb8(image) => (image, PixelLayout::Rgb8, 3),
+            DynamicImage::ImageRgba8(image) => (image, PixelLayout::Rgba8, 4),
+            _ => return Err(Error::InvalidColor),
+        };
+        let (width, height) = image.dimensions();
+        Ok(Self {
+            data,
+            layout,
+            size: Size { width, height },
+            stride: width as usize * bytes_per_pixel,
+            bytes_per_pixel,
+        })
+    }
+
+    /// View `data` laid out as `frame_format`, checking it is big enough for its format.
+    pub(crate) fn new(data: &'a [u8], frame_format: FrameFormat) -> Result<Self> {
+        let format = frame_format.format;
+        let bytes_per_pixel = match format {
+            Format::Xbgr8888
+            | Format::Abgr8888
+            | Format::Xrgb8888
+            | Format::Argb8888
+            | Format::Xbgr2101010
+            | Format::Abgr2101010 => 4,
+            Format::Bgr888 => 3,
+            _ => {
+                tracing::error!("Unsupported buffer format: {format:?}");
+                return Err(Error::no_usable_format([format]));
+            }
+        };
+        let size = frame_format.size;
+        let stride = frame_format.stride as usize;
+        let row_len = size.width as usize * bytes_per_pixel;
+        if size.width == 0 || size.height == 0 || stride < row_len {
+            return Err(Error::BufferTooSmall);
+        }
+        // The last row doesn't need its padding.
+        let len = (size.height as usize - 1) * stride + row_len;
+        let Some(data) = data.get(..len) else {
+            return Err(Error::BufferTooSmall);
+        };
+        Ok(Self {
+            data,
+            layout: PixelLayout::Raw(format),
+            size,
+            stride,
+            bytes_per_pixel,
+        })
+    }
+
+    pub fn size(&self) -> Size {
+        self.size
+    }
+
+    pub fn dimensions(&self) -> (u32, u32) {
+        (self.size.width, self.size.height)
+    }
+
+    /// Distance between the starts of two rows in bytes, at least the length of a row.
+    pub fn stride(&self) -> usize {
+        self.stride
+    }
+
+    pub fn bytes_per_pixel(&self) -> usize {
+        self.bytes_per_pixel
+    }
+
+    /// The bytes of row `y` without the padding after it, in the layout of the buffer.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `y` is outside of the view.
+    pub fn row(&self, y: u32) -> &'a [u8] {
+        assert!(
+            y < self.size.height,
+            "row {y} is outside of a view {} rows high",
+            self.size.height
+        );
+        let start = y as usize * self.stride;
+        &self.data[start..start + self.size.width as usize * self.bytes_per_pixel]
+    }
+
+    /// Every row of the view from the top, see [`Self::row`].
+    pub fn rows(&self) -> impl DoubleEndedIterator<Item = &'a [u8]> + ExactSizeIterator + 'a {
+        let view = *self;
+        (0..self.size.height).map(move |y| view.row(y))
+    }
+
+    /// The pixel at (`x`, `y`) as RGBA.
+    ///
+    /// # Panics
+    ///
+    /// Panics if (`x`, `y`) is outside of the view.
+    pub fn pixel(&self, x: u32, y: u32) -> [u8; 4] {
+        assert!(
+            x < self.size.width,
+            "column {x} is outside of a view {} pixels wide",
+            self.size.width
+        );
+        let offset = x as usize * self.bytes_per_pixel;
+        self.read_pixel(&self.row(y)[offset..offset + self.bytes_per_pixel])
+    }
+
+    /// View of `region` of this view, in its pixel coordinates.
+    ///
+    /// Returns [`Error::EmptyRegion`] for a region without area and
+    /// [`Error::RegionOutOfBounds`] if it isn't entirely inside of the view.
+    pub fn sub_view(&self, region: Region) -> Result<FrameView<'a>> {
+        let Region { position, size } = region;
+        if size.width == 0 || size.height == 0 {
+            return Err(Error::EmptyRegion);
+        }
+        let right = position.x as i64 + size.width as i64;
+        let bottom = position.y as i64 + size.height as i64;
+        if position.x < 0
+            || position.y < 0
+            || right > self.size.width as i64
+            || bottom > self.size.height as i64
+        {
+            return Err(Error::RegionOutOfBounds {
+                region,
+                bounds: self.size,
+            });
+        }
+        let start = position.y as usize * self.stride + position.x as usize * self.bytes_per_pixel;
+        let len =
+            (size.height as usize - 1) * self.stride + size.width as usize * self.bytes_per_pixel;
+        Ok(Self {
+            data: &self.data[start..start + len],
+            size,
+            ..*self
+        })
+    }
+
+    /// Convert the whole view to RGBA, dropping the row padding.
+    pub(crate) fn to_rgba(self) -> RgbaImage {
+        let mut pixels =
+            Vec::with_capacity(self.size.width as usize * self.size.height as usize * 4);
+        self.for_each_pixel(|pixel| pixels.extend_from_slice(&pixel));
+        RgbaImage::from_raw(self.size.width, self.size.height, pixels)
+            .expect("buffer matches the frame dimensions")
+    }
+
+    /// Copy the frame into `canvas` with its top left corner at (`x`, `y`),
+    /// clipped to the canvas, converting row by row straight from the raw
+    /// buffer. Like [`image::imageops::replace`], without an image in between.
+    pub(crate) fn blit(&self, canvas: &mut RgbaImage, x: i64, y: i64) {
+        let (canvas_width, canvas_height) = canvas.dimensions();
+        let (x0, y0) = (x.max(0), y.max(0));
+        let x1 = (x + self.size.width as i64).min(canvas_width as i64);
+        let y1 = (y + self.size.height as i64).min(canvas_height as i64);
+        if x0 >= x1 || y0 >= y1 {
+            return;
+        }
+        let source_start = (x0 - x) as usize * self.bytes_per_pixel;
+        let source_end = (x1 - x) as usize * self.bytes_per_pixel;
+        let canvas_stride = canvas_width as usize * 4;
+        let canvas_start = x0 as usize * 4;
+        let canvas_end = x1 as usize * 4;
+        let canvas: &mut [u8] = canvas;
+        for canvas_y in y0..y1 {
+            let source = &self.row((canvas_y - y) as u32)[source_start..source_end];
+            let canvas_row = &mut canvas[canvas_y as usize * canvas_stride..];
+            let destination = &mut canvas_row[canvas_start..canvas_end];
+            match self.layout {
+                PixelLayout::Rgba8 => destination.copy_from_slice(source),
+                PixelLayout::Raw(Format::Xbgr8888) => {
+                    destination.copy_from_slice(source);
+                    destination
+                        .iter_mut()
+                        .skip(3)
+                        .step_by(4)
+                        .for_each(|alpha| *alpha = 255);
+                }
+                _ => {
+                    for (destination, source) in destination
+                        .chunks_exact_mut(4)
+                        .zip(source.chunks_exact(self.bytes_per_pixel))
+                    {
+                        destination.copy_from_slice(&self.read_pixel(source));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Call `f` with every pixel of the view as RGBA, row by row.
+    fn for_each_pixel(&self, mut f: impl FnMut([u8; 4])) {
+        for row in self.rows() {
+            for pixel in row.chunks_exact(self.bytes_per_pixel) {
+                f(self.read_pixel(pixel));
+            }
+        }
+    }
+
+    /// Read the pixel starting at `bytes` as RGBA.
+    #[inline(always)]
+    fn read_pixel(&self, bytes: &[u8]) -> [u8; 4] {
+        match self.layout {
+            PixelLayout::Raw(Format::Xbgr8888) => [bytes[0], bytes[1], bytes[2], 255],
+            PixelLayout::Raw(Format::Abgr8888) => {
+                convert::unpremultiplied([bytes[0], bytes[1], bytes[2], bytes[3]])
+            }
+            PixelLayout::Rgba8 => [bytes[0], bytes[1], bytes[2], bytes[3]],
+            PixelLayout::Raw(Format::Xrgb8888) => [bytes[2], bytes[1], bytes[0], 255],
+            PixelLayout::Raw(Format::Argb8888) => {
+                convert::unpremultiplied([bytes[2], bytes[1], bytes[0], bytes[3]])
+            }
+            PixelLayout::Raw(Format::Xbgr2101010 | Format::Abgr2101010) => {
+                let pixel = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+                [
+                    ((pixel >> 2) & 255) as u8,
+                    ((pixel >> 12) & 255) as u8,
+                    ((pixel >> 22) & 255) as u8,
+                    255,
+                ]
+            }
+            // Bgr888 and anything else accepted by `new` is 3 bytes in RGB order.
+            PixelLayout::Raw(_) | PixelLayout::Rgb8 => [bytes[0], bytes[1], bytes[2], 255],
+        }
+    }
+
+    /// Feed every pixel to `encoder`, read as RGBA by `read`, flushing after every row.
+    #[inline(always)]
+    fn encode_rows<W: Write, const BYTES_PER_PIXEL: usize>(
+        &self,
+        encoder: &mut QoiEncoder<W>,
+        read: impl Fn(&[u8; BYTES_PER_PIXEL]) -> [u8; 4],
+    ) -> Result<()> {
+        debug_assert_eq!(BYTES_PER_PIXEL, self.bytes_per_pixel);
+        for row in self.rows() {
+            let (pixels, _) = row.as_chunks::<BYTES_PER_PIXEL>();
+            for pixel in pixels {
+                encoder.push(read(pixel));
+            }
+            encoder.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Box filter the source rows belonging to thumbnail row `y` into `out_row`.
+    fn downscale_row(&self, thumbnail_size: Size, y: u32, out_row: &mut [u8]) {
+        let (src_width, src_height) = (self.size.width as u64, self.size.height as u64);
+        let (dst_width, dst_height) = (thumbnail_size.width as u64, thumbnail_size.height as u64);
+
+        let y0 = y as u64 * src_height / dst_height;
+        let y1 = ((y as u64 + 1) * src_height / dst_height).max(y0 + 1);
+        let row_step = ((y1 - y0) / THUMBNAIL_MAX_SAMPLED_ROWS as u64).max(1) as usize;
+
+        for (x, out_pixel) in out_row.chunks_exact_mut(4).enumerate() {
+            let x0 = x as u64 * src_width / dst_width;
+            let x1 = ((x as u64 + 1) * src_width / dst_width).max(x0 + 1);
+
+            let mut sums = [0u64; 4];
+            let mut count = 0u64;
+            for src_y in (y0..y1).step_by(row_step) {
+                let row = self.row(src_y as u32);
+                for src_x in x0..x1 {
+                    let offset = src_x as usize * self.bytes_per_pixel;
+                    let pixel = self.read_pixel(&row[offset..offset + self.bytes_per_pixel]);
+                    for (sum, channel) in sums.iter_mut().zip(pixel) {
+                        *sum += channel as u64;
+                    }
+                    count += 1;
+                }
+            }
+            for (out, sum) in out_pixel.iter_mut().zip(sums) {
+                *out = (sum / count) as u8;
+            }
+        }
+    }
+}
+
+/// Downscale the raw frame so that its largest side is at most `max_dim`.
+///
+/// This box filters the stride-aware buffer directly, without converting the
+/// whole frame first, and spreads the rows over all available cores. The
+/// output transform is not applied.
+#[tracing::instrument(skip(frame), fields(frame_format = ?frame.frame_format))]
+pub(crate) fn thumbnail(frame: &FrameCopy, max_dim: u32) -> Result<RgbaImage> {
+    let raw = FrameView::from_frame(frame)?;
+    let size = raw.size;
+
+    let max_dim = max_dim.max(1);
+    let largest = size.width.max(size.height);
+    let thumbnail_size = if largest <= max_dim {
+        size
+    } else {
+        Size {
+            width: ((size.width as u64 * max_dim as u64 / largest as u64) as u32).max(1),
+            height: ((size.height as u64 * max_dim as u64 / largest as u64) as u32).max(1),
+        }
     };
+    tracing::debug!("Creating {thumbnail_size} thumbnail");
 
-    let scale = rotated_image.width() as f64 / logical_width as f64;
-    // The amount of scaling left to perform.
-    let scaling_left = max_scale / scale;
-    if scaling_left <= 1.0 {
-        tracing::debug!("No scaling left to do");
-        return rotated_image;
+    let mut image = RgbaImage::new(thumbnail_size.width, thumbnail_size.height);
+    let row_len = thumbnail_size.width as usize * 4;
+    let threads = thread::available_parallelism().map_or(1, NonZeroUsize::get);
+    let rows_per_chunk = (thumbnail_size.height as usize).div_ceil(threads);
+    thread::scope(|scope| {
+        for (chunk_index, chunk) in image.chunks_mut(rows_per_chunk * row_len).enumerate() {
+            scope.spawn(move || {
+                for (row_index, out_row) in chunk.chunks_mut(row_len).enumerate() {
+                    let y = chunk_index * rows_per_chunk + row_index;
+                    raw.downscale_row(thumbnail_size, y as u32, out_row);
+                }
+            });
+        }
+    });
+
+    Ok(image)
+}
+
+/// Write the frame as a binary PPM (P6) image without converting the whole frame first.
+pub(crate) fn write_ppm<W: Write>(frame: &FrameCopy, writer: &mut W) -> Result<()> {
+    write_ppm_view(FrameView::from_frame(frame)?, writer)
+}
+
+/// Write the pixels of `raw` as a binary PPM (P6) image.
+pub(crate) fn write_ppm_view<W: Write>(raw: FrameView, writer: &mut W) -> Result<()> {
+    let Size { width, height } = raw.size;
+    write!(writer, "P6\n{width} {height}\n255\n")?;
+
+    let mut out_row = vec![0; width as usize * 3];
+    for row in raw.rows() {
+        for (pixel, out_pixel) in row
+            .chunks_exact(raw.bytes_per_pixel)
+            .zip(out_row.chunks_exact_mut(3))
+        {
+            out_pixel.copy_from_slice(&raw.read_pixel(pixel)[..3]);
+        }
+        writer.write_all(&out_row)?;
     }
+    Ok(())
+}
 
-    tracing::debug!("Scaling left to do: {scaling_left}");
-    let new_width = (rotated_image.width() as f64 * scaling_left).round() as u32;
-    let new_height = (rotated_image.height() as f64 * scaling_left).round() as u32;
-    tracing::debug!("Resizing image to {new_width}x{new_height}");
-    image::imageops::resize(
-        &rotated_image,
-        new_width,
-        new_height,
-        image::imageops::FilterType::Gaussian,
-    )
-    .into()
+/// Write the frame as a QOI image without converting the whole frame first.
+///
+/// Frames in a format with alpha are written with 4 channels, others with 3.
+pub(crate) fn write_qoi<W: Write>(frame: &FrameCopy, writer: &mut W) -> Result<()> {
+    write_qoi_view(FrameView::from_frame(frame)?, writer)
+}
+
+/// Write the pixels of `raw` as a QOI image, with 4 channels if it has alpha.
+pub(crate) fn write_qoi_view<W: Write>(raw: FrameView, writer: &mut W) -> Result<()> {
+    let channels = match raw.layout {
+        PixelLayout::Raw(Format::Argb8888 | Format::Abgr8888) | PixelLayout::Rgba8 => 4,
+        _ => 3,
+    };
+    let mut encoder = QoiEncoder::new(writer, raw.size, channels)?;
+    // Pick the pixel layout once instead of for every pixel.
+    match raw.layout {
+        PixelLayout::Raw(Format::Xrgb8888) => {
+            raw.encode_rows(&mut encoder, |&[b, g, r, _]| [r, g, b, 255])?
+        }
+        PixelLayout::Raw(Format::Argb8888) => raw.encode_rows(&mut encoder, |&[b, g, r, a]| {
+            convert::unpremultiplied([r, g, b, a])
+        })?,
+        PixelLayout::Raw(Format::Xbgr8888) => {
+            raw.encode_rows(&mut encoder, |&[r, g, b, _]| [r, g, b, 255])?
+        }
+        PixelLayout::Raw(Format::Abgr8888) => {
+            raw.encode_rows(&mut encoder, |&pixel| convert::unpremultiplied(pixel))?
+        }
+        PixelLayout::Rgba8 => raw.encode_rows(&mut encoder, |&pixel| pixel)?,
+        PixelLayout::Raw(Format::Bgr888) | PixelLayout::Rgb8 => {
+            raw.encode_rows(&mut encoder, |&[r, g, b]| [r, g, b, 255])?
+        }
+        _ => raw.encode_rows(&mut encoder, |pixel: &[u8; 4]| raw.read_pixel(pixel))?,
+    }
+    encoder.finish()
+}
+
+/// Decode an sRGB encoded image into linear light, leaving alpha linear as it already is.
+pub fn srgb_to_linear(image: &RgbaImage) -> Rgba32FImage {
+    let lut: [f32; 256] = std::array::from_fn(|value| srgb_component_to_linear(value as u8));
+    let (width, height) = image.dimensions();
+    let pixels = image
+        .pixels()
+        .flat_map(|Rgba([r, g, b, a])| {
+            [
+                lut[*r as usize],
+                lut[*g as usize],
+                lut[*b as usize],
+                *a as f32 / 255.0,
+            ]
+        })
+        .collect();
+    Rgba32FImage::from_raw(width, height, pixels).expect("buffer matches the image dimensions")
+}
+
+/// The inverse sRGB transfer function (IEC 61966-2-1).
+fn srgb_component_to_linear(value: u8) -> f32 {
+    let value = value as f64 / 255.0;
+    let linear = if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    };
+    linear as f32
+}
+
+/// Number of buckets per channel of the colour histogram used for [`FrameStats::dominant_color`].
+const STATS_COLOR_BITS: u32 = 4;
+const STATS_COLOR_BUCKETS: usize = 1 << (3 * STATS_COLOR_BITS);
+
+/// Brightness and colour summary of a captured frame.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FrameStats {
+    /// Mean Rec. 709 luminance, from 0 to 255.
+    pub mean_luminance: f64,
+    /// Average colour of the most common bucket of a histogram with
+    /// 4 bits per channel.
+    pub dominant_color: [u8; 3],
+    luminance_histogram: [u64; 256],
+    pixel_count: u64,
+}
+
+impl FrameStats {
+    /// Luminance below which `percentile` percent of the pixels lie, from 0 to 255.
+    pub fn luminance_percentile(&self, percentile: f64) -> u8 {
+        let target = ((percentile.clamp(0.0, 100.0) / 100.0) * self.pixel_count as f64).ceil();
+        let target = (target as u64).max(1);
+        let mut seen = 0;
+        for (luminance, count) in self.luminance_histogram.iter().enumerate() {
+            seen += count;
+            if seen >= target {
+                return luminance as u8;
+            }
+        }
+        u8::MAX
+    }
+
+    /// Median luminance, from 0 to 255.
+    pub fn median_luminance(&self) -> u8 {
+        self.luminance_percentile(50.0)
+    }
+}
+
+/// Per thread accumulator of [`stats`].
+///
+/// Screen content is mostly runs of identical pixels, so those are counted
+/// before touching the histograms, which also avoids stalling on repeated
+/// increments of the same bucket.
+struct StatsAccumulator {
+    luminance_histogram: [u64; 256],
+    /// Pixel count followed by the red, green and blue sums of each colour bucket.
+    color_buckets: Box<[[u64; 4]; STATS_COLOR_BUCKETS]>,
+    run_pixel: [u8; 4],
+    run_length: u64,
+}
+
+impl StatsAccumulator {
+    fn new() -> Self {
+        Self {
+            luminance_histogram: [0; 256],
+            color_buckets: Box::new([[0; 4]; STATS_COLOR_BUCKETS]),
+            run_pixel: [0; 4],
+            run_length: 0,
+        }
+    }
+
+    #[inline(always)]
+    fn add(&mut self, pixel: [u8; 4]) {
+        if pixel != self.run_pixel {
+            self.flush_run();
+            self.run_pixel = pixel;
+        }
+        self.run_length += 1;
+    }
+
+    fn flush_run(&mut self) {
+        let [r, g, b, _] = self.run_pixel;
+        let count = mem::take(&mut self.run_length);
+
+        // Integer Rec. 709 weights that sum up to 256, so this never exceeds 255.
+        let luminance = (54 * r as u32 + 183 * g as u32 + 19 * b as u32) >> 8;
+        self.luminance_histogram[luminance as u8 as usize] += count;
+
+        let shift = 8 - STATS_COLOR_BITS;
+        let bucket = ((r as usize >> shift) << (2 * STATS_COLOR_BITS))
+            | ((g as usize >> shift) << STATS_COLOR_BITS)
+            | (b as usize >> shift);
+        let bucket = &mut self.color_buckets[bucket % STATS_COLOR_BUCKETS];
+        bucket[0] += count;
+        bucket[1] += r as u64 * count;
+        bucket[2] += g as u64 * count;
+        bucket[3] += b as u64 * count;
+    }
+
+    fn merge(&mut self, other: &Self) {
+        for (total, count) in self
+            .luminance_histogram
+            .iter_mut()
+            .zip(other.luminance_histogram)
+        {
+            *total += count;
+        }
+        for (total, bucket) in self
+            .color_buckets
+            .iter_mut()
+            .zip(other.color_buckets.iter())
+        {
+            for (total, value) in total.iter_mut().zip(bucket) {
+                *total += value;
+            }
+        }
+    }
+}
+
+/// Compute [`FrameStats`] straight from the raw frame buffer, reducing rows in parallel.
+pub(crate) fn stats(frame: &FrameCopy) -> Result<FrameStats> {
+    let raw = FrameView::from_frame(frame)?;
+    let Size { width, height } = raw.size;
+
+    let threads = thread::available_parallelism().map_or(1, NonZeroUsize::get);
+    let rows_per_chunk = height.div_ceil(threads as u32);
+    let accumulator = thread::scope(|scope| {
+        (0..height)
+            .step_by(rows_per_chunk as usize)
+            .map(|y| {
+                let chunk = raw
+                    .sub_view(Region {
+                        position: Position { x: 0, y: y as i32 },
+                        size: Size {
+                            width,
+                            height: rows_per_chunk.min(height - y),
+                        },
+                    })
+                    .expect("rows are inside of the frame");
+                scope.spawn(move || {
+                    let mut accumulator = StatsAccumulator::new();
+                    chunk.for_each_pixel(|pixel| accumulator.add(pixel));
+                    accumulator.flush_run();
+                    accumulator
+                })
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+            .map(|handle| handle.join().expect("stats thread panicked"))
+            .fold(StatsAccumulator::new(), |mut total, accumulator| {
+                total.merge(&accumulator);
+                total
+            })
+    });
+
+    let pixel_count = width as u64 * height as u64;
+    let luminance_sum: u64 = (0..)
+        .zip(accumulator.luminance_histogram)
+        .map(|(luminance, count)| luminance * count)
+        .sum();
+    let [count, r, g, b] = accumulator
+        .color_buckets
+        .iter()
+        .max_by_key(|bucket| bucket[0])
+        .copied()
+        .unwrap_or_default();
+    Ok(FrameStats {
+        mean_luminance: luminance_sum as f64 / pixel_count as f64,
+        dominant_color: [r, g, b].map(|sum| (sum / count.max(1)) as u8),
+        luminance_histogram: accumulator.luminance_histogram,
+        pixel_count,
+    })
+}
+
+/// Largest side of the thumbnail [`average_color`] and [`dominant_colors`]
+/// sample, enough for a colour summary while skipping most of the frame.
+const COLOR_SAMPLE_MAX_DIM: u32 = 64;
+
+/// Average colour of `frame`, computed from a small thumbnail of it.
+pub(crate) fn average_color(frame: &FrameCopy) -> Result<Rgba<u8>> {
+    let sample = thumbnail(frame, COLOR_SAMPLE_MAX_DIM)?;
+    let mut sums = [0u64; 4];
+    for pixel in sample.pixels() {
+        for (sum, channel) in sums.iter_mut().zip(pixel.0) {
+            *sum += channel as u64;
+        }
+    }
+    let pixel_count = (sample.width() as u64 * sample.height() as u64).max(1);
+    Ok(Rgba(sums.map(|sum| (sum / pixel_count) as u8)))
+}
+
+/// Up to `n` most common colours of `frame`, most common first, computed from
+/// a small thumbnail of it. Colours are the averages of the buckets of a
+/// histogram with 4 bits per channel, like [`FrameStats::dominant_color`].
+pub(crate) fn dominant_colors(frame: &FrameCopy, n: usize) -> Result<Vec<Rgba<u8>>> {
+    let sample = thumbnail(frame, COLOR_SAMPLE_MAX_DIM)?;
+    let mut accumulator = StatsAccumulator::new();
+    for pixel in sample.pixels() {
+        accumulator.add(pixel.0);
+    }
+    accumulator.flush_run();
+
+    let mut buckets: Vec<[u64; 4]> = accumulator
+        .color_buckets
+        .iter()
+        .filter(|bucket| bucket[0] > 0)
+        .copied()
+        .collect();
+    buckets.sort_by_key(|bucket| std::cmp::Reverse(bucket[0]));
+    Ok(buckets
+        .into_iter()
+        .take(n)
+        .map(|[count, r, g, b]| {
+            let [r, g, b] = [r, g, b].map(|sum| (sum / count) as u8);
+            Rgba([r, g, b, 255])
+        })
+        .collect())
+}
+
+/// Whether every pixel of `frame` is within `tolerance` of the first one in
+/// every channel, stopping at the first one that isn't.
+pub(crate) fn is_uniform(frame: &FrameCopy, tolerance: u8) -> Result<bool> {
+    let raw = FrameView::from_frame(frame)?;
+    let first = raw.pixel(0, 0);
+    Ok(raw.rows().all(|row| {
+        row.chunks_exact(raw.bytes_per_pixel).all(|pixel| {
+            raw.read_pixel(pixel)
+                .into_iter()
+                .zip(first)
+                .all(|(channel, first)| channel.abs_diff(first) <= tolerance)
+        })
+    }))
+}
+
+/// Fewest rows two scrolled frames must share to be stitched at that overlap,
+/// so a few matching rows of plain background don't count as a match.
+const MIN_SCROLL_OVERLAP_ROWS: usize = 8;
+/// At most one in this many rows of an overlap may differ, e.g. because of a
+/// blinking caret.
+const SCROLL_OVERLAP_MISMATCH_RATIO: usize = 16;
+
+fn row_hashes(image: &RgbaImage) -> Vec<u64> {
+    image
+        .rows()
+        .map(|row| {
+            let mut hasher = DefaultHasher::new();
+            row.for_each(|pixel| pixel.0.hash(&mut hasher));
+            hasher.finish()
+        })
+        .collect()
+}
+
+/// Number of rows at the top of `next` that repeat the bottom of `previous`,
+/// given the hashes of their rows, preferring the largest overlap.
+fn vertical_overlap(previous: &[u64], next: &[u64]) -> Option<usize> {
+    (MIN_SCROLL_OVERLAP_ROWS..=previous.len().min(next.len()))
+        .rev()
+        .find(|&overlap| {
+            let mismatches = previous[previous.len() - overlap..]
+                .iter()
+                .zip(&next[..overlap])
+                .filter(|(previous, next)| previous != next)
+                .count();
+            mismatches * SCROLL_OVERLAP_MISMATCH_RATIO <= overlap
+        })
+}
+
+/// Stitch frames of a region captured while scrolling down into one tall image.
+///
+/// Every frame is matched against the previous one and only the rows that
+/// scrolled into view are appended. Frames without any overlap with the
+/// previous one are appended whole, frames that didn't scroll add nothing.
+pub fn stitch_scrolled(frames: &[RgbaImage]) -> Result<RgbaImage> {
+    let Some(first) = frames.first() else {
+        return Err(Error::NoOutputs);
+    };
+    let width = first.width();
+    if let Some(frame) = frames.iter().find(|frame| frame.width() != width) {
+        return Err(Error::Unsupported(format!(
+            "stitching frames of different widths, {width} and {}",
+            frame.width()
+        )));
+    }
+
+    let row_len = width as usize * 4;
+    let mut stitched = first.as_raw().clone();
+    let mut previous_hashes = row_hashes(first);
+    for frame in &frames[1..] {
+        let hashes = row_hashes(frame);
+        let overlap = vertical_overlap(&previous_hashes, &hashes).unwrap_or_else(|| {
+            tracing::debug!("No overlap with the previous frame found, appending it whole");
+            0
+        });
+        stitched.extend_from_slice(&frame.as_raw()[overlap * row_len..]);
+        previous_hashes = hashes;
+    }
+
+    let height = (stitched.len() / row_len) as u32;
+    Ok(RgbaImage::from_raw(width, height, stitched).expect("whole rows were appended"))
+}
+
+/// A vertical slice of a wider image.
+#[derive(Debug, Clone)]
+pub struct Tile {
+    /// Column of the source image the tile starts at.
+    pub x_offset: u32,
+    pub image: DynamicImage,
+}
+
+/// Split `image` into tiles of `tile_width` pixels that share `overlap` columns
+/// with their neighbours, e.g. for wallpaper engines spanning several monitors.
+///
+/// Tiles start every `tile_width - overlap` columns. All tiles are exactly
+/// `tile_width` wide, so the last tile is moved left to end at the right edge
+/// of the image and overlaps its left neighbour by the remaining columns
+/// instead. An image narrower than `tile_width` becomes a single tile.
+pub fn split_tiles(image: &DynamicImage, tile_width: u32, overlap: u32) -> Result<Vec<Tile>> {
+    if tile_width == 0 || overlap >= tile_width {
+        return Err(Error::InvalidTileLayout(format!(
+            "overlap {overlap} must be smaller than the tile width {tile_width}"
+        )));
+    }
+    let width = image.width();
+    if width <= tile_width {
+        return Ok(vec![Tile {
+            x_offset: 0,
+            image: image.clone(),
+        }]);
+    }
+
+    let step = tile_width - overlap;
+    let last_offset = width - tile_width;
+    let mut offsets: Vec<u32> = (0..last_offset).step_by(step as usize).collect();
+    offsets.push(last_offset);
+    Ok(offsets
+        .into_iter()
+        .map(|x_offset| Tile {
+            x_offset,
+            image: image.crop_imm(x_offset, 0, tile_width, image.height()),
+        })
+        .collect())
+}
+
+/// How [`OutputResolution`] fits an image of a different aspect ratio.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ResizeFit {
+    /// Scale the image to fit and fill the rest with transparent bars, which
+    /// are black in encodings without alpha.
+    #[default]
+    Letterbox,
+    /// Scale each side to the target, distorting the image.
+    Stretch,
+}
+
+impl fmt::Display for ResizeFit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Letterbox => "letterbox",
+            Self::Stretch => "stretch",
+        })
+    }
+}
+
+impl FromStr for ResizeFit {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "letterbox" => Ok(Self::Letterbox),
+            "stretch" => Ok(Self::Stretch),
+            _ => Err(format!(
+                "unknown resize fit '{s}', expected letterbox or stretch"
+            )),
+        }
+    }
+}
+
+/// A fixed size output captures are resized to, so captures of outputs in
+/// different modes are comparable, e.g. visual regression baselines.
+///
+/// Unlike a thumbnail this is an exact target: smaller captures are upscaled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutputResolution {
+    size: Size,
+    fit: ResizeFit,
+}
+
+impl OutputResolution {
+    /// Resize to `width` by `height` pixels, letterboxing by default.
+    pub fn new(width: u32, height: u32) -> Result<Self> {
+        if width == 0 || height == 0 {
+            return Err(Error::Unsupported(format!(
+                "output resolution {width}x{height}"
+            )));
+        }
+        Ok(Self {
+            size: Size { width, height },
+            fit: ResizeFit::default(),
+        })
+    }
+
+    pub fn fit(mut self, fit: ResizeFit) -> Self {
+        self.fit = fit;
+        self
+    }
+
+    pub fn size(&self) -> Size {
+        self.size
+    }
+
+    pub fn get_fit(&self) -> ResizeFit {
+        self.fit
+    }
+
+    /// Resize `image` to this resolution. Images that already have it are returned as they are.
+    pub fn apply(&self, image: DynamicImage) -> DynamicImage {
+        let Size { width, height } = self.size;
+        if image.dimensions() == (width, height) {
+            return image;
+        }
+        let filter = image::imageops::FilterType::Triangle;
+        match self.fit {
+            ResizeFit::Stretch => image.resize_exact(width, height, filter),
+            ResizeFit::Letterbox => {
+                let ratio = (width as f64 / image.width() as f64)
+                    .min(height as f64 / image.height() as f64);
+                let scaled = image
+                    .resize_exact(
+                        ((image.width() as f64 * ratio).round() as u32).clamp(1, width),
+                        ((image.height() as f64 * ratio).round() as u32).clamp(1, height),
+                        filter,
+                    )
+                    .into_rgba8();
+                let mut canvas = RgbaImage::new(width, height);
+                image::imageops::replace(
+                    &mut canvas,
+                    &scaled,
+                    ((width - scaled.width()) / 2) as i64,
+                    ((height - scaled.height()) / 2) as i64,
+                );
+                canvas.into()
+            }
+        }
+    }
+}
+
+/// Make `image` transparent outside the circle around `center` with `radius`,
+/// both in pixels, anti-aliasing the edge.
+pub fn apply_circle_mask(image: &mut RgbaImage, center: (f64, f64), radius: f64) {
+    apply_mask(image, |x, y| (x - center.0).hypot(y - center.1) - radius);
+}
+
+/// Make the corners of `image` outside a rounded rectangle with corner `radius`
+/// transparent, anti-aliasing the edge. The radius is limited to half the
+/// shorter side, where the shape becomes a pill or circle.
+pub fn apply_rounded_rect_mask(image: &mut RgbaImage, radius: f64) {
+    let (half_width, half_height) = (image.width() as f64 / 2.0, image.height() as f64 / 2.0);
+    let radius = radius.clamp(0.0, half_width.min(half_height));
+    apply_mask(image, |x, y| {
+        // Signed distance to a rounded box centered on the image.
+        let qx = (x - half_width).abs() - (half_width - radius);
+        let qy = (y - half_height).abs() - (half_height - radius);
+        qx.max(0.0).hypot(qy.max(0.0)) + qx.max(qy).min(0.0) - radius
+    });
+}
+
+/// Draw the outline of `rect` onto `image` in `color`, `thickness` pixels wide
+/// on the inside of `rect`. Parts outside of the image are left out.
+pub(crate) fn draw_rect_outline(
+    image: &mut RgbaImage,
+    rect: Region,
+    color: Rgba<u8>,
+    thickness: u32,
+) {
+    let (x0, y0) = (rect.position.x as i64, rect.position.y as i64);
+    let (x1, y1) = (x0 + rect.size.width as i64, y0 + rect.size.height as i64);
+    let thickness = thickness as i64;
+    let (width, height) = (image.width() as i64, image.height() as i64);
+    // The top, bottom, left and right bands, overlapping in the corners.
+    for (left, top, right, bottom) in [
+        (x0, y0, x1, (y0 + thickness).min(y1)),
+        (x0, (y1 - thickness).max(y0), x1, y1),
+        (x0, y0, (x0 + thickness).min(x1), y1),
+        ((x1 - thickness).max(x0), y0, x1, y1),
+    ] {
+        for y in top.clamp(0, height)..bottom.clamp(0, height) {
+            for x in left.clamp(0, width)..right.clamp(0, width) {
+                image.put_pixel(x as u32, y as u32, color);
+            }
+        }
+    }
+}
+
+/// Scale the alpha of every pixel by how much of it lies inside a shape, given
+/// the signed distance from a point to the shape's edge (negative inside).
+fn apply_mask(image: &mut RgbaImage, distance: impl Fn(f64, f64) -> f64) {
+    for (x, y, pixel) in image.enumerate_pixels_mut() {
+        // Distance from the pixel center, a one pixel wide ramp approximates coverage.
+        let coverage = (0.5 - distance(x as f64 + 0.5, y as f64 + 0.5)).clamp(0.0, 1.0);
+        if coverage < 1.0 {
+            pixel[3] = (pixel[3] as f64 * coverage).round() as u8;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        region::LogicalRegion,
+        screencopy::{ColorHandling, FrameData, FrameFormat},
+    };
+    use image::{ColorType, GenericImageView, imageops::FilterType};
+    use memmap2::MmapMut;
+
+    /// Create an Xrgb8888 frame with a smooth gradient and `padding` unused bytes per row.
+    fn gradient_frame(width: u32, height: u32, padding: u32) -> FrameCopy {
+        let stride = width * 4 + padding;
+        let mut mmap = MmapMut::map_anon((stride * height) as usize).expect("anonymous mmap");
+        for y in 0..height {
+            for x in 0..width {
+                let offset = (y * stride + x * 4) as usize;
+                mmap[offset] = (x * 255 / width) as u8; // B
+                mmap[offset + 1] = (y * 255 / height) as u8; // G
+                mmap[offset + 2] = ((x + y) * 255 / (width + height)) as u8; // R
+                mmap[offset + 3] = 0;
+            }
+            let padding_start = (y * stride + width * 4) as usize;
+            mmap[padding_start..(y + 1) as usize * stride as usize].fill(0xff);
+        }
+        let size = Size { width, height };
+        FrameCopy {
+            frame_format: FrameFormat {
+                format: Format::Xrgb8888,
+                size,
+                stride,
+            },
+            frame_color_type: ColorType::Rgb8,
+            frame_data: FrameData::Mmap(mmap),
+            transform: Transform::Normal,
+            logical_region: LogicalRegion::default(),
+            physical_size: size,
+            color_handling: ColorHandling::Passthrough,
+            converted: false,
+            capture_id: Default::default(),
+        }
+    }
+
+    /// Mean absolute difference of the RGB channels.
+    fn mean_abs_diff(a: &RgbaImage, b: &RgbaImage) -> f64 {
+        assert_eq!(a.dimensions(), b.dimensions());
+        let total: u64 = a
+            .pixels()
+            .zip(b.pixels())
+            .flat_map(|(a, b)| (0..3).map(move |channel| a.0[channel].abs_diff(b.0[channel])))
+            .map(u64::from)
+            .sum();
+        total as f64 / (a.width() * a.height() * 3) as f64
+    }
+
+    const BLIT_OFFSETS: [(i64, i64); 5] = [(0, 0), (5, 3), (-4, -2), (20, 10), (60, 0)];
+
+    /// `image` pasted onto a transparent 40x24 canvas at `(x, y)`.
+    fn replaced(image: &RgbaImage, x: i64, y: i64) -> RgbaImage {
+        let mut canvas = RgbaImage::new(40, 24);
+        image::imageops::replace(&mut canvas, image, x, y);
+        canvas
+    }
+
+    #[test]
+    fn raw_blit_matches_converted_frame() {
+        for format in [
+            Format::Argb8888,
+            Format::Abgr8888,
+            Format::Xrgb8888,
+            Format::Xbgr8888,
+        ] {
+            let mut frame = gradient_frame(37, 21, 0);
+            frame.frame_format.format = format;
+            let FrameData::Mmap(mmap) = &mut frame.frame_data else {
+                unreachable!()
+            };
+            // Alpha is kept, the padding byte of the X formats is made opaque.
+            for (index, pixel) in mmap.chunks_exact_mut(4).enumerate() {
+                pixel[3] = (index * 7) as u8;
+            }
+
+            let blitted = BLIT_OFFSETS.map(|(x, y)| {
+                let mut canvas = RgbaImage::new(40, 24);
+                FrameView::from_frame(&frame)
+                    .expect("raw frame")
+                    .blit(&mut canvas, x, y);
+                canvas
+            });
+            let converted = frame.get_image().expect("image").into_rgba8();
+
+            for ((x, y), blitted) in BLIT_OFFSETS.into_iter().zip(blitted) {
+                assert_eq!(
+                    blitted,
+                    replaced(&converted, x, y),
+                    "{format:?} at ({x}, {y})"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn raw_blit_skips_row_padding() {
+        for format in [Format::Xrgb8888, Format::Xbgr2101010, Format::Bgr888] {
+            let mut frame = gradient_frame(37, 21, 12);
+            frame.frame_format.format = format;
+            let raw = FrameView::from_frame(&frame).expect("raw frame");
+            let converted = raw.to_rgba();
+
+            for (x, y) in BLIT_OFFSETS {
+                let mut canvas = RgbaImage::new(40, 24);
+                raw.blit(&mut canvas, x, y);
+                assert_eq!(
+                    canvas,
+                    replaced(&converted, x, y),
+                    "{format:?} at ({x}, {y})"
+                );
+            }
+        }
+    }
+
+    /// A 6x100 page where every row has its own colour, and a 40 rows high view of it.
+    fn scroll_page() -> (RgbaImage, impl Fn(&RgbaImage, u32) -> RgbaImage) {
+        let page = RgbaImage::from_fn(6, 100, |x, y| Rgba([y as u8, (y * 7) as u8, x as u8, 255]));
+        (page, |page: &RgbaImage, offset| {
+            page.view(0, offset, 6, 40).to_image()
+        })
+    }
+
+    #[test]
+    fn stitch_scrolled_appends_only_new_rows() {
+        let (page, view) = scroll_page();
+
+        let stitched = stitch_scrolled(&[
+            view(&page, 0),
+            view(&page, 30),
+            view(&page, 30),
+            view(&page, 60),
+        ])
+        .expect("stitch");
+
+        assert_eq!(stitched, page);
+    }
+
+    #[test]
+    fn stitch_scrolled_tolerates_a_changed_row() {
+        let (page, view) = scroll_page();
+        let mut next = view(&page, 10);
+        // A caret that blinked between the captures.
+        next.put_pixel(3, 5, Rgba([0, 0, 0, 255]));
+
+        let stitched = stitch_scrolled(&[view(&page, 0), next]).expect("stitch");
+
+        assert_eq!(stitched, page.view(0, 0, 6, 50).to_image());
+    }
+
+    #[test]
+    fn stitch_scrolled_abuts_frames_without_overlap() {
+        let (page, view) = scroll_page();
+        let first = view(&page, 0);
+        let unrelated = RgbaImage::from_fn(6, 40, |x, y| Rgba([200, x as u8, y as u8, 255]));
+
+        let stitched = stitch_scrolled(&[first.clone(), unrelated.clone()]).expect("stitch");
+
+        assert_eq!(stitched.dimensions(), (6, 80));
+        assert_eq!(stitched.view(0, 40, 6, 40).to_image(), unrelated);
+        assert!(stitch_scrolled(&[first, RgbaImage::new(5, 40)]).is_err());
+        assert!(stitch_scrolled(&[]).is_err());
+    }
+
+    #[test]
+    fn output_resolution_letterboxes_by_default() {
+        let image = DynamicImage::ImageRgba8(RgbaImage::from_pixel(40, 10, Rgba([9, 9, 9, 255])));
+
+        let resized = OutputResolution::new(20, 20)
+            .unwrap()
+            .apply(image)
+            .into_rgba8();
+
+        assert_eq!(resized.dimensions(), (20, 20));
+        // Scaled to 20x5 and centered vertically.
+        assert_eq!(resized.get_pixel(10, 1).0, [0, 0, 0, 0]);
+        assert_eq!(resized.get_pixel(10, 10).0, [9, 9, 9, 255]);
+        assert_eq!(resized.get_pixel(10, 18).0, [0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn output_resolution_stretches_and_upscales() {
+        let image = DynamicImage::ImageRgba8(RgbaImage::from_pixel(4, 3, Rgba([9, 9, 9, 255])));
+
+        let resized = OutputResolution::new(16, 4)
+            .unwrap()
+            .fit(ResizeFit::Stretch)
+            .apply(image)
+            .into_rgba8();
+
+        assert_eq!(resized.dimensions(), (16, 4));
+        assert!(resized.pixels().all(|pixel| pixel.0 == [9, 9, 9, 255]));
+        assert!(OutputResolution::new(0, 4).is_err());
+        assert_eq!("stretch".parse(), Ok(ResizeFit::Stretch));
+    }
+
+    #[test]
+    fn thumbnail_fits_inside_max_dim_and_keeps_aspect_ratio() {
+        let frame = gradient_frame(640, 360, 0);
+
+        let thumbnail = thumbnail(&frame, 64).expect("thumbnail");
+
+        assert_eq!(thumbnail.dimensions(), (64, 36));
+    }
+
+    #[test]
+    fn thumbnail_does_not_upscale() {
+        let frame = gradient_frame(32, 16, 0);
+
+        let thumbnail = thumbnail(&frame, 256).expect("thumbnail");
+
+        assert_eq!(thumbnail.dimensions(), (32, 16));
+    }
+
+    #[test]
+    fn thumbnail_matches_imageops_within_tolerance() {
+        let mut frame = gradient_frame(1280, 720, 0);
+        let fast = thumbnail(&frame, 128).expect("thumbnail");
+
+        let full = frame.get_image().expect("image").to_rgba8();
+        let reference =
+            image::imageops::resize(&full, fast.width(), fast.height(), FilterType::Triangle);
+
+        assert!(mean_abs_diff(&fast, &reference) < 2.0);
+        assert!(fast.pixels().all(|pixel| pixel.0[3] == 255));
+    }
+
+    #[test]
+    fn view_rows_skip_padding_down_to_the_last_row() {
+        let frame = gradient_frame(5, 4, 8);
+        let view = frame.view().expect("view");
+        let converted = view.to_rgba();
+
+        assert_eq!(view.rows().len(), 4);
+        let last = view.rows().next_back().expect("last row");
+        assert_eq!(last, view.row(3));
+        assert_eq!(last.len(), 5 * 4);
+        assert_eq!(view.pixel(4, 3), converted.get_pixel(4, 3).0);
+        assert_eq!(view.pixel(0, 0), converted.get_pixel(0, 0).0);
+    }
+
+    #[test]
+    #[should_panic(expected = "row 4 is outside")]
+    fn view_row_is_bounds_checked() {
+        let frame = gradient_frame(5, 4, 8);
+        frame.view().expect("view").row(4);
+    }
+
+    #[test]
+    fn sub_view_touches_the_last_byte_without_padding() {
+        let frame = gradient_frame(5, 4, 8);
+        let FrameData::Mmap(mmap) = &frame.frame_data else {
+            unreachable!()
+        };
+        // A buffer that ends right after the last pixel, without its padding.
+        let data = &mmap[..3 * (5 * 4 + 8) + 5 * 4];
+        let view = FrameView::new(data, frame.frame_format).expect("view");
+        let corner = view
+            .sub_view(Region {
+                position: Position { x: 3, y: 2 },
+                size: Size {
+                    width: 2,
+                    height: 2,
+                },
+            })
+            .expect("bottom right corner");
+
+        assert_eq!(corner.dimensions(), (2, 2));
+        assert_eq!(corner.pixel(1, 1), view.pixel(4, 3));
+        assert_eq!(corner.pixel(0, 0), view.pixel(3, 2));
+        assert_eq!(
+            corner.row(1).as_ptr_range().end,
+            data.as_ptr_range().end,
+            "the last row ends at the last byte"
+        );
+        assert_eq!(corner.rows().count(), 2);
+
+        assert!(matches!(
+            view.sub_view(Region {
+                position: Position { x: 4, y: 3 },
+                size: Size {
+                    width: 2,
+                    height: 1,
+                },
+            }),
+            Err(Error::RegionOutOfBounds { .. })
+        ));
+        assert!(matches!(
+            view.sub_view(Region {
+                position: Position { x: -1, y: 0 },
+                size: Size {
+                    width: 1,
+                    height: 1,
+                },
+            }),
+            Err(Error::RegionOutOfBounds { .. })
+        ));
+        assert!(matches!(
+            view.sub_view(Region::default()),
+            Err(Error::EmptyRegion)
+        ));
+        assert!(matches!(
+            FrameView::new(&data[..data.len() - 1], frame.frame_format),
+            Err(Error::BufferTooSmall)
+        ));
+    }
+
+    #[test]
+    fn view_reads_converted_frames_as_their_image() {
+        // `get_image` does not skip stride padding.
+        let mut frame = gradient_frame(6, 3, 0);
+        let before = frame.view().expect("view").to_rgba();
+        let image = frame.get_image().expect("image").into_rgba8();
+
+        assert!(frame.converted);
+        let view = frame.view().expect("view");
+        for (x, y, pixel) in image.enumerate_pixels() {
+            assert_eq!(view.pixel(x, y), pixel.0, "({x}, {y})");
+        }
+        // The X byte becomes alpha once converted, the colours stay the same.
+        assert_eq!(view.pixel(2, 1)[..3], before.get_pixel(2, 1).0[..3]);
+        // Converting twice doesn't swap the channels back.
+        assert_eq!(frame.get_image().expect("image").into_rgba8(), image);
+    }
+
+    #[test]
+    fn thumbnail_ignores_stride_padding() {
+        let padded = thumbnail(&gradient_frame(300, 200, 48), 100).expect("thumbnail");
+        let unpadded = thumbnail(&gradient_frame(300, 200, 0), 100).expect("thumbnail");
+
+        assert_eq!(padded, unpadded);
+    }
+
+    #[test]
+    fn write_ppm_matches_converted_image() {
+        let mut frame = gradient_frame(37, 21, 12);
+        let mut ppm = Vec::new();
+        write_ppm(&frame, &mut ppm).expect("write ppm");
+
+        let decoded = image::load_from_memory(&ppm).expect("decode ppm").to_rgb8();
+        let reference = frame.get_image().expect("image").to_rgb8();
+
+        assert!(ppm.starts_with(b"P6\n37 21\n255\n"));
+        assert_eq!(decoded.dimensions(), (37, 21));
+        // `get_image` does not skip stride padding, so only compare the first row.
+        assert_eq!(
+            decoded.rows().next().unwrap().collect::<Vec<_>>(),
+            reference.rows().next().unwrap().collect::<Vec<_>>()
+        );
+    }
+
+    /// Encode `image` with the image crate's QOI encoder.
+    fn reference_qoi(image: DynamicImage) -> Vec<u8> {
+        let mut qoi = std::io::Cursor::new(Vec::new());
+        image
+            .write_to(&mut qoi, image::ImageFormat::Qoi)
+            .expect("encode qoi");
+        qoi.into_inner()
+    }
+
+    #[test]
+    fn write_qoi_matches_image_crate_encoder() {
+        // Flat areas, pairs, small steps and large jumps exercise every opcode.
+        let frame = frame_from_fn(70, 40, 8, |x, y| match (x / 10, y % 3) {
+            (0, _) => [10, 20, 30],
+            (3, 1) => [(x / 2 * 50) as u8, 0, 0],
+            (1, _) => [(x * 3) as u8, (y * 5) as u8, 40],
+            (2, 0) => [(x * 37) as u8, (y * 91) as u8, (x * y) as u8],
+            _ => [
+                (x * 255 / 70) as u8,
+                (y * 255 / 40) as u8,
+                ((x + y) * 2) as u8,
+            ],
+        });
+        let mut qoi = Vec::new();
+        write_qoi(&frame, &mut qoi).expect("write qoi");
+
+        let raw = FrameView::from_frame(&frame).expect("raw frame").to_rgba();
+        assert_eq!(
+            qoi,
+            reference_qoi(DynamicImage::from(raw).into_rgb8().into())
+        );
+    }
+
+    #[test]
+    fn write_qoi_keeps_alpha_of_formats_with_alpha() {
+        let mut frame = gradient_frame(33, 17, 4);
+        frame.frame_format.format = Format::Argb8888;
+        let FrameData::Mmap(mmap) = &mut frame.frame_data else {
+            unreachable!()
+        };
+        for (index, pixel) in mmap.chunks_exact_mut(4).enumerate() {
+            pixel[3] = (index * 7) as u8;
+        }
+        let mut qoi = Vec::new();
+        write_qoi(&frame, &mut qoi).expect("write qoi");
+
+        let raw = FrameView::from_frame(&frame).expect("raw frame").to_rgba();
+        assert_eq!(qoi, reference_qoi(raw.clone().into()));
+        let decoded = image::load_from_memory(&qoi).expect("decode qoi");
+        assert_eq!(decoded.to_rgba8(), raw);
+    }
+
+    #[test]
+    fn write_qoi_view_of_resized_image_matches_image_crate_encoder() {
+        let mut frame = gradient_frame(33, 17, 4);
+        let resized = frame.get_image().expect("converted frame").resize_exact(
+            20,
+            10,
+            image::imageops::FilterType::Triangle,
+        );
+        let mut qoi = Vec::new();
+        write_qoi_view(FrameView::from_image(&resized).expect("view"), &mut qoi)
+            .expect("write qoi");
+
+        assert_eq!(qoi, reference_qoi(resized));
+    }
+
+    #[test]
+    fn write_ppm_concatenates_frames() {
+        let frame = gradient_frame(8, 4, 0);
+        let mut stream = Vec::new();
+        write_ppm(&frame, &mut stream).expect("first frame");
+        let frame_len = stream.len();
+        write_ppm(&frame, &mut stream).expect("second frame");
+
+        assert_eq!(stream.len(), frame_len * 2);
+        assert_eq!(stream[..frame_len], stream[frame_len..]);
+    }
+
+    #[test]
+    fn write_ppm_streams_over_a_socket_until_the_reader_leaves() {
+        use std::{io::Read, os::unix::net::UnixStream};
+
+        let frame = gradient_frame(640, 480, 16);
+        let mut expected = Vec::new();
+        write_ppm(&frame, &mut expected).expect("write ppm");
+
+        let (mut sender, mut receiver) = UnixStream::pair().expect("socket pair");
+        let reader = thread::spawn(move || {
+            let mut received = Vec::new();
+            receiver.read_to_end(&mut received).map(|_| received)
+        });
+        write_ppm(&frame, &mut sender).expect("stream ppm");
+        drop(sender);
+        assert_eq!(reader.join().unwrap().expect("read"), expected);
+
+        // A reader that hangs up after the header leaves the rest unwritten.
+        let (mut sender, mut receiver) = UnixStream::pair().expect("socket pair");
+        let reader = thread::spawn(move || {
+            let mut header = [0; 15];
+            receiver.read_exact(&mut header).map(|()| header)
+        });
+        let result = write_ppm(&frame, &mut sender).map_err(Error::on_stream);
+        assert_eq!(
+            &reader.join().unwrap().expect("read"),
+            b"P6\n640 480\n255\n"
+        );
+        assert!(matches!(result, Err(Error::StreamClosed(_))), "{result:?}");
+    }
+
+    /// Compares streaming a frame as PPM with converting it to an image and
+    /// encoding that. Run with
+    /// `cargo test -p libwayshot --release -- --ignored --nocapture ppm_streaming`.
+    #[test]
+    #[ignore = "benchmark"]
+    fn ppm_streaming_benchmark() {
+        use std::{io, os::unix::net::UnixStream, time::Instant};
+
+        const ROUNDS: u32 = 20;
+        let frame = gradient_frame(3840, 2160, 0);
+        let measure = |name: &str, write: &dyn Fn(&mut UnixStream)| {
+            let (mut sender, mut receiver) = UnixStream::pair().expect("socket pair");
+            let reader = thread::spawn(move || io::copy(&mut receiver, &mut io::sink()));
+            let start = Instant::now();
+            for _ in 0..ROUNDS {
+                write(&mut sender);
+            }
+            drop(sender);
+            let bytes = reader.join().unwrap().expect("read");
+            println!(
+                "{name}: {:?} per frame, {} bytes streamed",
+                start.elapsed() / ROUNDS,
+                bytes / ROUNDS as u64
+            );
+        };
+
+        measure("streamed", &|stream| {
+            let mut writer = io::BufWriter::with_capacity(256 * 1024, stream);
+            write_ppm(&frame, &mut writer).expect("stream ppm");
+            writer.flush().expect("flush");
+        });
+        // What a PPM encoder does with an image, without needing the pnm feature.
+        measure("image and encode", &|stream| {
+            let image = DynamicImage::from(frame.view().expect("view").to_rgba()).into_rgb8();
+            let mut encoded =
+                format!("P6\n{} {}\n255\n", image.width(), image.height()).into_bytes();
+            encoded.extend_from_slice(image.as_raw());
+            stream.write_all(&encoded).expect("write");
+        });
+    }
+
+    #[test]
+    fn cropped_frames_convert_only_the_crop() {
+        let crop = Region {
+            position: Position { x: 5, y: 3 },
+            size: Size {
+                width: 7,
+                height: 4,
+            },
+        };
+        let expected = gradient_frame(20, 10, 8).view().expect("view").to_rgba();
+        let expected = expected.view(5, 3, 7, 4).to_image();
+
+        let cropped = gradient_frame(20, 10, 8)
+            .into_cropped_image(crop)
+            .expect("crop")
+            .into_rgba8();
+        assert_eq!(cropped.dimensions(), (7, 4));
+        for (pixel, expected) in cropped.pixels().zip(expected.pixels()) {
+            assert_eq!(pixel.0[..3], expected.0[..3]);
+        }
+
+        // Without padding the crop matches converting the whole frame.
+        let mut whole = gradient_frame(20, 10, 0);
+        let image = whole.get_image().expect("image").crop_imm(5, 3, 7, 4);
+        let cropped = gradient_frame(20, 10, 0)
+            .into_cropped_image(crop)
+            .expect("crop");
+        assert_eq!(cropped, image);
+        // Frames converted already are cropped as they are.
+        assert_eq!(whole.into_cropped_image(crop).expect("crop"), image);
+
+        assert!(matches!(
+            gradient_frame(20, 10, 0).into_cropped_image(Region {
+                position: Position { x: 15, y: 0 },
+                ..crop
+            }),
+            Err(Error::RegionOutOfBounds { .. })
+        ));
+        assert_eq!(
+            clamp_crop(
+                Region {
+                    position: Position { x: 15, y: 8 },
+                    ..crop
+                },
+                20,
+                10
+            )
+            .size,
+            Size {
+                width: 5,
+                height: 2
+            }
+        );
+    }
+
+    /// Compares converting a 300x300 crop of a 4K frame with converting all of
+    /// it. Run with
+    /// `cargo test -p libwayshot --release -- --ignored --nocapture crop_conversion`.
+    #[test]
+    #[ignore = "benchmark"]
+    fn crop_conversion_benchmark() {
+        use std::time::{Duration, Instant};
+
+        const ROUNDS: u32 = 20;
+        let crop = Region {
+            position: Position { x: 1800, y: 900 },
+            size: Size {
+                width: 300,
+                height: 300,
+            },
+        };
+        let measure = |name: &str, convert: &dyn Fn(FrameCopy) -> DynamicImage| {
+            let mut elapsed = Duration::ZERO;
+            for _ in 0..ROUNDS {
+                let frame = gradient_frame(3840, 2160, 0);
+                let start = Instant::now();
+                let image = convert(frame);
+                elapsed += start.elapsed();
+                assert_eq!(image.dimensions(), (300, 300));
+            }
+            println!("{name}: {:?} per frame", elapsed / ROUNDS);
+        };
+
+        measure("whole frame", &|mut frame| {
+            frame
+                .get_image()
+                .expect("image")
+                .crop_imm(1800, 900, 300, 300)
+        });
+        measure("crop only", &|frame| {
+            frame.into_cropped_image(crop).expect("crop")
+        });
+    }
+
+    /// Create an Xbgr8888 frame where every pixel is produced by `color`, rows padded by `padding`.
+    fn frame_from_fn(
+        width: u32,
+        height: u32,
+        padding: u32,
+        color: impl Fn(u32, u32) -> [u8; 3],
+    ) -> FrameCopy {
+        let mut frame = gradient_frame(width, height, padding);
+        frame.frame_format.format = Format::Xbgr8888;
+        let stride = frame.frame_format.stride;
+        let FrameData::Mmap(mmap) = &mut frame.frame_data else {
+            unreachable!()
+        };
+        for y in 0..height {
+            for x in 0..width {
+                let offset = (y * stride + x * 4) as usize;
+                mmap[offset..offset + 3].copy_from_slice(&color(x, y));
+            }
+        }
+        frame
+    }
+
+    #[test]
+    fn average_and_dominant_colors_of_solid_frame() {
+        let frame = frame_from_fn(300, 200, 8, |_, _| [200, 40, 90]);
+
+        assert_eq!(average_color(&frame).unwrap(), Rgba([200, 40, 90, 255]));
+        assert_eq!(
+            dominant_colors(&frame, 3).unwrap(),
+            [Rgba([200, 40, 90, 255])]
+        );
+    }
+
+    #[test]
+    fn average_and_dominant_colors_of_gradient_frame() {
+        // Red ramps from 0 to 255 left to right, the bottom quarter is blue.
+        let frame = frame_from_fn(256, 128, 8, |x, y| match y {
+            96.. => [0, 0, 255],
+            _ => [x as u8, 0, 0],
+        });
+
+        let Rgba([r, g, b, a]) = average_color(&frame).unwrap();
+        assert!((r as i32 - 96).abs() <= 2, "red {r}");
+        assert_eq!(g, 0);
+        assert!((b as i32 - 64).abs() <= 2, "blue {b}");
+        assert_eq!(a, 255);
+
+        // The blue quarter is one bucket, each red bucket covers 1/16 of the rest.
+        let dominant = dominant_colors(&frame, 2).unwrap();
+        assert_eq!(dominant.len(), 2);
+        assert_eq!(dominant[0], Rgba([0, 0, 255, 255]));
+        assert_eq!(dominant[1].0[1..], [0, 0, 255]);
+    }
+
+    #[test]
+    fn uniform_frame_is_detected() {
+        // The row padding is filled with 0xff and must not count.
+        let black = frame_from_fn(64, 32, 16, |_, _| [0, 0, 0]);
+        assert!(is_uniform(&black, 0).expect("uniform"));
+
+        let noisy = frame_from_fn(64, 32, 16, |x, y| [((x + y) % 3) as u8, 0, 1]);
+        assert!(!is_uniform(&noisy, 1).expect("uniform"));
+        assert!(is_uniform(&noisy, 2).expect("uniform"));
+
+        let gradient = gradient_frame(64, 32, 16);
+        assert!(!is_uniform(&gradient, 2).expect("uniform"));
+    }
+
+    #[test]
+    fn stats_of_uniform_frame() {
+        let frame = frame_from_fn(64, 32, 16, |_, _| [200, 100, 50]);
+
+        let stats = stats(&frame).expect("stats");
+
+        // (54 * 200 + 183 * 100 + 19 * 50) >> 8
+        assert_eq!(stats.mean_luminance, 117.0);
+        assert_eq!(stats.median_luminance(), 117);
+        assert_eq!(stats.dominant_color, [200, 100, 50]);
+    }
+
+    #[test]
+    fn stats_percentiles_and_dominant_color() {
+        // A quarter of the columns white, the rest black.
+        let frame = frame_from_fn(
+            100,
+            40,
+            8,
+            |x, _| {
+                if x < 25 { [255, 255, 255] } else { [0, 0, 0] }
+            },
+        );
+
+        let stats = stats(&frame).expect("stats");
+
+        assert_eq!(stats.luminance_percentile(10.0), 0);
+        assert_eq!(stats.luminance_percentile(75.0), 0);
+        assert_eq!(stats.luminance_percentile(80.0), 255);
+        assert_eq!(stats.dominant_color, [0, 0, 0]);
+        assert!((stats.mean_luminance - 255.0 / 4.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn srgb_to_linear_known_values() {
+        let image = RgbaImage::from_raw(2, 1, vec![0, 128, 255, 255, 10, 188, 255, 0]).unwrap();
+
+        let linear = srgb_to_linear(&image);
+
+        let Rgba([r, g, b, a]) = *linear.get_pixel(0, 0);
+        assert_eq!((r, b, a), (0.0, 1.0, 1.0));
+        // sRGB 128 is 21.6% linear light.
+        assert!((g - 0.215_861).abs() < 1e-5, "{g}");
+        let Rgba([r, g, b, a]) = *linear.get_pixel(1, 0);
+        // Below the linear segment threshold: 10 / 255 / 12.92.
+        assert!((r - 0.003_035).abs() < 1e-5, "{r}");
+        assert!((g - 0.502_886).abs() < 1e-5, "{g}");
+        assert_eq!((b, a), (1.0, 0.0));
+    }
+
+    fn panorama(width: u32, height: u32) -> DynamicImage {
+        RgbaImage::from_fn(width, height, |x, y| {
+            image::Rgba([(x % 256) as u8, (x / 256) as u8, y as u8, 255])
+        })
+        .into()
+    }
+
+    #[test]
+    fn split_tiles_with_overlap_reconstructs_original() {
+        let original = panorama(1000, 8);
+
+        let tiles = split_tiles(&original, 300, 40).expect("tiles");
+
+        assert_eq!(
+            tiles.iter().map(|tile| tile.x_offset).collect::<Vec<_>>(),
+            [0, 260, 520, 700]
+        );
+        assert!(tiles.iter().all(|tile| tile.image.width() == 300));
+
+        let mut reconstructed = DynamicImage::new_rgba8(1000, 8);
+        for tile in &tiles {
+            image::imageops::replace(&mut reconstructed, &tile.image, tile.x_offset as i64, 0);
+        }
+        assert_eq!(reconstructed.to_rgba8(), original.to_rgba8());
+
+        // Overlapping columns hold the same pixels in both tiles.
+        let (first, second) = (&tiles[0].image, &tiles[1].image);
+        for x in 0..40 {
+            assert_eq!(first.get_pixel(260 + x, 3), second.get_pixel(x, 3));
+        }
+    }
+
+    #[test]
+    fn split_tiles_without_overlap_cuts_exact_tiles() {
+        let tiles = split_tiles(&panorama(900, 4), 300, 0).expect("tiles");
+
+        assert_eq!(
+            tiles.iter().map(|tile| tile.x_offset).collect::<Vec<_>>(),
+            [0, 300, 600]
+        );
+    }
+
+    #[test]
+    fn split_tiles_keeps_narrow_images_whole() {
+        let tiles = split_tiles(&panorama(100, 4), 300, 20).expect("tiles");
+
+        assert_eq!(tiles.len(), 1);
+        assert_eq!(tiles[0].image.width(), 100);
+    }
+
+    #[test]
+    fn split_tiles_rejects_overlap_not_smaller_than_tile() {
+        assert!(matches!(
+            split_tiles(&panorama(100, 4), 30, 30),
+            Err(Error::InvalidTileLayout(_))
+        ));
+    }
+
+    #[test]
+    fn circle_mask_clears_outside_and_keeps_inside() {
+        let mut image = RgbaImage::from_pixel(20, 20, Rgba([10, 20, 30, 255]));
+
+        apply_circle_mask(&mut image, (10.0, 10.0), 8.0);
+
+        assert_eq!(image.get_pixel(10, 10).0, [10, 20, 30, 255]);
+        assert_eq!(image.get_pixel(0, 0)[3], 0);
+        assert_eq!(image.get_pixel(19, 10)[3], 0);
+        // The pixel centered on the edge is half covered.
+        let edge = image.get_pixel(17, 9)[3];
+        assert!(edge > 0 && edge < 255, "{edge}");
+    }
+
+    #[test]
+    fn rounded_rect_mask_only_touches_corners() {
+        let mut image = RgbaImage::from_pixel(40, 20, Rgba([0, 0, 0, 200]));
+
+        apply_rounded_rect_mask(&mut image, 6.0);
+
+        assert_eq!(image.get_pixel(0, 0)[3], 0);
+        assert_eq!(image.get_pixel(39, 19)[3], 0);
+        assert_eq!(image.get_pixel(20, 0)[3], 200);
+        assert_eq!(image.get_pixel(0, 10)[3], 200);
+        assert_eq!(image.get_pixel(6, 6)[3], 200);
+    }
+
+    #[test]
+    fn rounded_rect_mask_radius_is_limited_to_a_circle() {
+        let mut rounded = RgbaImage::from_pixel(16, 16, Rgba([255; 4]));
+        let mut circle = rounded.clone();
+
+        apply_rounded_rect_mask(&mut rounded, 100.0);
+        apply_circle_mask(&mut circle, (8.0, 8.0), 8.0);
+
+        assert_eq!(rounded, circle);
+    }
+
+    #[test]
+    fn thumbnail_rejects_short_buffers() {
+        let mut frame = gradient_frame(16, 16, 0);
+        frame.frame_format.size.height = 32;
+
+        assert!(matches!(thumbnail(&frame, 8), Err(Error::BufferTooSmall)));
+    }
 }
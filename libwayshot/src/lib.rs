@@ -1,39 +1,84 @@
 //! `libwayshot` is a convenient wrapper over the wlroots screenshot protocol
 //! that provides a simple API to take screenshots with.
 //!
-//! To get started, look at [`WayshotConnection`].
-
-mod convert;
+//! To get started, look at [`WayshotConnection`]. For a single capture as PNG
+//! bytes without managing a connection, use [`capture_region_png`] or
+//! [`capture_output_png`].
+
+pub mod cache;
+mod capture_id;
+mod compose;
+pub mod convert;
+mod cursor;
+mod cursor_session;
+mod damage_tracker;
 mod dispatch;
+mod encoding;
+pub mod environment;
 mod error;
+mod freeze;
 mod image_util;
 pub mod output;
+#[cfg(feature = "output-management")]
+mod output_management;
+mod pacing;
+mod progress;
 pub mod region;
 pub mod screencast;
 mod screencopy;
+mod seat;
+#[cfg(feature = "interactive")]
+mod selection;
+mod session_lock;
+pub mod state;
+#[cfg(feature = "workspaces")]
+mod workspace;
+mod yuv;
 
 use std::{
-    collections::HashSet,
-    ffi::c_void,
+    collections::HashMap,
+    env,
+    ffi::{OsString, c_void},
     fs::File,
-    os::fd::{AsFd, IntoRawFd},
-    sync::atomic::Ordering,
+    io::{self, BufWriter, Cursor, ErrorKind, Write},
+    num::{NonZeroU32, NonZeroUsize},
+    os::{
+        fd::{AsFd, IntoRawFd},
+        unix::net::UnixStream,
+    },
+    path::{Path, PathBuf},
+    slice,
+    sync::{
+        Arc, Mutex, OnceLock, PoisonError,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        mpsc,
+    },
     thread,
+    time::{Duration, Instant},
 };
 
-use dispatch::{DMABUFState, LayerShellState};
-use image::{DynamicImage, imageops::replace};
+use dispatch::{DMABUFState, DmabufFeedbackState, LayerShellState};
+use freeze::OverlayFrame;
+use image::{DynamicImage, Rgba, Rgba32FImage, RgbaImage};
 use khronos_egl::{self as egl, Instance};
 use memmap2::MmapMut;
-use screencopy::{DMAFrameFormat, DMAFrameGuard, EGLImageGuard, FrameData, FrameGuard};
+use rustix::{
+    event::{self, PollFd, PollFlags, Timespec},
+    io::Errno,
+};
+use screencopy::{DMAFrameFormat, DMAFrameGuard, EGLImageGuard, FrameGuard, ResidentBytes};
 use tracing::debug;
 use wayland_client::{
-    Connection, EventQueue, Proxy,
-    globals::{GlobalList, registry_queue_init},
+    Connection, Dispatch, DispatchError, EventQueue, Proxy, QueueHandle,
+    backend::WaylandError,
+    globals::{BindError, GlobalList, registry_queue_init},
     protocol::{
+        wl_buffer::WlBuffer,
         wl_compositor::WlCompositor,
         wl_output::{Transform, WlOutput},
         wl_shm::{self, WlShm},
+        wl_shm_pool::WlShmPool,
+        wl_surface::WlSurface,
     },
 };
 use wayland_protocols::{
@@ -57,14 +102,12 @@ use wayland_protocols::{
         },
         viewporter::client::wp_viewporter::WpViewporter,
     },
-    xdg::xdg_output::zv1::client::{
-        zxdg_output_manager_v1::ZxdgOutputManagerV1, zxdg_output_v1::ZxdgOutputV1,
-    },
+    xdg::xdg_output::zv1::client::zxdg_output_manager_v1::ZxdgOutputManagerV1,
 };
 use wayland_protocols_wlr::{
     layer_shell::v1::client::{
         zwlr_layer_shell_v1::{Layer, ZwlrLayerShellV1},
-        zwlr_layer_surface_v1::Anchor,
+        zwlr_layer_surface_v1::{Anchor, ZwlrLayerSurfaceV1},
     },
     screencopy::v1::client::{
         zwlr_screencopy_frame_v1::ZwlrScreencopyFrameV1,
@@ -73,16 +116,49 @@ use wayland_protocols_wlr::{
 };
 
 use crate::{
+    compose::BlitSource,
+    damage_tracker::DamageTracker,
     dispatch::{CaptureFrameState, FrameState, OutputCaptureState, WayshotState},
-    screencopy::{FrameCopy, FrameFormat, create_shm_fd},
+    environment::{
+        MIN_COMPOSITOR_VERSION, MIN_FOREIGN_TOPLEVEL_LIST_VERSION,
+        MIN_IMAGE_CAPTURE_SOURCE_VERSION, MIN_IMAGE_COPY_CAPTURE_VERSION, MIN_LAYER_SHELL_VERSION,
+        MIN_LINUX_DMABUF_VERSION, MIN_SCREENCOPY_VERSION, MIN_XDG_OUTPUT_VERSION, has_global,
+        negotiated_version,
+    },
+    progress::ProgressCallback,
+    region::RegionCapturer,
+    screencopy::{create_shm_buffer, create_shm_fd, map_shm_frame, seal_shm_size},
 };
 
 pub use crate::{
-    output::OutputInfo,
-    region::{EmbeddedRegion, LogicalRegion, RegionCapturer, Size, TopLevel},
+    cache::ScreenshotCache,
+    capture_id::{CaptureId, CaptureIds},
+    compose::{
+        ComposeOptions, Composite, OfflineFrame, OutputPlacement, compose, compose_with_layout,
+    },
+    cursor::{CursorImage, CursorMode, CursorOverlayCheck, CursorSource, FrameCursor},
+    encoding::{
+        ChecksumAlgorithm, EncodingOptions, HashingWriter, JpegSubsampling, PNG_CAPTURE_ID_KEYWORD,
+        PixelFormat, VerifyMode,
+    },
+    environment::{CompositorInfo, EnvironmentInfo, StartupTimings},
+    freeze::{FreezeOptions, FreezeScope},
+    image_util::{
+        FrameStats, FrameView, OutputResolution, ResizeFit, Tile, apply_circle_mask,
+        apply_rounded_rect_mask, split_tiles, srgb_to_linear, stitch_scrolled,
+    },
+    output::{OutputCapture, OutputDiff, OutputInfo},
+    pacing::{FramePacer, LoopStats},
+    progress::ProgressEvent,
+    region::{EmbeddedRegion, LogicalRegion, Region, Size, TopLevel, Transform2D},
+    screencopy::{ColorHandling, FrameCopy, FrameData, FrameFormat, FrameImage, FrameMeta},
+    seat::{SeatInfo, SeatSelector},
+    yuv::{YuvFrame, YuvLayout, YuvMatrix, YuvOptions, YuvPlane},
 };
 
 pub use crate::error::{Error, Result};
+#[cfg(feature = "interactive")]
+pub use crate::selection::SelectionMode;
 
 pub mod reexport {
     use wayland_client::protocol::wl_output;
@@ -90,8 +166,32 @@ pub mod reexport {
     pub use wayland_protocols::ext::image_copy_capture::v1::client::ext_image_copy_capture_frame_v1::FailureReason;
     pub use wl_output::{Transform, WlOutput};
 }
+use drm::node::DrmNode;
 use gbm::{BufferObject, BufferObjectFlags, Device as GBMDevice};
 
+/// Number of times a failed output capture is tried before giving up.
+const CAPTURE_ATTEMPTS: u32 = 2;
+
+/// Number of buffer commits [`WayshotConnection::flash_outputs`] fades out in.
+const FLASH_STEPS: u8 = 6;
+/// Opacity [`WayshotConnection::flash_outputs`] starts at.
+const FLASH_ALPHA: u8 = 0xa0;
+
+/// How often [`WayshotConnection::capture_on_damage`] checks whether to stop
+/// while waiting for damage.
+const DAMAGE_STOP_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Per channel difference up to which a frame still counts as a single colour
+/// for [`WayshotConnection::set_warn_on_uniform`].
+const UNIFORM_FRAME_TOLERANCE: u8 = 2;
+
+/// Time between the captures of [`WayshotConnection::capture_scroll_stitch`].
+const SCROLL_CAPTURE_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Bytes of a streamed PPM frame collected before they are written, so a
+/// socket gets a few large writes instead of one per row.
+const PPM_STREAM_BUFFER: usize = 256 * 1024;
+
 /// Struct to store wayland connection and globals list.
 /// # Example usage
 ///
@@ -100,6 +200,22 @@ use gbm::{BufferObject, BufferObjectFlags, Device as GBMDevice};
 /// let wayshot_connection = WayshotConnection::new()?;
 /// let image_buffer = wayshot_connection.screenshot_all()?;
 /// ```
+///
+/// # Sharing the connection
+///
+/// A connection made with [`Self::from_connection`] may be shared with the
+/// host application. Every function talking to the compositor, including
+/// the captures, creates the objects it needs on a private event queue and
+/// only ever dispatches that queue, so the host's queues are never
+/// dispatched. Events for them that arrive meanwhile are read into them and
+/// wait for the host.
+///
+/// Waiting on a private queue flushes the connection, which sends the host's
+/// pending requests too, ahead of the ones wayshot makes next. The compositor
+/// handles requests in the order they were sent, so when ordering matters,
+/// call [`Self::flush`] or [`Self::sync`] before handing over to wayshot.
+/// [`Self::set_outputs`] takes outputs the host already knows, without any
+/// roundtrip.
 #[derive(Debug)]
 pub struct WayshotConnection {
     pub conn: Connection,
@@ -109,9 +225,54 @@ pub struct WayshotConnection {
     dmabuf_state: Option<DMABUFState>,
     toplevel_capture_support: bool,
     image_copy_support: bool,
+    /// See [`Self::screencopy_version`].
+    screencopy_version: u32,
+    screenshot_cache: Option<Mutex<ScreenshotCache>>,
+    /// Damage of the outputs with cached screenshots, by output and cursor
+    /// overlay, see [`Self::screenshot_single_output_cached`].
+    damage_trackers: Mutex<HashMap<(WlOutput, bool), DamageTracker>>,
+    environment_info: OnceLock<EnvironmentInfo>,
+    /// Display passed to [`Self::connect_to`], `None` for connections from the environment.
+    wayland_display: Option<String>,
+    wait_for_frame: bool,
+    warn_on_uniform: bool,
+    stride_alignment: Option<NonZeroU32>,
+    output_resolution: Option<OutputResolution>,
+    resident_capture_bytes: Arc<AtomicU64>,
+    /// wl_shm for capture buffers, see [`Self::capture_shm`].
+    capture_shm: OnceLock<WlShm>,
+    capture_queue_events: AtomicU64,
+    /// Ids of the frames captured, see [`Self::set_capture_ids`].
+    capture_ids: CaptureIds,
+    /// See [`Self::last_color_handling`].
+    last_color_handling: Mutex<Option<ColorHandling>>,
+    startup_timings: StartupTimings,
+    /// Shaded overlay shown while a freeze callback runs, for
+    /// [`Self::set_freeze_selection`].
+    freeze_overlay: Mutex<Option<FreezeOverlay>>,
+    /// See [`Self::set_progress`].
+    progress: Option<ProgressCallback>,
+    /// See [`Self::set_cursor_source`].
+    cursor_source: CursorSource,
+}
+
+/// The shaded surfaces of a running freeze, see [`FreezeOptions`].
+#[derive(Debug)]
+struct FreezeOverlay {
+    shm: WlShm,
+    qh: QueueHandle<LayerShellState>,
+    surfaces: Vec<OverlaySurface>,
+}
+
+#[derive(Debug)]
+struct OverlaySurface {
+    surface: WlSurface,
+    meta: FrameMeta,
+    frame: OverlayFrame,
+    buffer: (WlShmPool, WlBuffer),
 }
 
-pub enum WayshotFrame {
+pub(crate) enum WayshotFrame {
     WlrScreenshot(ZwlrScreencopyFrameV1),
     ExtImageCopy(ExtImageCopyCaptureFrameV1),
 }
@@ -145,38 +306,259 @@ impl WayshotTarget {
     }
 }
 
-fn check_toplevel_protocols(globals: &GlobalList, conn: &Connection) -> Result<()> {
-    let event_queue = conn.new_event_queue::<CaptureFrameState>();
-    let qh = event_queue.handle();
+/// Capture `region`, or all outputs for `None`, and encode it as PNG.
+///
+/// This is the simple entry point: it connects to the compositor in
+/// `WAYLAND_DISPLAY`, captures, encodes with the default [`EncodingOptions`]
+/// and disconnects again. Use a [`WayshotConnection`] to take several captures.
+///
+/// ```no_run
+/// let png = libwayshot::capture_region_png(None, false)?;
+/// std::fs::write("screenshot.png", png)?;
+/// # Ok::<_, Box<dyn std::error::Error>>(())
+/// ```
+pub fn capture_region_png(region: Option<LogicalRegion>, cursor_overlay: bool) -> Result<Vec<u8>> {
+    let wayshot = WayshotConnection::new()?;
+    let image = match region {
+        Some(region) => wayshot.screenshot(region, cursor_overlay)?,
+        None => wayshot.screenshot_all(cursor_overlay)?,
+    };
+    encode_png(&image)
+}
+
+/// Capture the output named `name`, e.g. `DP-1`, and encode it as PNG, like
+/// [`capture_region_png`]. Fails with [`Error::OutputNotFound`] if there is no
+/// such output.
+pub fn capture_output_png(name: &str, cursor_overlay: bool) -> Result<Vec<u8>> {
+    let wayshot = WayshotConnection::new()?;
+    let output_info = wayshot
+        .get_all_outputs()
+        .iter()
+        .find(|output_info| output_info.name == name)
+        .ok_or_else(|| Error::OutputNotFound(name.to_string()))?;
+    let image = wayshot.screenshot_single_output(output_info, cursor_overlay)?;
+    encode_png(&image)
+}
+
+fn encode_png(image: &DynamicImage) -> Result<Vec<u8>> {
+    let mut png = Cursor::new(Vec::new());
+    EncodingOptions::new().encode(image, image::ImageFormat::Png, &mut png)?;
+    Ok(png.into_inner())
+}
+
+/// The error for a `wl_shm` that could not be bound. Every compositor should
+/// offer it, minimal ones may not.
+fn shm_bind_error(error: BindError) -> Error {
+    match error {
+        BindError::NotPresent | BindError::UnsupportedVersion => {
+            Error::ProtocolNotFound("WlShm not found".to_string())
+        }
+    }
+}
 
-    // Bind managers
-    globals.bind::<ExtImageCopyCaptureManagerV1, _, _>(&qh, 1..=1, ())?;
-    globals.bind::<ExtForeignToplevelImageCaptureSourceManagerV1, _, _>(&qh, 1..=1, ())?;
+/// Fails with [`Error::ProtocolNotFound`] without wlr-screencopy, `version` 0,
+/// and with [`Error::Unsupported`] if `version` is older than the `min_version`
+/// that `feature` needs.
+fn check_screencopy_version(version: u32, min_version: u32, feature: &str) -> Result<()> {
+    if version == 0 {
+        return Err(Error::ProtocolNotFound(
+            "ZwlrScreencopy Manager not found".to_string(),
+        ));
+    }
+    if version < min_version {
+        return Err(Error::Unsupported(format!(
+            "{feature} needs zwlr_screencopy_manager_v1 version {min_version}, \
+             the compositor offers version {version}"
+        )));
+    }
     Ok(())
 }
 
-fn check_ext_image_copy_protocols(globals: &GlobalList, conn: &Connection) -> Result<()> {
-    let event_queue = conn.new_event_queue::<CaptureFrameState>();
-    let qh = event_queue.handle();
+/// Path of the socket of the Wayland display `display`, resolved like `WAYLAND_DISPLAY`.
+fn display_socket_path(display: &str, runtime_dir: Option<OsString>) -> Result<PathBuf> {
+    let display = Path::new(display);
+    if display.is_absolute() {
+        return Ok(display.to_path_buf());
+    }
+    match runtime_dir {
+        Some(runtime_dir) => Ok(Path::new(&runtime_dir).join(display)),
+        None => Err(Error::DisplayConnect {
+            socket: display.to_path_buf(),
+            source: std::io::Error::new(ErrorKind::NotFound, "XDG_RUNTIME_DIR is not set"),
+        }),
+    }
+}
+
+/// Wrap the mapped frame data of an output capture in a [`FrameCopy`].
+fn output_frame_copy(
+    output_info: &OutputInfo,
+    frame_format: FrameFormat,
+    frame_mmap: MmapMut,
+    capture_region: Option<EmbeddedRegion>,
+    capture_id: CaptureId,
+) -> FrameCopy {
+    let meta = FrameMeta::new(
+        frame_format,
+        output_info.transform,
+        capture_region
+            .map(|capture_region| capture_region.logical())
+            .unwrap_or(output_info.logical_region),
+    );
+    FrameCopy {
+        frame_format,
+        frame_color_type: image::ColorType::Rgb8,
+        frame_data: FrameData::Mmap(frame_mmap),
+        transform: meta.transform,
+        logical_region: meta.logical_region,
+        physical_size: meta.physical_size,
+        color_handling: ColorHandling::Passthrough,
+        converted: false,
+        capture_id,
+    }
+}
+
+/// Wrap the mapped frame data of a capture that isn't of an output, like a
+/// window or the cursor, in a [`FrameCopy`] placed at the origin.
+fn buffer_frame_copy(
+    frame_format: FrameFormat,
+    frame_mmap: MmapMut,
+    capture_id: CaptureId,
+) -> FrameCopy {
+    FrameCopy {
+        frame_format,
+        frame_color_type: image::ColorType::Rgb8, // will be updated by get_image
+        frame_data: FrameData::Mmap(frame_mmap),
+        transform: Transform::Normal,
+        logical_region: LogicalRegion {
+            inner: crate::region::Region {
+                position: crate::region::Position { x: 0, y: 0 },
+                size: frame_format.size,
+            },
+        },
+        physical_size: frame_format.size,
+        color_handling: ColorHandling::Passthrough,
+        converted: false,
+        capture_id,
+    }
+}
+
+// Only look at the advertised versions, binding the managers would create
+// objects on the compositor that are never used.
+fn check_toplevel_protocols(globals: &GlobalList) -> bool {
+    check_ext_image_copy_protocols(globals)
+        && has_global::<ExtForeignToplevelImageCaptureSourceManagerV1>(
+            globals,
+            MIN_IMAGE_CAPTURE_SOURCE_VERSION,
+        )
+}
+
+fn check_ext_image_copy_protocols(globals: &GlobalList) -> bool {
+    has_global::<ExtImageCopyCaptureManagerV1>(globals, MIN_IMAGE_COPY_CAPTURE_VERSION)
+}
 
-    // Bind managers
-    globals.bind::<ExtImageCopyCaptureManagerV1, _, _>(&qh, 1..=1, ())?;
+/// Flush `conn` and wait for the compositor to handle everything sent on it,
+/// on a private queue that dispatches nothing else.
+fn sync_connection(conn: &Connection) -> Result<()> {
+    conn.new_event_queue::<()>().roundtrip(&mut ())?;
     Ok(())
 }
 
+/// Dispatch the events that arrive on `event_queue` within `timeout`, returning
+/// early with their number once some were dispatched.
+fn dispatch_with_timeout<S>(
+    event_queue: &mut EventQueue<S>,
+    state: &mut S,
+    timeout: Duration,
+) -> Result<usize> {
+    event_queue.flush().map_err(DispatchError::Backend)?;
+    // No guard means events are already queued and only need dispatching.
+    if let Some(guard) = event_queue.prepare_read() {
+        let connection_fd = guard.connection_fd();
+        let mut fds = [PollFd::new(&connection_fd, PollFlags::IN)];
+        let poll_timeout = Timespec::try_from(timeout).unwrap_or(Timespec {
+            tv_sec: i64::MAX,
+            tv_nsec: 0,
+        });
+        match event::poll(&mut fds, Some(&poll_timeout)) {
+            Ok(0) | Err(Errno::INTR) => {}
+            Ok(_) => match guard.read() {
+                Ok(_) => {}
+                Err(WaylandError::Io(e)) if e.kind() == ErrorKind::WouldBlock => {}
+                Err(e) => return Err(DispatchError::Backend(e).into()),
+            },
+            Err(e) => return Err(std::io::Error::from(e).into()),
+        }
+    }
+    Ok(event_queue.dispatch_pending(state)?)
+}
+
+/// Render node of the main device the compositor advertises in its default
+/// dmabuf feedback, if any.
+fn dmabuf_main_device(
+    conn: &Connection,
+    linux_dmabuf: &ZwpLinuxDmabufV1,
+) -> Result<Option<PathBuf>> {
+    let mut event_queue = conn.new_event_queue::<DmabufFeedbackState>();
+    let feedback = linux_dmabuf.get_default_feedback(&event_queue.handle(), ());
+    let mut state = DmabufFeedbackState::default();
+    while !state.done {
+        event_queue.blocking_dispatch(&mut state)?;
+    }
+    feedback.destroy();
+    Ok(state
+        .main_device
+        .as_ref()
+        .and_then(dispatch::render_node_path))
+}
+
 impl WayshotConnection {
     pub fn new() -> Result<Self> {
+        let start = Instant::now();
         let conn = Connection::connect_to_env()?;
+        let connect = start.elapsed();
+
+        let mut wayshot = Self::from_connection(conn)?;
+        wayshot.startup_timings.connect = connect;
+        Ok(wayshot)
+    }
+
+    /// Connect to the compositor on the Wayland display `display` instead of the
+    /// one in `WAYLAND_DISPLAY`, e.g. `wayland-2` of a nested compositor.
+    ///
+    /// Relative names are looked up in `XDG_RUNTIME_DIR` like `WAYLAND_DISPLAY`,
+    /// absolute paths are used as they are.
+    ///# Errors
+    /// - [`Error::DisplayConnect`] if there is no compositor listening on the socket.
+    pub fn connect_to(display: &str) -> Result<Self> {
+        let start = Instant::now();
+        let socket = display_socket_path(display, env::var_os("XDG_RUNTIME_DIR"))?;
+        let stream = UnixStream::connect(&socket)
+            .map_err(|source| Error::DisplayConnect { socket, source })?;
+        let connection = Connection::from_socket(stream)?;
+        let connect = start.elapsed();
+
+        let mut wayshot = Self::from_connection(connection)?;
+        wayshot.wayland_display = Some(display.to_string());
+        wayshot.startup_timings.connect = connect;
+        Ok(wayshot)
+    }
 
-        Self::from_connection(conn)
+    /// The display given to [`Self::connect_to`], or `None` if the connection
+    /// came from the environment or the caller.
+    pub fn wayland_display(&self) -> Option<&str> {
+        self.wayland_display.as_deref()
     }
 
     /// Recommended if you already have a [`wayland_client::Connection`].
     pub fn from_connection(conn: Connection) -> Result<Self> {
+        let start = Instant::now();
         let (globals, _) = registry_queue_init::<WayshotState>(&conn)?;
+        let globals_received = start.elapsed();
 
-        let image_copy_support = check_ext_image_copy_protocols(&globals, &conn).is_ok();
-        let toplevel_capture_support = check_toplevel_protocols(&globals, &conn).is_ok();
+        let image_copy_support = check_ext_image_copy_protocols(&globals);
+        let toplevel_capture_support = check_toplevel_protocols(&globals);
+        let screencopy_version =
+            negotiated_version::<ZwlrScreencopyManagerV1>(&globals).unwrap_or_default();
         let mut initial_state = Self {
             conn,
             globals,
@@ -185,10 +567,35 @@ impl WayshotConnection {
             dmabuf_state: None,
             toplevel_capture_support,
             image_copy_support,
+            screencopy_version,
+            screenshot_cache: None,
+            damage_trackers: Mutex::default(),
+            environment_info: OnceLock::new(),
+            wayland_display: None,
+            wait_for_frame: false,
+            warn_on_uniform: false,
+            stride_alignment: None,
+            output_resolution: None,
+            resident_capture_bytes: Arc::default(),
+            capture_shm: OnceLock::new(),
+            capture_queue_events: AtomicU64::new(0),
+            progress: None,
+            cursor_source: CursorSource::default(),
+            capture_ids: CaptureIds::default(),
+            last_color_handling: Mutex::new(None),
+            startup_timings: StartupTimings {
+                globals: globals_received,
+                ..Default::default()
+            },
+            freeze_overlay: Mutex::default(),
         };
 
-        initial_state.refresh_outputs()?;
+        let start = Instant::now();
+        initial_state.discover_outputs(true)?;
+        initial_state.startup_timings.outputs = start.elapsed();
+        let start = Instant::now();
         initial_state.refresh_toplevels()?;
+        initial_state.startup_timings.toplevels = start.elapsed();
 
         Ok(initial_state)
     }
@@ -202,14 +609,66 @@ impl WayshotConnection {
     /// - conn: a Wayland connection
     /// - device_path: string pointing to the DRI device that is to be used for creating the DMA-BUFs on. For example: "/dev/dri/renderD128"
     pub fn from_connection_with_dmabuf(conn: Connection, device_path: &str) -> Result<Self> {
+        Self::from_connection_with_dmabuf_device(conn, Some(Path::new(device_path)))
+    }
+
+    /// Create a WayshotConnection struct having DMA-BUF support, allocating the
+    /// DMA-BUFs on `device_path`.
+    ///
+    /// On multi-GPU systems the buffers must be allocated on the device the
+    /// compositor imports them on. With `None`, the main device the compositor
+    /// advertises in its default dmabuf feedback is used.
+    ///# Errors
+    /// - [`Error::DrmDevice`] if the device can't be opened.
+    /// - [`Error::Unsupported`] if `device_path` is `None` and the compositor
+    ///   did not advertise a main device.
+    ///
+    /// Captures failing while the device differs from the compositor's return
+    /// [`Error::DmabufDeviceMismatch`].
+    pub fn from_connection_with_dmabuf_device(
+        conn: Connection,
+        device_path: Option<&Path>,
+    ) -> Result<Self> {
+        let start = Instant::now();
         let (globals, evq) = registry_queue_init::<WayshotState>(&conn)?;
-        let linux_dmabuf =
-            globals.bind(&evq.handle(), 4..=ZwpLinuxDmabufV1::interface().version, ())?;
-        let gpu = dispatch::Card::open(device_path);
+        let globals_received = start.elapsed();
+        let linux_dmabuf: ZwpLinuxDmabufV1 = globals.bind(
+            &evq.handle(),
+            MIN_LINUX_DMABUF_VERSION..=ZwpLinuxDmabufV1::interface().version,
+            (),
+        )?;
+        let compositor_device = dmabuf_main_device(&conn, &linux_dmabuf)?;
+        let device = match device_path {
+            // Compare render nodes, so e.g. /dev/dri/card0 matches /dev/dri/renderD128.
+            Some(path) => DrmNode::from_path(path)
+                .ok()
+                .as_ref()
+                .and_then(dispatch::render_node_path)
+                .unwrap_or_else(|| path.to_owned()),
+            None => compositor_device.clone().ok_or_else(|| {
+                Error::Unsupported("compositor did not advertise a DMA-BUF main device".into())
+            })?,
+        };
+        if let Some(compositor_device) = &compositor_device
+            && *compositor_device != device
+        {
+            tracing::warn!(
+                "Allocating DMA-BUFs on {} while the compositor uses {}",
+                device.display(),
+                compositor_device.display()
+            );
+        }
+        tracing::debug!("Using DRM device {} for DMA-BUFs", device.display());
+        let gpu = dispatch::Card::open(&device).map_err(|source| Error::DrmDevice {
+            device: device.clone(),
+            source,
+        })?;
         // init a GBM device
         let gbm = GBMDevice::new(gpu)?;
-        let image_copy_support = check_ext_image_copy_protocols(&globals, &conn).is_ok();
-        let toplevel_capture_support = check_toplevel_protocols(&globals, &conn).is_ok();
+        let image_copy_support = check_ext_image_copy_protocols(&globals);
+        let toplevel_capture_support = check_toplevel_protocols(&globals);
+        let screencopy_version =
+            negotiated_version::<ZwlrScreencopyManagerV1>(&globals).unwrap_or_default();
         let mut initial_state = Self {
             conn,
             globals,
@@ -218,12 +677,37 @@ impl WayshotConnection {
             dmabuf_state: Some(DMABUFState {
                 linux_dmabuf,
                 gbmdev: gbm,
+                device,
+                compositor_device,
             }),
             toplevel_capture_support,
             image_copy_support,
+            screencopy_version,
+            screenshot_cache: None,
+            damage_trackers: Mutex::default(),
+            environment_info: OnceLock::new(),
+            wayland_display: None,
+            wait_for_frame: false,
+            warn_on_uniform: false,
+            stride_alignment: None,
+            output_resolution: None,
+            resident_capture_bytes: Arc::default(),
+            capture_shm: OnceLock::new(),
+            capture_queue_events: AtomicU64::new(0),
+            progress: None,
+            cursor_source: CursorSource::default(),
+            capture_ids: CaptureIds::default(),
+            last_color_handling: Mutex::new(None),
+            startup_timings: StartupTimings {
+                globals: globals_received,
+                ..Default::default()
+            },
+            freeze_overlay: Mutex::default(),
         };
 
-        initial_state.refresh_outputs()?;
+        let start = Instant::now();
+        initial_state.discover_outputs(true)?;
+        initial_state.startup_timings.outputs = start.elapsed();
 
         Ok(initial_state)
     }
@@ -238,24 +722,113 @@ impl WayshotConnection {
         self.image_copy_support
     }
 
+    /// The `zwlr_screencopy_manager_v1` version wlr-screencopy captures bind,
+    /// the highest one both the compositor and libwayshot support, or 0 if the
+    /// compositor doesn't offer wlr-screencopy.
+    pub fn screencopy_version(&self) -> u32 {
+        self.screencopy_version
+    }
+
+    /// Version of the global the compositor advertises for `interface`, e.g.
+    /// `"zwlr_screencopy_manager_v1"`, or `None` if it does not.
+    ///
+    /// Compare it with the `MIN_*` constants in [`environment`] to find out
+    /// up front whether a feature is available.
+    pub fn global_version(&self, interface: &str) -> Option<u32> {
+        environment::global_version(&self.globals, interface)
+    }
+
+    /// Details about the compositor and session for diagnostics and bug reports.
+    ///
+    /// Queried on the first call and cached afterwards.
+    pub fn environment_info(&self) -> &EnvironmentInfo {
+        self.environment_info.get_or_init(|| {
+            EnvironmentInfo::query(&self.conn, &self.globals, self.wayland_display())
+        })
+    }
+
+    /// Send all pending requests on the connection, the host's included,
+    /// without waiting for the compositor.
+    pub fn flush(&self) -> Result<()> {
+        self.conn.flush().map_err(DispatchError::Backend)?;
+        Ok(())
+    }
+
+    /// Send all pending requests on the connection and wait until the
+    /// compositor handled them, on a private event queue.
+    ///
+    /// Events the compositor sent for the host's queues in the meantime are
+    /// read into them but not dispatched.
+    pub fn sync(&self) -> Result<()> {
+        sync_connection(&self.conn)
+    }
+
+    /// How long connecting took, phase by phase.
+    pub fn startup_timings(&self) -> StartupTimings {
+        self.startup_timings
+    }
+
+    /// Best guess of the compositor name together with the versions of the
+    /// capture related protocols it advertises, for bug reports.
+    pub fn compositor_info(&self) -> CompositorInfo {
+        CompositorInfo::query(&self.globals)
+    }
+
     /// Fetch all accessible wayland outputs.
     pub fn get_all_outputs(&self) -> &[OutputInfo] {
         self.output_infos.as_slice()
     }
 
+    /// The outputs grouped by the logical region they cover, so mirrored
+    /// outputs end up in the same group and all others in a group of their own.
+    pub fn mirror_groups(&self) -> Vec<Vec<&OutputInfo>> {
+        output::mirror_groups(&self.output_infos)
+    }
+
+    /// Whether `region` lies entirely on the outputs, so capturing it leaves no
+    /// black gaps. See [`LogicalRegion::covered_by`].
+    pub fn region_fully_covered(&self, region: &LogicalRegion) -> bool {
+        region.covered_by(self.get_all_outputs())
+    }
+
+    /// The outputs touched by a drag between two global logical points and the
+    /// region spanning all of them. See [`LogicalRegion::from_drag`].
+    pub fn drag_region(
+        &self,
+        start: region::Position,
+        end: region::Position,
+    ) -> Result<(LogicalRegion, Vec<OutputInfo>)> {
+        LogicalRegion::from_drag(start, end, self.get_all_outputs())
+    }
+
     /// refresh the outputs, to get new outputs
     pub fn refresh_outputs(&mut self) -> Result<()> {
-        // Connecting to wayland environment.
-        let mut state = OutputCaptureState {
-            outputs: Vec::new(),
-        };
+        self.refresh_outputs_diff().map(|_| ())
+    }
+
+    /// Like [`Self::refresh_outputs`], returning which outputs were added,
+    /// removed or modified since the outputs were last listed, so e.g. a
+    /// settings panel can update its list instead of rebuilding it.
+    pub fn refresh_outputs_diff(&mut self) -> Result<OutputDiff> {
+        let previous = self.output_infos.clone();
+        self.discover_outputs(false)?;
+        Ok(OutputDiff::between(&previous, &self.output_infos))
+    }
+
+    /// Bind the outputs and receive their names and logical regions, requesting
+    /// the xdg_output of every output as soon as it is bound.
+    ///
+    /// With `from_globals` the outputs are taken from the globals received when
+    /// connecting, so a single roundtrip answers everything. Outputs plugged in
+    /// since are missed, so later refreshes list them on a new registry first.
+    fn discover_outputs(&mut self, from_globals: bool) -> Result<()> {
         let mut event_queue = self.conn.new_event_queue::<OutputCaptureState>();
         let qh = event_queue.handle();
 
         // Bind to xdg_output global.
         let zxdg_output_manager = match self.globals.bind::<ZxdgOutputManagerV1, _, _>(
             &qh,
-            3..=3,
+            MIN_XDG_OUTPUT_VERSION..=MIN_XDG_OUTPUT_VERSION,
             (),
         ) {
             Ok(x) => x,
@@ -267,23 +840,26 @@ impl WayshotConnection {
             }
         };
 
-        // Fetch all outputs; when their names arrive, add them to the list
-        let _ = self.conn.display().get_registry(&qh, ());
-        event_queue.roundtrip(&mut state)?;
-
-        // We loop over each output and request its position data.
-        let xdg_outputs: Vec<ZxdgOutputV1> = state
-            .outputs
-            .iter()
-            .enumerate()
-            .map(|(index, output)| {
-                zxdg_output_manager.get_xdg_output(&output.wl_output, &qh, index)
-            })
-            .collect();
+        let mut state = OutputCaptureState {
+            xdg_output_manager: Some(zxdg_output_manager),
+            ..Default::default()
+        };
 
+        if from_globals {
+            let registry = self.globals.registry();
+            for global in self.globals.contents().clone_list() {
+                if global.interface == WlOutput::interface().name {
+                    state.bind_output(registry, global.name, global.version, &qh);
+                }
+            }
+        } else {
+            // Fetch all outputs, they are bound as they are announced.
+            let _ = self.conn.display().get_registry(&qh, ());
+            event_queue.roundtrip(&mut state)?;
+        }
         event_queue.roundtrip(&mut state)?;
 
-        for xdg_output in xdg_outputs {
+        for xdg_output in state.xdg_outputs.drain(..) {
             xdg_output.destroy();
         }
 
@@ -293,6 +869,72 @@ impl WayshotConnection {
         }
         tracing::trace!("Outputs detected: {:#?}", state.outputs);
         self.output_infos = state.outputs;
+        self.retain_cached_outputs();
+
+        Ok(())
+    }
+
+    /// Use `outputs` instead of asking the compositor, e.g. when the host
+    /// application sharing the connection already bound them and received
+    /// their names and logical regions. Unlike [`Self::refresh_outputs`] this
+    /// makes no roundtrip.
+    ///
+    /// Returns [`Error::NoOutputs`] if `outputs` is empty.
+    pub fn set_outputs(&mut self, outputs: Vec<OutputInfo>) -> Result<()> {
+        if outputs.is_empty() {
+            return Err(Error::NoOutputs);
+        }
+        self.output_infos = outputs;
+        self.retain_cached_outputs();
+        Ok(())
+    }
+
+    /// Block until at least `min_count` outputs are fully configured or `timeout`
+    /// expires, then use those outputs like [`Self::refresh_outputs`] would.
+    ///
+    /// Meant for scripts started together with the compositor, before it announced
+    /// all outputs. An output counts as configured once `wl_output.done` arrives
+    /// after its logical region is known. A `min_count` of 0 waits for one output.
+    pub fn wait_for_outputs(&mut self, min_count: usize, timeout: Duration) -> Result<()> {
+        let deadline = Instant::now() + timeout;
+        let min_count = min_count.max(1);
+        let mut event_queue = self.conn.new_event_queue::<OutputCaptureState>();
+        let qh = event_queue.handle();
+        let mut state = OutputCaptureState {
+            xdg_output_manager: Some(self.globals.bind::<ZxdgOutputManagerV1, _, _>(
+                &qh,
+                MIN_XDG_OUTPUT_VERSION..=MIN_XDG_OUTPUT_VERSION,
+                (),
+            )?),
+            ..Default::default()
+        };
+        let _registry = self.conn.display().get_registry(&qh, ());
+
+        while state.complete_outputs.len() < min_count {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(Error::OutputWaitTimeout {
+                    expected: min_count,
+                    found: state.complete_outputs.len(),
+                });
+            }
+
+            dispatch_with_timeout(&mut event_queue, &mut state, remaining)?;
+        }
+
+        for xdg_output in state.xdg_outputs {
+            xdg_output.destroy();
+        }
+        let (complete, incomplete): (Vec<_>, Vec<_>) = state
+            .outputs
+            .into_iter()
+            .partition(|output| state.complete_outputs.contains(&output.wl_output));
+        for output in incomplete {
+            output.wl_output.release();
+        }
+        tracing::trace!("Outputs detected: {:#?}", complete);
+        self.output_infos = complete;
+        self.retain_cached_outputs();
 
         Ok(())
     }
@@ -307,9 +949,11 @@ impl WayshotConnection {
         let mut event_queue = self.conn.new_event_queue::<CaptureFrameState>();
         let qh = event_queue.handle();
 
-        let _toplevel_list = self
-            .globals
-            .bind::<ExtForeignToplevelListV1, _, _>(&qh, 1..=1, ())?;
+        let _toplevel_list = self.globals.bind::<ExtForeignToplevelListV1, _, _>(
+            &qh,
+            MIN_FOREIGN_TOPLEVEL_LIST_VERSION..=MIN_FOREIGN_TOPLEVEL_LIST_VERSION,
+            (),
+        )?;
 
         event_queue.roundtrip(&mut state)?;
 
@@ -319,6 +963,13 @@ impl WayshotConnection {
 
     /// print the displays' info
     pub fn print_displays_info(&self) {
+        self.write_displays_info(&mut io::stdout().lock())
+            .expect("failed printing to stdout");
+    }
+
+    /// Write the displays' info, as printed by [`Self::print_displays_info`],
+    /// to `writer`.
+    pub fn write_displays_info<W: Write + ?Sized>(&self, writer: &mut W) -> io::Result<()> {
         for OutputInfo {
             physical_size: Size { width, height },
             logical_region:
@@ -338,12 +989,13 @@ impl WayshotConnection {
             ..
         } in self.get_all_outputs()
         {
-            println!("{name}");
-            println!("description: {description}");
-            println!("    Size: {width},{height}");
-            println!("    LogicSize: {logical_width}, {logical_height}");
-            println!("    Position: {x}, {y}");
+            writeln!(writer, "{name}")?;
+            writeln!(writer, "description: {description}")?;
+            writeln!(writer, "    Size: {width},{height}")?;
+            writeln!(writer, "    LogicSize: {logical_width}, {logical_height}")?;
+            writeln!(writer, "    Position: {x}, {y}")?;
         }
+        Ok(())
     }
 
     /// Query which `wl_shm::Format` the compositor supports for this output by performing a trial screenshot through wlr-screencopy protocol.
@@ -367,6 +1019,80 @@ impl WayshotConnection {
         Ok(state.formats)
     }
 
+    /// Estimate how many bytes capturing `outputs` into one image needs at its
+    /// peak, before capturing anything.
+    ///
+    /// Negotiates the frame format with the compositor for every output
+    /// without copying a frame, and adds the RGBA image each frame is
+    /// converted to and the composited image at the highest scale.
+    pub fn estimate_capture_bytes(
+        &self,
+        outputs: &[OutputInfo],
+        cursor_overlay: bool,
+    ) -> Result<u64> {
+        let rgba_bytes = |width: f64, height: f64| (width * height).ceil() as u64 * 4;
+        let mut bytes = 0;
+        for output_info in outputs {
+            let (_, _, _, frame_format) = self.capture_output_frame_get_state_shm(
+                cursor_overlay as i32,
+                &output_info.wl_output,
+                None,
+            )?;
+            let Size { width, height } = frame_format.size;
+            bytes += frame_format.byte_size() + rgba_bytes(width as f64, height as f64);
+        }
+
+        let region = LogicalRegion::try_from(outputs)?;
+        let scale = outputs.iter().map(OutputInfo::scale).fold(1.0, f64::max);
+        let Size { width, height } = region.inner.size;
+        Ok(bytes + rgba_bytes(width as f64 * scale, height as f64 * scale))
+    }
+
+    /// Bytes of shared memory held by capture buffers of this connection that
+    /// are still alive.
+    pub fn current_resident_capture_bytes(&self) -> u64 {
+        self.resident_capture_bytes.load(Ordering::Relaxed)
+    }
+
+    /// Events dispatched on the event queues of captures of this connection
+    /// so far.
+    ///
+    /// Capture queues only hold the capture protocol objects and their
+    /// buffers, so this grows by a handful of events per frame however busy
+    /// the compositor is, e.g. the buffer formats, `buffer_done`, `flags` and
+    /// `ready` for wlr-screencopy.
+    pub fn capture_queue_events(&self) -> u64 {
+        self.capture_queue_events.load(Ordering::Relaxed)
+    }
+
+    /// Block until events arrive on the queue of a capture and dispatch them,
+    /// counting them for [`Self::capture_queue_events`].
+    fn dispatch_capture(
+        &self,
+        event_queue: &mut EventQueue<CaptureFrameState>,
+        state: &mut CaptureFrameState,
+    ) -> std::result::Result<(), DispatchError> {
+        let events = event_queue.blocking_dispatch(state)?;
+        self.capture_queue_events
+            .fetch_add(events as u64, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// The wl_shm capture buffers are created from.
+    ///
+    /// It is bound once on a queue of its own and its format events are
+    /// drained there, instead of binding a new one on every capture queue
+    /// which then receives all the formats again.
+    fn capture_shm(&self) -> Result<WlShm> {
+        if let Some(shm) = self.capture_shm.get() {
+            return Ok(shm.clone());
+        }
+        let mut event_queue = self.conn.new_event_queue::<CaptureFrameState>();
+        let shm = self.bind_shm(&event_queue.handle())?;
+        event_queue.roundtrip(&mut CaptureFrameState::new(false))?;
+        Ok(self.capture_shm.get_or_init(|| shm).clone())
+    }
+
     /// Captures a screenshot into a shared memory buffer using a specified format, if available, and writes pixel data in the provided file descriptor.
     /// This function uses wlr-screencopy protocol to capture pixel data from a `WlOutput`.
     /// # Parameters
@@ -379,6 +1105,7 @@ impl WayshotConnection {
     /// - A [`FrameGuard`] instance that holds the screen capture result, if screen capture is successful and frame_format is supported.
     /// - [`Error::FramecopyFailed`] if screen capture fails.
     /// - [`Error::NoSupportedBufferFormat`] if frame_format is not supported for the given output.
+    /// - [`Error::NoFormatsAdvertised`] if the compositor advertised no formats at all.
     pub fn capture_output_frame_shm_fd_with_format<T: AsFd>(
         &self,
         cursor_overlay: i32,
@@ -399,7 +1126,9 @@ impl WayshotConnection {
                 self.image_copy_frame_inner(state, event_queue, frame, format, fd)?;
             Ok(frame_guard)
         } else {
-            Err(Error::NoSupportedBufferFormat)
+            Err(Error::no_usable_format(
+                state.formats.iter().map(|format| format.format),
+            ))
         }
     }
     /// Get a FrameCopy instance with screenshot pixel data for any wl_output object.
@@ -430,6 +1159,7 @@ impl WayshotConnection {
             self.capture_output_frame_get_state_shm(cursor_overlay as i32, output, capture_region)?;
 
         file.set_len(frame_format.byte_size())?;
+        seal_shm_size(file);
 
         let frame_guard =
             self.image_copy_frame_inner(state, event_queue, frame, frame_format, file)?;
@@ -622,11 +1352,7 @@ impl WayshotConnection {
         };
         let (state, event_queue, frame) =
             self.capture_target_frame_get_state(cursor_overlay, target, capture_region)?;
-        if state.dmabuf_formats.is_empty() {
-            return Err(Error::NoSupportedBufferFormat);
-        }
-
-        let frame_format = state.dmabuf_formats[0];
+        let frame_format = state.first_dmabuf_format()?;
         tracing::trace!("Selected frame buffer format: {:#?}", frame_format);
         let gbm = &dmabuf_state.gbmdev;
         let bo = gbm.create_buffer_object::<()>(
@@ -675,23 +1401,18 @@ impl WayshotConnection {
     )> {
         let qh = event_queue.handle();
 
-        // Instantiating screencopy manager.
-        let screencopy_manager = match self.globals.bind::<ZwlrScreencopyManagerV1, _, _>(
-            &qh,
-            3..=3,
-            (),
-        ) {
-            Ok(x) => x,
-            Err(e) => {
+        let feature = if capture_region.is_some() {
+            "capturing regions"
+        } else {
+            "capturing outputs"
+        };
+        let screencopy_manager = self
+            .bind_screencopy_manager(&qh, MIN_SCREENCOPY_VERSION, feature)
+            .inspect_err(|_| {
                 tracing::error!(
                     "Failed to create screencopy manager. Does your compositor implement ZwlrScreencopy?"
                 );
-                tracing::error!("err: {e}");
-                return Err(Error::ProtocolNotFound(
-                    "ZwlrScreencopy Manager not found".to_string(),
-                ));
-            }
-        };
+            })?;
 
         tracing::debug!("Capturing output(shm buffer)...");
         let frame = if let Some(embedded_region) = capture_region {
@@ -712,7 +1433,7 @@ impl WayshotConnection {
         // Empty internal event buffer until buffer_done is set to true which is when the Buffer done
         // event is fired, aka the capture from the compositor is successful.
         while !state.buffer_done.load(Ordering::SeqCst) {
-            event_queue.blocking_dispatch(&mut state)?;
+            self.dispatch_capture(&mut event_queue, &mut state)?;
         }
 
         tracing::trace!(
@@ -737,7 +1458,11 @@ impl WayshotConnection {
         let qh = event_queue.handle();
         let output_management = self
             .globals
-            .bind::<ExtOutputImageCaptureSourceManagerV1, _, _>(&qh, 1..=1, ())
+            .bind::<ExtOutputImageCaptureSourceManagerV1, _, _>(
+                &qh,
+                MIN_IMAGE_CAPTURE_SOURCE_VERSION..=MIN_IMAGE_CAPTURE_SOURCE_VERSION,
+                (),
+            )
             .map_err(|_| {
                 Error::ProtocolNotFound("ExtOutputImageCaptureSourceManagerV1".to_string())
             })?;
@@ -747,7 +1472,7 @@ impl WayshotConnection {
         let session = manager.create_session(&source, options, &qh, ());
         let frame = session.create_frame(&qh, ());
         while !state.session_done {
-            event_queue.blocking_dispatch(&mut state)?;
+            self.dispatch_capture(&mut event_queue, &mut state)?;
         }
         tracing::trace!(
             "Received compositor frame buffer formats: {:#?}",
@@ -770,33 +1495,25 @@ impl WayshotConnection {
     )> {
         let (state, event_queue, frame) =
             self.capture_output_frame_get_state(cursor_overlay, output, capture_region)?;
-        // Filter advertised wl_shm formats and select the first one that matches.
-        let frame_format = state
-            .formats
-            .iter()
-            .find(|frame| {
-                matches!(
-                    frame.format,
-                    wl_shm::Format::Xbgr2101010
-                        | wl_shm::Format::Abgr2101010
-                        | wl_shm::Format::Argb8888
-                        | wl_shm::Format::Xrgb8888
-                        | wl_shm::Format::Xbgr8888
-                        | wl_shm::Format::Bgr888
-                )
-            })
-            .copied()
-            // Check if frame format exists.
+        // Full output captures are expected to match the output's current mode.
+        let mode_size = capture_region.is_none().then(|| {
+            self.output_infos
+                .iter()
+                .find(|output_info| &output_info.wl_output == output)
+                .map(|output_info| output_info.physical_size)
+        });
+        let frame_format = FrameFormat::select_shm(&state.formats, mode_size.flatten())
             .ok_or_else(|| {
                 tracing::error!("No suitable frame format found");
-                Error::NoSupportedBufferFormat
+                Error::no_usable_format(state.formats.iter().map(|format| format.format))
             })?;
+        let frame_format = self.align_stride(frame_format);
         tracing::trace!("Selected frame buffer format: {:#?}", frame_format);
 
         Ok((state, event_queue, frame, frame_format))
     }
 
-    pub fn capture_target_frame_get_state(
+    pub(crate) fn capture_target_frame_get_state(
         &self,
         cursor_overlay: bool,
         target: &WayshotTarget,
@@ -821,7 +1538,7 @@ impl WayshotConnection {
     // format. Consequently, using PipeWire to capture streams without knowing
     // the current format can lead to color distortion. This function attempts
     // a trial screenshot to determine the screen's properties.
-    pub fn capture_output_frame_get_state(
+    pub(crate) fn capture_output_frame_get_state(
         &self,
         cursor_overlay: i32,
         output: &WlOutput,
@@ -834,10 +1551,11 @@ impl WayshotConnection {
         let state = CaptureFrameState::new(!self.has_gbm());
         let event_queue = self.conn.new_event_queue::<CaptureFrameState>();
         let qh = event_queue.handle();
-        match self
-            .globals
-            .bind::<ExtImageCopyCaptureManagerV1, _, _>(&qh, 1..=1, ())
-        {
+        match self.globals.bind::<ExtImageCopyCaptureManagerV1, _, _>(
+            &qh,
+            MIN_IMAGE_COPY_CAPTURE_VERSION..=MIN_IMAGE_COPY_CAPTURE_VERSION,
+            (),
+        ) {
             Ok(manager) => self.capture_output_frame_get_state_ext(
                 state,
                 event_queue,
@@ -941,11 +1659,13 @@ impl WayshotConnection {
                 match state {
                     FrameState::Failed => {
                         tracing::error!("Frame copy failed");
-                        return Err(Error::FramecopyFailed);
+                        return Err(dmabuf_state.import_error(Error::FramecopyFailed));
                     }
                     FrameState::FailedWithReason(reason) => {
                         tracing::error!("Frame copy failed");
-                        return Err(Error::FramecopyFailedWithReason(reason));
+                        return Err(
+                            dmabuf_state.import_error(Error::FramecopyFailedWithReason(reason))
+                        );
                     }
                     FrameState::Finished => {
                         tracing::trace!("Frame copy finished");
@@ -956,7 +1676,8 @@ impl WayshotConnection {
                 }
             }
 
-            event_queue.blocking_dispatch(&mut state)?;
+            self.dispatch_capture(&mut event_queue, &mut state)
+                .map_err(|error| dmabuf_state.import_error(error.into()))?;
         }
     }
 
@@ -1014,11 +1735,13 @@ impl WayshotConnection {
                 match state {
                     FrameState::Failed => {
                         tracing::error!("Frame copy failed");
-                        return Err(Error::FramecopyFailed);
+                        return Err(dmabuf_state.import_error(Error::FramecopyFailed));
                     }
                     FrameState::FailedWithReason(reason) => {
                         tracing::error!("Frame copy failed");
-                        return Err(Error::FramecopyFailedWithReason(reason));
+                        return Err(
+                            dmabuf_state.import_error(Error::FramecopyFailedWithReason(reason))
+                        );
                     }
                     FrameState::Finished => {
                         tracing::trace!("Frame copy finished");
@@ -1030,7 +1753,8 @@ impl WayshotConnection {
                 }
             }
 
-            event_queue.blocking_dispatch(&mut state)?;
+            self.dispatch_capture(&mut event_queue, &mut state)
+                .map_err(|error| dmabuf_state.import_error(error.into()))?;
         }
     }
 
@@ -1063,26 +1787,8 @@ impl WayshotConnection {
         // Connecting to wayland environment.
         let qh = event_queue.handle();
 
-        // Instantiate shm global.
-        let shm = self.globals.bind::<WlShm, _, _>(&qh, 1..=1, ())?;
-        let shm_pool = shm.create_pool(
-            fd.as_fd(),
-            frame_format
-                .byte_size()
-                .try_into()
-                .map_err(|_| Error::BufferTooSmall)?,
-            &qh,
-            (),
-        );
-        let buffer = shm_pool.create_buffer(
-            0,
-            frame_format.size.width as i32,
-            frame_format.size.height as i32,
-            frame_format.stride as i32,
-            frame_format.format,
-            &qh,
-            (),
-        );
+        let shm = self.capture_shm()?;
+        let (shm_pool, buffer) = create_shm_buffer(&shm, fd.as_fd(), &frame_format, &qh)?;
 
         // Copy the pixel data advertised by the compositor into the buffer we just created.
         frame.copy(&buffer);
@@ -1101,12 +1807,16 @@ impl WayshotConnection {
                             buffer,
                             shm_pool,
                             size: frame_format.size,
+                            _resident: ResidentBytes::new(
+                                &self.resident_capture_bytes,
+                                frame_format.byte_size(),
+                            ),
                         });
                     }
                 }
             }
 
-            event_queue.blocking_dispatch(&mut state)?;
+            self.dispatch_capture(&mut event_queue, &mut state)?;
         }
     }
 
@@ -1121,26 +1831,8 @@ impl WayshotConnection {
         // Connecting to wayland environment.
         let qh = event_queue.handle();
 
-        // Instantiate shm global.
-        let shm = self.globals.bind::<WlShm, _, _>(&qh, 1..=1, ())?;
-        let shm_pool = shm.create_pool(
-            fd.as_fd(),
-            frame_format
-                .byte_size()
-                .try_into()
-                .map_err(|_| Error::BufferTooSmall)?,
-            &qh,
-            (),
-        );
-        let buffer = shm_pool.create_buffer(
-            0,
-            frame_format.size.width as i32,
-            frame_format.size.height as i32,
-            frame_format.stride as i32,
-            frame_format.format,
-            &qh,
-            (),
-        );
+        let shm = self.capture_shm()?;
+        let (shm_pool, buffer) = create_shm_buffer(&shm, fd.as_fd(), &frame_format, &qh)?;
 
         // Copy the pixel data advertised by the compositor into the buffer we just created.
         frame.attach_buffer(&buffer);
@@ -1164,92 +1856,499 @@ impl WayshotConnection {
                             buffer,
                             shm_pool,
                             size: frame_format.size,
+                            _resident: ResidentBytes::new(
+                                &self.resident_capture_bytes,
+                                frame_format.byte_size(),
+                            ),
                         });
                     }
                 }
             }
 
-            event_queue.blocking_dispatch(&mut state)?;
+            self.dispatch_capture(&mut event_queue, &mut state)?;
         }
     }
 
     /// Get a FrameCopy instance with screenshot pixel data for any wl_output object.
-    #[tracing::instrument(skip_all, fields(output = format!("{output_info}"), region = capture_region.map(|r| format!("{r:}")).unwrap_or("fullscreen".to_string())))]
     fn capture_frame_copy(
         &self,
         cursor_overlay: bool,
         output_info: &OutputInfo,
         capture_region: Option<EmbeddedRegion>,
     ) -> Result<(FrameCopy, FrameGuard)> {
-        // Create an in memory file and return it's file descriptor.
-        let fd = create_shm_fd()?;
-        // Create a writeable memory map backed by a mem_file.
-        let mem_file = File::from(fd);
-
-        let (frame_format, frame_guard) = self.capture_output_frame_shm_from_file(
-            cursor_overlay,
-            &output_info.wl_output,
-            &mem_file,
-            capture_region,
-        )?;
-
-        let frame_mmap = unsafe { MmapMut::map_mut(&mem_file)? };
+        if self.wait_for_frame {
+            self.wait_for_frame(slice::from_ref(output_info))?;
+        }
+        let (frame_copy, frame_guard) =
+            self.capture_frame_copy_now(cursor_overlay, output_info, capture_region)?;
+        if self.warn_on_uniform && frame_copy.is_uniform(UNIFORM_FRAME_TOLERANCE)? {
+            tracing::warn!(
+                "Capture of {output_info} is a single colour, the compositor may have failed to copy it"
+            );
+        }
+        Ok((frame_copy, frame_guard))
+    }
 
-        let rotated_physical_size = match output_info.transform {
-            Transform::_90 | Transform::_270 | Transform::Flipped90 | Transform::Flipped270 => {
-                Size {
-                    width: frame_format.size.height,
-                    height: frame_format.size.width,
-                }
-            }
-            _ => frame_format.size,
-        };
-        let frame_copy = FrameCopy {
-            frame_format,
-            frame_color_type: image::ColorType::Rgb8,
-            frame_data: FrameData::Mmap(frame_mmap),
-            transform: output_info.transform,
-            logical_region: capture_region
-                .map(|capture_region| capture_region.logical())
-                .unwrap_or(output_info.logical_region),
-            physical_size: rotated_physical_size,
+    /// Like [`Self::capture_frame_copy`] without waiting for a new frame.
+    #[tracing::instrument(skip_all, fields(output = format!("{output_info}"), region = capture_region.map(|r| format!("{r:}")).unwrap_or("fullscreen".to_string()), capture_id = tracing::field::Empty))]
+    fn capture_frame_copy_now(
+        &self,
+        cursor_overlay: bool,
+        output_info: &OutputInfo,
+        capture_region: Option<EmbeddedRegion>,
+    ) -> Result<(FrameCopy, FrameGuard)> {
+        let capture_id = self.next_capture_id();
+        let mut attempt = 1;
+        let (mem_file, frame_format, frame_guard) = loop {
+            // Every attempt gets a fresh shm file, as the last one is sealed at
+            // the size of a format that may have changed since.
+            let mem_file = File::from(create_shm_fd()?);
+            match self.capture_output_frame_shm_from_file(
+                cursor_overlay,
+                &output_info.wl_output,
+                &mem_file,
+                capture_region,
+            ) {
+                Err(Error::FramecopyFailed | Error::FramecopyFailedWithReason(_))
+                    if attempt < CAPTURE_ATTEMPTS =>
+                {
+                    tracing::debug!("Capture attempt {attempt} of {output_info} failed, retrying");
+                    attempt += 1;
+                }
+                Err(e @ (Error::FramecopyFailed | Error::FramecopyFailedWithReason(_))) => {
+                    // Compositors refuse every capture while the session is locked.
+                    return Err(match self.session_locked() {
+                        Ok(true) => {
+                            tracing::debug!("All capture attempts failed ({e}), session is locked");
+                            Error::SessionLocked
+                        }
+                        Ok(false) => e,
+                        Err(lock_error) => {
+                            tracing::debug!("Failed to probe the session lock: {lock_error}");
+                            e
+                        }
+                    });
+                }
+                result => {
+                    let (frame_format, frame_guard) = result?;
+                    break (mem_file, frame_format, frame_guard);
+                }
+            }
         };
+
+        let frame_mmap = map_shm_frame(&mem_file, frame_format)?;
+        let frame_copy = output_frame_copy(
+            output_info,
+            frame_format,
+            frame_mmap,
+            capture_region,
+            capture_id,
+        );
         tracing::debug!("Created frame copy: {:#?}", frame_copy);
+        self.set_last_color_handling(ColorHandling::of_format(frame_format.format));
+        self.report_progress(|| ProgressEvent::OutputCaptured {
+            output: output_info.name.clone(),
+            capture_id,
+        });
         Ok((frame_copy, frame_guard))
     }
 
+    /// Capture the output over and over, handing every frame to `on_frame`
+    /// until `stop` is set. Returns the first error of a capture or of the callback.
+    ///
+    /// A single shared memory buffer is reused for all frames and the frame
+    /// format is negotiated again for every capture, so changes in size or
+    /// format are picked up.
+    ///
+    /// `on_frame` runs on the capturing thread and the next capture only starts
+    /// once it returns, so keep it fast. The next capture overwrites the pixel
+    /// data of the frame, [`FrameCopy::detach`] frames that have to outlive the
+    /// callback.
+    pub fn capture_loop<F>(
+        &self,
+        output_info: &OutputInfo,
+        cursor_overlay: bool,
+        stop: &AtomicBool,
+        mut on_frame: F,
+    ) -> Result<()>
+    where
+        F: FnMut(FrameCopy) -> Result<()>,
+    {
+        let mem_file = File::from(create_shm_fd()?);
+        let mut previous_format = None;
+
+        while !stop.load(Ordering::Relaxed) {
+            on_frame(self.capture_loop_frame(
+                output_info,
+                cursor_overlay,
+                &mem_file,
+                &mut previous_format,
+            )?)?;
+        }
+
+        tracing::debug!("Capture loop stopped");
+        Ok(())
+    }
+
+    /// Like [`Self::capture_loop`], but capture into a pool of `buffers` shared
+    /// memory buffers and run `on_frame` on another thread, so one frame is
+    /// handled, e.g. encoded, while the next is captured into another buffer.
+    ///
+    /// A buffer returns to the pool once `on_frame` returns, and capturing
+    /// waits while every buffer is still being handled. The pool costs
+    /// `buffers` times the size of a frame in memory, about 33 MB per buffer
+    /// for a 3840x2160 output. Two or three buffers are enough to overlap
+    /// capturing with handling, one makes this as slow as [`Self::capture_loop`].
+    pub fn capture_loop_pipelined<F>(
+        &self,
+        output_info: &OutputInfo,
+        cursor_overlay: bool,
+        stop: &AtomicBool,
+        buffers: NonZeroUsize,
+        mut on_frame: F,
+    ) -> Result<()>
+    where
+        F: FnMut(FrameCopy) -> Result<()> + Send,
+    {
+        let mem_files = (0..buffers.get())
+            .map(|_| Ok(File::from(create_shm_fd()?)))
+            .collect::<Result<Vec<_>>>()?;
+        let (free_sender, free_receiver) = mpsc::channel();
+        for index in 0..buffers.get() {
+            free_sender.send(index).expect("the receiver is alive");
+        }
+        let (frame_sender, frame_receiver) =
+            mpsc::sync_channel::<(usize, FrameCopy)>(buffers.get());
+
+        thread::scope(|scope| {
+            let handler = scope.spawn(move || -> Result<()> {
+                for (index, frame_copy) in frame_receiver {
+                    // Unmaps the buffer before it is captured into again.
+                    on_frame(frame_copy)?;
+                    if free_sender.send(index).is_err() {
+                        break;
+                    }
+                }
+                Ok(())
+            });
+
+            let mut previous_format = None;
+            let captured = (|| {
+                while !stop.load(Ordering::Relaxed) {
+                    // Fails once the handler stopped with an error.
+                    let Ok(index) = free_receiver.recv() else {
+                        break;
+                    };
+                    let frame_copy = self.capture_loop_frame(
+                        output_info,
+                        cursor_overlay,
+                        &mem_files[index],
+                        &mut previous_format,
+                    )?;
+                    if frame_sender.send((index, frame_copy)).is_err() {
+                        break;
+                    }
+                }
+                Ok(())
+            })();
+            // Let the handler finish the frames that are still queued.
+            drop(frame_sender);
+            let handled = handler
+                .join()
+                .unwrap_or_else(|panic| std::panic::resume_unwind(panic));
+            tracing::debug!("Pipelined capture loop stopped");
+            captured.and(handled)
+        })
+    }
+
+    /// Capture one frame of a capture loop into `mem_file`, growing it for the
+    /// frame format when needed.
+    fn capture_loop_frame(
+        &self,
+        output_info: &OutputInfo,
+        cursor_overlay: bool,
+        mem_file: &File,
+        previous_format: &mut Option<FrameFormat>,
+    ) -> Result<FrameCopy> {
+        let (state, event_queue, frame, frame_format) = self.capture_output_frame_get_state_shm(
+            cursor_overlay as i32,
+            &output_info.wl_output,
+            None,
+        )?;
+        if previous_format.is_some_and(|previous| previous != frame_format) {
+            tracing::debug!("Frame format changed to {frame_format:?}");
+        }
+        *previous_format = Some(frame_format);
+
+        // The shm file is sealed against shrinking, so only ever grow it.
+        if mem_file.metadata()?.len() < frame_format.byte_size() {
+            mem_file.set_len(frame_format.byte_size())?;
+        }
+        // The compositor is done with the buffer once the copy finished, the
+        // pixels stay in `mem_file`.
+        let _frame_guard =
+            self.image_copy_frame_inner(state, event_queue, frame, frame_format, mem_file)?;
+
+        let frame_mmap = map_shm_frame(mem_file, frame_format)?;
+        Ok(output_frame_copy(
+            output_info,
+            frame_format,
+            frame_mmap,
+            None,
+            self.next_capture_id(),
+        ))
+    }
+
+    /// Like [`Self::capture_loop`], but start a capture only every `1 / fps`
+    /// seconds, see [`FramePacer`]. Returns how many frames were captured and
+    /// the frame rate that was achieved once `stop` is set.
+    pub fn capture_loop_paced<F>(
+        &self,
+        output_info: &OutputInfo,
+        cursor_overlay: bool,
+        stop: &AtomicBool,
+        fps: f64,
+        mut on_frame: F,
+    ) -> Result<LoopStats>
+    where
+        F: FnMut(FrameCopy) -> Result<()>,
+    {
+        let mut pacer = FramePacer::new(fps)?;
+        self.capture_loop(output_info, cursor_overlay, stop, |frame| {
+            on_frame(frame)?;
+            pacer.wait();
+            Ok(())
+        })?;
+        Ok(pacer.stats())
+    }
+
+    /// Like [`Self::capture_loop`], but every capture waits until the compositor
+    /// reports damage on the output, so a static screen produces no frames and
+    /// leaves the process idle. The first frame is copied right away.
+    ///
+    /// `on_frame` also gets the regions that changed since the previous frame,
+    /// in the logical coordinate space, see [`FrameMeta::damage_logical`].
+    ///
+    /// Needs wlr-screencopy with `copy_with_damage`, version
+    /// [`environment::MIN_SCREENCOPY_FOR_DAMAGE`], as implemented by wlroots
+    /// based compositors such as Sway, Hyprland and river. Returns
+    /// [`Error::ProtocolNotFound`] otherwise, e.g. on compositors that only
+    /// offer ext-image-copy-capture, and [`Error::Unsupported`] if the
+    /// [`Self::screencopy_version`] is too old. `stop` is also checked every
+    /// 100 ms while waiting for damage.
+    pub fn capture_on_damage<F>(
+        &self,
+        output_info: &OutputInfo,
+        cursor_overlay: bool,
+        stop: &AtomicBool,
+        mut on_frame: F,
+    ) -> Result<()>
+    where
+        F: FnMut(FrameCopy, &[LogicalRegion]) -> Result<()>,
+    {
+        let mut event_queue = self.conn.new_event_queue::<CaptureFrameState>();
+        let qh = event_queue.handle();
+        // Compositors track the damage per manager, so all frames share one.
+        let screencopy_manager = self.bind_screencopy_manager(
+            &qh,
+            MIN_SCREENCOPY_VERSION.max(environment::MIN_SCREENCOPY_FOR_DAMAGE),
+            "capturing on damage",
+        )?;
+        let shm = self.capture_shm()?;
+        let mem_file = File::from(create_shm_fd()?);
+        let mut buffer: Option<(FrameFormat, FrameGuard)> = None;
+
+        while !stop.load(Ordering::Relaxed) {
+            let mut state = CaptureFrameState::new(false);
+            let frame = screencopy_manager.capture_output(
+                cursor_overlay as i32,
+                &output_info.wl_output,
+                &qh,
+                (),
+            );
+            while !state.buffer_done.load(Ordering::SeqCst) {
+                self.dispatch_capture(&mut event_queue, &mut state)?;
+            }
+            let frame_format =
+                FrameFormat::select_shm(&state.formats, Some(output_info.physical_size))
+                    .ok_or_else(|| {
+                        Error::no_usable_format(state.formats.iter().map(|format| format.format))
+                    })?;
+            let frame_format = self.align_stride(frame_format);
+
+            let frame_guard = match buffer {
+                Some((format, ref frame_guard)) if format == frame_format => frame_guard,
+                _ => {
+                    tracing::debug!("Creating buffer for {frame_format:?}");
+                    // Drop the previous buffer before its memory is resized.
+                    buffer = None;
+                    // The shm file is sealed against shrinking, so only ever grow it.
+                    if mem_file.metadata()?.len() < frame_format.byte_size() {
+                        mem_file.set_len(frame_format.byte_size())?;
+                    }
+                    let (shm_pool, shm_buffer) =
+                        create_shm_buffer(&shm, mem_file.as_fd(), &frame_format, &qh)?;
+                    let frame_guard = FrameGuard {
+                        buffer: shm_buffer,
+                        shm_pool,
+                        size: frame_format.size,
+                        _resident: ResidentBytes::new(
+                            &self.resident_capture_bytes,
+                            frame_format.byte_size(),
+                        ),
+                    };
+                    &buffer.insert((frame_format, frame_guard)).1
+                }
+            };
+
+            frame.copy_with_damage(&frame_guard.buffer);
+            while state.state.is_none() {
+                if stop.load(Ordering::Relaxed) {
+                    frame.destroy();
+                    tracing::debug!("Damage capture stopped while waiting for damage");
+                    return Ok(());
+                }
+                let events = dispatch_with_timeout(
+                    &mut event_queue,
+                    &mut state,
+                    DAMAGE_STOP_CHECK_INTERVAL,
+                )?;
+                self.capture_queue_events
+                    .fetch_add(events as u64, Ordering::Relaxed);
+            }
+            frame.destroy();
+            if state.state != Some(FrameState::Finished) {
+                tracing::error!("Frame copy failed");
+                return Err(Error::FramecopyFailed);
+            }
+
+            let frame_mmap = map_shm_frame(&mem_file, frame_format)?;
+            let frame_copy = output_frame_copy(
+                output_info,
+                frame_format,
+                frame_mmap,
+                None,
+                self.next_capture_id(),
+            );
+            let damage = frame_copy.meta().damage_logical(&state.damage);
+            on_frame(frame_copy, &damage)?;
+        }
+
+        tracing::debug!("Damage capture stopped");
+        Ok(())
+    }
+
+    /// Like [`Self::capture_on_damage`] with the cursor, but damage within
+    /// `exclusion_radius` logical pixels of the cursor is left out, so moving
+    /// the pointer over a static screen doesn't show up as changed content.
+    /// Both where the cursor was and where it is now are left out, so the
+    /// radius should cover the cursor image.
+    ///
+    /// The frames are captured without the cursor, `on_frame` gets it
+    /// separately to draw with [`FrameCursor::draw`], none while it isn't over
+    /// the output. When only the cursor moved, the damage is empty and only
+    /// the cursor needs to be drawn again.
+    ///
+    /// The cursor is read after every frame with a cursor session of
+    /// ext-image-copy-capture, so next to the protocols of
+    /// [`Self::capture_on_damage`] this needs that one, failing with
+    /// [`Error::ProtocolNotFound`] without it.
+    pub fn capture_on_damage_excluding_cursor<F>(
+        &self,
+        output_info: &OutputInfo,
+        exclusion_radius: u32,
+        stop: &AtomicBool,
+        mut on_frame: F,
+    ) -> Result<()>
+    where
+        F: FnMut(FrameCopy, &[LogicalRegion], Option<&FrameCursor>) -> Result<()>,
+    {
+        if !self.cursor_sessions_supported() {
+            return Err(Error::ProtocolNotFound(
+                "ext-image-copy-capture cursor sessions, to leave the cursor out of the damage"
+                    .to_string(),
+            ));
+        }
+        let mut previous_hotspot = None;
+        self.capture_on_damage(output_info, false, stop, |frame, damage| {
+            let cursor = self
+                .cursor_plane(output_info)?
+                .map(|plane| FrameCursor::new(&plane, output_info));
+            let hotspot = cursor.as_ref().map(|cursor| cursor.position);
+            let hotspots: Vec<_> = previous_hotspot.into_iter().chain(hotspot).collect();
+            previous_hotspot = hotspot;
+            let damage = cursor::damage_outside_cursor(damage, &hotspots, exclusion_radius);
+            on_frame(frame, &damage, cursor.as_ref())
+        })
+    }
+
     pub fn capture_frame_copies(
         &self,
         output_capture_regions: &[(OutputInfo, Option<EmbeddedRegion>)],
         cursor_overlay: bool,
     ) -> Result<Vec<(FrameCopy, FrameGuard, OutputInfo)>> {
-        output_capture_regions
+        if self.wait_for_frame {
+            let outputs: Vec<_> = output_capture_regions
+                .iter()
+                .map(|(output_info, _)| output_info.clone())
+                .collect();
+            self.wait_for_frame(&outputs)?;
+        }
+        let frame_copies: Vec<_> = output_capture_regions
             .iter()
             .map(|(output_info, capture_region)| {
-                self.capture_frame_copy(cursor_overlay, output_info, *capture_region)
+                self.capture_frame_copy_now(cursor_overlay, output_info, *capture_region)
                     .map(|(frame_copy, frame_guard)| (frame_copy, frame_guard, output_info.clone()))
             })
-            .collect()
+            .collect::<Result<_>>()?;
+        if let Some(color_handling) = frame_copies
+            .iter()
+            .map(|(frame_copy, _, _)| ColorHandling::of_format(frame_copy.frame_format.format))
+            .reduce(ColorHandling::combine)
+        {
+            self.set_last_color_handling(color_handling);
+        }
+        Ok(frame_copies)
     }
 
-    /// Create a layer shell surface for each output,
-    /// render the screen captures on them and use the callback to select a region from them
-    fn overlay_frames_and_select_region<F>(
+    /// Bind wlr-screencopy in [`Self::screencopy_version`], which `feature`
+    /// needs to be at least `min_version`.
+    fn bind_screencopy_manager(
         &self,
-        frames: &[(FrameCopy, FrameGuard, OutputInfo)],
-        callback: F,
-    ) -> Result<LogicalRegion>
+        qh: &QueueHandle<CaptureFrameState>,
+        min_version: u32,
+        feature: &str,
+    ) -> Result<ZwlrScreencopyManagerV1> {
+        check_screencopy_version(self.screencopy_version, min_version, feature)?;
+        let version = self.screencopy_version;
+        Ok(self
+            .globals
+            .bind::<ZwlrScreencopyManagerV1, _, _>(qh, version..=version, ())?)
+    }
+
+    /// Bind `wl_shm`, which every capture into shared memory needs.
+    pub(crate) fn bind_shm<D>(&self, qh: &QueueHandle<D>) -> Result<WlShm>
     where
-        F: Fn(&WayshotConnection) -> Result<LogicalRegion, Error>,
+        D: Dispatch<WlShm, ()> + 'static,
     {
-        let mut state = LayerShellState {
-            configured_outputs: HashSet::new(),
-        };
-        let mut event_queue: EventQueue<LayerShellState> =
-            self.conn.new_event_queue::<LayerShellState>();
-        let qh = event_queue.handle();
+        self.globals
+            .bind::<WlShm, _, _>(qh, 1..=1, ())
+            .map_err(|e| {
+                tracing::error!("Failed to bind wl_shm: {e}");
+                shm_bind_error(e)
+            })
+    }
 
-        let compositor = match self.globals.bind::<WlCompositor, _, _>(&qh, 3..=3, ()) {
+    /// Bind the globals needed to show overlay surfaces on top of the outputs.
+    fn bind_overlay_globals(
+        &self,
+        qh: &QueueHandle<LayerShellState>,
+    ) -> Result<(WlCompositor, ZwlrLayerShellV1)> {
+        let compositor = match self.globals.bind::<WlCompositor, _, _>(
+            qh,
+            MIN_COMPOSITOR_VERSION..=MIN_COMPOSITOR_VERSION,
+            (),
+        ) {
             Ok(x) => x,
             Err(e) => {
                 tracing::error!(
@@ -1261,7 +2360,11 @@ impl WayshotConnection {
                 ));
             }
         };
-        let layer_shell = match self.globals.bind::<ZwlrLayerShellV1, _, _>(&qh, 1..=1, ()) {
+        let layer_shell = match self.globals.bind::<ZwlrLayerShellV1, _, _>(
+            qh,
+            MIN_LAYER_SHELL_VERSION..=MIN_LAYER_SHELL_VERSION,
+            (),
+        ) {
             Ok(x) => x,
             Err(e) => {
                 tracing::error!(
@@ -1273,6 +2376,59 @@ impl WayshotConnection {
                 ));
             }
         };
+        Ok((compositor, layer_shell))
+    }
+
+    /// Create a fullscreen overlay layer surface on the output and wait until it is configured.
+    fn create_overlay_surface(
+        compositor: &WlCompositor,
+        layer_shell: &ZwlrLayerShellV1,
+        output_info: &OutputInfo,
+        state: &mut LayerShellState,
+        event_queue: &mut EventQueue<LayerShellState>,
+    ) -> Result<(WlSurface, ZwlrLayerSurfaceV1)> {
+        let qh = event_queue.handle();
+        let surface = compositor.create_surface(&qh, ());
+
+        let layer_surface = layer_shell.get_layer_surface(
+            &surface,
+            Some(&output_info.wl_output),
+            Layer::Overlay,
+            "wayshot".to_string(),
+            &qh,
+            output_info.wl_output.clone(),
+        );
+
+        layer_surface.set_exclusive_zone(-1);
+        layer_surface.set_anchor(Anchor::all());
+
+        debug!("Committing surface creation changes.");
+        surface.commit();
+
+        debug!("Waiting for layer surface to be configured.");
+        while !state.configured_outputs.contains(&output_info.wl_output) {
+            event_queue.blocking_dispatch(state)?;
+        }
+        Ok((surface, layer_surface))
+    }
+
+    /// Create a layer shell surface for each output,
+    /// render the screen captures on them and use the callback to select a region from them
+    fn overlay_frames_and_select_region<F>(
+        &self,
+        frames: &[(FrameCopy, FrameGuard, OutputInfo)],
+        callback: F,
+        options: &FreezeOptions,
+    ) -> Result<LogicalRegion>
+    where
+        F: Fn(&WayshotConnection) -> Result<LogicalRegion, Error>,
+    {
+        let mut state = LayerShellState::default();
+        let mut event_queue: EventQueue<LayerShellState> =
+            self.conn.new_event_queue::<LayerShellState>();
+        let qh = event_queue.handle();
+
+        let (compositor, layer_shell) = self.bind_overlay_globals(&qh)?;
         let viewporter = self.globals.bind::<WpViewporter, _, _>(&qh, 1..=1, ()).ok();
         if viewporter.is_none() {
             tracing::info!(
@@ -1280,40 +2436,47 @@ impl WayshotConnection {
             );
         }
 
+        let shm = self.bind_shm(&qh)?;
+
         let mut layer_shell_surfaces = Vec::with_capacity(frames.len());
+        // Shaded copies of the frames, the captured buffers stay untouched.
+        let mut overlay_surfaces = Vec::new();
 
-        for (_, frame_guard, output_info) in frames {
+        for (frame_copy, frame_guard, output_info) in frames {
             tracing::span!(
                 tracing::Level::DEBUG,
                 "overlay_frames::surface",
                 output = format!("{output_info}")
             )
             .in_scope(|| -> Result<()> {
-                let surface = compositor.create_surface(&qh, ());
-
-                let layer_surface = layer_shell.get_layer_surface(
-                    &surface,
-                    Some(&output_info.wl_output),
-                    Layer::Overlay,
-                    "wayshot".to_string(),
-                    &qh,
-                    output_info.wl_output.clone(),
-                );
-
-                layer_surface.set_exclusive_zone(-1);
-                layer_surface.set_anchor(Anchor::all());
-
-                debug!("Committing surface creation changes.");
-                surface.commit();
-
-                debug!("Waiting for layer surface to be configured.");
-                while !state.configured_outputs.contains(&output_info.wl_output) {
-                    event_queue.blocking_dispatch(&mut state)?;
-                }
+                let (surface, layer_surface) = Self::create_overlay_surface(
+                    &compositor,
+                    &layer_shell,
+                    output_info,
+                    &mut state,
+                    &mut event_queue,
+                )?;
 
                 surface.set_buffer_transform(output_info.transform);
                 // surface.set_buffer_scale(output_info.scale());
-                surface.attach(Some(&frame_guard.buffer), 0, 0);
+                if options.is_unshaded() {
+                    surface.attach(Some(&frame_guard.buffer), 0, 0);
+                } else {
+                    let frame = options.overlay_frame(&frame_copy.view()?);
+                    let buffer = Self::create_overlay_buffer(
+                        &shm,
+                        frame_copy.frame_format.size,
+                        &frame.pixels(None),
+                        &qh,
+                    )?;
+                    surface.attach(Some(&buffer.1), 0, 0);
+                    overlay_surfaces.push(OverlaySurface {
+                        surface: surface.clone(),
+                        meta: frame_copy.meta(),
+                        frame,
+                        buffer,
+                    });
+                }
 
                 if let Some(viewporter) = viewporter.as_ref() {
                     let viewport = viewporter.get_viewport(&surface, &qh, ());
@@ -1332,7 +2495,14 @@ impl WayshotConnection {
             })?;
         }
 
+        // Nested freezes restore the overlay of the outer one afterwards.
+        let previous_overlay = self.set_freeze_overlay(Some(FreezeOverlay {
+            shm,
+            qh,
+            surfaces: overlay_surfaces,
+        }));
         let callback_result = callback(self);
+        let overlay = self.set_freeze_overlay(previous_overlay);
 
         debug!("Unmapping and destroying layer shell surfaces.");
         for (surface, layer_shell_surface) in layer_shell_surfaces.iter() {
@@ -1341,10 +2511,185 @@ impl WayshotConnection {
             layer_shell_surface.destroy();
         }
         event_queue.roundtrip(&mut state)?;
+        for OverlaySurface {
+            buffer: (shm_pool, buffer),
+            ..
+        } in overlay.into_iter().flat_map(|overlay| overlay.surfaces)
+        {
+            buffer.destroy();
+            shm_pool.destroy();
+        }
 
         callback_result
     }
 
+    fn set_freeze_overlay(&self, overlay: Option<FreezeOverlay>) -> Option<FreezeOverlay> {
+        std::mem::replace(
+            &mut *self
+                .freeze_overlay
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner),
+            overlay,
+        )
+    }
+
+    /// A shm buffer of `size` showing the `Xrgb8888` `pixels`.
+    fn create_overlay_buffer(
+        shm: &WlShm,
+        size: Size,
+        pixels: &[u8],
+        qh: &QueueHandle<LayerShellState>,
+    ) -> Result<(WlShmPool, WlBuffer)> {
+        let frame_format = FrameFormat {
+            format: wl_shm::Format::Xrgb8888,
+            size,
+            stride: size.width * 4,
+        };
+        let mut mem_file = File::from(create_shm_fd()?);
+        mem_file.write_all(pixels)?;
+        create_shm_buffer(shm, mem_file.as_fd(), &frame_format, qh)
+    }
+
+    /// Wait until the compositor presented a new frame on each of the outputs,
+    /// so a capture right after gets a fresh frame instead of one that may
+    /// still show a half finished UI change.
+    ///
+    /// Maps a transparent layer surface on every output and waits for its
+    /// `wl_surface.frame` callback. This adds a compositor roundtrip plus up to
+    /// one refresh interval of latency, e.g. up to about 17ms at 60Hz. Outputs
+    /// that are off never present a frame, so don't wait on those.
+    pub fn wait_for_frame(&self, outputs: &[OutputInfo]) -> Result<()> {
+        if outputs.is_empty() {
+            return Ok(());
+        }
+        let mut state = LayerShellState::default();
+        let mut event_queue: EventQueue<LayerShellState> =
+            self.conn.new_event_queue::<LayerShellState>();
+        let qh = event_queue.handle();
+
+        let (compositor, layer_shell) = self.bind_overlay_globals(&qh)?;
+        let shm = self.bind_shm(&qh)?;
+        let mut mem_file = File::from(create_shm_fd()?);
+        mem_file.write_all(&[0; 4])?;
+        let pool = shm.create_pool(mem_file.as_fd(), 4, &qh, ());
+        let buffer = pool.create_buffer(0, 1, 1, 4, wl_shm::Format::Argb8888, &qh, ());
+
+        let mut surfaces = Vec::with_capacity(outputs.len());
+        for output_info in outputs {
+            let (surface, layer_surface) = Self::create_overlay_surface(
+                &compositor,
+                &layer_shell,
+                output_info,
+                &mut state,
+                &mut event_queue,
+            )?;
+            let input_region = compositor.create_region(&qh, ());
+            surface.set_input_region(Some(&input_region));
+            input_region.destroy();
+            surface.attach(Some(&buffer), 0, 0);
+            surface.frame(&qh, ());
+            surface.commit();
+            surfaces.push((surface, layer_surface));
+        }
+
+        debug!("Waiting for a frame on {} output(s).", outputs.len());
+        while state.frames_done < surfaces.len() {
+            event_queue.blocking_dispatch(&mut state)?;
+        }
+
+        for (surface, layer_surface) in surfaces {
+            layer_surface.destroy();
+            surface.destroy();
+        }
+        buffer.destroy();
+        pool.destroy();
+        event_queue.roundtrip(&mut state)?;
+        Ok(())
+    }
+
+    /// Briefly show a white translucent overlay on the outputs that fades out
+    /// over `duration`, as feedback that a screenshot was taken.
+    ///
+    /// Only call this once the capture finished, the flash would end up in it otherwise.
+    pub fn flash_outputs(&self, outputs: &[OutputInfo], duration: Duration) -> Result<()> {
+        if outputs.is_empty() {
+            return Ok(());
+        }
+        let mut state = LayerShellState::default();
+        let mut event_queue: EventQueue<LayerShellState> =
+            self.conn.new_event_queue::<LayerShellState>();
+        let qh = event_queue.handle();
+
+        let (compositor, layer_shell) = self.bind_overlay_globals(&qh)?;
+        let viewporter = self
+            .globals
+            .bind::<WpViewporter, _, _>(&qh, 1..=1, ())
+            .map_err(|_| Error::ProtocolNotFound("WpViewporter not found".to_string()))?;
+        let shm = self.bind_shm(&qh)?;
+
+        // A premultiplied white pixel per fade step, stretched over the output by a viewport.
+        let pixels: Vec<u8> = (0..FLASH_STEPS)
+            .flat_map(|step| {
+                let alpha = FLASH_ALPHA as u32 * (FLASH_STEPS - step) as u32 / FLASH_STEPS as u32;
+                [alpha as u8; 4]
+            })
+            .collect();
+        let mut mem_file = File::from(create_shm_fd()?);
+        mem_file.write_all(&pixels)?;
+        let pool = shm.create_pool(mem_file.as_fd(), pixels.len() as i32, &qh, ());
+        let buffers: Vec<_> = (0..FLASH_STEPS)
+            .map(|step| {
+                pool.create_buffer(step as i32 * 4, 1, 1, 4, wl_shm::Format::Argb8888, &qh, ())
+            })
+            .collect();
+
+        let mut surfaces = Vec::with_capacity(outputs.len());
+        for output_info in outputs {
+            let (surface, layer_surface) = Self::create_overlay_surface(
+                &compositor,
+                &layer_shell,
+                output_info,
+                &mut state,
+                &mut event_queue,
+            )?;
+            // Let clicks through to the windows below while flashing.
+            let input_region = compositor.create_region(&qh, ());
+            surface.set_input_region(Some(&input_region));
+            input_region.destroy();
+            let viewport = viewporter.get_viewport(&surface, &qh, ());
+            viewport.set_destination(
+                output_info.logical_region.inner.size.width as i32,
+                output_info.logical_region.inner.size.height as i32,
+            );
+            surfaces.push((surface, layer_surface, viewport));
+        }
+
+        for buffer in &buffers {
+            for (surface, ..) in &surfaces {
+                surface.attach(Some(buffer), 0, 0);
+                surface.damage(0, 0, i32::MAX, i32::MAX);
+                surface.commit();
+            }
+            event_queue.flush().map_err(DispatchError::Backend)?;
+            thread::sleep(duration / FLASH_STEPS as u32);
+        }
+
+        debug!("Unmapping and destroying flash surfaces.");
+        for (surface, layer_surface, viewport) in surfaces {
+            surface.attach(None, 0, 0);
+            surface.commit();
+            viewport.destroy();
+            layer_surface.destroy();
+            surface.destroy();
+        }
+        for buffer in buffers {
+            buffer.destroy();
+        }
+        pool.destroy();
+        event_queue.roundtrip(&mut state)?;
+        Ok(())
+    }
+
     /// Take a screenshot from the specified region.
     #[tracing::instrument(skip_all, fields(max_scale = tracing::field::Empty))]
     fn screenshot_region_capturer(
@@ -1394,9 +2739,10 @@ impl WayshotConnection {
                 RegionCapturer::TopLevel(ref toplevel) => {
                     return self.capture_toplevel(toplevel.as_ref(), cursor_overlay);
                 }
-                RegionCapturer::Freeze(_) => self
+                RegionCapturer::Freeze(_, ref options) => self
                     .get_all_outputs()
                     .iter()
+                    .filter(|output_info| options.shows(output_info))
                     .map(|output_info| (output_info.clone(), None))
                     .collect(),
             };
@@ -1406,8 +2752,8 @@ impl WayshotConnection {
         let capture_region: LogicalRegion = match region_capturer {
             RegionCapturer::Outputs(outputs) => outputs.as_slice().try_into()?,
             RegionCapturer::Region(region) => region,
-            RegionCapturer::Freeze(callback) => {
-                self.overlay_frames_and_select_region(&frames, callback)?
+            RegionCapturer::Freeze(callback, options) => {
+                self.overlay_frames_and_select_region(&frames, callback, &options)?
             }
             RegionCapturer::TopLevel(_) => unreachable!("TopLevel handled earlier"),
         };
@@ -1425,68 +2771,63 @@ impl WayshotConnection {
 
             let rotate_join_handles = frames
                 .into_iter()
-                .map(|(mut frame_copy, _, _)| {
-                    scope.spawn(move || {
+                .map(|(mut frame_copy, _, output_info)| {
+                    let handle = scope.spawn(move || {
+                        // Unrotated frames at the final scale are pasted from their
+                        // raw buffer by `blit`, skipping the conversion to an image.
+                        if matches!(frame_copy.frame_data, FrameData::Mmap(_))
+                            && image_util::rotate_is_noop(
+                                frame_copy.transform,
+                                frame_copy.frame_format.size,
+                                frame_copy.logical_region.inner.size,
+                                max_scale,
+                            )
+                        {
+                            return Ok((None, frame_copy));
+                        }
                         let image = frame_copy.get_image()?;
                         Ok((
-                            image_util::rotate_image_buffer(
+                            Some(image_util::rotate_image_buffer(
                                 image,
                                 frame_copy.transform,
                                 frame_copy.logical_region.inner.size,
                                 max_scale,
-                            ),
+                            )),
                             frame_copy,
                         ))
-                    })
+                    });
+                    (handle, output_info.name)
                 })
                 .collect::<Vec<_>>();
 
-            rotate_join_handles
+            let images = rotate_join_handles
                 .into_iter()
-                .flat_map(|join_handle| join_handle.join())
-                .fold(
-                    None,
-                    |composite_image: Option<Result<_>>, image: Result<_>| {
-                        // Default to a transparent image.
-                        let composite_image = composite_image.unwrap_or_else(|| {
-                            Ok(DynamicImage::new_rgba8(
-                                (capture_region.inner.size.width as f64 * max_scale) as u32,
-                                (capture_region.inner.size.height as f64 * max_scale) as u32,
-                            ))
-                        });
-
-                        Some(|| -> Result<_> {
-                            let mut composite_image = composite_image?;
-                            let (image, frame_copy) = image?;
-                            let (x, y) = (
-                                ((frame_copy.logical_region.inner.position.x as f64
-                                    - capture_region.inner.position.x as f64)
-                                    * max_scale) as i64,
-                                ((frame_copy.logical_region.inner.position.y as f64
-                                    - capture_region.inner.position.y as f64)
-                                    * max_scale) as i64,
-                            );
-                            tracing::span!(
-                                tracing::Level::DEBUG,
-                                "replace",
-                                frame_copy_region = format!("{}", frame_copy.logical_region),
-                                capture_region = format!("{}", capture_region),
-                                x = x,
-                                y = y,
-                            )
-                            .in_scope(|| {
-                                tracing::debug!("Replacing parts of the final image");
-                                replace(&mut composite_image, &image, x, y);
-                            });
-
-                            Ok(composite_image)
-                        }())
-                    },
-                )
-                .ok_or_else(|| {
-                    tracing::error!("Provided capture region doesn't intersect with any outputs!");
-                    Error::NoOutputs
-                })?
+                .flat_map(|(join_handle, output)| {
+                    let image = join_handle.join().ok()?;
+                    if image.is_ok() {
+                        self.report_progress(|| ProgressEvent::FrameConverted { output });
+                    }
+                    Some(image)
+                })
+                .collect::<Result<Vec<_>>>()?;
+            let sources = images
+                .iter()
+                .map(|(image, frame_copy)| {
+                    let source = match image {
+                        Some(image) => BlitSource::Image(image),
+                        None => BlitSource::Raw(FrameView::from_frame(frame_copy)?),
+                    };
+                    Ok((source, frame_copy.logical_region))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            let outputs = sources.len();
+            let (image, _) = compose::blit(sources, capture_region, max_scale, false)?;
+            self.report_progress(|| ProgressEvent::Composited {
+                outputs,
+                width: image.width(),
+                height: image.height(),
+            });
+            Ok(image)
         })
     }
 
@@ -1498,24 +2839,657 @@ impl WayshotConnection {
         self.screenshot_region_capturer(RegionCapturer::Region(capture_region), cursor_overlay)
     }
 
+    /// Take a screenshot of `capture_region` split at the output borders, one
+    /// image per output it overlaps instead of a single composite.
+    ///
+    /// Every image has the scale of its output and comes with the output and
+    /// the part of `capture_region` it shows, in logical pixels relative to the
+    /// top left of `capture_region`. The parts share edges the way [`Region`]s
+    /// do, so they tile the parts of `capture_region` covered by outputs.
+    ///
+    /// Returns [`Error::EmptyRegion`] or [`Error::RegionNotVisible`] like
+    /// [`LogicalRegion::check_selection`].
+    pub fn screenshot_region_split(
+        &self,
+        capture_region: LogicalRegion,
+        cursor_overlay: bool,
+    ) -> Result<Vec<(OutputInfo, Region, RgbaImage)>> {
+        let capture_region = capture_region.check_selection(self.get_all_outputs())?;
+        capture_region
+            .split_at_outputs(self.get_all_outputs())
+            .into_iter()
+            .map(|(output_info, embedded_region, placed)| {
+                let image = self.screenshot_output_logical_region(
+                    output_info,
+                    embedded_region.inner,
+                    cursor_overlay,
+                )?;
+                Ok((output_info.clone(), placed, image.into_rgba8()))
+            })
+            .collect()
+    }
+
     /// Take a screenshot, overlay the screenshot, run the callback, and then
     /// unfreeze the screenshot and return the selected region.
+    ///
+    /// A region without area fails with [`Error::EmptyRegion`] and one outside
+    /// of every output with [`Error::RegionNotVisible`], both of which callers
+    /// may treat like [`Error::SelectionCancelled`].
     pub fn screenshot_freeze<F>(&self, callback: F, cursor_overlay: bool) -> Result<DynamicImage>
     where
         F: Fn(&WayshotConnection) -> Result<LogicalRegion> + 'static,
     {
-        self.screenshot_region_capturer(RegionCapturer::Freeze(Box::new(callback)), cursor_overlay)
+        self.screenshot_freeze_with_retry(callback, |_| false, cursor_overlay)
     }
 
-    /// Take a screenshot from one output
-    pub fn screenshot_single_output(
+    /// Like [`Self::screenshot_freeze`], but when the callback fails or returns
+    /// an empty or invisible region, `retry` is asked whether to run the
+    /// callback again. The outputs stay frozen in between, so the user picks
+    /// again from the same screenshot.
+    pub fn screenshot_freeze_with_retry<F, R>(
         &self,
-        output_info: &OutputInfo,
+        callback: F,
+        retry: R,
         cursor_overlay: bool,
-    ) -> Result<DynamicImage> {
-        let (mut frame_copy, _) = self.capture_frame_copy(cursor_overlay, output_info, None)?;
-        frame_copy.get_image()
-    }
+    ) -> Result<DynamicImage>
+    where
+        F: Fn(&WayshotConnection) -> Result<LogicalRegion> + 'static,
+        R: Fn(&Error) -> bool + 'static,
+    {
+        self.screenshot_freeze_with_options(callback, retry, cursor_overlay, FreezeOptions::new())
+    }
+
+    /// Like [`Self::screenshot_freeze_with_retry`], with control over how the
+    /// frozen outputs look and which outputs are frozen, see [`FreezeOptions`].
+    ///
+    /// Returns [`Error::OutputNotFound`] if [`FreezeScope::InvolvedOutputs`]
+    /// names an output that doesn't exist.
+    pub fn screenshot_freeze_with_options<F, R>(
+        &self,
+        callback: F,
+        retry: R,
+        cursor_overlay: bool,
+        options: FreezeOptions,
+    ) -> Result<DynamicImage>
+    where
+        F: Fn(&WayshotConnection) -> Result<LogicalRegion> + 'static,
+        R: Fn(&Error) -> bool + 'static,
+    {
+        if let FreezeScope::InvolvedOutputs(names) = options.get_show_on()
+            && let Some(missing) = names.iter().find(|name| {
+                !self
+                    .get_all_outputs()
+                    .iter()
+                    .any(|output_info| output_info.name == **name)
+            })
+        {
+            return Err(Error::OutputNotFound(missing.clone()));
+        }
+        let involved = options.clone();
+        let callback = move |conn: &WayshotConnection| {
+            let outputs: Vec<OutputInfo> = conn
+                .get_all_outputs()
+                .iter()
+                .filter(|output_info| involved.shows(output_info))
+                .cloned()
+                .collect();
+            loop {
+                match callback(conn).and_then(|region| region.check_selection(&outputs)) {
+                    Err(e) if retry(&e) => {
+                        tracing::debug!("Selection failed ({e}), asking again");
+                    }
+                    result => return result,
+                }
+            }
+        };
+        self.screenshot_region_capturer(
+            RegionCapturer::Freeze(Box::new(callback), options),
+            cursor_overlay,
+        )
+    }
+
+    /// Show `selection` sharp and unshaded on the frozen outputs, or shade
+    /// everything again with `None`. Call it from a freeze callback while the
+    /// user drags out a selection, so the [`FreezeOptions`] blur, dim and
+    /// tint only cover what's left out.
+    ///
+    /// Selectors like waysip don't report their selection until it is done,
+    /// so this only has an effect for selectors that call it themselves.
+    /// Outside of a freeze callback, or when the frozen outputs aren't shaded,
+    /// this does nothing.
+    pub fn set_freeze_selection(&self, selection: Option<LogicalRegion>) -> Result<()> {
+        let mut overlay = self
+            .freeze_overlay
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let Some(FreezeOverlay { shm, qh, surfaces }) = overlay.as_mut() else {
+            return Ok(());
+        };
+        let mut old_buffers = Vec::with_capacity(surfaces.len());
+        for overlay_surface in surfaces.iter_mut() {
+            let pixels = overlay_surface.frame.pixels(
+                selection.and_then(|selection| overlay_surface.meta.logical_to_buffer(selection)),
+            );
+            let buffer = Self::create_overlay_buffer(
+                shm,
+                overlay_surface.meta.frame_format.size,
+                &pixels,
+                qh,
+            )?;
+            overlay_surface.surface.attach(Some(&buffer.1), 0, 0);
+            overlay_surface.surface.damage(0, 0, i32::MAX, i32::MAX);
+            overlay_surface.surface.commit();
+            old_buffers.push(std::mem::replace(&mut overlay_surface.buffer, buffer));
+        }
+        // The compositor is done with the old buffers once it applied the commits.
+        sync_connection(&self.conn)?;
+        for (shm_pool, buffer) in old_buffers {
+            buffer.destroy();
+            shm_pool.destroy();
+        }
+        Ok(())
+    }
+
+    /// Take a screenshot from one output
+    pub fn screenshot_single_output(
+        &self,
+        output_info: &OutputInfo,
+        cursor_overlay: bool,
+    ) -> Result<DynamicImage> {
+        self.screenshot_single_output_image(output_info, cursor_overlay)
+            .map(|(image, _)| image)
+    }
+
+    /// Take a screenshot from one output, resized to the output resolution,
+    /// along with the frame it was converted from.
+    fn screenshot_single_output_image(
+        &self,
+        output_info: &OutputInfo,
+        cursor_overlay: bool,
+    ) -> Result<(DynamicImage, FrameCopy)> {
+        let (mut frame_copy, _) = self.capture_frame_copy(cursor_overlay, output_info, None)?;
+        let mut image = frame_copy.get_image()?;
+        self.report_progress(|| ProgressEvent::FrameConverted {
+            output: output_info.name.clone(),
+        });
+        if let Some(output_resolution) = self.output_resolution {
+            image = output_resolution.apply(image);
+        }
+        Ok((image, frame_copy))
+    }
+
+    /// Capture one output and stream it to `writer` as a binary PPM (P6), e.g.
+    /// to the `UnixStream` or `TcpStream` of a viewer.
+    ///
+    /// The rows are converted straight from the captured buffer, honouring its
+    /// stride, and written through a small buffer, so neither an image nor a
+    /// frame sized buffer is allocated, unless the frame is resized to the
+    /// [output resolution](Self::set_output_resolution). Like
+    /// [`Self::screenshot_single_output`] the output transform is not applied.
+    /// Frames streamed back to back form a PPM stream, see
+    /// [`FrameCopy::write_ppm`].
+    ///
+    /// Fails with [`Error::StreamClosed`] if the reader closes the stream
+    /// while the frame is written, leaving a partial frame behind.
+    pub fn stream_single_output_ppm<W: Write>(
+        &self,
+        output_info: &OutputInfo,
+        cursor_overlay: bool,
+        writer: &mut W,
+    ) -> Result<()> {
+        let (mut frame_copy, _) = self.capture_frame_copy(cursor_overlay, output_info, None)?;
+        let resized = self.resize_to_output_resolution(&mut frame_copy)?;
+        let mut writer = BufWriter::with_capacity(PPM_STREAM_BUFFER, writer);
+        match &resized {
+            Some(image) => FrameView::from_image(image)
+                .and_then(|view| image_util::write_ppm_view(view, &mut writer)),
+            None => frame_copy.write_ppm(&mut writer),
+        }
+        .and_then(|()| Ok(writer.flush()?))
+        .map_err(Error::on_stream)
+    }
+
+    /// Capture one output into shared memory without converting it, for
+    /// callers that read the pixels where they are with [`FrameCopy::image`]
+    /// or [`FrameCopy::view`] instead of copying them into an image. Like
+    /// [`Self::screenshot_single_output`] the output transform is not applied.
+    pub fn screenshot_single_output_frame(
+        &self,
+        output_info: &OutputInfo,
+        cursor_overlay: bool,
+    ) -> Result<FrameCopy> {
+        self.capture_frame_copy(cursor_overlay, output_info, None)
+            .map(|(frame_copy, _)| frame_copy)
+    }
+
+    /// Take a screenshot from one output and write it to `writer` as QOI,
+    /// encoded straight from the captured frame, which skips converting it to
+    /// an image first unless it is resized to the
+    /// [output resolution](Self::set_output_resolution). Like
+    /// [`Self::screenshot_single_output`] the output transform is not applied.
+    pub fn screenshot_single_output_qoi<W: Write>(
+        &self,
+        output_info: &OutputInfo,
+        cursor_overlay: bool,
+        writer: &mut W,
+    ) -> Result<()> {
+        let (mut frame_copy, _) = self.capture_frame_copy(cursor_overlay, output_info, None)?;
+        match self.resize_to_output_resolution(&mut frame_copy)? {
+            Some(image) => image_util::write_qoi_view(FrameView::from_image(&image)?, writer),
+            None => frame_copy.write_qoi(writer),
+        }
+    }
+
+    /// The frame as an image resized to [`Self::set_output_resolution`], none
+    /// without an output resolution, so it can be written straight from the
+    /// captured buffer.
+    fn resize_to_output_resolution(
+        &self,
+        frame_copy: &mut FrameCopy,
+    ) -> Result<Option<DynamicImage>> {
+        let Some(output_resolution) = self.output_resolution else {
+            return Ok(None);
+        };
+        Ok(Some(output_resolution.apply(frame_copy.get_image()?)))
+    }
+
+    /// Capture `region` of one output `frames` times while the user scrolls
+    /// down, and stitch the captures into one tall image with [`stitch_scrolled`].
+    ///
+    /// `region` is in the output's own logical coordinate space, like for
+    /// [`Self::screenshot_output_logical_region`]. Captures are 250ms apart, so
+    /// scroll steadily and not by more than the height of `region` in between.
+    /// Captures that don't overlap the previous one are appended whole.
+    pub fn capture_scroll_stitch(
+        &self,
+        output_info: &OutputInfo,
+        region: Region,
+        frames: usize,
+    ) -> Result<RgbaImage> {
+        let mut captures = Vec::with_capacity(frames);
+        for frame in 0..frames {
+            if frame > 0 {
+                thread::sleep(SCROLL_CAPTURE_INTERVAL);
+            }
+            captures.push(
+                self.screenshot_output_logical_region(output_info, region, false)?
+                    .into_rgba8(),
+            );
+        }
+        stitch_scrolled(&captures)
+    }
+
+    /// The output the pointer is over, which has the focus on most
+    /// compositors. Follows the pointer of the first seat with one.
+    ///
+    /// Returns [`Error::ProtocolNotFound`] without the cursor sessions of
+    /// ext-image-copy-capture and [`Error::FocusedOutputUnknown`] when the
+    /// pointer isn't over any output.
+    pub fn focused_output(&self) -> Result<&OutputInfo> {
+        if !self.cursor_sessions_supported() {
+            return Err(Error::ProtocolNotFound(
+                "ext-image-copy-capture cursor sessions, to find the output of the pointer"
+                    .to_string(),
+            ));
+        }
+        for output_info in self.get_all_outputs() {
+            if self.pointer_is_over(output_info)? {
+                return Ok(output_info);
+            }
+        }
+        Err(Error::FocusedOutputUnknown)
+    }
+
+    /// Take a screenshot of `region` of the [`Self::focused_output`], given in
+    /// its own logical coordinate space like for
+    /// [`Self::screenshot_output_logical_region`]. Keybindings can capture e.g.
+    /// the top left corner of whichever output has the focus.
+    ///
+    /// Returns the image along with `region` in global logical coordinates.
+    pub fn screenshot_focused_output_region(
+        &self,
+        region: Region,
+        cursor_overlay: bool,
+    ) -> Result<(DynamicImage, LogicalRegion)> {
+        let output_info = self.focused_output()?;
+        let image = self.screenshot_output_logical_region(output_info, region, cursor_overlay)?;
+        let region::Position { x, y } = output_info.logical_region.inner.position;
+        Ok((image, LogicalRegion { inner: region }.translated(x, y)))
+    }
+
+    /// Take a screenshot of `region` of one output, given in the output's own
+    /// logical coordinate space: (0, 0) is the output's top left and units are
+    /// logical pixels. The image has the output's scale.
+    ///
+    /// Returns [`Error::RegionOutOfBounds`] if `region` is not entirely inside of the output.
+    pub fn screenshot_output_logical_region(
+        &self,
+        output_info: &OutputInfo,
+        region: Region,
+        cursor_overlay: bool,
+    ) -> Result<DynamicImage> {
+        let embedded_region = EmbeddedRegion::within(region, output_info.logical_region)?;
+        let scale = output_info.scale();
+        // NOTE: ext-image-copy has no region capture, crop the whole output instead.
+        let crop = self.image_copy_support();
+        let (mut frame_copy, _) = self.capture_frame_copy(
+            cursor_overlay,
+            output_info,
+            (!crop).then_some(embedded_region),
+        )?;
+        // A frame that is already in the logical orientation and scale is
+        // cropped before converting, so only the rows and columns of the
+        // region are converted.
+        let crop_raw = crop
+            && matches!(frame_copy.frame_data, FrameData::Mmap(_))
+            && image_util::rotate_is_noop(
+                frame_copy.transform,
+                frame_copy.frame_format.size,
+                frame_copy.logical_region.inner.size,
+                scale,
+            );
+        if crop_raw {
+            let Size { width, height } = frame_copy.frame_format.size;
+            return frame_copy.into_cropped_image(image_util::clamp_crop(
+                region.to_physical(scale),
+                width,
+                height,
+            ));
+        }
+        let image = image_util::rotate_image_buffer(
+            frame_copy.get_image()?,
+            frame_copy.transform,
+            frame_copy.logical_region.inner.size,
+            scale,
+        );
+        if !crop {
+            return Ok(image);
+        }
+
+        let Region { position, size } =
+            image_util::clamp_crop(region.to_physical(scale), image.width(), image.height());
+        Ok(image.crop_imm(
+            position.x as u32,
+            position.y as u32,
+            size.width,
+            size.height,
+        ))
+    }
+
+    /// Take a screenshot from one output and also return the [`FrameFormat`]
+    /// (format, size and stride) the compositor copied the frame in and what
+    /// converting it did to the colour values.
+    pub fn screenshot_single_output_detailed(
+        &self,
+        output_info: &OutputInfo,
+        cursor_overlay: bool,
+    ) -> Result<(RgbaImage, FrameFormat, ColorHandling)> {
+        let (image, frame_copy) =
+            self.screenshot_single_output_image(output_info, cursor_overlay)?;
+        Ok((
+            image.into_rgba8(),
+            frame_copy.frame_format,
+            frame_copy.color_handling,
+        ))
+    }
+
+    /// Take a screenshot from one output in linear light, for compositing or
+    /// effects that have to blend colours correctly. See [`srgb_to_linear`].
+    pub fn screenshot_single_output_linear(
+        &self,
+        output_info: &OutputInfo,
+        cursor_overlay: bool,
+    ) -> Result<Rgba32FImage> {
+        let (image, ..) = self.screenshot_single_output_detailed(output_info, cursor_overlay)?;
+        Ok(srgb_to_linear(&image))
+    }
+
+    /// Take a screenshot from one output as planar YUV for a video encoder,
+    /// see [`YuvOptions`]. Like [`Self::screenshot_single_output`] the output
+    /// transform is not applied.
+    pub fn screenshot_single_output_yuv(
+        &self,
+        output_info: &OutputInfo,
+        cursor_overlay: bool,
+        options: YuvOptions,
+    ) -> Result<YuvFrame> {
+        let (image, ..) = self.screenshot_single_output_detailed(output_info, cursor_overlay)?;
+        Ok(options.convert(&image))
+    }
+
+    /// Capture the output and summarize its brightness and colours, without
+    /// converting the frame into an image.
+    pub fn output_stats(&self, output_info: &OutputInfo) -> Result<FrameStats> {
+        let (frame_copy, _) = self.capture_frame_copy(false, output_info, None)?;
+        image_util::stats(&frame_copy)
+    }
+
+    /// Average colour of the output, e.g. for bias lighting. Computed from a
+    /// small thumbnail of the frame, so it is cheap enough to poll.
+    pub fn average_color(&self, output_info: &OutputInfo) -> Result<Rgba<u8>> {
+        let (frame_copy, _) = self.capture_frame_copy(false, output_info, None)?;
+        image_util::average_color(&frame_copy)
+    }
+
+    /// Up to `n` most common colours of the output, most common first, computed
+    /// from a small thumbnail of the frame like [`Self::average_color`].
+    pub fn dominant_colors(&self, output_info: &OutputInfo, n: usize) -> Result<Vec<Rgba<u8>>> {
+        let (frame_copy, _) = self.capture_frame_copy(false, output_info, None)?;
+        image_util::dominant_colors(&frame_copy, n)
+    }
+
+    /// Wait for the compositor to present a new frame before every capture,
+    /// see [`Self::wait_for_frame`] for the latency this adds. Off by default.
+    pub fn set_wait_for_frame(&mut self, wait_for_frame: bool) {
+        self.wait_for_frame = wait_for_frame;
+    }
+
+    /// Where [`CursorMode::ComposeOnce`] gets the cursor image from when the
+    /// compositor reports the cursor position but its image can't be copied.
+    /// [`CursorSource::Theme`] by default.
+    pub fn set_cursor_source(&mut self, source: CursorSource) {
+        self.cursor_source = source;
+    }
+
+    /// Hand out the [`CaptureId`]s of this connection's captures from `ids`,
+    /// e.g. fixed ones for reproducible ids in tests. By default the ids are
+    /// those of this process, counting from 1.
+    pub fn set_capture_ids(&mut self, ids: CaptureIds) {
+        self.capture_ids = ids;
+    }
+
+    /// Call `callback` with every [`ProgressEvent`] of this connection's
+    /// screenshots, e.g. to show the progress of a capture of many outputs.
+    ///
+    /// The callback runs on the thread taking the screenshot, between its
+    /// steps, so it should return quickly.
+    pub fn set_progress(&mut self, callback: impl Fn(&ProgressEvent) + Send + Sync + 'static) {
+        self.progress = Some(ProgressCallback::new(callback));
+    }
+
+    /// Reports `event()` to the callback of [`Self::set_progress`], making the
+    /// event only when there is one.
+    fn report_progress(&self, event: impl FnOnce() -> ProgressEvent) {
+        if let Some(progress) = &self.progress {
+            progress.report(&event());
+        }
+    }
+
+    /// The id of the frame captured last, see [`FrameCopy::capture_id`].
+    ///
+    /// Screenshots of several outputs capture a frame each with consecutive
+    /// ids, this is the one of the last of them.
+    pub fn last_capture_id(&self) -> Option<CaptureId> {
+        self.capture_ids.last_id()
+    }
+
+    /// How the colour values of the last screenshot were handled, combined
+    /// over its frames for screenshots of several outputs, see
+    /// [`ColorHandling::combine`]. For screenshots returned as plain images,
+    /// where [`FrameCopy::color_handling`] isn't at hand.
+    pub fn last_color_handling(&self) -> Option<ColorHandling> {
+        *self
+            .last_color_handling
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn set_last_color_handling(&self, color_handling: ColorHandling) {
+        *self
+            .last_color_handling
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = Some(color_handling);
+    }
+
+    /// The id of the next capture, recorded in the current tracing span.
+    fn next_capture_id(&self) -> CaptureId {
+        let capture_id = self.capture_ids.next_id();
+        tracing::Span::current().record("capture_id", tracing::field::display(capture_id));
+        tracing::debug!(%capture_id, "Capturing frame");
+        capture_id
+    }
+
+    /// Log a warning for every output capture whose pixels are all the same
+    /// colour, which is what compositors that fail to copy a frame silently
+    /// tend to produce. Off by default, as it reads every captured pixel once.
+    ///
+    /// Use [`FrameCopy::is_uniform`] to check frames yourself instead.
+    pub fn set_warn_on_uniform(&mut self, warn_on_uniform: bool) {
+        self.warn_on_uniform = warn_on_uniform;
+    }
+
+    /// Allocate shm buffers with a stride that is a multiple of `alignment`
+    /// bytes instead of the compositor's minimum. The stride of captured frames
+    /// is in their [`FrameFormat`]. `None`, the default, uses the minimum.
+    ///
+    /// A larger stride is valid for `wl_shm`, but compositors may still refuse
+    /// to copy into it, which fails the capture.
+    pub fn set_stride_alignment(&mut self, alignment: Option<NonZeroU32>) {
+        self.stride_alignment = alignment;
+    }
+
+    /// Resize captures of a single output, such as [`Self::screenshot_single_output`],
+    /// to a fixed resolution. `None`, the default, keeps the output's size.
+    ///
+    /// Composited captures of several outputs or regions are not resized.
+    /// Cached screenshots are invalidated, as they may have a different size.
+    pub fn set_output_resolution(&mut self, output_resolution: Option<OutputResolution>) {
+        if self.output_resolution != output_resolution
+            && let Some(cache) = &mut self.screenshot_cache
+        {
+            let cache = cache.get_mut().unwrap_or_else(PoisonError::into_inner);
+            for output_info in &self.output_infos {
+                cache.invalidate(&output_info.wl_output);
+            }
+        }
+        self.output_resolution = output_resolution;
+    }
+
+    fn align_stride(&self, frame_format: FrameFormat) -> FrameFormat {
+        match self.stride_alignment {
+            Some(alignment) => frame_format.with_stride_alignment(alignment),
+            None => frame_format,
+        }
+    }
+
+    /// Drop the cached screenshots and damage tracking of outputs that are gone.
+    fn retain_cached_outputs(&self) {
+        if let Some(cache) = &self.screenshot_cache {
+            cache
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .retain_outputs(&self.output_infos);
+        }
+        self.retain_damage_trackers(&self.output_infos);
+    }
+
+    /// Enable the in-memory cache used by [`Self::screenshot_single_output_cached`].
+    pub fn enable_screenshot_cache(&mut self) {
+        if self.screenshot_cache.is_none() {
+            self.screenshot_cache = Some(Mutex::new(ScreenshotCache::new()));
+        }
+    }
+
+    /// Limit the screenshot cache to `max_bytes` of images, evicting the
+    /// oldest first. Enables the cache if it isn't yet. `None` removes the limit.
+    pub fn set_screenshot_cache_budget(&mut self, max_bytes: Option<u64>) {
+        self.enable_screenshot_cache();
+        if let Some(cache) = &mut self.screenshot_cache {
+            cache
+                .get_mut()
+                .unwrap_or_else(PoisonError::into_inner)
+                .set_byte_budget(max_bytes);
+        }
+    }
+
+    /// Mark the cached screenshot of an output as stale. Damage is tracked
+    /// where the compositor supports it, see
+    /// [`Self::screenshot_single_output_cached`], elsewhere call this whenever
+    /// the output is known to have changed.
+    pub fn invalidate_screenshot_cache(&self, output_info: &OutputInfo) {
+        if let Some(cache) = &self.screenshot_cache {
+            cache
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .invalidate(&output_info.wl_output);
+        }
+    }
+
+    /// Take a screenshot from one output, reusing the cached one if it is not
+    /// older than `max_age` and has not been invalidated since.
+    ///
+    /// The first call for an output starts tracking its damage with a
+    /// wlr-screencopy session that stays open, and damage invalidates the
+    /// cached screenshot. Compositors without damage reporting, screencopy
+    /// before version 2, only invalidate through `max_age` and
+    /// [`Self::invalidate_screenshot_cache`].
+    ///
+    /// Without [`Self::enable_screenshot_cache`] this always captures.
+    pub fn screenshot_single_output_cached(
+        &self,
+        output_info: &OutputInfo,
+        cursor_overlay: bool,
+        max_age: Duration,
+    ) -> Result<DynamicImage> {
+        let Some(cache) = &self.screenshot_cache else {
+            return self.screenshot_single_output(output_info, cursor_overlay);
+        };
+        if self.take_damage(output_info, cursor_overlay) == Some(true) {
+            self.invalidate_screenshot_cache(output_info);
+        }
+        if let Some(image) = cache.lock().unwrap_or_else(PoisonError::into_inner).get(
+            &output_info.wl_output,
+            cursor_overlay,
+            max_age,
+        ) {
+            tracing::debug!("Screenshot cache hit for {output_info}");
+            return Ok(image.clone());
+        }
+
+        tracing::debug!("Screenshot cache miss for {output_info}");
+        let image = self.screenshot_single_output(output_info, cursor_overlay)?;
+        cache.lock().unwrap_or_else(PoisonError::into_inner).insert(
+            &output_info.wl_output,
+            cursor_overlay,
+            image.clone(),
+        );
+        Ok(image)
+    }
+
+    /// Take a screenshot from one output, downscaled so that its largest side is at most `max_dim`.
+    ///
+    /// This is a lot faster than resizing the result of [`Self::screenshot_single_output`]
+    /// as the raw frame is box filtered directly.
+    pub fn thumbnail_output(
+        &self,
+        output_info: &OutputInfo,
+        cursor_overlay: bool,
+        max_dim: u32,
+    ) -> Result<RgbaImage> {
+        let (frame_copy, _) = self.capture_frame_copy(cursor_overlay, output_info, None)?;
+        let thumbnail = image_util::thumbnail(&frame_copy, max_dim)?;
+        Ok(image_util::apply_transform(thumbnail.into(), frame_copy.transform).into_rgba8())
+    }
 
     /// Take a screenshot from all of the specified outputs.
     pub fn screenshot_outputs(
@@ -1535,6 +3509,167 @@ impl WayshotConnection {
         self.screenshot_outputs(self.get_all_outputs(), cursor_overlay)
     }
 
+    /// Take a screenshot of every output on its own, in the order of
+    /// [`Self::get_all_outputs`], each with the id of its capture and, with the
+    /// `workspaces` feature, the workspace active on the output, see
+    /// [`OutputCapture::workspace`].
+    pub fn screenshot_all_separate(&self, cursor_overlay: bool) -> Result<Vec<OutputCapture>> {
+        self.capture_all_separate(|output| self.screenshot_single_output(output, cursor_overlay))
+    }
+
+    /// Like [`Self::screenshot_all_separate`], with every output downscaled
+    /// like [`Self::thumbnail_output`] does.
+    pub fn thumbnail_all_separate(
+        &self,
+        cursor_overlay: bool,
+        max_dim: u32,
+    ) -> Result<Vec<OutputCapture>> {
+        self.capture_all_separate(|output| {
+            self.thumbnail_output(output, cursor_overlay, max_dim)
+                .map(DynamicImage::from)
+        })
+    }
+
+    /// Every output captured on its own with `capture`, see
+    /// [`Self::screenshot_all_separate`].
+    fn capture_all_separate(
+        &self,
+        capture: impl Fn(&OutputInfo) -> Result<DynamicImage>,
+    ) -> Result<Vec<OutputCapture>> {
+        let mut workspaces = self.workspaces_if_available();
+        self.get_all_outputs()
+            .iter()
+            .map(|output| {
+                let image = capture(output)?;
+                Ok(OutputCapture {
+                    output: output.clone(),
+                    image,
+                    capture_id: self.last_capture_id(),
+                    color_handling: self.last_color_handling().unwrap_or_default(),
+                    workspace: workspaces.remove(&output.name),
+                })
+            })
+            .collect()
+    }
+
+    /// The active workspaces with the `workspaces` feature, logging failures
+    /// to read them. Empty without the feature.
+    fn workspaces_if_available(&self) -> HashMap<String, String> {
+        #[cfg(feature = "workspaces")]
+        match self.active_workspaces() {
+            Ok(workspaces) => return workspaces,
+            Err(e) => tracing::warn!("Failed to read the active workspaces: {e}"),
+        }
+        HashMap::new()
+    }
+
+    /// Like [`Self::screenshot_outputs`], composing the frames with `options`
+    /// and returning where each output was placed in the image, in the order of
+    /// `outputs`. See [`ComposeOptions::pack_outputs`] to leave out the gaps
+    /// between far apart outputs and [`ComposeOptions::with_coverage_mask`]
+    /// to find them.
+    ///
+    /// Frames are copied out of their shm buffers first, so this is slower than
+    /// [`Self::screenshot_outputs`].
+    pub fn screenshot_outputs_with_layout(
+        &self,
+        outputs: &[OutputInfo],
+        cursor_overlay: bool,
+        options: ComposeOptions,
+    ) -> Result<Composite> {
+        let capture_region: LogicalRegion = outputs.try_into()?;
+        let output_capture_regions: Vec<_> = outputs
+            .iter()
+            .map(|output_info| (output_info.clone(), None))
+            .collect();
+        let frames = self
+            .capture_frame_copies(&output_capture_regions, cursor_overlay)?
+            .iter()
+            .map(|(frame_copy, _, output_info)| {
+                let frame = OfflineFrame::try_from(frame_copy)?;
+                self.report_progress(|| ProgressEvent::FrameConverted {
+                    output: output_info.name.clone(),
+                });
+                Ok(frame)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let composite = compose_with_layout(&frames, capture_region, options)?;
+        self.report_progress(|| ProgressEvent::Composited {
+            outputs: frames.len(),
+            width: composite.image.width(),
+            height: composite.image.height(),
+        });
+        Ok(composite)
+    }
+
+    /// Like [`Self::screenshot_outputs`] with control over how the cursor is
+    /// drawn on the stitched image, see [`CursorMode`].
+    ///
+    /// [`CursorMode::ComposeOnce`] opens a cursor session per output to find
+    /// the cursor, a failure there leaves the cursor out instead of failing
+    /// the capture.
+    pub fn screenshot_outputs_with_cursor_mode(
+        &self,
+        outputs: &[OutputInfo],
+        cursor_mode: CursorMode,
+    ) -> Result<DynamicImage> {
+        if cursor_mode != CursorMode::ComposeOnce {
+            return self.screenshot_outputs(outputs, cursor_mode == CursorMode::PerOutput);
+        }
+        if !self.cursor_sessions_supported() {
+            tracing::debug!("No cursor sessions, letting the compositor draw the cursor");
+            return self.screenshot_outputs(outputs, true);
+        }
+
+        let mut composite = self.screenshot_outputs(outputs, false)?.into_rgba8();
+        let capture_region: LogicalRegion = outputs.try_into()?;
+        let max_scale = outputs
+            .iter()
+            .map(|output_info| output_info.scale())
+            .fold(1.0, f64::max);
+
+        let mut layers = Vec::new();
+        for output_info in outputs {
+            match self.cursor_plane(output_info) {
+                Ok(Some(plane)) => {
+                    tracing::debug!("Cursor found on {output_info}");
+                    layers.push(cursor::CursorLayer::from_plane(
+                        &plane,
+                        output_info,
+                        capture_region,
+                        max_scale,
+                    ));
+                }
+                Ok(None) => {}
+                Err(e) => tracing::warn!("Failed to find the cursor on {output_info}: {e}"),
+            }
+        }
+        cursor::compose_cursor_layers(&mut composite, &layers);
+
+        Ok(composite.into())
+    }
+
+    /// Check whether the compositor honors `overlay_cursor` by capturing each
+    /// of `outputs` with and without the cursor and comparing the captures.
+    ///
+    /// Stops at the first output where they differ. Only meaningful while the
+    /// cursor is shown on one of `outputs`.
+    pub fn check_cursor_overlay(&self, outputs: &[OutputInfo]) -> Result<CursorOverlayCheck> {
+        for output_info in outputs {
+            let [with_cursor, without_cursor] = [true, false].map(|cursor_overlay| {
+                let (mut frame_copy, _) =
+                    self.capture_frame_copy(cursor_overlay, output_info, None)?;
+                Ok::<_, Error>(frame_copy.get_image()?.into_rgba8())
+            });
+            if cursor::CursorLayer::extract(&with_cursor?, &without_cursor?, (0, 0)).is_some() {
+                tracing::debug!("Cursor overlay honored on {output_info}");
+                return Ok(CursorOverlayCheck::Honored);
+            }
+        }
+        tracing::debug!("Captures with and without the cursor are identical");
+        Ok(CursorOverlayCheck::Unverified)
+    }
+
     /// Take a screenshot from a specific toplevel (window).
     pub fn screenshot_toplevel(
         &self,
@@ -1562,12 +3697,18 @@ impl WayshotConnection {
         let qh = event_queue.handle();
 
         // Bind managers
-        let manager = self
-            .globals
-            .bind::<ExtImageCopyCaptureManagerV1, _, _>(&qh, 1..=1, ())?;
+        let manager = self.globals.bind::<ExtImageCopyCaptureManagerV1, _, _>(
+            &qh,
+            MIN_IMAGE_COPY_CAPTURE_VERSION..=MIN_IMAGE_COPY_CAPTURE_VERSION,
+            (),
+        )?;
         let toplevel_source_manager = self
             .globals
-            .bind::<ExtForeignToplevelImageCaptureSourceManagerV1, _, _>(&qh, 1..=1, ())?;
+            .bind::<ExtForeignToplevelImageCaptureSourceManagerV1, _, _>(
+                &qh,
+                MIN_IMAGE_CAPTURE_SOURCE_VERSION..=MIN_IMAGE_CAPTURE_SOURCE_VERSION,
+                (),
+            )?;
 
         let source = toplevel_source_manager.create_source(toplevel, &qh, ());
 
@@ -1580,7 +3721,7 @@ impl WayshotConnection {
         let session = manager.create_session(&source, options, &qh, ());
         let frame = session.create_frame(&qh, ());
         while !state.session_done {
-            event_queue.blocking_dispatch(&mut state)?;
+            self.dispatch_capture(&mut event_queue, &mut state)?;
         }
 
         Ok((state, event_queue, WayshotFrame::ExtImageCopy(frame)))
@@ -1625,7 +3766,9 @@ impl WayshotConnection {
                 self.ext_image_copy_frame_inner(state, event_queue, frame, format, fd)?;
             Ok(frame_guard)
         } else {
-            Err(Error::NoSupportedBufferFormat)
+            Err(Error::no_usable_format(
+                state.formats.iter().map(|format| format.format),
+            ))
         }
     }
 
@@ -1639,6 +3782,7 @@ impl WayshotConnection {
             self.capture_toplevel_frame_get_state_shm(toplevel, cursor_overlay)?;
 
         file.set_len(frame_format.byte_size())?;
+        seal_shm_size(file);
 
         let WayshotFrame::ExtImageCopy(frame) = frame else {
             unreachable!()
@@ -1662,26 +3806,12 @@ impl WayshotConnection {
             self.capture_toplevel_frame_shm_from_file(cursor_overlay, toplevel, &memfile)?;
 
         // Map and convert to image
-        let frame_mmap = unsafe { MmapMut::map_mut(&memfile)? };
-        let mut frame_copy = FrameCopy {
-            frame_format,
-            frame_color_type: image::ColorType::Rgb8, // will be updated by get_image
-            frame_data: FrameData::Mmap(frame_mmap),
-            transform: Transform::Normal,
-            logical_region: LogicalRegion {
-                inner: crate::region::Region {
-                    position: crate::region::Position { x: 0, y: 0 },
-                    size: frame_format.size,
-                },
-            },
-            physical_size: frame_format.size,
-        };
-
-        frame_copy.get_image()
+        let frame_mmap = map_shm_frame(&memfile, frame_format)?;
+        buffer_frame_copy(frame_format, frame_mmap, self.next_capture_id()).get_image()
     }
 
     // Helper method to get frame format for toplevel using ext-image session events
-    pub fn capture_toplevel_frame_get_state_shm(
+    pub(crate) fn capture_toplevel_frame_get_state_shm(
         &self,
         toplevel: &ExtForeignToplevelHandleV1,
         cursor_overlay: bool,
@@ -1697,23 +3827,117 @@ impl WayshotConnection {
         let frame_format = state
             .formats
             .iter()
-            .find(|frame| {
-                matches!(
-                    frame.format,
-                    wl_shm::Format::Xbgr2101010
-                        | wl_shm::Format::Abgr2101010
-                        | wl_shm::Format::Argb8888
-                        | wl_shm::Format::Xrgb8888
-                        | wl_shm::Format::Xbgr8888
-                        | wl_shm::Format::Bgr888
-                )
-            })
+            .find(|frame| convert::is_supported(frame.format))
             .copied()
             .ok_or_else(|| {
                 tracing::error!("No suitable frame format found");
-                Error::NoSupportedBufferFormat
+                Error::no_usable_format(state.formats.iter().map(|format| format.format))
             })?;
 
-        Ok((state, event_queue, frame, frame_format))
+        Ok((state, event_queue, frame, self.align_stride(frame_format)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    #[test]
+    fn sync_sends_pending_host_requests_first_and_waits_for_them() {
+        use std::{
+            io::{Read, Write},
+            os::unix::net::UnixStream,
+            thread,
+        };
+
+        use super::{OutputCaptureState, sync_connection};
+        use wayland_client::Connection;
+
+        let (client, mut compositor) = UnixStream::pair().unwrap();
+        let conn = Connection::from_socket(client).unwrap();
+        // A request of the host sharing the connection, not flushed yet.
+        let host_queue = conn.new_event_queue::<OutputCaptureState>();
+        let _registry = conn.display().get_registry(&host_queue.handle(), ());
+
+        let compositor = thread::spawn(move || {
+            let mut requests = [0; 24];
+            compositor.read_exact(&mut requests).unwrap();
+            let words: Vec<u32> = requests
+                .chunks_exact(4)
+                .map(|word| u32::from_ne_bytes(word.try_into().unwrap()))
+                .collect();
+            // Answer wl_display.sync with wl_callback.done.
+            let callback = words[5];
+            for word in [callback, 12 << 16, 0] {
+                compositor.write_all(&word.to_ne_bytes()).unwrap();
+            }
+            (compositor, words)
+        });
+        sync_connection(&conn).unwrap();
+        let (_compositor, words) = compositor.join().unwrap();
+
+        // wl_display.get_registry, then wl_display.sync, both on wl_display.
+        assert_eq!((words[0], words[1] & 0xffff), (1, 1));
+        assert_eq!((words[3], words[4] & 0xffff), (1, 0));
+    }
+
+    #[test]
+    fn missing_shm_is_reported_as_protocol_not_found() {
+        use super::{Error, shm_bind_error};
+        use wayland_client::globals::BindError;
+
+        for error in [BindError::NotPresent, BindError::UnsupportedVersion] {
+            assert!(matches!(
+                shm_bind_error(error),
+                Error::ProtocolNotFound(message) if message == "WlShm not found"
+            ));
+        }
+    }
+
+    #[test]
+    fn screencopy_features_check_the_negotiated_version() {
+        use super::{Error, check_screencopy_version};
+
+        assert!(check_screencopy_version(3, 3, "capturing regions").is_ok());
+        assert!(matches!(
+            check_screencopy_version(0, 3, "capturing regions"),
+            Err(Error::ProtocolNotFound(_))
+        ));
+        assert!(matches!(
+            check_screencopy_version(2, 3, "capturing regions"),
+            Err(Error::Unsupported(message))
+                if message == "capturing regions needs zwlr_screencopy_manager_v1 version 3, \
+                               the compositor offers version 2"
+        ));
+    }
+
+    #[test]
+    fn display_socket_path_resolves_like_wayland_display() {
+        use super::{Error, display_socket_path};
+
+        assert_eq!(
+            display_socket_path("wayland-2", Some("/run/user/1000".into())).unwrap(),
+            Path::new("/run/user/1000/wayland-2")
+        );
+        assert_eq!(
+            display_socket_path("/tmp/nested/wayland-0", None).unwrap(),
+            Path::new("/tmp/nested/wayland-0")
+        );
+        assert!(matches!(
+            display_socket_path("wayland-2", None),
+            Err(Error::DisplayConnect { socket, .. }) if socket == Path::new("wayland-2")
+        ));
+    }
+
+    #[test]
+    fn encode_png_round_trips() {
+        use image::{DynamicImage, Rgba, RgbaImage};
+
+        let image = RgbaImage::from_fn(3, 2, |x, y| Rgba([x as u8 * 80, y as u8 * 120, 7, 255]));
+
+        let png = super::encode_png(&DynamicImage::ImageRgba8(image.clone())).unwrap();
+
+        assert!(png.starts_with(b"\x89PNG"));
+        assert_eq!(image::load_from_memory(&png).unwrap().into_rgba8(), image);
     }
 }
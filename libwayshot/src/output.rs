@@ -1,13 +1,18 @@
 use std::fmt::Display;
 
+use image::DynamicImage;
 use wayland_client::protocol::{wl_output, wl_output::WlOutput};
 
-use crate::region::{LogicalRegion, Size};
+use crate::{
+    CaptureId, ColorHandling,
+    region::{LogicalRegion, Position, Size, Transform2D},
+};
 
 /// Represents an accessible wayland output.
 ///
 /// Do not instantiate, instead use [`crate::WayshotConnection::get_all_outputs`].
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[non_exhaustive]
 pub struct OutputInfo {
     pub wl_output: WlOutput,
     pub name: String,
@@ -17,6 +22,20 @@ pub struct OutputInfo {
     pub logical_region: LogicalRegion,
 }
 
+/// One output captured by [`crate::WayshotConnection::screenshot_all_separate`].
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct OutputCapture {
+    pub output: OutputInfo,
+    pub image: DynamicImage,
+    pub capture_id: Option<CaptureId>,
+    /// What was done to the colour values of `image`.
+    pub color_handling: ColorHandling,
+    /// Name of the workspace active on the output, only with the `workspaces`
+    /// feature and compositors offering ext-workspace.
+    pub workspace: Option<String>,
+}
+
 impl AsRef<WlOutput> for OutputInfo {
     fn as_ref(&self) -> &WlOutput {
         &self.wl_output
@@ -35,8 +54,140 @@ impl Display for OutputInfo {
 }
 
 impl OutputInfo {
-    pub(crate) fn scale(&self) -> f64 {
-        self.physical_size.height as f64 / self.logical_region.inner.size.height as f64
+    /// Ratio between physical and logical pixels of the output.
+    ///
+    /// Derived from the mode and the xdg-output logical size rather than the
+    /// integer `wl_output` scale, so fractional scales such as 1.25 are exact.
+    pub fn scale(&self) -> f64 {
+        // The mode is in buffer orientation, the logical size is not.
+        let physical_height = match self.transform {
+            wl_output::Transform::_90
+            | wl_output::Transform::_270
+            | wl_output::Transform::Flipped90
+            | wl_output::Transform::Flipped270 => self.physical_size.width,
+            _ => self.physical_size.height,
+        };
+        physical_height as f64 / self.logical_region.inner.size.height as f64
+    }
+
+    /// Map points in the global logical coordinate space to pixels of a
+    /// capture of the whole output, in the buffer orientation it is captured
+    /// in, that is before [`Self::transform`] is applied.
+    ///
+    /// Points are continuous, pixel `(x, y)` covers `[x, x + 1) × [y, y + 1)`.
+    pub fn logical_to_physical(&self) -> Transform2D {
+        let Position { x, y } = self.logical_region.inner.position;
+        let output_to_buffer = self
+            .buffer_to_output()
+            .inverse()
+            .expect("rotations and flips are invertible");
+        Transform2D::translation(-x as f64, -y as f64)
+            .then(Transform2D::scale(self.scale()))
+            .then(output_to_buffer)
+    }
+
+    /// The inverse of [`Self::logical_to_physical`], mapping pixels of a
+    /// capture of the whole output to the global logical coordinate space.
+    pub fn physical_to_logical(&self) -> Transform2D {
+        let Position { x, y } = self.logical_region.inner.position;
+        self.buffer_to_output()
+            .then(Transform2D::scale(1.0 / self.scale()))
+            .then(Transform2D::translation(x as f64, y as f64))
+    }
+
+    /// Rotate and flip buffer pixels into the output orientation, like the
+    /// captured images are.
+    fn buffer_to_output(&self) -> Transform2D {
+        Transform2D::buffer_to_output(self.transform, self.physical_size)
+    }
+}
+
+/// How the outputs changed between two refreshes, matched by name. See
+/// [`crate::WayshotConnection::refresh_outputs_diff`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OutputDiff {
+    /// Outputs that weren't there before, in their new order.
+    pub added: Vec<String>,
+    /// Outputs that are gone, in their previous order.
+    pub removed: Vec<String>,
+    /// Outputs whose logical region, transform or mode changed, which covers
+    /// their scale as well.
+    pub modified: Vec<String>,
+}
+
+impl OutputDiff {
+    pub(crate) fn between(previous: &[OutputInfo], current: &[OutputInfo]) -> Self {
+        let find = |outputs: &[OutputInfo], name: &str| {
+            outputs.iter().find(|output| output.name == name).cloned()
+        };
+        let mut diff = Self::default();
+        for output in current {
+            match find(previous, &output.name) {
+                None => diff.added.push(output.name.clone()),
+                Some(old)
+                    if old.logical_region != output.logical_region
+                        || old.transform != output.transform
+                        || old.physical_size != output.physical_size =>
+                {
+                    diff.modified.push(output.name.clone())
+                }
+                Some(_) => {}
+            }
+        }
+        diff.removed = previous
+            .iter()
+            .filter(|output| find(current, &output.name).is_none())
+            .map(|output| output.name.clone())
+            .collect();
+        diff
+    }
+
+    /// Whether no output changed.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.modified.is_empty()
+    }
+}
+
+/// Group outputs that cover the same logical region, i.e. mirror each other,
+/// in the order they first appear. Outputs that are not mirrored get a group
+/// of their own.
+pub(crate) fn mirror_groups(outputs: &[OutputInfo]) -> Vec<Vec<&OutputInfo>> {
+    let mut groups: Vec<Vec<&OutputInfo>> = Vec::new();
+    for output in outputs {
+        match groups
+            .iter_mut()
+            .find(|group| group[0].logical_region == output.logical_region)
+        {
+            Some(group) => group.push(output),
+            None => groups.push(vec![output]),
+        }
+    }
+    groups
+}
+
+#[cfg(test)]
+impl OutputInfo {
+    /// An output on a connection to nowhere, for tests of code that only
+    /// reads the geometry of outputs.
+    pub(crate) fn detached(name: &str, physical_size: Size, logical_region: LogicalRegion) -> Self {
+        use std::os::unix::net::UnixStream;
+
+        use wayland_backend::client::Backend;
+        use wayland_client::Proxy;
+
+        let (client, server) = UnixStream::pair().expect("unix stream");
+        Box::leak(Box::new(server));
+        let backend = Backend::connect(client).expect("backend");
+        let weak = backend.downgrade();
+        Box::leak(Box::new(backend));
+        Self {
+            wl_output: WlOutput::inert(weak),
+            name: name.to_string(),
+            description: String::new(),
+            transform: wl_output::Transform::Normal,
+            physical_size,
+            logical_region,
+        }
     }
 }
 
@@ -45,9 +196,6 @@ mod tests {
     use super::*;
     use crate::region::{Position, Region};
     use std::mem;
-    use std::os::unix::net::UnixStream;
-    use wayland_backend::client::Backend;
-    use wayland_client::Proxy;
 
     fn make_output_info(
         name: &str,
@@ -56,24 +204,11 @@ mod tests {
         logical_region: LogicalRegion,
     ) -> OutputInfo {
         OutputInfo {
-            wl_output: dummy_wl_output(),
-            name: name.to_string(),
             description: description.to_string(),
-            transform: wl_output::Transform::Normal,
-            physical_size,
-            logical_region,
+            ..OutputInfo::detached(name, physical_size, logical_region)
         }
     }
 
-    fn dummy_wl_output() -> WlOutput {
-        let (client, server) = UnixStream::pair().expect("unix stream");
-        Box::leak(Box::new(server));
-        let backend = Backend::connect(client).expect("backend");
-        let weak = backend.downgrade();
-        Box::leak(Box::new(backend));
-        WlOutput::inert(weak)
-    }
-
     #[test]
     fn display_formats_name_and_description() {
         let output_info = make_output_info(
@@ -192,6 +327,190 @@ mod tests {
         mem::forget(output_info_1_5);
     }
 
+    #[test]
+    fn scale_is_fractional_and_follows_the_transform() {
+        let logical_region = |width, height| LogicalRegion {
+            inner: Region {
+                position: Position { x: 0, y: 0 },
+                size: Size { width, height },
+            },
+        };
+        let output_info_1_25 = make_output_info(
+            "DP-3",
+            "Fractional Display",
+            Size {
+                width: 3200,
+                height: 1800,
+            },
+            logical_region(2560, 1440),
+        );
+        assert_eq!(output_info_1_25.scale(), 1.25);
+        mem::forget(output_info_1_25);
+
+        // A portrait output: the mode stays in landscape buffer orientation.
+        let mut output_info_1_6 = make_output_info(
+            "DP-4",
+            "Portrait Display",
+            Size {
+                width: 2560,
+                height: 1600,
+            },
+            logical_region(1000, 1600),
+        );
+        output_info_1_6.transform = wl_output::Transform::_90;
+        assert_eq!(output_info_1_6.scale(), 1.6);
+        mem::forget(output_info_1_6);
+    }
+
+    #[test]
+    fn logical_to_physical_maps_a_rotated_scaled_output() {
+        // A 1920x1080 mode rotated to a portrait output at scale 2.
+        let mut output_info = make_output_info(
+            "DP-5",
+            "Portrait Display",
+            Size {
+                width: 1920,
+                height: 1080,
+            },
+            LogicalRegion {
+                inner: Region {
+                    position: Position { x: 100, y: 50 },
+                    size: Size {
+                        width: 540,
+                        height: 960,
+                    },
+                },
+            },
+        );
+        output_info.transform = wl_output::Transform::_90;
+        let to_physical = output_info.logical_to_physical();
+        let to_logical = output_info.physical_to_logical();
+
+        // The top left of the output is the bottom left of the buffer.
+        assert_eq!(to_physical.apply((100.0, 50.0)), (0.0, 1080.0));
+        assert_eq!(to_physical.apply((640.0, 50.0)), (0.0, 0.0));
+        assert_eq!(to_physical.apply((640.0, 1010.0)), (1920.0, 0.0));
+        for point in [(100.0, 50.0), (333.5, 700.25), (639.0, 1009.0)] {
+            let (x, y) = to_logical.apply(to_physical.apply(point));
+            assert!((x - point.0).abs() < 1e-9 && (y - point.1).abs() < 1e-9);
+        }
+
+        mem::forget(output_info);
+    }
+
+    #[test]
+    fn buffer_to_output_matches_how_captures_are_rotated() {
+        use crate::image_util::apply_transform;
+        use image::{DynamicImage, Rgba, RgbaImage};
+
+        let (width, height) = (5, 3);
+        let (marked_x, marked_y) = (1, 0);
+        let buffer = RgbaImage::from_fn(width, height, |x, y| {
+            Rgba([((x, y) == (marked_x, marked_y)) as u8 * 255, 0, 0, 255])
+        });
+        for transform in [
+            wl_output::Transform::Normal,
+            wl_output::Transform::_90,
+            wl_output::Transform::_180,
+            wl_output::Transform::_270,
+            wl_output::Transform::Flipped,
+            wl_output::Transform::Flipped90,
+            wl_output::Transform::Flipped180,
+            wl_output::Transform::Flipped270,
+        ] {
+            let mut output_info =
+                make_output_info("DP-6", "", Size { width, height }, LogicalRegion::default());
+            output_info.transform = transform;
+            let rotated = apply_transform(DynamicImage::from(buffer.clone()), transform);
+            let (x, y, _) = rotated
+                .to_rgba8()
+                .enumerate_pixels()
+                .find(|(_, _, pixel)| pixel.0[0] == 255)
+                .expect("marked pixel");
+
+            let center = (marked_x as f64 + 0.5, marked_y as f64 + 0.5);
+            assert_eq!(
+                output_info.buffer_to_output().apply(center),
+                (x as f64 + 0.5, y as f64 + 0.5),
+                "{transform:?}"
+            );
+
+            mem::forget(output_info);
+        }
+    }
+
+    #[test]
+    fn mirror_groups_group_outputs_with_the_same_logical_region() {
+        let region = |x| LogicalRegion {
+            inner: Region {
+                position: Position { x, y: 0 },
+                size: Size {
+                    width: 1920,
+                    height: 1080,
+                },
+            },
+        };
+        let physical_size = Size {
+            width: 1920,
+            height: 1080,
+        };
+        let outputs = [
+            make_output_info("HDMI-A-1", "Projector", physical_size, region(0)),
+            make_output_info("DP-1", "Side Display", physical_size, region(1920)),
+            make_output_info("eDP-1", "Laptop Screen", physical_size, region(0)),
+        ];
+
+        let names: Vec<Vec<&str>> = mirror_groups(&outputs)
+            .iter()
+            .map(|group| group.iter().map(|output| output.name.as_str()).collect())
+            .collect();
+        assert_eq!(names, [vec!["HDMI-A-1", "eDP-1"], vec!["DP-1"]]);
+
+        for output in outputs {
+            mem::forget(output);
+        }
+    }
+
+    #[test]
+    fn output_diff_matches_outputs_by_name() {
+        let region = |x, width| LogicalRegion {
+            inner: Region {
+                position: Position { x, y: 0 },
+                size: Size {
+                    width,
+                    height: 1080,
+                },
+            },
+        };
+        let physical_size = Size {
+            width: 1920,
+            height: 1080,
+        };
+        let previous = [
+            make_output_info("eDP-1", "Laptop", physical_size, region(0, 1920)),
+            make_output_info("DP-1", "Left", physical_size, region(1920, 1920)),
+            make_output_info("DP-2", "Right", physical_size, region(3840, 1920)),
+        ];
+        let mut rotated = make_output_info("DP-2", "Right", physical_size, region(3840, 1920));
+        rotated.transform = wl_output::Transform::_90;
+        let current = [
+            // Scaled to 1.5, which shrinks its logical size.
+            make_output_info("eDP-1", "Laptop", physical_size, region(0, 1280)),
+            rotated,
+            make_output_info("HDMI-A-1", "TV", physical_size, region(1920, 1920)),
+        ];
+
+        let diff = OutputDiff::between(&previous, &current);
+        assert_eq!(diff.added, ["HDMI-A-1"]);
+        assert_eq!(diff.removed, ["DP-1"]);
+        assert_eq!(diff.modified, ["eDP-1", "DP-2"]);
+        assert!(OutputDiff::between(&current, &current).is_empty());
+
+        for output in previous.into_iter().chain(current) {
+            mem::forget(output);
+        }
+    }
+
     #[test]
     fn debug_format() {
         let output_info = make_output_info(
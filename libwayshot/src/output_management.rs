@@ -0,0 +1,379 @@
+//! Capturing an output that is disabled by enabling it for the capture, with
+//! the wlr-output-management protocol. Behind the `output-management` feature.
+//!
+//! Enabling an output is a modeset: screens flicker, windows may be moved to
+//! the enabled output and back, and the compositor's output layout changes
+//! while the capture runs. Only use this where that is acceptable, e.g. in
+//! kiosk setups with an output that is switched off most of the time.
+
+use std::{
+    collections::HashSet,
+    time::{Duration, Instant},
+};
+
+use image::DynamicImage;
+use wayland_client::{Connection, Dispatch, EventQueue, Proxy, QueueHandle, delegate_noop};
+use wayland_protocols_wlr::output_management::v1::client::{
+    zwlr_output_configuration_head_v1::ZwlrOutputConfigurationHeadV1,
+    zwlr_output_configuration_v1::{self, ZwlrOutputConfigurationV1},
+    zwlr_output_head_v1::{self, ZwlrOutputHeadV1},
+    zwlr_output_manager_v1::{self, ZwlrOutputManagerV1},
+    zwlr_output_mode_v1::{self, ZwlrOutputModeV1},
+};
+
+use crate::{
+    Error, Result, WayshotConnection, dispatch_with_timeout,
+    environment::{MIN_OUTPUT_MANAGER_VERSION, has_global},
+};
+
+/// A head as last announced by the compositor.
+#[derive(Debug)]
+struct Head {
+    head: ZwlrOutputHeadV1,
+    name: String,
+    enabled: bool,
+    modes: Vec<ZwlrOutputModeV1>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ConfigurationResult {
+    Succeeded,
+    Failed,
+    Cancelled,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct OutputManagementState {
+    heads: Vec<Head>,
+    preferred_modes: HashSet<ZwlrOutputModeV1>,
+    /// Serial of the last `done` event, taken when a configuration is applied
+    /// so the next one waits for the state it left behind.
+    serial: Option<u32>,
+    result: Option<ConfigurationResult>,
+}
+
+impl OutputManagementState {
+    fn head(&self, name: &str) -> Option<&Head> {
+        self.heads.iter().find(|head| head.name == name)
+    }
+}
+
+/// Whether each of `heads`, given as their name and whether they are enabled,
+/// is enabled in a configuration that sets `target` to `enable` and leaves
+/// every other head as it is. The protocol requires listing all of them.
+fn configured_heads<'a>(
+    heads: impl IntoIterator<Item = (&'a str, bool)>,
+    target: &str,
+    enable: bool,
+) -> Vec<bool> {
+    heads
+        .into_iter()
+        .map(|(name, enabled)| if name == target { enable } else { enabled })
+        .collect()
+}
+
+impl WayshotConnection {
+    /// Capture the output named `name` even while it is disabled, by enabling
+    /// it with wlr-output-management, capturing it and disabling it again.
+    ///
+    /// **This is disruptive.** Enabling and disabling an output are modesets,
+    /// which make screens flicker and can rearrange the output layout and the
+    /// windows on it for the duration of the capture. An output that is
+    /// already enabled is captured like [`Self::screenshot_single_output`]
+    /// without touching the configuration.
+    ///
+    /// The output comes up in its preferred mode and wherever the compositor
+    /// places it. Every other output keeps its configuration. `timeout` bounds
+    /// each wait for the compositor: for applying a configuration and for the
+    /// enabled output to be announced.
+    ///
+    /// Once the output was enabled it is disabled again on every path out of
+    /// this function, including failed captures. If that fails too, the error
+    /// of the capture is returned and the restore error is logged, a failed
+    /// restore after a successful capture is returned as
+    /// [`Error::OutputConfiguration`]. The outputs are refreshed like
+    /// [`Self::refresh_outputs`] afterwards.
+    ///
+    /// Fails with [`Error::ProtocolNotFound`] without `zwlr_output_manager_v1`,
+    /// with [`Error::OutputNotFound`] if the compositor has no such head and
+    /// with [`Error::OutputConfiguration`] if it refuses to enable it.
+    pub fn screenshot_disabled_output(
+        &mut self,
+        name: &str,
+        cursor_overlay: bool,
+        timeout: Duration,
+    ) -> Result<DynamicImage> {
+        if !has_global::<ZwlrOutputManagerV1>(&self.globals, MIN_OUTPUT_MANAGER_VERSION) {
+            return Err(Error::ProtocolNotFound(
+                "ZwlrOutputManagerV1 not found".to_string(),
+            ));
+        }
+        let mut event_queue = self.conn.new_event_queue::<OutputManagementState>();
+        let qh = event_queue.handle();
+        let manager = self.globals.bind::<ZwlrOutputManagerV1, _, _>(
+            &qh,
+            MIN_OUTPUT_MANAGER_VERSION..=ZwlrOutputManagerV1::interface().version,
+            (),
+        )?;
+        let mut state = OutputManagementState::default();
+
+        let result = self.capture_with_head_enabled(
+            &mut event_queue,
+            &mut state,
+            &manager,
+            name,
+            cursor_overlay,
+            timeout,
+        );
+        manager.stop();
+        self.conn.flush().ok();
+        result
+    }
+
+    fn capture_with_head_enabled(
+        &mut self,
+        event_queue: &mut EventQueue<OutputManagementState>,
+        state: &mut OutputManagementState,
+        manager: &ZwlrOutputManagerV1,
+        name: &str,
+        cursor_overlay: bool,
+        timeout: Duration,
+    ) -> Result<DynamicImage> {
+        dispatch_until(event_queue, state, timeout, |state| state.serial.is_some())?;
+        let head = state
+            .head(name)
+            .ok_or_else(|| Error::OutputNotFound(name.to_string()))?;
+        if head.enabled {
+            tracing::debug!("Output {name} is already enabled, capturing it as is");
+            return self.screenshot_named_output(name, cursor_overlay);
+        }
+
+        let output_count = self.get_all_outputs().len();
+        apply_configuration(event_queue, state, manager, name, true, timeout)?;
+        tracing::debug!("Enabled output {name} for the capture");
+
+        // From here on the output has to be disabled again, whatever happens.
+        let capture = self
+            .wait_for_outputs(output_count + 1, timeout)
+            .and_then(|()| self.screenshot_named_output(name, cursor_overlay));
+        let restore = apply_configuration(event_queue, state, manager, name, false, timeout)
+            .and_then(|()| self.refresh_outputs());
+        match (capture, restore) {
+            (capture, Ok(())) => {
+                tracing::debug!("Disabled output {name} again");
+                capture
+            }
+            (Ok(_), Err(e)) => Err(Error::OutputConfiguration(format!(
+                "output {name} was captured but could not be disabled again: {e}"
+            ))),
+            (Err(e), Err(restore_error)) => {
+                tracing::error!("Output {name} could not be disabled again: {restore_error}");
+                Err(e)
+            }
+        }
+    }
+
+    fn screenshot_named_output(&self, name: &str, cursor_overlay: bool) -> Result<DynamicImage> {
+        let output_info = self
+            .get_all_outputs()
+            .iter()
+            .find(|output_info| output_info.name == name)
+            .ok_or_else(|| Error::OutputNotFound(name.to_string()))?;
+        self.screenshot_single_output(output_info, cursor_overlay)
+    }
+}
+
+/// Apply a configuration setting the head named `name` to `enable`, waiting
+/// for the state it applies to and for the compositor's answer.
+fn apply_configuration(
+    event_queue: &mut EventQueue<OutputManagementState>,
+    state: &mut OutputManagementState,
+    manager: &ZwlrOutputManagerV1,
+    name: &str,
+    enable: bool,
+    timeout: Duration,
+) -> Result<()> {
+    dispatch_until(event_queue, state, timeout, |state| state.serial.is_some())?;
+    let qh = event_queue.handle();
+    let serial = state.serial.take().expect("waited for the serial");
+    let configuration = manager.create_configuration(serial, &qh, ());
+    let enabled = configured_heads(
+        state
+            .heads
+            .iter()
+            .map(|head| (head.name.as_str(), head.enabled)),
+        name,
+        enable,
+    );
+    for (head, enabled) in state.heads.iter().zip(enabled) {
+        if !enabled {
+            configuration.disable_head(&head.head);
+            continue;
+        }
+        let configuration_head = configuration.enable_head(&head.head, &qh, ());
+        // Heads left enabled keep their mode, the enabled one gets its preferred mode.
+        if !head.enabled
+            && let Some(mode) = head
+                .modes
+                .iter()
+                .find(|mode| state.preferred_modes.contains(*mode))
+        {
+            configuration_head.set_mode(mode);
+        }
+    }
+    state.result = None;
+    configuration.apply();
+    let result = dispatch_until(event_queue, state, timeout, |state| state.result.is_some());
+    configuration.destroy();
+    result?;
+
+    let action = if enable { "enabling" } else { "disabling" };
+    match state.result {
+        Some(ConfigurationResult::Succeeded) => Ok(()),
+        Some(ConfigurationResult::Cancelled) => Err(Error::OutputConfiguration(format!(
+            "{action} output {name} was cancelled because the outputs changed meanwhile"
+        ))),
+        _ => Err(Error::OutputConfiguration(format!(
+            "the compositor refused {action} output {name}"
+        ))),
+    }
+}
+
+/// Dispatch events until `done` returns true, failing after `timeout`.
+fn dispatch_until(
+    event_queue: &mut EventQueue<OutputManagementState>,
+    state: &mut OutputManagementState,
+    timeout: Duration,
+    done: impl Fn(&OutputManagementState) -> bool,
+) -> Result<()> {
+    let deadline = Instant::now() + timeout;
+    while !done(state) {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(Error::OutputConfiguration(
+                "timed out waiting for the compositor".to_string(),
+            ));
+        }
+        dispatch_with_timeout(event_queue, state, remaining)?;
+    }
+    Ok(())
+}
+
+impl Dispatch<ZwlrOutputManagerV1, ()> for OutputManagementState {
+    fn event(
+        state: &mut Self,
+        _: &ZwlrOutputManagerV1,
+        event: zwlr_output_manager_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        match event {
+            zwlr_output_manager_v1::Event::Head { head } => state.heads.push(Head {
+                head,
+                name: String::new(),
+                enabled: false,
+                modes: Vec::new(),
+            }),
+            zwlr_output_manager_v1::Event::Done { serial } => state.serial = Some(serial),
+            _ => {}
+        }
+    }
+
+    wayland_client::event_created_child!(OutputManagementState, ZwlrOutputManagerV1, [
+        zwlr_output_manager_v1::EVT_HEAD_OPCODE => (ZwlrOutputHeadV1, ())
+    ]);
+}
+
+impl Dispatch<ZwlrOutputHeadV1, ()> for OutputManagementState {
+    fn event(
+        state: &mut Self,
+        proxy: &ZwlrOutputHeadV1,
+        event: zwlr_output_head_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        if let zwlr_output_head_v1::Event::Finished = event {
+            state.heads.retain(|head| head.head != *proxy);
+            return;
+        }
+        let Some(head) = state.heads.iter_mut().find(|head| head.head == *proxy) else {
+            return;
+        };
+        match event {
+            zwlr_output_head_v1::Event::Name { name } => head.name = name,
+            zwlr_output_head_v1::Event::Enabled { enabled } => head.enabled = enabled != 0,
+            zwlr_output_head_v1::Event::Mode { mode } => head.modes.push(mode),
+            _ => {}
+        }
+    }
+
+    wayland_client::event_created_child!(OutputManagementState, ZwlrOutputHeadV1, [
+        zwlr_output_head_v1::EVT_MODE_OPCODE => (ZwlrOutputModeV1, ())
+    ]);
+}
+
+impl Dispatch<ZwlrOutputModeV1, ()> for OutputManagementState {
+    fn event(
+        state: &mut Self,
+        proxy: &ZwlrOutputModeV1,
+        event: zwlr_output_mode_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        match event {
+            zwlr_output_mode_v1::Event::Preferred => {
+                state.preferred_modes.insert(proxy.clone());
+            }
+            zwlr_output_mode_v1::Event::Finished => {
+                state.preferred_modes.remove(proxy);
+                for head in &mut state.heads {
+                    head.modes.retain(|mode| mode != proxy);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Dispatch<ZwlrOutputConfigurationV1, ()> for OutputManagementState {
+    fn event(
+        state: &mut Self,
+        _: &ZwlrOutputConfigurationV1,
+        event: zwlr_output_configuration_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        state.result = match event {
+            zwlr_output_configuration_v1::Event::Succeeded => Some(ConfigurationResult::Succeeded),
+            zwlr_output_configuration_v1::Event::Failed => Some(ConfigurationResult::Failed),
+            zwlr_output_configuration_v1::Event::Cancelled => Some(ConfigurationResult::Cancelled),
+            _ => return,
+        };
+    }
+}
+
+delegate_noop!(OutputManagementState: ignore ZwlrOutputConfigurationHeadV1);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn configurations_only_toggle_the_target_head() {
+        let heads = [("eDP-1", true), ("HDMI-A-1", false), ("DP-1", false)];
+        assert_eq!(
+            configured_heads(heads, "HDMI-A-1", true),
+            [true, true, false]
+        );
+        // Restoring disables the target again and keeps the rest as they are now.
+        let enabled = [("eDP-1", true), ("HDMI-A-1", true), ("DP-1", false)];
+        assert_eq!(
+            configured_heads(enabled, "HDMI-A-1", false),
+            [true, false, false]
+        );
+    }
+}
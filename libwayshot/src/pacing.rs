@@ -0,0 +1,156 @@
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{Error, Result};
+
+/// Paces a capture loop to a target frame rate.
+///
+/// Frame `n` is due `n` intervals after the pacer was created, so a slow
+/// capture doesn't push every later frame back. A capture that overruns its
+/// slot skips to the next slot that hasn't started yet instead of catching up
+/// with a burst of frames.
+#[derive(Debug, Clone)]
+pub struct FramePacer {
+    interval: Duration,
+    start: Instant,
+    /// Slot of the last finished frame.
+    slot: u64,
+    frames: u64,
+    skipped: u64,
+}
+
+/// How a paced capture loop kept up with its target frame rate.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LoopStats {
+    pub frames: u64,
+    /// Slots left empty because a capture overran its own.
+    pub skipped: u64,
+    pub elapsed: Duration,
+}
+
+impl LoopStats {
+    /// Frames per second the loop actually delivered.
+    pub fn achieved_fps(&self) -> f64 {
+        if self.elapsed.is_zero() {
+            return 0.0;
+        }
+        self.frames as f64 / self.elapsed.as_secs_f64()
+    }
+}
+
+impl FramePacer {
+    /// Pace to `fps` frames per second, starting now.
+    ///
+    /// Returns [`Error::Unsupported`] if `fps` isn't a positive number.
+    pub fn new(fps: f64) -> Result<Self> {
+        if !(fps.is_finite() && fps > 0.0) {
+            return Err(Error::Unsupported(format!("capturing at {fps} fps")));
+        }
+        Ok(Self::with_interval(
+            Duration::from_secs_f64(1.0 / fps),
+            Instant::now(),
+        ))
+    }
+
+    fn with_interval(interval: Duration, start: Instant) -> Self {
+        Self {
+            interval: interval.max(Duration::from_nanos(1)),
+            start,
+            slot: 0,
+            frames: 0,
+            skipped: 0,
+        }
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// Record a finished frame and sleep until the next frame is due.
+    pub fn wait(&mut self) {
+        let now = Instant::now();
+        let deadline = self.frame_done(now);
+        thread::sleep(deadline.saturating_duration_since(now));
+    }
+
+    /// Record a frame finished at `now` and return when the next one is due.
+    fn frame_done(&mut self, now: Instant) -> Instant {
+        self.frames += 1;
+        let elapsed = now.saturating_duration_since(self.start).as_nanos();
+        let interval = self.interval.as_nanos();
+        // The first slot that hasn't started yet, a frame finishing right on
+        // a deadline starts that slot at once.
+        let due = elapsed.div_ceil(interval) as u64;
+        let next = self.slot + 1;
+        if due > next {
+            self.skipped += due - next;
+        }
+        self.slot = due.max(next);
+        self.start + Duration::from_nanos((interval * self.slot as u128) as u64)
+    }
+
+    pub fn stats(&self) -> LoopStats {
+        LoopStats {
+            frames: self.frames,
+            skipped: self.skipped,
+            elapsed: self.start.elapsed(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MS: Duration = Duration::from_millis(1);
+
+    #[test]
+    fn deadlines_stay_aligned_to_the_start() {
+        let start = Instant::now();
+        let mut pacer = FramePacer::with_interval(10 * MS, start);
+
+        // Early and late frames within their slot don't move the next deadline.
+        assert_eq!(pacer.frame_done(start + 3 * MS), start + 10 * MS);
+        assert_eq!(pacer.frame_done(start + 19 * MS), start + 20 * MS);
+        assert_eq!(pacer.frame_done(start + 20 * MS), start + 30 * MS);
+        assert_eq!(pacer.skipped, 0);
+    }
+
+    #[test]
+    fn overrunning_frames_skip_to_the_next_aligned_slot() {
+        let start = Instant::now();
+        let mut pacer = FramePacer::with_interval(10 * MS, start);
+
+        // Slots 1 and 2 started while the frame was captured.
+        assert_eq!(pacer.frame_done(start + 25 * MS), start + 30 * MS);
+        assert_eq!(pacer.skipped, 2);
+        // Finishing right on a deadline starts that slot at once.
+        assert_eq!(pacer.frame_done(start + 40 * MS), start + 40 * MS);
+        assert_eq!(pacer.skipped, 2);
+        assert_eq!(pacer.frame_done(start + 41 * MS), start + 50 * MS);
+        assert_eq!(pacer.frames, 3);
+    }
+
+    #[test]
+    fn achieved_fps_counts_frames_over_time() {
+        let stats = LoopStats {
+            frames: 45,
+            skipped: 15,
+            elapsed: Duration::from_secs(3),
+        };
+        assert_eq!(stats.achieved_fps(), 15.0);
+        assert_eq!(
+            LoopStats {
+                frames: 0,
+                skipped: 0,
+                elapsed: Duration::ZERO
+            }
+            .achieved_fps(),
+            0.0
+        );
+        assert!(FramePacer::new(0.0).is_err());
+        assert!(FramePacer::new(f64::NAN).is_err());
+    }
+}
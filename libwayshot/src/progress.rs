@@ -0,0 +1,52 @@
+//! Progress of screenshots, for tools that show it while a capture of many or
+//! large outputs runs. See [`WayshotConnection::set_progress`](crate::WayshotConnection::set_progress).
+
+use std::{
+    fmt::{self, Debug},
+    sync::Arc,
+};
+
+use crate::CaptureId;
+
+/// A step of a screenshot, handed to the callback of
+/// [`WayshotConnection::set_progress`](crate::WayshotConnection::set_progress).
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ProgressEvent {
+    /// The compositor copied a frame of `output`.
+    OutputCaptured {
+        output: String,
+        capture_id: CaptureId,
+    },
+    /// The frame of `output` was converted, its pixels are ready to be
+    /// composited or returned.
+    FrameConverted { output: String },
+    /// The frames of `outputs` outputs were composited into one image of
+    /// `width` by `height` pixels.
+    Composited {
+        outputs: usize,
+        width: u32,
+        height: u32,
+    },
+}
+
+/// The callback set with [`WayshotConnection::set_progress`](crate::WayshotConnection::set_progress),
+/// called from the thread taking the screenshot.
+#[derive(Clone)]
+pub(crate) struct ProgressCallback(Arc<dyn Fn(&ProgressEvent) + Send + Sync>);
+
+impl ProgressCallback {
+    pub(crate) fn new(callback: impl Fn(&ProgressEvent) + Send + Sync + 'static) -> Self {
+        Self(Arc::new(callback))
+    }
+
+    pub(crate) fn report(&self, event: &ProgressEvent) {
+        (self.0)(event)
+    }
+}
+
+impl Debug for ProgressCallback {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ProgressCallback")
+    }
+}
@@ -1,15 +1,17 @@
 use crate::{
     WayshotConnection,
     error::{Error, Result},
+    freeze::FreezeOptions,
     output::OutputInfo,
 };
 use std::cmp;
+use wayland_client::protocol::wl_output;
 use wayland_protocols::ext::foreign_toplevel_list::v1::client::ext_foreign_toplevel_handle_v1::ExtForeignToplevelHandleV1;
 
-pub type FreezeCallback = Box<dyn Fn(&WayshotConnection) -> Result<LogicalRegion>>;
+pub(crate) type FreezeCallback = Box<dyn Fn(&WayshotConnection) -> Result<LogicalRegion>>;
 
 /// Ways to say how a region for a screenshot should be captured.
-pub enum RegionCapturer {
+pub(crate) enum RegionCapturer {
     /// Capture all of the given outputs.
     Outputs(Vec<OutputInfo>),
     /// Capture an already known `LogicalRegion`.
@@ -19,7 +21,7 @@ pub enum RegionCapturer {
     /// The outputs will be "frozen" to the user at which point the given
     /// callback is called to get the region to capture. This callback is often
     /// a user interaction to let the user select a region.
-    Freeze(FreezeCallback),
+    Freeze(FreezeCallback, FreezeOptions),
 }
 
 #[derive(Debug, Clone)]
@@ -58,10 +60,202 @@ impl TopLevel {
 /// scaling have been applied. A unit is a logical pixel, meaning that this is
 /// after scaling has been applied.
 #[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct LogicalRegion {
     pub inner: Region,
 }
 
+impl LogicalRegion {
+    /// Region at `x`, `y` of `width` by `height` logical pixels.
+    pub fn new(x: i32, y: i32, width: u32, height: u32) -> Self {
+        LogicalRegion {
+            inner: Region {
+                position: Position { x, y },
+                size: Size { width, height },
+            },
+        }
+    }
+
+    /// Region spanned by two opposite corners, in any order. Both corner pixels
+    /// are included, so identical corners give a 1x1 region.
+    pub fn from_corners(a: Position, b: Position) -> Self {
+        let position = Position {
+            x: cmp::min(a.x, b.x),
+            y: cmp::min(a.y, b.y),
+        };
+        LogicalRegion {
+            inner: Region {
+                position,
+                size: Size {
+                    width: a.x.abs_diff(b.x) + 1,
+                    height: a.y.abs_diff(b.y) + 1,
+                },
+            },
+        }
+    }
+
+    /// The same region moved by `dx` and `dy` logical pixels, e.g. by its own
+    /// width to capture the area next to it.
+    pub fn translated(self, dx: i32, dy: i32) -> Self {
+        let Region { position, size } = self.inner;
+        LogicalRegion {
+            inner: Region {
+                position: Position {
+                    x: position.x.saturating_add(dx),
+                    y: position.y.saturating_add(dy),
+                },
+                size,
+            },
+        }
+    }
+
+    /// Whether both regions share at least one logical pixel.
+    pub fn overlaps(&self, other: &LogicalRegion) -> bool {
+        self.intersection(other).is_some()
+    }
+
+    /// The logical pixels both regions share, `None` if they share none.
+    ///
+    /// Regions that only touch, like two outputs side by side, don't
+    /// intersect, see [`Region`].
+    pub fn intersection(&self, other: &LogicalRegion) -> Option<LogicalRegion> {
+        let (a, b) = (self.inner, other.inner);
+        let x1 = (a.position.x as i64).max(b.position.x as i64);
+        let y1 = (a.position.y as i64).max(b.position.y as i64);
+        let x2 = a.right().min(b.right());
+        let y2 = a.bottom().min(b.bottom());
+        if x1 >= x2 || y1 >= y2 {
+            return None;
+        }
+        Some(LogicalRegion {
+            inner: Region {
+                position: Position {
+                    x: x1 as i32,
+                    y: y1 as i32,
+                },
+                size: Size {
+                    width: (x2 - x1) as u32,
+                    height: (y2 - y1) as u32,
+                },
+            },
+        })
+    }
+
+    /// The outputs touched by a drag from `start` to `end` together with the
+    /// region spanning all of them, e.g. for selections across monitors.
+    ///
+    /// Returns [`Error::NoOutputs`] when the drag does not touch any output.
+    pub fn from_drag(
+        start: Position,
+        end: Position,
+        outputs: &[OutputInfo],
+    ) -> Result<(Self, Vec<OutputInfo>)> {
+        let drag = Self::from_corners(start, end);
+        let touched: Vec<OutputInfo> = outputs
+            .iter()
+            .filter(|output| drag.overlaps(&output.logical_region))
+            .cloned()
+            .collect();
+        let region = Self::try_from(touched.as_slice())?;
+        Ok((region, touched))
+    }
+
+    /// The parts of this region on each of `outputs` that it overlaps, as the
+    /// part of the output to capture and where that part lies in this region,
+    /// relative to its top left.
+    pub(crate) fn split_at_outputs<'a>(
+        &self,
+        outputs: &'a [OutputInfo],
+    ) -> Vec<(&'a OutputInfo, EmbeddedRegion, Region)> {
+        outputs
+            .iter()
+            .filter_map(|output| {
+                let embedded = EmbeddedRegion::new(*self, output.logical_region)?;
+                let Region { position, size } = embedded.logical().inner;
+                let placed = Region {
+                    position: Position {
+                        x: position.x - self.inner.position.x,
+                        y: position.y - self.inner.position.y,
+                    },
+                    size,
+                };
+                Some((output, embedded, placed))
+            })
+            .collect()
+    }
+
+    /// Whether every logical pixel of this region is on one of `outputs`, so
+    /// capturing it leaves no gaps. An empty region is never covered.
+    ///
+    /// The outputs' parts of the region are split into cells at their edges,
+    /// which works for any union of outputs, e.g. L-shaped layouts.
+    pub fn covered_by(&self, outputs: &[OutputInfo]) -> bool {
+        let parts: Vec<Region> = outputs
+            .iter()
+            .filter_map(|output| Some(self.intersection(&output.logical_region)?.inner))
+            .collect();
+        if parts.is_empty() {
+            return false;
+        }
+        let edges = |start: fn(&Region) -> i64, end: fn(&Region) -> i64| {
+            let mut edges: Vec<i64> = parts
+                .iter()
+                .flat_map(|part| [start(part), end(part)])
+                .collect();
+            edges.sort_unstable();
+            edges.dedup();
+            edges
+        };
+        let columns = edges(|part| part.position.x as i64, Region::right);
+        let rows = edges(|part| part.position.y as i64, Region::bottom);
+        let outer = (
+            columns[0],
+            rows[0],
+            columns[columns.len() - 1],
+            rows[rows.len() - 1],
+        );
+        if outer
+            != (
+                self.inner.position.x as i64,
+                self.inner.position.y as i64,
+                self.inner.right(),
+                self.inner.bottom(),
+            )
+        {
+            return false;
+        }
+        // Every cell is either fully inside or fully outside of each part.
+        rows.windows(2).all(|row| {
+            columns.windows(2).all(|column| {
+                parts.iter().any(|part| {
+                    part.position.x as i64 <= column[0]
+                        && column[1] <= part.right()
+                        && part.position.y as i64 <= row[0]
+                        && row[1] <= part.bottom()
+                })
+            })
+        })
+    }
+
+    /// Check a region returned by a freeze callback or read back from
+    /// [`crate::state`] against the outputs.
+    ///
+    /// Returns [`Error::EmptyRegion`] if it has no area and
+    /// [`Error::RegionNotVisible`] if it does not overlap any of the outputs.
+    pub fn check_selection(self, outputs: &[OutputInfo]) -> Result<Self> {
+        if self.inner.size.width == 0 || self.inner.size.height == 0 {
+            return Err(Error::EmptyRegion);
+        }
+        if !outputs
+            .iter()
+            .any(|output| self.overlaps(&output.logical_region))
+        {
+            return Err(Error::RegionNotVisible(self));
+        }
+        Ok(self)
+    }
+}
+
 /// An embedded region is a region entirely inside of another (often an output).
 ///
 /// It can only be contained inside of another and cannot exceed its bounds.
@@ -94,9 +288,16 @@ pub struct EmbeddedRegion {
 
 /// Rectangle area in an unspecified coordinate system.
 ///
+/// A region covers the half-open ranges `[x, x + width)` and
+/// `[y, y + height)`: its right and bottom edges are the first column and
+/// row outside of it. Two regions sharing an edge, like outputs side by side,
+/// touch without overlapping, and cropping a region at such an edge splits it
+/// without gaps or overlap.
+///
 /// Use `LogicalRegion` or `EmbeddedRegion` instead as they convey the
 /// coordinate system used.
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Region {
     /// Position of the region.
     pub position: Position,
@@ -105,6 +306,7 @@ pub struct Region {
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Position {
     /// X coordinate.
     pub x: i32,
@@ -113,6 +315,7 @@ pub struct Position {
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Size<T = u32> {
     /// Width.
     pub width: T,
@@ -125,49 +328,45 @@ impl EmbeddedRegion {
     /// `relative_to` (think the output we want to capture), create an
     /// embedded region that is entirely inside of the `relative_to` region.
     ///
-    /// See `EmbeddedRegion` for an example ASCII visualisation.
+    /// Returns `None` if they don't intersect, see
+    /// [`LogicalRegion::intersection`]. See `EmbeddedRegion` for an example
+    /// ASCII visualisation.
     #[tracing::instrument(ret, level = "debug")]
     pub fn new(viewport: LogicalRegion, relative_to: LogicalRegion) -> Option<Self> {
-        let x_relative: i32 = viewport.inner.position.x - relative_to.inner.position.x;
-        let y_relative = viewport.inner.position.y - relative_to.inner.position.y;
-
-        let x1 = cmp::max(x_relative, 0);
-        let x2 = cmp::min(
-            x_relative + viewport.inner.size.width as i32,
-            relative_to.inner.size.width as i32,
-        );
-        let width = if let Ok(width) = (x2 - x1).try_into() {
-            if width < 1 {
-                return None;
-            };
-            width
-        } else {
-            return None;
-        };
-
-        let y1 = cmp::max(y_relative, 0);
-        let y2 = cmp::min(
-            y_relative + viewport.inner.size.height as i32,
-            relative_to.inner.size.height as i32,
-        );
-        let height = if let Ok(height) = (y2 - y1).try_into() {
-            if height < 1 {
-                return None;
-            };
-            height
-        } else {
-            return None;
-        };
-
+        let Region { position, size } = viewport.intersection(&relative_to)?.inner;
         Some(Self {
             relative_to,
             inner: Region {
-                position: Position { x: x1, y: y1 },
-                size: Size { width, height },
+                position: Position {
+                    x: position.x - relative_to.inner.position.x,
+                    y: position.y - relative_to.inner.position.y,
+                },
+                size,
             },
         })
     }
 
+    /// Embed `region`, given in the logical coordinates of `relative_to` with
+    /// (0, 0) at its top left, without clamping it.
+    ///
+    /// Returns [`Error::RegionOutOfBounds`] if `region` is empty or does not
+    /// lie entirely inside of `relative_to`.
+    pub fn within(region: Region, relative_to: LogicalRegion) -> Result<Self> {
+        let bounds = relative_to.inner.size;
+        let fits = |position: i32, length: u32, bound: u32| {
+            position >= 0 && length > 0 && position as u64 + length as u64 <= bound as u64
+        };
+        if !fits(region.position.x, region.size.width, bounds.width)
+            || !fits(region.position.y, region.size.height, bounds.height)
+        {
+            return Err(Error::RegionOutOfBounds { region, bounds });
+        }
+        Ok(Self {
+            relative_to,
+            inner: region,
+        })
+    }
+
     /// Return the `LogicalRegion` of the embedded region.
     ///
     /// Note that this remains a region of the same size, it's not the inverse
@@ -186,6 +385,141 @@ impl EmbeddedRegion {
     }
 }
 
+impl Region {
+    /// The first column right of the region.
+    pub(crate) fn right(&self) -> i64 {
+        self.position.x as i64 + self.size.width as i64
+    }
+
+    /// The first row below the region.
+    pub(crate) fn bottom(&self) -> i64 {
+        self.position.y as i64 + self.size.height as i64
+    }
+
+    /// This region in physical pixels at `scale` physical pixels per logical pixel.
+    ///
+    /// The edges are rounded to the nearest pixel, halfway cases away from zero,
+    /// rather than the position and size, the way wlroots maps boxes to buffers.
+    /// Regions that share an edge in logical space share it at every scale, so
+    /// stitched crops have neither gaps nor overlap even at fractional scales.
+    pub fn to_physical(&self, scale: f64) -> Region {
+        let edge = |logical: i64| (logical as f64 * scale).round() as i64;
+        let (left, top) = (edge(self.position.x as i64), edge(self.position.y as i64));
+        let (right, bottom) = (edge(self.right()), edge(self.bottom()));
+        Region {
+            position: Position {
+                x: left as i32,
+                y: top as i32,
+            },
+            size: Size {
+                width: (right - left) as u32,
+                height: (bottom - top) as u32,
+            },
+        }
+    }
+}
+
+/// An affine transform of 2D points, `(x, y)` maps to
+/// `(xx * x + xy * y + x0, yx * x + yy * y + y0)`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Transform2D {
+    pub xx: f64,
+    pub xy: f64,
+    pub x0: f64,
+    pub yx: f64,
+    pub yy: f64,
+    pub y0: f64,
+}
+
+impl Transform2D {
+    pub const IDENTITY: Self = Self {
+        xx: 1.0,
+        xy: 0.0,
+        x0: 0.0,
+        yx: 0.0,
+        yy: 1.0,
+        y0: 0.0,
+    };
+
+    pub fn translation(x: f64, y: f64) -> Self {
+        Self {
+            x0: x,
+            y0: y,
+            ..Self::IDENTITY
+        }
+    }
+
+    pub fn scale(scale: f64) -> Self {
+        Self {
+            xx: scale,
+            yy: scale,
+            ..Self::IDENTITY
+        }
+    }
+
+    /// Rotate and flip points of a `buffer_size` buffer into the orientation
+    /// `transform` puts it in, the way captured images are rotated.
+    pub(crate) fn buffer_to_output(transform: wl_output::Transform, buffer_size: Size) -> Self {
+        let (width, height) = (buffer_size.width as f64, buffer_size.height as f64);
+        // Rows are (xx, xy, x0) and (yx, yy, y0).
+        let ([xx, xy, x0], [yx, yy, y0]) = match transform {
+            wl_output::Transform::_90 => ([0.0, -1.0, height], [1.0, 0.0, 0.0]),
+            wl_output::Transform::_180 => ([-1.0, 0.0, width], [0.0, -1.0, height]),
+            wl_output::Transform::_270 => ([0.0, 1.0, 0.0], [-1.0, 0.0, width]),
+            wl_output::Transform::Flipped => ([-1.0, 0.0, width], [0.0, 1.0, 0.0]),
+            wl_output::Transform::Flipped90 => ([0.0, -1.0, height], [-1.0, 0.0, width]),
+            wl_output::Transform::Flipped180 => ([1.0, 0.0, 0.0], [0.0, -1.0, height]),
+            wl_output::Transform::Flipped270 => ([0.0, 1.0, 0.0], [1.0, 0.0, 0.0]),
+            _ => return Self::IDENTITY,
+        };
+        Self {
+            xx,
+            xy,
+            x0,
+            yx,
+            yy,
+            y0,
+        }
+    }
+
+    /// The transform that applies `self` and then `next`.
+    pub fn then(self, next: Self) -> Self {
+        Self {
+            xx: next.xx * self.xx + next.xy * self.yx,
+            xy: next.xx * self.xy + next.xy * self.yy,
+            x0: next.xx * self.x0 + next.xy * self.y0 + next.x0,
+            yx: next.yx * self.xx + next.yy * self.yx,
+            yy: next.yx * self.xy + next.yy * self.yy,
+            y0: next.yx * self.x0 + next.yy * self.y0 + next.y0,
+        }
+    }
+
+    /// The transform undoing `self`, or `None` if it collapses the plane.
+    pub fn inverse(&self) -> Option<Self> {
+        let determinant = self.xx * self.yy - self.xy * self.yx;
+        if determinant == 0.0 || !determinant.is_finite() {
+            return None;
+        }
+        let (xx, xy) = (self.yy / determinant, -self.xy / determinant);
+        let (yx, yy) = (-self.yx / determinant, self.xx / determinant);
+        Some(Self {
+            xx,
+            xy,
+            x0: -(xx * self.x0 + xy * self.y0),
+            yx,
+            yy,
+            y0: -(yx * self.x0 + yy * self.y0),
+        })
+    }
+
+    pub fn apply(&self, (x, y): (f64, f64)) -> (f64, f64) {
+        (
+            self.xx * x + self.xy * y + self.x0,
+            self.yx * x + self.yy * y + self.y0,
+        )
+    }
+}
+
 impl std::fmt::Display for EmbeddedRegion {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
@@ -486,6 +820,298 @@ mod tests {
         }
     }
 
+    fn dual_monitor_outputs() -> Vec<OutputInfo> {
+        vec![
+            make_output(
+                "left",
+                Position { x: 0, y: 0 },
+                Size {
+                    width: 1920,
+                    height: 1080,
+                },
+            ),
+            make_output(
+                "right",
+                Position { x: 1920, y: 200 },
+                Size {
+                    width: 1280,
+                    height: 1024,
+                },
+            ),
+        ]
+    }
+
+    #[test]
+    fn from_corners_normalizes_corner_order() {
+        let region =
+            LogicalRegion::from_corners(Position { x: 30, y: 5 }, Position { x: 10, y: 25 });
+
+        assert_eq!(region.inner.position, Position { x: 10, y: 5 });
+        assert_eq!(
+            region.inner.size,
+            Size {
+                width: 21,
+                height: 21
+            }
+        );
+    }
+
+    #[test]
+    fn check_selection_rejects_empty_and_offscreen_regions() {
+        let outputs = dual_monitor_outputs();
+
+        assert!(matches!(
+            LogicalRegion::new(10, 10, 0, 0).check_selection(&outputs),
+            Err(Error::EmptyRegion)
+        ));
+        assert!(matches!(
+            LogicalRegion::new(10, 10, 100, 0).check_selection(&outputs),
+            Err(Error::EmptyRegion)
+        ));
+        assert!(matches!(
+            LogicalRegion::new(-200, 0, 200, 100).check_selection(&outputs),
+            Err(Error::RegionNotVisible(_))
+        ));
+        let straddling = LogicalRegion::new(-50, -50, 100, 100);
+        assert_eq!(
+            straddling.check_selection(&outputs).expect("visible"),
+            straddling
+        );
+
+        for output in outputs {
+            mem::forget(output);
+        }
+    }
+
+    #[test]
+    fn covered_by_finds_gaps_between_outputs() {
+        let output = |name, x, y, width, height| {
+            make_output(name, Position { x, y }, Size { width, height })
+        };
+
+        // The right output is 1024 high and starts 200 below the top of the left one.
+        let outputs = dual_monitor_outputs();
+        assert!(LogicalRegion::new(100, 100, 1700, 900).covered_by(&outputs));
+        assert!(LogicalRegion::new(1800, 300, 400, 500).covered_by(&outputs));
+        // The corner above the right output and below the left one are gaps.
+        assert!(!LogicalRegion::new(1800, 100, 400, 500).covered_by(&outputs));
+        assert!(!LogicalRegion::new(1800, 1000, 400, 200).covered_by(&outputs));
+        assert!(!LogicalRegion::new(-10, 0, 100, 100).covered_by(&outputs));
+        assert!(!LogicalRegion::new(0, 0, 0, 0).covered_by(&outputs));
+
+        // An L of a wide output with a narrow one below its left half.
+        let l_shaped = vec![
+            output("top", 0, 0, 2000, 1000),
+            output("bottom", 0, 1000, 1000, 1000),
+        ];
+        assert!(LogicalRegion::new(0, 500, 1000, 1000).covered_by(&l_shaped));
+        assert!(LogicalRegion::new(500, 0, 1500, 1000).covered_by(&l_shaped));
+        assert!(!LogicalRegion::new(500, 500, 1000, 1000).covered_by(&l_shaped));
+
+        // Overlapping outputs and a one pixel gap between two others.
+        let gapped = vec![
+            output("a", 0, 0, 100, 100),
+            output("b", 50, 50, 100, 100),
+            output("c", 151, 0, 100, 150),
+        ];
+        assert!(LogicalRegion::new(0, 0, 100, 100).covered_by(&gapped));
+        assert!(LogicalRegion::new(50, 50, 100, 100).covered_by(&gapped));
+        assert!(!LogicalRegion::new(0, 0, 150, 150).covered_by(&gapped));
+        assert!(!LogicalRegion::new(140, 60, 20, 20).covered_by(&gapped));
+        assert!(LogicalRegion::new(151, 0, 100, 150).covered_by(&gapped));
+
+        for output in outputs.into_iter().chain(l_shaped).chain(gapped) {
+            mem::forget(output);
+        }
+    }
+
+    #[test]
+    fn translated_moves_the_region_and_keeps_its_size() {
+        let outputs = dual_monitor_outputs();
+        let region =
+            LogicalRegion::from_corners(Position { x: 100, y: 100 }, Position { x: 399, y: 299 });
+
+        let right = region.translated(300, 0);
+        assert_eq!(right.inner.position, Position { x: 400, y: 100 });
+        assert_eq!(right.inner.size, region.inner.size);
+        assert!(right.check_selection(&outputs).is_ok());
+        assert!(matches!(
+            region.translated(-10_000, 0).check_selection(&outputs),
+            Err(Error::RegionNotVisible(_))
+        ));
+        assert_eq!(region.translated(i32::MAX, 0).inner.position.x, i32::MAX);
+    }
+
+    #[test]
+    fn overlaps_excludes_touching_edges() {
+        let outputs = dual_monitor_outputs();
+        let left = outputs[0].logical_region;
+        let right = outputs[1].logical_region;
+
+        assert!(!left.overlaps(&right));
+        assert_eq!(left.intersection(&right), None);
+        assert!(left.overlaps(&LogicalRegion::from_corners(
+            Position { x: 1919, y: 0 },
+            Position { x: 1920, y: 0 },
+        )));
+
+        for output in outputs {
+            mem::forget(output);
+        }
+    }
+
+    #[test]
+    fn selections_at_an_output_edge_split_without_seams() {
+        let outputs = dual_monitor_outputs();
+        let (left, right) = (outputs[0].logical_region, outputs[1].logical_region);
+
+        // Crossing the border, starting right on it and ending right on it.
+        for (x, width) in [(1900, 40), (1920, 20), (1900, 20)] {
+            let selection = LogicalRegion {
+                inner: Region {
+                    position: Position { x, y: 300 },
+                    size: Size { width, height: 10 },
+                },
+            };
+            let crops: Vec<_> = [left, right]
+                .into_iter()
+                .filter_map(|output| EmbeddedRegion::new(selection, output))
+                .map(|embedded| embedded.logical().inner)
+                .collect();
+            assert_eq!(
+                crops.len(),
+                [left, right]
+                    .iter()
+                    .filter(|output| selection.overlaps(output))
+                    .count()
+            );
+            // The crops tile the selection: each starts where the last ended.
+            let mut next = x as i64;
+            for crop in &crops {
+                assert_eq!(crop.position.x as i64, next, "selection at {x}");
+                next = crop.right();
+            }
+            assert_eq!(next, selection.inner.right(), "selection at {x}");
+        }
+
+        for output in outputs {
+            mem::forget(output);
+        }
+    }
+
+    #[test]
+    fn split_at_outputs_places_each_part_in_the_region() {
+        let outputs = dual_monitor_outputs();
+        let region =
+            LogicalRegion::from_corners(Position { x: 1800, y: 100 }, Position { x: 2019, y: 399 });
+
+        let parts = region.split_at_outputs(&outputs);
+
+        let names: Vec<_> = parts.iter().map(|(o, _, _)| o.name.as_str()).collect();
+        assert_eq!(names, ["left", "right"]);
+        let (_, left, left_placed) = parts[0];
+        assert_eq!(
+            left.inner,
+            Region {
+                position: Position { x: 1800, y: 100 },
+                size: Size {
+                    width: 120,
+                    height: 300
+                },
+            }
+        );
+        assert_eq!(left_placed.position, Position { x: 0, y: 0 });
+        // The right output starts 200 logical pixels lower, above it is uncovered.
+        let (_, right, right_placed) = parts[1];
+        assert_eq!(
+            right.inner,
+            Region {
+                position: Position { x: 0, y: 0 },
+                size: Size {
+                    width: 100,
+                    height: 200
+                },
+            }
+        );
+        assert_eq!(right_placed.position, Position { x: 120, y: 100 });
+        assert_eq!(right_placed.size, right.inner.size);
+
+        let offscreen = region.translated(0, 10_000);
+        assert!(offscreen.split_at_outputs(&outputs).is_empty());
+
+        for output in outputs {
+            mem::forget(output);
+        }
+    }
+
+    #[test]
+    fn from_drag_across_monitors_spans_both_outputs() {
+        let outputs = dual_monitor_outputs();
+
+        let (region, touched) = LogicalRegion::from_drag(
+            Position { x: 1800, y: 900 },
+            Position { x: 2000, y: 400 },
+            &outputs,
+        )
+        .expect("drag touches outputs");
+
+        assert_eq!(
+            touched.iter().map(|o| o.name.as_str()).collect::<Vec<_>>(),
+            ["left", "right"]
+        );
+        assert_eq!(
+            region.inner,
+            Region {
+                position: Position { x: 0, y: 0 },
+                size: Size {
+                    width: 3200,
+                    height: 1224
+                }
+            }
+        );
+
+        for output in outputs.into_iter().chain(touched) {
+            mem::forget(output);
+        }
+    }
+
+    #[test]
+    fn from_drag_within_one_monitor_returns_only_that_output() {
+        let outputs = dual_monitor_outputs();
+
+        let (region, touched) = LogicalRegion::from_drag(
+            Position { x: 2500, y: 300 },
+            Position { x: 2100, y: 700 },
+            &outputs,
+        )
+        .expect("drag touches outputs");
+
+        assert_eq!(touched.len(), 1);
+        assert_eq!(touched[0].name, "right");
+        assert_eq!(region, outputs[1].logical_region);
+
+        for output in outputs.into_iter().chain(touched) {
+            mem::forget(output);
+        }
+    }
+
+    #[test]
+    fn from_drag_outside_outputs_errors() {
+        let outputs = dual_monitor_outputs();
+
+        let result = LogicalRegion::from_drag(
+            Position { x: 100, y: 1100 },
+            Position { x: 200, y: 1200 },
+            &outputs,
+        );
+
+        assert!(matches!(result, Err(Error::NoOutputs)));
+
+        for output in outputs {
+            mem::forget(output);
+        }
+    }
+
     #[test]
     fn logical_region_try_from_empty_slice_errors() {
         let empty: [OutputInfo; 0] = [];
@@ -495,4 +1121,88 @@ mod tests {
             _ => panic!("expected Error::NoOutputs"),
         }
     }
+
+    #[test]
+    fn embedded_region_within_validates_logical_bounds() {
+        let output = LogicalRegion {
+            inner: Region {
+                position: Position { x: 1920, y: 0 },
+                size: Size {
+                    width: 1280,
+                    height: 720,
+                },
+            },
+        };
+        let region = |x, y, width, height| Region {
+            position: Position { x, y },
+            size: Size { width, height },
+        };
+
+        let embedded = EmbeddedRegion::within(region(1180, 620, 100, 100), output).expect("inside");
+        assert_eq!(embedded.logical().inner, region(3100, 620, 100, 100));
+
+        for outside in [
+            region(-1, 0, 10, 10),
+            region(1200, 0, 81, 10),
+            region(0, 700, 10, 21),
+            region(0, 0, 0, 10),
+        ] {
+            assert!(matches!(
+                EmbeddedRegion::within(outside, output),
+                Err(Error::RegionOutOfBounds { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn to_physical_rounds_edges_so_neighbours_meet() {
+        let region = |x, y, width, height| Region {
+            position: Position { x, y },
+            size: Size { width, height },
+        };
+
+        for scale in [1.25, 1.6] {
+            let (left, right) = (region(-3, 1, 5, 3), region(2, 1, 7, 3));
+            let (left, right) = (left.to_physical(scale), right.to_physical(scale));
+            assert_eq!(
+                left.position.x + left.size.width as i32,
+                right.position.x,
+                "seam at scale {scale}"
+            );
+        }
+
+        assert_eq!(region(3, 1, 5, 3).to_physical(1.25), region(4, 1, 6, 4));
+        assert_eq!(region(-3, 1, 5, 3).to_physical(1.25), region(-4, 1, 7, 4));
+        assert_eq!(region(1, 3, 7, 5).to_physical(1.6), region(2, 5, 11, 8));
+        assert_eq!(
+            region(0, 0, 2560, 1440).to_physical(1.25).size,
+            Size {
+                width: 3200,
+                height: 1800,
+            }
+        );
+    }
+
+    #[test]
+    fn transform_2d_composes_and_inverts() {
+        let transform = Transform2D::translation(-10.0, 5.0)
+            .then(Transform2D::scale(1.5))
+            .then(Transform2D {
+                xx: 0.0,
+                xy: -1.0,
+                x0: 100.0,
+                yx: 1.0,
+                yy: 0.0,
+                y0: 0.0,
+            });
+
+        assert_eq!(transform.apply((10.0, -5.0)), (100.0, 0.0));
+        assert_eq!(transform.apply((12.0, -3.0)), (97.0, 3.0));
+        let inverse = transform.inverse().expect("invertible");
+        for point in [(0.0, 0.0), (12.0, -3.0), (-7.5, 40.25)] {
+            let (x, y) = inverse.apply(transform.apply(point));
+            assert!((x - point.0).abs() < 1e-9 && (y - point.1).abs() < 1e-9);
+        }
+        assert_eq!(Transform2D::scale(0.0).inverse(), None);
+    }
 }
@@ -6,7 +6,7 @@ use wayland_client::{
     globals::registry_queue_init,
     protocol::{
         wl_buffer::{self, WlBuffer},
-        wl_shm::{self, WlShm},
+        wl_shm,
         wl_shm_pool::WlShmPool,
     },
 };
@@ -17,6 +17,8 @@ use wayland_protocols::wp::linux_dmabuf::zv1::client::{
 use crate::{
     EmbeddedRegion, Error, Result, Size, WayshotConnection, WayshotFrame, WayshotTarget,
     dispatch::{DMABUFState, FrameState, WayshotState},
+    environment::MIN_LINUX_DMABUF_VERSION,
+    screencopy::create_shm_buffer,
 };
 
 /// It is a unit to do screencast. It storages used information for screencast
@@ -69,14 +71,19 @@ impl WayshotConnection {
         }
         let (mut state, _, _) = self.capture_target_frame_get_state(false, &target, None)?;
         let (globals, evq) = registry_queue_init::<WayshotState>(&self.conn)?;
-        let Some(gbm) = state.gbm.take() else {
+        let Some((device, gbm)) = state.gbm.take() else {
             return Err(Error::NoDMAStateError);
         };
-        let linux_dmabuf =
-            globals.bind(&evq.handle(), 4..=ZwpLinuxDmabufV1::interface().version, ())?;
+        let linux_dmabuf = globals.bind(
+            &evq.handle(),
+            MIN_LINUX_DMABUF_VERSION..=ZwpLinuxDmabufV1::interface().version,
+            (),
+        )?;
         self.dmabuf_state = Some(DMABUFState {
             linux_dmabuf,
             gbmdev: gbm,
+            compositor_device: Some(device.clone()),
+            device,
         });
         Ok(())
     }
@@ -94,10 +101,7 @@ impl WayshotConnection {
         };
         let (state, event_queue, _) =
             self.capture_target_frame_get_state(cursor_overlay, &target, capture_region)?;
-        if state.dmabuf_formats.is_empty() {
-            return Err(Error::NoSupportedBufferFormat);
-        }
-        let frame_format = state.dmabuf_formats[0];
+        let frame_format = state.first_dmabuf_format()?;
         tracing::trace!("Selected frame buffer format: {:#?}", frame_format);
         let gbm = &dmabuf_state.gbmdev;
         let bo = gbm.create_buffer_object::<()>(
@@ -178,30 +182,14 @@ impl WayshotConnection {
             .find(|f| f.format == shm_format)
             .copied()
         else {
-            return Err(Error::NoSupportedBufferFormat);
+            return Err(Error::no_usable_format(
+                state.formats.iter().map(|format| format.format),
+            ));
         };
         let qh = event_queue.handle();
 
-        // Instantiate shm global.
-        let shm = self.globals.bind::<WlShm, _, _>(&qh, 1..=1, ())?;
-        let shm_pool = shm.create_pool(
-            fd.as_fd(),
-            frame_format
-                .byte_size()
-                .try_into()
-                .map_err(|_| Error::BufferTooSmall)?,
-            &qh,
-            (),
-        );
-        let buffer = shm_pool.create_buffer(
-            0,
-            frame_format.size.width as i32,
-            frame_format.size.height as i32,
-            frame_format.stride as i32,
-            frame_format.format,
-            &qh,
-            (),
-        );
+        let shm = self.capture_shm()?;
+        let (shm_pool, buffer) = create_shm_buffer(&shm, fd.as_fd(), &frame_format, &qh)?;
 
         let origin_size = Size {
             width: frame_format.size.width as i32,
@@ -239,7 +227,9 @@ impl WayshotConnection {
                 .find(|f| f.format == *shm_format)
                 .copied()
             else {
-                return Err(Error::NoSupportedBufferFormat);
+                return Err(Error::no_usable_format(
+                    state.formats.iter().map(|format| format.format),
+                ));
             };
 
             cast.current_size = Size {
@@ -247,9 +237,7 @@ impl WayshotConnection {
                 height: frame_format.size.height as i32,
             };
         } else {
-            let Some(frame_format) = state.formats.first() else {
-                return Err(Error::NoSupportedBufferFormat);
-            };
+            let frame_format = state.first_dmabuf_format()?;
             cast.current_size = Size {
                 width: frame_format.size.width as i32,
                 height: frame_format.size.height as i32,
@@ -284,7 +272,7 @@ impl WayshotConnection {
                 }
             }
 
-            event_queue.blocking_dispatch(&mut state)?;
+            self.dispatch_capture(&mut event_queue, &mut state)?;
         }
     }
 }
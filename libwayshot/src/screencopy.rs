@@ -1,30 +1,46 @@
 use std::{
     ffi::CString,
-    os::fd::OwnedFd,
+    fmt,
+    fs::File,
+    io::Write,
+    num::NonZeroU32,
+    os::fd::{BorrowedFd, OwnedFd},
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
     time::{SystemTime, UNIX_EPOCH},
 };
 
 use gbm::BufferObject;
-use image::{ColorType, DynamicImage, ImageBuffer, Pixel};
-use memmap2::MmapMut;
+use image::{ColorType, DynamicImage, ImageBuffer, Pixel, Rgb, Rgba};
+use memmap2::{MmapMut, MmapOptions};
 use rustix::{
     fs::{self, SealFlags},
     io, shm,
 };
-use wayland_client::protocol::{
-    wl_buffer::WlBuffer, wl_output, wl_shm::Format, wl_shm_pool::WlShmPool,
+use wayland_client::{
+    Dispatch, QueueHandle,
+    protocol::{
+        wl_buffer::WlBuffer,
+        wl_output,
+        wl_shm::{Format, WlShm},
+        wl_shm_pool::WlShmPool,
+    },
 };
 
 use crate::{
-    Error, Result,
-    convert::create_converter,
-    region::{LogicalRegion, Size},
+    CaptureId, Error, OfflineFrame, Result,
+    convert::{self, create_converter},
+    image_util::{self, FrameView},
+    region::{LogicalRegion, Position, Region, Size, Transform2D},
 };
 
 pub struct FrameGuard {
     pub buffer: WlBuffer,
     pub shm_pool: WlShmPool,
     pub size: Size,
+    pub(crate) _resident: ResidentBytes,
 }
 
 impl Drop for FrameGuard {
@@ -34,6 +50,29 @@ impl Drop for FrameGuard {
     }
 }
 
+/// Bytes of a capture buffer, counted in the shared total while alive.
+#[derive(Debug)]
+pub(crate) struct ResidentBytes {
+    bytes: u64,
+    total: Arc<AtomicU64>,
+}
+
+impl ResidentBytes {
+    pub fn new(total: &Arc<AtomicU64>, bytes: u64) -> Self {
+        total.fetch_add(bytes, Ordering::Relaxed);
+        Self {
+            bytes,
+            total: total.clone(),
+        }
+    }
+}
+
+impl Drop for ResidentBytes {
+    fn drop(&mut self) {
+        self.total.fetch_sub(self.bytes, Ordering::Relaxed);
+    }
+}
+
 pub struct DMAFrameGuard {
     pub buffer: WlBuffer,
 }
@@ -59,12 +98,157 @@ impl<T: khronos_egl::api::EGL1_5> Drop for EGLImageGuard<'_, T> {
     }
 }
 
+/// Everything about a copied frame except its pixel data.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FrameMeta {
+    pub frame_format: FrameFormat,
+    #[cfg_attr(feature = "serde", serde(with = "crate::compose::wl_enum_serde"))]
+    pub transform: wl_output::Transform,
+    /// Logical region with the transform already applied.
+    pub logical_region: LogicalRegion,
+    /// Size of the frame in pixels with the transform applied.
+    pub physical_size: Size,
+    /// Id of the capture, the default for frames that weren't captured.
+    pub capture_id: CaptureId,
+    /// What was done to the colour values, or converting the pixel data will
+    /// do to them.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub color_handling: ColorHandling,
+}
+
+impl FrameMeta {
+    pub fn new(
+        frame_format: FrameFormat,
+        transform: wl_output::Transform,
+        logical_region: LogicalRegion,
+    ) -> Self {
+        let physical_size = match transform {
+            wl_output::Transform::_90
+            | wl_output::Transform::_270
+            | wl_output::Transform::Flipped90
+            | wl_output::Transform::Flipped270 => Size {
+                width: frame_format.size.height,
+                height: frame_format.size.width,
+            },
+            _ => frame_format.size,
+        };
+        Self {
+            frame_format,
+            transform,
+            logical_region,
+            physical_size,
+            capture_id: CaptureId::default(),
+            color_handling: ColorHandling::of_format(frame_format.format),
+        }
+    }
+
+    /// Physical pixels per logical pixel.
+    pub fn scale(&self) -> f64 {
+        self.physical_size.height as f64 / self.logical_region.inner.size.height as f64
+    }
+
+    /// The buffer pixels of this frame showing `region`, clipped to the frame,
+    /// or `None` if `region` is not on it. The inverse of
+    /// [`Self::damage_logical`], with the edges rounded to the nearest pixel
+    /// like [`Region::to_physical`].
+    pub(crate) fn logical_to_buffer(&self, region: LogicalRegion) -> Option<Region> {
+        let Region { position, size } = region.intersection(&self.logical_region)?.inner;
+        let origin = self.logical_region.inner.position;
+        let to_buffer = Transform2D::translation(-origin.x as f64, -origin.y as f64)
+            .then(Transform2D::scale(self.scale()))
+            .then(Transform2D::buffer_to_output(self.transform, self.frame_format.size).inverse()?);
+        let (x1, y1) = to_buffer.apply((position.x as f64, position.y as f64));
+        let (x2, y2) = to_buffer.apply((
+            position.x as f64 + size.width as f64,
+            position.y as f64 + size.height as f64,
+        ));
+        let Size { width, height } = self.frame_format.size;
+        let edge = |a: f64, b: f64, bound: u32| {
+            (
+                a.min(b).round().clamp(0.0, bound as f64) as u32,
+                a.max(b).round().clamp(0.0, bound as f64) as u32,
+            )
+        };
+        let (left, right) = edge(x1, x2, width);
+        let (top, bottom) = edge(y1, y2, height);
+        if left >= right || top >= bottom {
+            return None;
+        }
+        Some(Region {
+            position: Position {
+                x: left as i32,
+                y: top as i32,
+            },
+            size: Size {
+                width: right - left,
+                height: bottom - top,
+            },
+        })
+    }
+
+    /// Map damage rectangles of this frame, given in buffer coordinates as
+    /// screencopy reports them, through the transform and scale into the
+    /// logical coordinate space the frame is composited in.
+    ///
+    /// Rectangles are rounded outwards to whole logical pixels and clipped to
+    /// the frame, so at fractional scales they may cover a bit more than the
+    /// damage. Use [`Region::to_physical`] for pixels of a composited image.
+    pub fn damage_logical(&self, damage: &[Region]) -> Vec<LogicalRegion> {
+        let Region { position, size } = self.logical_region.inner;
+        let to_logical = Transform2D::buffer_to_output(self.transform, self.frame_format.size)
+            .then(Transform2D::scale(1.0 / self.scale()))
+            .then(Transform2D::translation(
+                position.x as f64,
+                position.y as f64,
+            ));
+        let (right, bottom) = (
+            position.x + size.width as i32,
+            position.y + size.height as i32,
+        );
+
+        damage
+            .iter()
+            .filter(|rect| rect.size.width > 0 && rect.size.height > 0)
+            .filter_map(|rect| {
+                let (x1, y1) = to_logical.apply((rect.position.x as f64, rect.position.y as f64));
+                let (x2, y2) = to_logical.apply((
+                    rect.position.x as f64 + rect.size.width as f64,
+                    rect.position.y as f64 + rect.size.height as f64,
+                ));
+                let left_top = Position {
+                    x: (x1.min(x2).floor() as i32).max(position.x),
+                    y: (y1.min(y2).floor() as i32).max(position.y),
+                };
+                let right_bottom = Position {
+                    x: (x1.max(x2).ceil() as i32).min(right),
+                    y: (y1.max(y2).ceil() as i32).min(bottom),
+                };
+                (left_top.x < right_bottom.x && left_top.y < right_bottom.y).then(|| {
+                    LogicalRegion {
+                        inner: Region {
+                            position: left_top,
+                            size: Size {
+                                width: (right_bottom.x - left_top.x) as u32,
+                                height: (right_bottom.y - left_top.y) as u32,
+                            },
+                        },
+                    }
+                })
+            })
+            .collect()
+    }
+}
+
 /// Type of frame supported by the compositor. For now we only support Argb8888, Xrgb8888, and
 /// Xbgr8888.
 ///
 /// See `zwlr_screencopy_frame_v1::Event::Buffer` as it's retrieved from there.
 #[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
 pub struct FrameFormat {
+    #[cfg_attr(feature = "serde", serde(with = "crate::compose::wl_enum_serde"))]
     pub format: Format,
     /// Size of the frame in pixels. This will always be in "landscape" so a
     /// portrait 1080x1920 frame will be 1920x1080 and will need to be rotated!
@@ -85,10 +269,76 @@ pub struct DMAFrameFormat {
 }
 
 impl FrameFormat {
+    pub fn new(format: Format, size: Size, stride: u32) -> Self {
+        Self {
+            format,
+            size,
+            stride,
+        }
+    }
+
+    /// The same frame with its stride rounded up to a multiple of `alignment`
+    /// bytes, e.g. for GPU APIs that need 256 byte aligned rows.
+    pub fn with_stride_alignment(self, alignment: NonZeroU32) -> Self {
+        Self {
+            stride: self.stride.next_multiple_of(alignment.get()),
+            ..self
+        }
+    }
+
     /// Returns the size of the frame in bytes, which is the stride * height.
     pub fn byte_size(&self) -> u64 {
         self.stride as u64 * self.size.height as u64
     }
+
+    /// Size of the shm pool holding this frame.
+    ///
+    /// wl_shm takes sizes as `i32`, so frames of more than 2 GiB, e.g. of huge
+    /// virtual outputs with 8 byte pixels, fail with [`Error::FrameTooLarge`]
+    /// instead of being truncated into a protocol error.
+    pub(crate) fn shm_pool_size(&self) -> Result<i32> {
+        let too_large = || Error::FrameTooLarge {
+            size: self.size,
+            stride: self.stride,
+        };
+        // The pool is at least as large as the stride, width and height.
+        i32::try_from(self.size.width).map_err(|_| too_large())?;
+        i32::try_from(self.size.height).map_err(|_| too_large())?;
+        i32::try_from(self.byte_size()).map_err(|_| too_large())
+    }
+
+    /// Whether the advertised stride fits a row of `size.width` pixels.
+    fn is_consistent(&self) -> bool {
+        let bytes_per_pixel = if self.format == Format::Bgr888 { 3 } else { 4 };
+        self.size.width > 0
+            && self.size.height > 0
+            && self.stride as u64 >= self.size.width as u64 * bytes_per_pixel
+    }
+
+    /// Select the buffer to allocate from the advertised `(format, width, height, stride)` tuples.
+    ///
+    /// Some compositors advertise several sizes, e.g. both the scaled and unscaled
+    /// variant. A buffer has to match a whole tuple, so pick a supported one whose
+    /// size matches `mode_size`, the output's current mode, and otherwise the
+    /// first supported one.
+    pub(crate) fn select_shm(formats: &[FrameFormat], mode_size: Option<Size>) -> Option<Self> {
+        for format in formats {
+            tracing::debug!(
+                "Advertised buffer: {:?} {}x{} stride {}",
+                format.format,
+                format.size.width,
+                format.size.height,
+                format.stride
+            );
+        }
+        let mut candidates = formats
+            .iter()
+            .filter(|format| convert::is_supported(format.format) && format.is_consistent());
+        mode_size
+            .and_then(|mode_size| candidates.clone().find(|format| format.size == mode_size))
+            .or_else(|| candidates.next())
+            .copied()
+    }
 }
 
 #[tracing::instrument(skip(frame_data))]
@@ -111,6 +361,51 @@ where
     }
 }
 
+/// What was done to the colour values of a frame between the compositor's
+/// buffer and the image, so encoders can tag the image correctly.
+///
+/// None of them change the colour space: images are sRGB encoded like the
+/// compositor's buffers are.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum ColorHandling {
+    /// The compositor's bytes, at most with the channels reordered. Formats
+    /// with alpha keep it, so transparent captures stay transparent, with the
+    /// colour values un-premultiplied. Formats without alpha are opaque.
+    #[default]
+    Passthrough,
+    /// 10 bit channels reduced to 8 bit by dropping their lowest 2 bits, and
+    /// the 2 bit alpha replaced by opaque. Otherwise like [`Self::Passthrough`].
+    ReducedDepth,
+}
+
+impl ColorHandling {
+    /// What converting a frame in `format` does to its colour values,
+    /// [`Self::Passthrough`] for formats that can't be converted.
+    pub fn of_format(format: Format) -> Self {
+        create_converter(format).map_or(Self::Passthrough, |converter| converter.color_handling())
+    }
+
+    /// How the colour values of an image made of frames handled as `self`
+    /// and `other` were handled, the lossier of the two.
+    pub fn combine(self, other: Self) -> Self {
+        match (self, other) {
+            (Self::Passthrough, Self::Passthrough) => Self::Passthrough,
+            _ => Self::ReducedDepth,
+        }
+    }
+}
+
+impl fmt::Display for ColorHandling {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Passthrough => "passthrough",
+            Self::ReducedDepth => "reduced-depth",
+        })
+    }
+}
+
 #[derive(Debug)]
 pub enum FrameData {
     Mmap(MmapMut),
@@ -118,6 +413,11 @@ pub enum FrameData {
 }
 /// The copied frame comprising of the FrameFormat, ColorType (Rgba8), and a memory backed shm
 /// file that holds the image data in it.
+///
+/// The frame maps the shared memory the compositor copied into. The mapping
+/// stays valid after the capture buffer and the connection are gone, but
+/// capture loops reuse that memory for the next frame. Use [`Self::detach`]
+/// for a frame of its own to keep around or send to another thread.
 #[derive(Debug)]
 pub struct FrameCopy {
     pub frame_format: FrameFormat,
@@ -127,28 +427,276 @@ pub struct FrameCopy {
     /// Logical region with the transform already applied.
     pub logical_region: LogicalRegion,
     pub physical_size: Size,
+    /// What was done to the colour values, updated once the frame is converted
+    /// to an image.
+    pub color_handling: ColorHandling,
+    /// See [`Self::converted`].
+    pub(crate) converted: bool,
+    /// Which capture of its connection the frame is, see
+    /// [`WayshotConnection::last_capture_id`](crate::WayshotConnection::last_capture_id).
+    pub capture_id: CaptureId,
 }
 
 impl FrameCopy {
+    /// Whether the pixel data was converted in place to [`Self::frame_color_type`],
+    /// which happens when the frame is turned into an image.
+    pub fn converted(&self) -> bool {
+        self.converted
+    }
+
+    /// The frame without its pixel data.
+    pub fn meta(&self) -> FrameMeta {
+        FrameMeta {
+            frame_format: self.frame_format,
+            transform: self.transform,
+            logical_region: self.logical_region,
+            physical_size: self.physical_size,
+            capture_id: self.capture_id,
+            color_handling: if self.converted {
+                self.color_handling
+            } else {
+                ColorHandling::of_format(self.frame_format.format)
+            },
+        }
+    }
+
+    /// Copy the pixels out of the shared memory into an [`OfflineFrame`] and
+    /// unmap it.
+    ///
+    /// The [`OfflineFrame`] owns its pixels and is `Send + 'static`, so it
+    /// outlives the capture buffer being reused, e.g. by
+    /// [`WayshotConnection::capture_loop`](crate::WayshotConnection::capture_loop),
+    /// and the [`WayshotConnection`](crate::WayshotConnection) itself. Frames
+    /// that were [`converted`](Self::converted) already keep their converted
+    /// pixels.
+    ///
+    /// Only frames copied into shared memory can be detached, others fail
+    /// with [`Error::Unsupported`].
+    ///
+    /// The [`FrameGuard`] returned along with the frame stays with the caller:
+    /// detaching doesn't destroy the buffer, so drop the guard once the
+    /// compositor is done with it.
+    pub fn detach(self) -> Result<OfflineFrame> {
+        self.to_offline()
+    }
+
+    /// An [`OfflineFrame`] with a copy of the pixels, see [`Self::detach`].
+    pub(crate) fn to_offline(&self) -> Result<OfflineFrame> {
+        let FrameData::Mmap(data) = &self.frame_data else {
+            return Err(Error::Unsupported(
+                "only shm frames can be detached".to_string(),
+            ));
+        };
+        let mut meta = self.meta();
+        let mut data = data.to_vec();
+        // Converted pixels are in memory order, which the shm formats name
+        // backwards. Only formats with alpha keep it through the conversion,
+        // un-premultiplied, while the shm formats with alpha are premultiplied.
+        if self.converted {
+            meta.frame_format.format = match self.frame_color_type {
+                ColorType::Rgba8
+                    if matches!(
+                        self.frame_format.format,
+                        Format::Argb8888 | Format::Abgr8888
+                    ) =>
+                {
+                    for pixel in data.chunks_exact_mut(4) {
+                        let [r, g, b, a] = [pixel[0], pixel[1], pixel[2], pixel[3]];
+                        pixel.copy_from_slice(&convert::premultiplied([r, g, b, a]));
+                    }
+                    Format::Abgr8888
+                }
+                ColorType::Rgba8 => Format::Xbgr8888,
+                ColorType::Rgb8 => Format::Bgr888,
+                _ => return Err(Error::InvalidColor),
+            };
+        }
+        Ok(OfflineFrame { meta, data })
+    }
+
+    /// Whether every pixel is within `tolerance` of the first one in every
+    /// channel, e.g. an all black frame of a capture that silently failed.
+    ///
+    /// Only frames copied into shared memory can be checked.
+    pub fn is_uniform(&self, tolerance: u8) -> Result<bool> {
+        image_util::is_uniform(self, tolerance)
+    }
+
+    /// Bounds checked access to the pixels, honouring the stride and whether
+    /// the frame was [`converted`](Self::converted) already.
+    ///
+    /// Only frames copied into shared memory can be viewed.
+    pub fn view(&self) -> Result<FrameView<'_>> {
+        FrameView::from_frame(self)
+    }
+
     pub(crate) fn get_image(&mut self) -> Result<DynamicImage, Error> {
+        self.convert()?;
+        (&*self).try_into()
+    }
+
+    /// The frame as an image borrowing the shared memory, for consumers that
+    /// only read the pixels, e.g. to encode them with
+    /// [`EncodingOptions::encode_frame_image`](crate::EncodingOptions::encode_frame_image).
+    ///
+    /// Unlike the [`DynamicImage`] of a capture this doesn't copy the pixels:
+    /// they are converted in place, and rows padded by the stride are moved
+    /// together, which sets the stride of [`Self::frame_format`] to the row
+    /// length. The output transform is not applied.
+    ///
+    /// Only frames copied into shared memory can be borrowed, others fail
+    /// with [`Error::Unsupported`].
+    pub fn image(&mut self) -> Result<FrameImage<'_>> {
+        self.convert()?;
+        let bytes_per_pixel = self.view()?.bytes_per_pixel();
+        let Size { width, height } = self.frame_format.size;
+        let stride = self.frame_format.stride as usize;
+        let row_len = width as usize * bytes_per_pixel;
+        let FrameData::Mmap(data) = &mut self.frame_data else {
+            return Err(Error::Unsupported(
+                "only shm frames can be borrowed".to_string(),
+            ));
+        };
+        if stride != row_len {
+            for y in 1..height as usize {
+                data.copy_within(y * stride..y * stride + row_len, y * row_len);
+            }
+            self.frame_format.stride = row_len as u32;
+        }
+        let data = &data[..row_len * height as usize];
+        match self.frame_color_type {
+            ColorType::Rgb8 => ImageBuffer::from_raw(width, height, data).map(FrameImage::Rgb8),
+            ColorType::Rgba8 => ImageBuffer::from_raw(width, height, data).map(FrameImage::Rgba8),
+            _ => return Err(Error::InvalidColor),
+        }
+        .ok_or(Error::BufferTooSmall)
+    }
+
+    /// Convert the pixel data in place to [`Self::frame_color_type`], unless
+    /// it already was.
+    fn convert(&mut self) -> Result<()> {
+        if self.converted {
+            return Ok(());
+        }
         let frame_color_type = match create_converter(self.frame_format.format) {
             Some(converter) => {
                 let FrameData::Mmap(raw) = &mut self.frame_data else {
                     return Err(Error::InvalidColor);
                 };
-                converter.convert_inplace(raw)
+                self.color_handling = converter.color_handling();
+                converter.convert_inplace(raw);
+                converter.color_type()
             }
             _ => {
                 tracing::error!("Unsupported buffer format: {:?}", self.frame_format.format);
                 tracing::error!(
                     "You can send a feature request for the above format to the mailing list for wayshot over at https://sr.ht/~shinyzenith/wayshot."
                 );
-                return Err(Error::NoSupportedBufferFormat);
+                return Err(Error::no_usable_format([self.frame_format.format]));
             }
         };
         self.frame_color_type = frame_color_type;
-        let image: DynamicImage = (&*self).try_into()?;
-        Ok(image)
+        self.converted = true;
+        Ok(())
+    }
+
+    /// The part `crop` of the frame, in buffer pixels, as an image.
+    ///
+    /// Only the pixels of `crop` are converted, which for small regions of
+    /// large outputs is a fraction of [`Self::get_image`]. The rest of the
+    /// frame is left in the compositor's format, so the frame is consumed.
+    pub(crate) fn into_cropped_image(mut self, crop: Region) -> Result<DynamicImage> {
+        let bytes_per_pixel = self.view()?.sub_view(crop)?.bytes_per_pixel();
+        let stride = self.frame_format.stride as usize;
+        let Size { width, height } = crop.size;
+        let rows = crop.position.y as usize..crop.position.y as usize + height as usize;
+        let columns = crop.position.x as usize * bytes_per_pixel
+            ..(crop.position.x as usize + width as usize) * bytes_per_pixel;
+        let FrameData::Mmap(raw) = &mut self.frame_data else {
+            return Err(Error::InvalidColor);
+        };
+        if !self.converted {
+            let converter = create_converter(self.frame_format.format)
+                .ok_or_else(|| Error::no_usable_format([self.frame_format.format]))?;
+            self.color_handling = converter.color_handling();
+            converter.convert_rows(raw, stride, rows.clone(), columns.clone());
+            self.frame_color_type = converter.color_type();
+        }
+
+        let pixels: Vec<u8> = raw
+            .chunks(stride)
+            .take(rows.end)
+            .skip(rows.start)
+            .flat_map(|row| &row[columns.clone()])
+            .copied()
+            .collect();
+        match self.frame_color_type {
+            ColorType::Rgb8 => {
+                ImageBuffer::from_vec(width, height, pixels).map(DynamicImage::ImageRgb8)
+            }
+            ColorType::Rgba8 => {
+                ImageBuffer::from_vec(width, height, pixels).map(DynamicImage::ImageRgba8)
+            }
+            _ => return Err(Error::InvalidColor),
+        }
+        .ok_or(Error::BufferTooSmall)
+    }
+
+    /// Write the frame as a binary PPM (P6) image, row by row.
+    ///
+    /// Frames written back to back form a PPM stream that can be consumed by
+    /// e.g. `ffmpeg -f image2pipe -c:v ppm`. The output transform is not applied.
+    pub fn write_ppm<W: Write>(&self, writer: &mut W) -> Result<()> {
+        image_util::write_ppm(self, writer)
+    }
+
+    /// Write the frame as a QOI image, encoded straight from the raw buffer
+    /// without converting it to an image first. The output transform is not applied.
+    pub fn write_qoi<W: Write>(&self, writer: &mut W) -> Result<()> {
+        image_util::write_qoi(self, writer)
+    }
+}
+
+/// An image borrowing the converted pixels of a [`FrameCopy`], see
+/// [`FrameCopy::image`]. The pixels are tightly packed rows of the colour type
+/// of the variant.
+#[derive(Debug, Clone)]
+pub enum FrameImage<'a> {
+    Rgb8(ImageBuffer<Rgb<u8>, &'a [u8]>),
+    Rgba8(ImageBuffer<Rgba<u8>, &'a [u8]>),
+}
+
+impl<'a> FrameImage<'a> {
+    pub fn dimensions(&self) -> (u32, u32) {
+        match self {
+            Self::Rgb8(image) => image.dimensions(),
+            Self::Rgba8(image) => image.dimensions(),
+        }
+    }
+
+    pub fn color_type(&self) -> ColorType {
+        match self {
+            Self::Rgb8(_) => ColorType::Rgb8,
+            Self::Rgba8(_) => ColorType::Rgba8,
+        }
+    }
+
+    /// The pixels, without any padding between the rows.
+    pub fn as_bytes(&self) -> &'a [u8] {
+        match self {
+            Self::Rgb8(image) => image.as_raw(),
+            Self::Rgba8(image) => image.as_raw(),
+        }
+    }
+
+    /// Copy the pixels into an image of its own.
+    pub fn to_image(&self) -> DynamicImage {
+        use image::buffer::ConvertBuffer;
+
+        match self {
+            Self::Rgb8(image) => DynamicImage::ImageRgb8(image.convert()),
+            Self::Rgba8(image) => DynamicImage::ImageRgba8(image.convert()),
+        }
     }
 }
 
@@ -181,7 +729,7 @@ fn get_mem_file_handle() -> String {
 /// Return a RawFd to a shm file. We use memfd create on linux and shm_open for BSD support.
 /// You don't need to mess around with this function, it is only used by
 /// capture_output_frame.
-pub fn create_shm_fd() -> std::io::Result<OwnedFd> {
+pub(crate) fn create_shm_fd() -> std::io::Result<OwnedFd> {
     // Only try memfd on linux and freebsd.
     #[cfg(any(target_os = "linux", target_os = "freebsd"))]
     loop {
@@ -193,8 +741,8 @@ pub fn create_shm_fd() -> std::io::Result<OwnedFd> {
             Ok(fd) => {
                 // This is only an optimization, so ignore errors.
                 // F_SEAL_SRHINK = File cannot be reduced in size.
-                // F_SEAL_SEAL = Prevent further calls to fcntl().
-                let _ = fs::fcntl_add_seals(&fd, fs::SealFlags::SHRINK | SealFlags::SEAL);
+                // The size is sealed for good by seal_shm_size once it is known.
+                let _ = fs::fcntl_add_seals(&fd, SealFlags::SHRINK);
                 return Ok(fd);
             }
             Err(io::Errno::INTR) => continue,
@@ -232,3 +780,547 @@ pub fn create_shm_fd() -> std::io::Result<OwnedFd> {
         }
     }
 }
+
+/// Create a shm pool on `fd` holding a single buffer for `frame_format`.
+pub(crate) fn create_shm_buffer<D>(
+    shm: &WlShm,
+    fd: BorrowedFd<'_>,
+    frame_format: &FrameFormat,
+    qh: &QueueHandle<D>,
+) -> Result<(WlShmPool, WlBuffer)>
+where
+    D: Dispatch<WlShmPool, ()> + Dispatch<WlBuffer, ()> + 'static,
+{
+    let pool_size = frame_format.shm_pool_size()?;
+    let shm_pool = shm.create_pool(fd, pool_size, qh, ());
+    // Neither is larger than the pool size checked above.
+    let buffer = shm_pool.create_buffer(
+        0,
+        frame_format.size.width as i32,
+        frame_format.size.height as i32,
+        frame_format.stride as i32,
+        frame_format.format,
+        qh,
+        (),
+    );
+    Ok((shm_pool, buffer))
+}
+
+/// Seal the size of a shm file from [`create_shm_fd`] after it was resized for
+/// a frame, so it can't be truncated below the mapped frame while the
+/// compositor or the converter touches it. Ignored where sealing isn't supported.
+pub(crate) fn seal_shm_size(file: &File) {
+    // F_SEAL_GROW = File cannot be increased in size.
+    // F_SEAL_SEAL = Prevent further calls to fcntl().
+    #[cfg(any(target_os = "linux", target_os = "freebsd"))]
+    let _ = fs::fcntl_add_seals(file, SealFlags::GROW | SealFlags::SHRINK | SealFlags::SEAL);
+    #[cfg(not(any(target_os = "linux", target_os = "freebsd")))]
+    let _ = file;
+}
+
+/// Map the `stride * height` bytes of a frame copied into `file`.
+///
+/// Touching pages past the end of the file raises SIGBUS, so a file shorter
+/// than the frame fails with [`Error::BufferSizeMismatch`] instead.
+pub(crate) fn map_shm_frame(file: &File, frame_format: FrameFormat) -> Result<MmapMut> {
+    let expected = frame_format.byte_size();
+    let got = file.metadata()?.len();
+    if got < expected {
+        return Err(Error::BufferSizeMismatch { expected, got });
+    }
+    let len = usize::try_from(expected).map_err(|_| Error::BufferTooSmall)?;
+    let frame_mmap = unsafe { MmapOptions::new().len(len).map_mut(file)? };
+    debug_assert_eq!(frame_mmap.len(), len);
+    Ok(frame_mmap)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+
+    fn frame_format(format: Format, width: u32, height: u32, stride: u32) -> FrameFormat {
+        FrameFormat {
+            format,
+            size: Size { width, height },
+            stride,
+        }
+    }
+
+    #[test]
+    fn detached_frames_own_their_pixels() {
+        fn assert_send_static<T: Send + 'static>(_: &T) {}
+
+        // A 2x1 Xrgb8888 frame, blue then red, stored as BGRX.
+        let format = frame_format(Format::Xrgb8888, 2, 1, 8);
+        let frame = |converted| {
+            let mut mmap = MmapMut::map_anon(8).expect("anonymous mmap");
+            mmap.copy_from_slice(&[255, 0, 0, 0, 0, 0, 255, 0]);
+            let mut frame = FrameCopy {
+                frame_format: format,
+                frame_color_type: ColorType::Rgb8,
+                frame_data: FrameData::Mmap(mmap),
+                transform: wl_output::Transform::Normal,
+                logical_region: LogicalRegion {
+                    inner: Region {
+                        position: Position { x: 0, y: 0 },
+                        size: Size {
+                            width: 2,
+                            height: 1,
+                        },
+                    },
+                },
+                physical_size: format.size,
+                color_handling: ColorHandling::Passthrough,
+                converted: false,
+                capture_id: Default::default(),
+            };
+            if converted {
+                frame.get_image().expect("convert");
+            }
+            frame
+        };
+
+        for converted in [false, true] {
+            let offline = frame(converted).detach().expect("detach");
+            assert_send_static(&offline);
+            // The capture memory is unmapped, the pixels live on on another thread.
+            let image = thread::spawn(move || {
+                FrameView::new(&offline.data, offline.meta.frame_format)
+                    .expect("view")
+                    .to_rgba()
+            })
+            .join()
+            .unwrap();
+            assert_eq!(
+                image.into_raw(),
+                [0, 0, 255, 255, 255, 0, 0, 255],
+                "converted: {converted}"
+            );
+        }
+    }
+
+    #[test]
+    fn select_shm_prefers_mode_matching_buffer() {
+        let scaled = frame_format(Format::Xrgb8888, 1280, 720, 1280 * 4);
+        let native = frame_format(Format::Xrgb8888, 2560, 1440, 2560 * 4);
+        let formats = [scaled, native];
+
+        let selected = FrameFormat::select_shm(
+            &formats,
+            Some(Size {
+                width: 2560,
+                height: 1440,
+            }),
+        );
+
+        assert_eq!(selected, Some(native));
+    }
+
+    #[test]
+    fn select_shm_falls_back_to_first_supported_buffer() {
+        let unsupported = frame_format(Format::Rgb565, 2560, 1440, 2560 * 2);
+        let scaled = frame_format(Format::Xrgb8888, 1280, 720, 1280 * 4);
+
+        let selected = FrameFormat::select_shm(
+            &[unsupported, scaled],
+            Some(Size {
+                width: 2560,
+                height: 1440,
+            }),
+        );
+
+        assert_eq!(selected, Some(scaled));
+        assert_eq!(FrameFormat::select_shm(&[scaled], None), Some(scaled));
+    }
+
+    #[test]
+    fn select_shm_skips_inconsistent_stride() {
+        let too_narrow = frame_format(Format::Xrgb8888, 2560, 1440, 2560 * 3);
+        let bgr = frame_format(Format::Bgr888, 2560, 1440, 2560 * 3);
+
+        assert_eq!(FrameFormat::select_shm(&[too_narrow], None), None);
+        assert_eq!(FrameFormat::select_shm(&[too_narrow, bgr], None), Some(bgr));
+    }
+
+    #[test]
+    fn select_shm_only_selects_convertible_formats() {
+        for format in [
+            Format::Argb8888,
+            Format::Xrgb8888,
+            Format::Abgr8888,
+            Format::Xbgr8888,
+            Format::Rgba8888,
+            Format::Bgra8888,
+            Format::Xbgr2101010,
+            Format::Abgr2101010,
+            Format::Xrgb2101010,
+            Format::Bgr888,
+            Format::Rgb888,
+            Format::Rgb565,
+            Format::Abgr16161616f,
+        ] {
+            // Wide enough for every format in the list.
+            let advertised = frame_format(format, 4, 4, 4 * 8);
+            assert_eq!(
+                FrameFormat::select_shm(&[advertised], None).is_some(),
+                convert::is_supported(format),
+                "{format:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn shm_pool_size_fits_an_i32() {
+        // 2^31 - 1 bytes is the largest pool wl_shm can describe.
+        let largest = frame_format(Format::Xrgb8888, 1, 1, i32::MAX as u32);
+        assert_eq!(largest.shm_pool_size().expect("largest pool"), i32::MAX);
+
+        // 8192 rows of 65536 x 4 bytes are 2^31 bytes, one too many.
+        let too_tall = frame_format(Format::Xrgb8888, 65536, 8192, 65536 * 4);
+        assert!(matches!(
+            too_tall.shm_pool_size(),
+            Err(Error::FrameTooLarge { stride, .. }) if stride == 65536 * 4
+        ));
+        let fits = frame_format(Format::Xrgb8888, 65536, 8191, 65536 * 4);
+        assert_eq!(fits.shm_pool_size().expect("fits"), 65536 * 4 * 8191);
+
+        let too_wide = frame_format(Format::Xrgb8888, 1 << 31, 0, 0);
+        assert!(too_wide.shm_pool_size().is_err());
+    }
+
+    #[test]
+    fn get_image_reports_color_handling() {
+        for (format, color_handling) in [
+            (Format::Xrgb8888, ColorHandling::Passthrough),
+            (Format::Xbgr2101010, ColorHandling::ReducedDepth),
+        ] {
+            let frame_format = frame_format(format, 2, 1, 8);
+            let mut frame_copy = FrameCopy {
+                frame_format,
+                frame_color_type: ColorType::Rgb8,
+                frame_data: FrameData::Mmap(MmapMut::map_anon(8).expect("anonymous mmap")),
+                transform: wl_output::Transform::Normal,
+                logical_region: LogicalRegion::default(),
+                physical_size: frame_format.size,
+                color_handling: ColorHandling::Passthrough,
+                converted: false,
+                capture_id: Default::default(),
+            };
+            assert_eq!(frame_copy.meta().color_handling, color_handling);
+
+            frame_copy.get_image().expect("image");
+
+            assert_eq!(frame_copy.color_handling, color_handling);
+            // Detached converted frames are 8 bit, but keep how they got there.
+            let offline = frame_copy.detach().expect("detached frame");
+            assert_eq!(offline.meta.color_handling, color_handling);
+        }
+    }
+
+    #[test]
+    fn reduced_depth_wins_when_combined() {
+        use ColorHandling::{Passthrough, ReducedDepth};
+
+        assert_eq!(Passthrough.combine(Passthrough), Passthrough);
+        assert_eq!(Passthrough.combine(ReducedDepth), ReducedDepth);
+        assert_eq!(ReducedDepth.combine(Passthrough), ReducedDepth);
+    }
+
+    #[test]
+    fn alpha_formats_are_encoded_into_transparent_pngs() {
+        use std::io::Cursor;
+
+        use crate::EncodingOptions;
+
+        for (format, pixels) in [
+            // Opaque red and half transparent blue, premultiplied as stored in memory.
+            (Format::Argb8888, [0, 0, 255, 255, 64, 0, 0, 128]),
+            (Format::Abgr8888, [255, 0, 0, 255, 0, 0, 64, 128]),
+            // The padding bytes of the X formats don't become alpha.
+            (Format::Xrgb8888, [0, 0, 255, 0, 255, 0, 0, 128]),
+            (Format::Xbgr8888, [255, 0, 0, 0, 0, 0, 255, 128]),
+        ] {
+            let frame_format = frame_format(format, 2, 1, 8);
+            let mut mmap = MmapMut::map_anon(8).expect("anonymous mmap");
+            mmap.copy_from_slice(&pixels);
+            let mut frame_copy = FrameCopy {
+                frame_format,
+                frame_color_type: ColorType::Rgba8,
+                frame_data: FrameData::Mmap(mmap),
+                transform: wl_output::Transform::Normal,
+                logical_region: LogicalRegion::default(),
+                physical_size: frame_format.size,
+                color_handling: ColorHandling::Passthrough,
+                converted: false,
+                capture_id: Default::default(),
+            };
+            let mut png = Cursor::new(Vec::new());
+            EncodingOptions::new()
+                .encode(
+                    &frame_copy.get_image().expect("image"),
+                    image::ImageFormat::Png,
+                    &mut png,
+                )
+                .expect("encode");
+
+            let decoded = image::load_from_memory(png.get_ref()).expect("decode");
+            // 64 of 128 is half of the full blue.
+            let (blue, alpha) = match format {
+                Format::Argb8888 | Format::Abgr8888 => (127, [255, 128]),
+                _ => (255, [255, 255]),
+            };
+            assert_eq!(decoded.color(), ColorType::Rgba8, "{format:?}");
+            assert_eq!(
+                decoded.into_rgba8().into_raw(),
+                [255, 0, 0, alpha[0], 0, 0, blue, alpha[1]],
+                "{format:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn borrowed_images_encode_like_owned_ones() {
+        use std::io::Cursor;
+
+        use crate::{EncodingOptions, PixelFormat};
+
+        // A 3x2 Xrgb8888 gradient, stored as BGRX, with rows of `stride` bytes.
+        let frame = |stride: u32| {
+            let frame_format = frame_format(Format::Xrgb8888, 3, 2, stride);
+            let mut mmap = MmapMut::map_anon(stride as usize * 2).expect("anonymous mmap");
+            for (y, row) in mmap.chunks_exact_mut(stride as usize).enumerate() {
+                for (x, byte) in row[..12].iter_mut().enumerate() {
+                    *byte = (y * 96 + x * 8) as u8;
+                }
+            }
+            FrameCopy {
+                frame_format,
+                frame_color_type: ColorType::Rgb8,
+                frame_data: FrameData::Mmap(mmap),
+                transform: wl_output::Transform::Normal,
+                logical_region: LogicalRegion::default(),
+                physical_size: frame_format.size,
+                color_handling: ColorHandling::Passthrough,
+                converted: false,
+                capture_id: Default::default(),
+            }
+        };
+        let owned = frame(12).get_image().expect("owned image");
+
+        // Rows padded by 4 bytes are moved together.
+        let mut frame_copy = frame(16);
+        let image = frame_copy.image().expect("borrowed image");
+        assert_eq!(image.dimensions(), (3, 2));
+        assert_eq!(image.color_type(), ColorType::Rgba8);
+        assert_eq!(image.as_bytes(), owned.as_bytes());
+        assert_eq!(image.to_image(), owned);
+
+        #[allow(unused_mut)]
+        let mut cases = vec![
+            (EncodingOptions::new(), image::ImageFormat::Png),
+            (
+                EncodingOptions::new().deterministic(true),
+                image::ImageFormat::Png,
+            ),
+            (
+                EncodingOptions::new().pixel_format(Some(PixelFormat::Bgr8)),
+                image::ImageFormat::Png,
+            ),
+        ];
+        #[cfg(feature = "jpeg")]
+        cases.push((EncodingOptions::new(), image::ImageFormat::Jpeg));
+        for (options, format) in cases {
+            let (mut borrowed, mut expected) = (Cursor::new(Vec::new()), Cursor::new(Vec::new()));
+            options
+                .encode_frame_image(&image, format, &mut borrowed)
+                .expect("encode borrowed");
+            options
+                .encode(&owned, format, &mut expected)
+                .expect("encode owned");
+            assert_eq!(
+                borrowed.into_inner(),
+                expected.into_inner(),
+                "{options:?} {format:?}"
+            );
+        }
+
+        // The frame now describes its compacted rows.
+        assert_eq!(frame_copy.frame_format.stride, 3 * 4);
+        assert_eq!(
+            frame_copy.view().expect("view").row(1),
+            &owned.as_bytes()[12..]
+        );
+    }
+
+    #[test]
+    fn stride_alignment_rounds_stride_up() {
+        let frame = frame_format(Format::Bgr888, 1366, 768, 1366 * 3);
+        let aligned = frame.with_stride_alignment(NonZeroU32::new(256).unwrap());
+
+        assert_eq!(aligned.stride, 4352);
+        assert_eq!(aligned.byte_size(), 4352 * 768);
+        assert_eq!(
+            aligned.with_stride_alignment(NonZeroU32::new(256).unwrap()),
+            aligned
+        );
+    }
+
+    #[test]
+    fn damage_logical_follows_every_transform() {
+        use image::{Rgba, RgbaImage};
+
+        // An asymmetric damage rectangle in an 8x4 buffer of a scale 2 output.
+        let buffer_size = Size {
+            width: 8,
+            height: 4,
+        };
+        let damage = Region {
+            position: Position { x: 2, y: 0 },
+            size: Size {
+                width: 4,
+                height: 2,
+            },
+        };
+        let buffer = RgbaImage::from_fn(buffer_size.width, buffer_size.height, |x, y| {
+            let inside = (2..6).contains(&x) && (0..2).contains(&y);
+            Rgba([inside as u8 * 255, 0, 0, 255])
+        });
+
+        for transform in [
+            wl_output::Transform::Normal,
+            wl_output::Transform::_90,
+            wl_output::Transform::_180,
+            wl_output::Transform::_270,
+            wl_output::Transform::Flipped,
+            wl_output::Transform::Flipped90,
+            wl_output::Transform::Flipped180,
+            wl_output::Transform::Flipped270,
+        ] {
+            let rotated =
+                image_util::apply_transform(DynamicImage::from(buffer.clone()), transform)
+                    .to_rgba8();
+            let logical_region = LogicalRegion {
+                inner: Region {
+                    position: Position { x: 10, y: -20 },
+                    size: Size {
+                        width: rotated.width() / 2,
+                        height: rotated.height() / 2,
+                    },
+                },
+            };
+            let meta = FrameMeta::new(
+                frame_format(Format::Xrgb8888, buffer_size.width, buffer_size.height, 32),
+                transform,
+                logical_region,
+            );
+
+            // Where the damaged pixels end up in the rotated image, in logical pixels.
+            let damaged: Vec<_> = rotated
+                .enumerate_pixels()
+                .filter(|(_, _, pixel)| pixel.0[0] == 255)
+                .map(|(x, y, _)| (x as i32, y as i32))
+                .collect();
+            let min = |f: fn(&(i32, i32)) -> i32| damaged.iter().map(f).min().unwrap();
+            let max = |f: fn(&(i32, i32)) -> i32| damaged.iter().map(f).max().unwrap() + 1;
+            let expected = LogicalRegion {
+                inner: Region {
+                    position: Position {
+                        x: 10 + min(|p| p.0) / 2,
+                        y: -20 + min(|p| p.1) / 2,
+                    },
+                    size: Size {
+                        width: ((max(|p| p.0) - min(|p| p.0)) / 2) as u32,
+                        height: ((max(|p| p.1) - min(|p| p.1)) / 2) as u32,
+                    },
+                },
+            };
+
+            assert_eq!(meta.damage_logical(&[damage]), [expected], "{transform:?}");
+            assert_eq!(
+                meta.logical_to_buffer(expected),
+                Some(damage),
+                "{transform:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn damage_logical_rounds_outwards_and_clips() {
+        // A 1.5 scale output, 6x3 buffer for a 4x2 logical region.
+        let meta = FrameMeta::new(
+            frame_format(Format::Xrgb8888, 6, 3, 24),
+            wl_output::Transform::Normal,
+            LogicalRegion {
+                inner: Region {
+                    position: Position { x: 0, y: 0 },
+                    size: Size {
+                        width: 4,
+                        height: 2,
+                    },
+                },
+            },
+        );
+        let region = |x, y, width, height| Region {
+            position: Position { x, y },
+            size: Size { width, height },
+        };
+
+        let damage =
+            meta.damage_logical(&[region(1, 1, 1, 1), region(5, 0, 9, 9), region(2, 2, 0, 1)]);
+
+        assert_eq!(
+            damage.iter().map(|region| region.inner).collect::<Vec<_>>(),
+            [region(0, 0, 2, 2), region(3, 0, 1, 2)]
+        );
+
+        // Back to the buffer, edges round to the nearest pixel and clip.
+        let logical = |x, y, width, height| LogicalRegion {
+            inner: region(x, y, width, height),
+        };
+        assert_eq!(
+            meta.logical_to_buffer(logical(1, 1, 9, 9)),
+            Some(region(2, 2, 4, 1))
+        );
+        assert_eq!(meta.logical_to_buffer(logical(4, 0, 1, 1)), None);
+    }
+
+    #[test]
+    fn map_shm_frame_rejects_short_file() {
+        let format = frame_format(Format::Xrgb8888, 16, 8, 16 * 4);
+        let file = File::from(create_shm_fd().expect("shm fd"));
+        file.set_len(format.byte_size() - 4).expect("set_len");
+
+        assert!(matches!(
+            map_shm_frame(&file, format),
+            Err(Error::BufferSizeMismatch {
+                expected: 512,
+                got: 508
+            })
+        ));
+    }
+
+    #[test]
+    fn map_shm_frame_maps_exactly_the_frame() {
+        let format = frame_format(Format::Xrgb8888, 16, 8, 16 * 4);
+        let file = File::from(create_shm_fd().expect("shm fd"));
+        // A buffer reused for a smaller frame is longer than needed.
+        file.set_len(format.byte_size() * 2).expect("set_len");
+
+        let frame_mmap = map_shm_frame(&file, format).expect("map");
+        assert_eq!(frame_mmap.len() as u64, format.byte_size());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn sealed_shm_file_keeps_its_size() {
+        let file = File::from(create_shm_fd().expect("shm fd"));
+        file.set_len(512).expect("set_len");
+        seal_shm_size(&file);
+
+        assert!(file.set_len(1024).is_err());
+        assert!(file.set_len(256).is_err());
+        assert_eq!(file.metadata().expect("metadata").len(), 512);
+    }
+}
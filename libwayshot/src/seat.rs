@@ -0,0 +1,195 @@
+//! Seats of multi-seat sessions, e.g. two sets of keyboard and mouse handed
+//! out by seatd, for the features that follow "the" pointer or focus.
+
+use std::{
+    fmt::{self, Display},
+    str::FromStr,
+};
+
+use wayland_client::{Proxy, protocol::wl_seat::WlSeat};
+
+use crate::{Error, Result, WayshotConnection, dispatch::SeatListState};
+
+/// `wl_seat` version announcing the seat's name.
+const MIN_SEAT_NAME_VERSION: u32 = 2;
+
+/// A seat as announced by the compositor, see [`WayshotConnection::seats`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct SeatInfo {
+    pub name: String,
+    /// Whether the seat has a pointer, which is needed for cursor and pick features.
+    pub pointer: bool,
+    pub keyboard: bool,
+}
+
+/// Which seat the cursor, focus and pick features follow.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum SeatSelector {
+    /// The first seat with a pointer, the only one on single-seat sessions.
+    #[default]
+    Default,
+    /// The seat with this name, e.g. `seat1`.
+    Named(String),
+    /// Every seat with a pointer.
+    All,
+}
+
+impl Display for SeatSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Default => f.write_str("default"),
+            Self::Named(name) => f.write_str(name),
+            Self::All => f.write_str("all"),
+        }
+    }
+}
+
+impl FromStr for SeatSelector {
+    type Err = String;
+
+    /// `default` and `all` select those, anything else a seat by name.
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        Ok(match s {
+            "" => return Err("a seat name can't be empty".to_string()),
+            "default" => Self::Default,
+            "all" => Self::All,
+            name => Self::Named(name.to_string()),
+        })
+    }
+}
+
+impl SeatSelector {
+    /// The seats out of `seats` this selects, only ones with a pointer.
+    ///
+    /// Fails with [`Error::SeatNotFound`] naming the available seats if a
+    /// named seat doesn't exist, and with [`Error::SeatWithoutPointer`] if it
+    /// or, for the others, every seat lacks a pointer.
+    pub fn resolve<'a>(&self, seats: &'a [SeatInfo]) -> Result<Vec<&'a SeatInfo>> {
+        let with_pointer: Vec<_> = seats.iter().filter(|seat| seat.pointer).collect();
+        let selected = match self {
+            Self::Default => with_pointer.into_iter().take(1).collect(),
+            Self::All => with_pointer,
+            Self::Named(name) => {
+                let seat = seats
+                    .iter()
+                    .find(|seat| seat.name == *name)
+                    .ok_or_else(|| Error::SeatNotFound {
+                        name: name.clone(),
+                        available: seat_names(seats),
+                    })?;
+                vec![seat].into_iter().filter(|seat| seat.pointer).collect()
+            }
+        };
+        if selected.is_empty() {
+            return Err(Error::SeatWithoutPointer {
+                selector: self.to_string(),
+                available: seat_names(seats),
+            });
+        }
+        Ok(selected)
+    }
+}
+
+/// Comma separated names of `seats`, `none` without any.
+fn seat_names(seats: &[SeatInfo]) -> String {
+    if seats.is_empty() {
+        return "none".to_string();
+    }
+    seats
+        .iter()
+        .map(|seat| seat.name.as_str())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+impl WayshotConnection {
+    /// The seats of the session with their names and capabilities, in the
+    /// order the compositor advertises them. Seats that don't announce a name
+    /// are listed with an empty one.
+    pub fn seats(&self) -> Result<Vec<SeatInfo>> {
+        let mut event_queue = self.conn.new_event_queue::<SeatListState>();
+        let qh = event_queue.handle();
+        let mut state = SeatListState::default();
+        let registry = self.globals.registry();
+        for global in self.globals.contents().clone_list() {
+            if global.interface != WlSeat::interface().name
+                || global.version < MIN_SEAT_NAME_VERSION
+            {
+                continue;
+            }
+            let version = global.version.min(WlSeat::interface().version);
+            let wl_seat = registry.bind::<WlSeat, _, _>(global.name, version, &qh, ());
+            state.seats.push((
+                wl_seat,
+                SeatInfo {
+                    name: String::new(),
+                    pointer: false,
+                    keyboard: false,
+                },
+            ));
+        }
+        event_queue.roundtrip(&mut state)?;
+
+        Ok(state
+            .seats
+            .into_iter()
+            .map(|(wl_seat, seat)| {
+                if wl_seat.version() >= 5 {
+                    wl_seat.release();
+                }
+                seat
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn seat(name: &str, pointer: bool) -> SeatInfo {
+        SeatInfo {
+            name: name.to_string(),
+            pointer,
+            keyboard: true,
+        }
+    }
+
+    fn names(seats: &[&SeatInfo]) -> Vec<String> {
+        seats.iter().map(|seat| seat.name.clone()).collect()
+    }
+
+    #[test]
+    fn selectors_pick_seats_with_a_pointer() {
+        let seats = [
+            seat("kbd-only", false),
+            seat("seat0", true),
+            seat("seat1", true),
+        ];
+        let resolve = |selector: &str| {
+            selector
+                .parse::<SeatSelector>()
+                .expect("parse")
+                .resolve(&seats)
+                .map(|seats| names(&seats))
+        };
+
+        assert_eq!(resolve("default").unwrap(), ["seat0"]);
+        assert_eq!(resolve("all").unwrap(), ["seat0", "seat1"]);
+        assert_eq!(resolve("seat1").unwrap(), ["seat1"]);
+        assert!(matches!(
+            resolve("kbd-only"),
+            Err(Error::SeatWithoutPointer { selector, .. }) if selector == "kbd-only"
+        ));
+        let missing = resolve("seat2").unwrap_err();
+        assert!(matches!(&missing, Error::SeatNotFound { name, .. } if name == "seat2"));
+        assert!(missing.to_string().contains("kbd-only, seat0, seat1"));
+
+        assert!(matches!(
+            SeatSelector::All.resolve(&[seat("kbd-only", false)]),
+            Err(Error::SeatWithoutPointer { .. })
+        ));
+        assert!("".parse::<SeatSelector>().is_err());
+    }
+}
@@ -0,0 +1,75 @@
+//! Letting the user select a region on the outputs with waysip.
+//! Behind the `interactive` feature.
+
+use libwaysip::{BoxInfo, SelectionType, WaySip};
+
+use crate::{
+    Error, Result, WayshotConnection,
+    region::{LogicalRegion, Position, Region, Size},
+};
+
+/// What [`WayshotConnection::select_region_interactive`] lets the user pick.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SelectionMode {
+    /// Drag a rectangle.
+    Rectangle,
+    /// Click one of these windows, topmost first. Wayland doesn't tell
+    /// clients where windows are, their geometry has to come from the
+    /// compositor, e.g. over its IPC.
+    Window(Vec<LogicalRegion>),
+    /// Click an output to select all of it.
+    Output,
+}
+
+impl WayshotConnection {
+    /// Let the user select a region on the outputs, as `mode` picks it.
+    ///
+    /// Meant to be called from the callback of [`Self::screenshot_freeze`],
+    /// so the user selects on the frozen outputs. Returns
+    /// [`Error::SelectionCancelled`] when the user cancels the selection.
+    pub fn select_region_interactive(&self, mode: &SelectionMode) -> Result<LogicalRegion> {
+        let waysip = WaySip::new().with_connection(self.conn.clone());
+        let waysip = match mode {
+            SelectionMode::Rectangle => waysip.with_selection_type(SelectionType::Area),
+            SelectionMode::Window(windows) => waysip
+                .with_selection_type(SelectionType::PredefinedBoxes)
+                .with_predefined_boxes(windows.iter().map(box_info).collect()),
+            SelectionMode::Output => waysip.with_selection_type(SelectionType::Screen),
+        };
+        let info = waysip
+            .get()
+            .map_err(|e| Error::FreezeCallbackError(e.to_string()))?
+            .ok_or(Error::SelectionCancelled)?;
+        waysip_to_region(info.size(), info.left_top_point())
+    }
+}
+
+fn box_info(region: &LogicalRegion) -> BoxInfo {
+    let Region { position, size } = region.inner;
+    BoxInfo {
+        start_x: position.x as f64,
+        start_y: position.y as f64,
+        end_x: (position.x + size.width as i32) as f64,
+        end_y: (position.y + size.height as i32) as f64,
+    }
+}
+
+fn waysip_to_region(size: libwaysip::Size, position: libwaysip::Position) -> Result<LogicalRegion> {
+    let size = Size {
+        width: size
+            .width
+            .try_into()
+            .map_err(|_| Error::FreezeCallbackError("width cannot be negative".to_string()))?,
+        height: size
+            .height
+            .try_into()
+            .map_err(|_| Error::FreezeCallbackError("height cannot be negative".to_string()))?,
+    };
+    let position = Position {
+        x: position.x,
+        y: position.y,
+    };
+    Ok(LogicalRegion {
+        inner: Region { position, size },
+    })
+}
@@ -0,0 +1,52 @@
+//! Whether captures fail because the session is locked.
+
+use std::fs::File;
+
+use wayland_protocols::ext::session_lock::v1::client::ext_session_lock_manager_v1::ExtSessionLockManagerV1;
+
+use crate::{
+    EmbeddedRegion, Error, Result, WayshotConnection,
+    environment::{MIN_SESSION_LOCK_VERSION, has_global},
+    region::{Position, Region, Size},
+    screencopy::create_shm_fd,
+};
+
+impl WayshotConnection {
+    /// Whether the compositor refuses captures because the session is
+    /// locked, `false` on compositors without ext-session-lock.
+    ///
+    /// The lock state can't be asked for without taking the lock, which a
+    /// screenshot tool must never do. Compositors refuse every capture while
+    /// the session is locked though, so this probes a single pixel of every
+    /// output and takes the session as locked when all of them are refused.
+    /// Only call this once the compositor refused to capture.
+    pub(crate) fn session_locked(&self) -> Result<bool> {
+        if !has_global::<ExtSessionLockManagerV1>(&self.globals, MIN_SESSION_LOCK_VERSION) {
+            return Ok(false);
+        }
+        let pixel = Region {
+            position: Position { x: 0, y: 0 },
+            size: Size {
+                width: 1,
+                height: 1,
+            },
+        };
+        let mut refused = false;
+        for output_info in self.get_all_outputs() {
+            let probe = EmbeddedRegion::within(pixel, output_info.logical_region)?;
+            let mem_file = File::from(create_shm_fd()?);
+            match self.capture_output_frame_shm_from_file(
+                false,
+                &output_info.wl_output,
+                &mem_file,
+                Some(probe),
+            ) {
+                Ok(_) => return Ok(false),
+                Err(Error::FramecopyFailed | Error::FramecopyFailedWithReason(_)) => refused = true,
+                // Outputs that can't be captured for other reasons don't tell.
+                Err(e) => tracing::debug!("Session lock probe of {output_info} failed: {e}"),
+            }
+        }
+        Ok(refused)
+    }
+}
@@ -0,0 +1,114 @@
+//! State shared between captures, e.g. the region of the last capture so it
+//! can be taken again. Frontends sharing [`last_region_path`] see each
+//! other's captures.
+
+use std::{
+    env, fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    Error, Result,
+    region::{LogicalRegion, Position, Region, Size},
+};
+
+/// Path of the file holding the last captured region,
+/// `$XDG_STATE_HOME/wayshot/last-region`.
+///
+/// Falls back to `~/.local/state` like the XDG base directory specification
+/// asks, `None` when neither is set.
+pub fn last_region_path() -> Option<PathBuf> {
+    let state_home = env::var_os("XDG_STATE_HOME")
+        .map(PathBuf::from)
+        .filter(|path| path.is_absolute())
+        .or_else(|| {
+            env::var_os("HOME").map(|home| PathBuf::from(home).join(".local").join("state"))
+        })?;
+    Some(state_home.join("wayshot").join("last-region"))
+}
+
+/// Read the region stored at `path` by [`write_last_region`], `None` if
+/// nothing was stored yet.
+pub fn read_last_region(path: &Path) -> Result<Option<LogicalRegion>> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    parse_region(contents.trim())
+        .map(Some)
+        .ok_or_else(|| Error::InvalidStateFile {
+            path: path.to_owned(),
+            reason: format!("'{}' is not a region like '10,20 300x200'", contents.trim()),
+        })
+}
+
+/// Store `region` at `path` as `X,Y WxH`, creating its directory.
+///
+/// The file is replaced atomically so concurrent readers never see half of it.
+pub fn write_last_region(path: &Path, region: LogicalRegion) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let Region { position, size } = region.inner;
+    let tmp_path = path.with_extension(format!("{}.tmp", std::process::id()));
+    let mut file = fs::File::create(&tmp_path)?;
+    writeln!(
+        file,
+        "{},{} {}x{}",
+        position.x, position.y, size.width, size.height
+    )?;
+    file.sync_all()?;
+    fs::rename(&tmp_path, path).inspect_err(|_| {
+        fs::remove_file(&tmp_path).ok();
+    })?;
+    Ok(())
+}
+
+fn parse_region(value: &str) -> Option<LogicalRegion> {
+    let (position, size) = value.split_once(' ')?;
+    let (x, y) = position.split_once(',')?;
+    let (width, height) = size.split_once('x')?;
+    Some(LogicalRegion {
+        inner: Region {
+            position: Position {
+                x: x.parse().ok()?,
+                y: y.parse().ok()?,
+            },
+            size: Size {
+                width: width.parse().ok()?,
+                height: height.parse().ok()?,
+            },
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn last_region_round_trips_through_the_file() {
+        let dir = env::temp_dir().join(format!("libwayshot-state-{}", std::process::id()));
+        let path = dir.join("wayshot").join("last-region");
+
+        assert_eq!(read_last_region(&path).expect("read missing"), None);
+        write_last_region(&path, LogicalRegion::new(-1920, 40, 300, 200)).expect("write");
+        assert_eq!(
+            fs::read_to_string(&path).expect("read file"),
+            "-1920,40 300x200\n"
+        );
+        assert_eq!(
+            read_last_region(&path).expect("read"),
+            Some(LogicalRegion::new(-1920, 40, 300, 200))
+        );
+
+        fs::write(&path, "300x200").expect("corrupt");
+        assert!(matches!(
+            read_last_region(&path),
+            Err(Error::InvalidStateFile { .. })
+        ));
+        fs::remove_dir_all(&dir).ok();
+    }
+}
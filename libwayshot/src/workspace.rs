@@ -0,0 +1,226 @@
+//! The workspace active on each output, read with the ext-workspace protocol.
+//! Behind the `workspaces` feature.
+
+use std::collections::HashMap;
+
+use wayland_client::{
+    Connection, Dispatch, Proxy, QueueHandle, WEnum, protocol::wl_output::WlOutput,
+};
+use wayland_protocols::ext::workspace::v1::client::{
+    ext_workspace_group_handle_v1::{self, ExtWorkspaceGroupHandleV1},
+    ext_workspace_handle_v1::{self, ExtWorkspaceHandleV1},
+    ext_workspace_manager_v1::{self, ExtWorkspaceManagerV1},
+};
+
+use crate::{
+    Result, WayshotConnection,
+    environment::{MIN_WORKSPACE_VERSION, has_global},
+};
+
+/// A workspace group, the workspaces shown on a set of outputs.
+#[derive(Debug)]
+struct Group {
+    handle: ExtWorkspaceGroupHandleV1,
+    outputs: Vec<WlOutput>,
+    workspaces: Vec<ExtWorkspaceHandleV1>,
+}
+
+#[derive(Debug)]
+struct Workspace {
+    handle: ExtWorkspaceHandleV1,
+    id: String,
+    name: String,
+    active: bool,
+}
+
+impl Workspace {
+    /// The name shown for the workspace, its id if it has no name.
+    fn label(&self) -> &str {
+        if self.name.is_empty() {
+            &self.id
+        } else {
+            &self.name
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct WorkspaceState {
+    groups: Vec<Group>,
+    workspaces: Vec<Workspace>,
+}
+
+impl WorkspaceState {
+    /// The first active workspace of the group showing each of `outputs`.
+    fn active_on<'a>(&self, outputs: impl IntoIterator<Item = &'a WlOutput>) -> Vec<Option<&str>> {
+        outputs
+            .into_iter()
+            .map(|output| {
+                let group = self
+                    .groups
+                    .iter()
+                    .find(|group| group.outputs.contains(output))?;
+                self.workspaces
+                    .iter()
+                    .find(|workspace| {
+                        workspace.active && group.workspaces.contains(&workspace.handle)
+                    })
+                    .map(Workspace::label)
+            })
+            .collect()
+    }
+}
+
+impl WayshotConnection {
+    /// Name of the workspace active on each output, by output name, as the
+    /// compositor announces them with ext-workspace. Outputs without an active
+    /// workspace are left out, and so are all of them on compositors without
+    /// the protocol.
+    ///
+    /// Workspaces without a name are listed by their id. If several
+    /// workspaces are active on an output, the first announced one is used.
+    pub fn active_workspaces(&self) -> Result<HashMap<String, String>> {
+        if !has_global::<ExtWorkspaceManagerV1>(&self.globals, MIN_WORKSPACE_VERSION) {
+            tracing::debug!("The compositor doesn't offer ext-workspace");
+            return Ok(HashMap::new());
+        }
+        let mut event_queue = self.conn.new_event_queue::<WorkspaceState>();
+        let qh = event_queue.handle();
+        let manager = self.globals.bind::<ExtWorkspaceManagerV1, _, _>(
+            &qh,
+            MIN_WORKSPACE_VERSION..=ExtWorkspaceManagerV1::interface().version,
+            (),
+        )?;
+        let mut state = WorkspaceState::default();
+        // The current groups and workspaces are sent right after binding.
+        let result = event_queue.roundtrip(&mut state);
+
+        let outputs = self.get_all_outputs();
+        let active = state
+            .active_on(outputs.iter().map(|output| &output.wl_output))
+            .into_iter()
+            .zip(outputs)
+            .filter_map(|(workspace, output)| Some((output.name.clone(), workspace?.to_string())))
+            .collect();
+        for group in &state.groups {
+            group.handle.destroy();
+        }
+        for workspace in &state.workspaces {
+            workspace.handle.destroy();
+        }
+        manager.stop();
+        self.conn.flush().ok();
+        result?;
+        Ok(active)
+    }
+}
+
+impl Dispatch<ExtWorkspaceManagerV1, ()> for WorkspaceState {
+    fn event(
+        state: &mut Self,
+        _: &ExtWorkspaceManagerV1,
+        event: ext_workspace_manager_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        match event {
+            ext_workspace_manager_v1::Event::WorkspaceGroup { workspace_group } => {
+                state.groups.push(Group {
+                    handle: workspace_group,
+                    outputs: Vec::new(),
+                    workspaces: Vec::new(),
+                })
+            }
+            ext_workspace_manager_v1::Event::Workspace { workspace } => {
+                state.workspaces.push(Workspace {
+                    handle: workspace,
+                    id: String::new(),
+                    name: String::new(),
+                    active: false,
+                })
+            }
+            _ => {}
+        }
+    }
+
+    wayland_client::event_created_child!(WorkspaceState, ExtWorkspaceManagerV1, [
+        ext_workspace_manager_v1::EVT_WORKSPACE_GROUP_OPCODE => (ExtWorkspaceGroupHandleV1, ()),
+        ext_workspace_manager_v1::EVT_WORKSPACE_OPCODE => (ExtWorkspaceHandleV1, ())
+    ]);
+}
+
+impl Dispatch<ExtWorkspaceGroupHandleV1, ()> for WorkspaceState {
+    fn event(
+        state: &mut Self,
+        proxy: &ExtWorkspaceGroupHandleV1,
+        event: ext_workspace_group_handle_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        if let ext_workspace_group_handle_v1::Event::Removed = event {
+            // The compositor is done with the group, the handle is ours to destroy.
+            proxy.destroy();
+            state.groups.retain(|group| group.handle != *proxy);
+            return;
+        }
+        let Some(group) = state.groups.iter_mut().find(|group| group.handle == *proxy) else {
+            return;
+        };
+        match event {
+            ext_workspace_group_handle_v1::Event::OutputEnter { output } => {
+                group.outputs.push(output)
+            }
+            ext_workspace_group_handle_v1::Event::OutputLeave { output } => {
+                group.outputs.retain(|entered| *entered != output)
+            }
+            ext_workspace_group_handle_v1::Event::WorkspaceEnter { workspace } => {
+                group.workspaces.push(workspace)
+            }
+            ext_workspace_group_handle_v1::Event::WorkspaceLeave { workspace } => {
+                group.workspaces.retain(|entered| *entered != workspace)
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Dispatch<ExtWorkspaceHandleV1, ()> for WorkspaceState {
+    fn event(
+        state: &mut Self,
+        proxy: &ExtWorkspaceHandleV1,
+        event: ext_workspace_handle_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        if let ext_workspace_handle_v1::Event::Removed = event {
+            // The compositor is done with the workspace, the handle is ours to destroy.
+            proxy.destroy();
+            state
+                .workspaces
+                .retain(|workspace| workspace.handle != *proxy);
+            return;
+        }
+        let Some(workspace) = state
+            .workspaces
+            .iter_mut()
+            .find(|workspace| workspace.handle == *proxy)
+        else {
+            return;
+        };
+        match event {
+            ext_workspace_handle_v1::Event::Id { id } => workspace.id = id,
+            ext_workspace_handle_v1::Event::Name { name } => workspace.name = name,
+            ext_workspace_handle_v1::Event::State { state } => {
+                let bits = match state {
+                    WEnum::Value(state) => state.bits(),
+                    WEnum::Unknown(bits) => bits,
+                };
+                workspace.active = bits & ext_workspace_handle_v1::State::Active.bits() != 0;
+            }
+            _ => {}
+        }
+    }
+}
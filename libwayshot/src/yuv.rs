@@ -0,0 +1,273 @@
+//! Conversion of captured frames to planar YUV, the input of hardware video
+//! encoders like VA-API and of x264.
+
+use image::RgbaImage;
+
+/// Options for converting a capture to YUV, see
+/// [`WayshotConnection::screenshot_single_output_yuv`](crate::WayshotConnection::screenshot_single_output_yuv).
+///
+/// # Example usage
+///
+/// ```ignore
+/// use libwayshot::{YuvLayout, YuvMatrix, YuvOptions};
+/// let options = YuvOptions::new()
+///     .layout(YuvLayout::I420)
+///     .matrix(YuvMatrix::Bt601);
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct YuvOptions {
+    layout: YuvLayout,
+    matrix: YuvMatrix,
+}
+
+/// How the planes of a [`YuvFrame`] are laid out. Both subsample the chroma
+/// 2x2, odd sizes round the chroma planes up.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum YuvLayout {
+    /// A Y plane followed by one plane of interleaved U and V samples.
+    #[default]
+    Nv12,
+    /// A Y plane followed by a U and a V plane, also known as YUV420p.
+    I420,
+}
+
+/// Matrix the RGB values are converted with. Both produce limited range
+/// values, Y from 16 to 235 and U and V from 16 to 240.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum YuvMatrix {
+    /// BT.601, for standard definition video.
+    Bt601,
+    /// BT.709, for HD video.
+    #[default]
+    Bt709,
+}
+
+impl YuvMatrix {
+    /// The red and blue luma coefficients, Kr and Kb.
+    fn coefficients(self) -> (f32, f32) {
+        match self {
+            Self::Bt601 => (0.299, 0.114),
+            Self::Bt709 => (0.2126, 0.0722),
+        }
+    }
+}
+
+/// One plane of a [`YuvFrame`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct YuvPlane {
+    /// Where the plane starts in [`YuvFrame::data`].
+    pub offset: usize,
+    /// Bytes from the start of one row to the next.
+    pub stride: u32,
+    /// Bytes per row, which is the number of samples except for the
+    /// interleaved NV12 chroma plane.
+    pub width: u32,
+    pub height: u32,
+}
+
+/// A frame converted to planar YUV, with the planes stored back to back.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct YuvFrame {
+    pub width: u32,
+    pub height: u32,
+    pub layout: YuvLayout,
+    pub matrix: YuvMatrix,
+    pub data: Vec<u8>,
+    /// Y and UV for NV12, Y, U and V for I420.
+    pub planes: Vec<YuvPlane>,
+}
+
+impl YuvOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Layout of the planes, NV12 by default.
+    pub fn layout(mut self, layout: YuvLayout) -> Self {
+        self.layout = layout;
+        self
+    }
+
+    /// Matrix to convert with, BT.709 by default.
+    pub fn matrix(mut self, matrix: YuvMatrix) -> Self {
+        self.matrix = matrix;
+        self
+    }
+
+    pub fn get_layout(&self) -> YuvLayout {
+        self.layout
+    }
+
+    pub fn get_matrix(&self) -> YuvMatrix {
+        self.matrix
+    }
+
+    /// Convert `image` to YUV, ignoring its alpha channel.
+    pub fn convert(&self, image: &RgbaImage) -> YuvFrame {
+        let (width, height) = image.dimensions();
+        let (chroma_width, chroma_height) = (width.div_ceil(2), height.div_ceil(2));
+        let luma_len = width as usize * height as usize;
+        let chroma_len = chroma_width as usize * chroma_height as usize;
+        let mut data = vec![0; luma_len + 2 * chroma_len];
+        let (luma, chroma) = data.split_at_mut(luma_len);
+
+        let (kr, kb) = self.matrix.coefficients();
+        let kg = 1.0 - kr - kb;
+        // Luma and the two colour differences of a pixel, unscaled.
+        let yuv = |pixel: &image::Rgba<u8>| {
+            let [r, g, b, _] = pixel.0.map(|channel| channel as f32 / 255.0);
+            let y = kr * r + kg * g + kb * b;
+            (
+                y,
+                (b - y) / (2.0 * (1.0 - kb)),
+                (r - y) / (2.0 * (1.0 - kr)),
+            )
+        };
+
+        for (target, pixel) in luma.iter_mut().zip(image.pixels()) {
+            *target = (16.0 + 219.0 * yuv(pixel).0).round() as u8;
+        }
+
+        for chroma_y in 0..chroma_height {
+            for chroma_x in 0..chroma_width {
+                let (mut u, mut v, mut samples) = (0.0, 0.0, 0.0);
+                for y in chroma_y * 2..(chroma_y * 2 + 2).min(height) {
+                    for x in chroma_x * 2..(chroma_x * 2 + 2).min(width) {
+                        let (_, pixel_u, pixel_v) = yuv(image.get_pixel(x, y));
+                        u += pixel_u;
+                        v += pixel_v;
+                        samples += 1.0;
+                    }
+                }
+                let u = (128.0 + 224.0 * u / samples).round() as u8;
+                let v = (128.0 + 224.0 * v / samples).round() as u8;
+                let index = (chroma_y * chroma_width + chroma_x) as usize;
+                let (u_index, v_index) = match self.layout {
+                    YuvLayout::Nv12 => (2 * index, 2 * index + 1),
+                    YuvLayout::I420 => (index, chroma_len + index),
+                };
+                chroma[u_index] = u;
+                chroma[v_index] = v;
+            }
+        }
+
+        let luma_plane = YuvPlane {
+            offset: 0,
+            stride: width,
+            width,
+            height,
+        };
+        let planes = match self.layout {
+            YuvLayout::Nv12 => vec![
+                luma_plane,
+                YuvPlane {
+                    offset: luma_len,
+                    stride: chroma_width * 2,
+                    width: chroma_width * 2,
+                    height: chroma_height,
+                },
+            ],
+            YuvLayout::I420 => vec![
+                luma_plane,
+                YuvPlane {
+                    offset: luma_len,
+                    stride: chroma_width,
+                    width: chroma_width,
+                    height: chroma_height,
+                },
+                YuvPlane {
+                    offset: luma_len + chroma_len,
+                    stride: chroma_width,
+                    width: chroma_width,
+                    height: chroma_height,
+                },
+            ],
+        };
+        YuvFrame {
+            width,
+            height,
+            layout: self.layout,
+            matrix: self.matrix,
+            data,
+            planes,
+        }
+    }
+}
+
+impl YuvFrame {
+    /// The bytes of the plane at `index`, see [`Self::planes`].
+    pub fn plane(&self, index: usize) -> Option<&[u8]> {
+        let plane = self.planes.get(index)?;
+        let len = plane.stride as usize * plane.height as usize;
+        self.data.get(plane.offset..plane.offset + len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::Rgba;
+
+    use super::*;
+
+    #[test]
+    fn known_colours_convert_per_matrix() {
+        let cases = [
+            (YuvMatrix::Bt601, [255, 255, 255], [235, 128, 128]),
+            (YuvMatrix::Bt601, [0, 0, 0], [16, 128, 128]),
+            (YuvMatrix::Bt601, [255, 0, 0], [81, 90, 240]),
+            (YuvMatrix::Bt709, [255, 0, 0], [63, 102, 240]),
+            (YuvMatrix::Bt709, [0, 255, 0], [173, 42, 26]),
+            (YuvMatrix::Bt709, [0, 0, 255], [32, 240, 118]),
+        ];
+        for (matrix, [r, g, b], expected) in cases {
+            let image = RgbaImage::from_pixel(2, 2, Rgba([r, g, b, 0]));
+            for layout in [YuvLayout::Nv12, YuvLayout::I420] {
+                let frame = YuvOptions::new()
+                    .layout(layout)
+                    .matrix(matrix)
+                    .convert(&image);
+                // The chroma follows the 4 luma samples in both layouts.
+                let sample = [frame.data[0], frame.data[4], frame.data[5]];
+                assert_eq!(sample, expected, "{matrix:?} {layout:?} {r},{g},{b}");
+            }
+        }
+    }
+
+    #[test]
+    fn planes_follow_the_layout() {
+        // 3x3 so the chroma planes round up to 2x2, left column red and the
+        // rest black.
+        let image = RgbaImage::from_fn(3, 3, |x, _| match x {
+            0 => Rgba([255, 0, 0, 255]),
+            _ => Rgba([0, 0, 0, 255]),
+        });
+        let options = YuvOptions::new().matrix(YuvMatrix::Bt601);
+
+        let nv12 = options.convert(&image);
+        assert_eq!(nv12.data.len(), 9 + 2 * 4);
+        assert_eq!(
+            nv12.plane(0),
+            Some(&[81, 16, 16, 81, 16, 16, 81, 16, 16][..])
+        );
+        assert_eq!(
+            nv12.planes[1],
+            YuvPlane {
+                offset: 9,
+                stride: 4,
+                width: 4,
+                height: 2,
+            }
+        );
+        // Half of the left blocks is red, the right blocks are black.
+        assert_eq!(
+            nv12.plane(1),
+            Some(&[109, 184, 128, 128, 109, 184, 128, 128][..])
+        );
+
+        let i420 = options.layout(YuvLayout::I420).convert(&image);
+        assert_eq!(i420.planes.len(), 3);
+        assert_eq!(i420.plane(1), Some(&[109, 128, 109, 128][..]));
+        assert_eq!(i420.plane(2), Some(&[184, 128, 184, 128][..]));
+        assert_eq!(i420.plane(3), None);
+    }
+}
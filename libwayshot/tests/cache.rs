@@ -0,0 +1,159 @@
+//! The screenshot cache, against the in-process compositor of `server`.
+
+mod server;
+
+use std::{thread, time::Duration};
+
+use image::DynamicImage;
+use libwayshot::{OutputInfo, ScreenshotCache, WayshotConnection};
+
+use server::{Compositor, Output, Server};
+
+/// A connection with the cache enabled to a compositor with `count` outputs.
+fn connect(count: usize) -> (WayshotConnection, Server) {
+    let (conn, server) = Compositor {
+        outputs: (0..count)
+            .map(|index| Output::new(&format!("TEST-{index}"), 64 * index as i32, 0, 64, 48))
+            .collect(),
+        ..Compositor::default()
+    }
+    .connect();
+    let mut wayshot = WayshotConnection::from_connection(conn).expect("connect");
+    wayshot.enable_screenshot_cache();
+    (wayshot, server)
+}
+
+fn cached(wayshot: &WayshotConnection, output: &OutputInfo, max_age: Duration) {
+    wayshot
+        .screenshot_single_output_cached(output, false, max_age)
+        .expect("cached screenshot");
+}
+
+#[test]
+fn cached_screenshots_are_reused() {
+    let (wayshot, server) = connect(1);
+    let output = &wayshot.get_all_outputs()[0];
+
+    cached(&wayshot, output, Duration::MAX);
+    assert_eq!(server.requests().copies, 1);
+    cached(&wayshot, output, Duration::MAX);
+    assert_eq!(server.requests().copies, 1);
+    // A baseline copy and a copy waiting for damage.
+    assert_eq!(server.requests().copies_with_damage, 2);
+
+    wayshot.invalidate_screenshot_cache(output);
+    cached(&wayshot, output, Duration::MAX);
+    assert_eq!(server.requests().copies, 2);
+}
+
+#[test]
+fn cached_screenshots_expire() {
+    let (wayshot, server) = connect(1);
+    let output = &wayshot.get_all_outputs()[0];
+
+    cached(&wayshot, output, Duration::from_millis(1));
+    thread::sleep(Duration::from_millis(2));
+    cached(&wayshot, output, Duration::from_millis(1));
+    assert_eq!(server.requests().copies, 2);
+}
+
+#[test]
+fn damage_invalidates_cached_screenshots() {
+    let (wayshot, server) = connect(2);
+    let [damaged, undamaged] = wayshot.get_all_outputs() else {
+        panic!("expected two outputs");
+    };
+    cached(&wayshot, damaged, Duration::MAX);
+    cached(&wayshot, undamaged, Duration::MAX);
+    assert_eq!(server.requests().copies, 2);
+
+    server.damage(0);
+    cached(&wayshot, damaged, Duration::MAX);
+    cached(&wayshot, undamaged, Duration::MAX);
+    assert_eq!(server.requests().copies, 3);
+    // The damage was taken, so the new capture is reused.
+    cached(&wayshot, damaged, Duration::MAX);
+    assert_eq!(server.requests().copies, 3);
+}
+
+#[test]
+fn damage_is_tracked_with_and_without_the_cursor() {
+    let (wayshot, server) = connect(1);
+    let output = &wayshot.get_all_outputs()[0];
+
+    for cursor_overlay in [false, true, false] {
+        wayshot
+            .screenshot_single_output_cached(output, cursor_overlay, Duration::MAX)
+            .expect("cached screenshot");
+    }
+    // The cache holds one screenshot per output, so switching is a miss.
+    assert_eq!(server.requests().copies, 3);
+    assert_eq!(server.requests().copies_with_damage, 4);
+}
+
+#[test]
+fn cache_misses_after_invalidate_or_cursor_change() {
+    let (wayshot, _server) = connect(1);
+    let output = &wayshot.get_all_outputs()[0].wl_output;
+    let mut cache = ScreenshotCache::new();
+    assert!(cache.get(output, false, Duration::MAX).is_none());
+
+    cache.insert(output, false, DynamicImage::new_rgba8(4, 2));
+    let image = cache.get(output, false, Duration::MAX).expect("cache hit");
+    assert_eq!((image.width(), image.height()), (4, 2));
+    assert!(cache.get(output, true, Duration::MAX).is_none());
+
+    cache.invalidate(output);
+    assert_eq!(cache.generation(output), 1);
+    assert!(cache.get(output, false, Duration::MAX).is_none());
+    cache.insert(output, false, DynamicImage::new_rgba8(1, 1));
+    assert!(cache.get(output, false, Duration::MAX).is_some());
+}
+
+#[test]
+fn cache_evicts_removed_outputs() {
+    let (wayshot, _server) = connect(2);
+    let [kept, removed] = wayshot.get_all_outputs() else {
+        panic!("expected two outputs");
+    };
+    let mut cache = ScreenshotCache::new();
+    cache.insert(&kept.wl_output, false, DynamicImage::new_rgba8(1, 1));
+    cache.insert(&removed.wl_output, false, DynamicImage::new_rgba8(1, 1));
+
+    cache.retain_outputs(std::slice::from_ref(kept));
+    assert_eq!(cache.len(), 1);
+    assert!(cache.get(&kept.wl_output, false, Duration::MAX).is_some());
+    assert!(
+        cache
+            .get(&removed.wl_output, false, Duration::MAX)
+            .is_none()
+    );
+}
+
+#[test]
+fn cache_evicts_the_oldest_over_its_byte_budget() {
+    let (wayshot, _server) = connect(3);
+    let outputs = wayshot.get_all_outputs();
+    let mut cache = ScreenshotCache::new();
+    // 64 bytes per image, so two fit.
+    cache.set_byte_budget(Some(150));
+
+    for output in outputs {
+        cache.insert(&output.wl_output, false, DynamicImage::new_rgba8(4, 4));
+        thread::sleep(Duration::from_millis(1));
+    }
+    assert_eq!(cache.byte_size(), 128);
+    assert!(
+        cache
+            .get(&outputs[0].wl_output, false, Duration::MAX)
+            .is_none()
+    );
+    assert!(
+        cache
+            .get(&outputs[2].wl_output, false, Duration::MAX)
+            .is_some()
+    );
+
+    cache.set_byte_budget(Some(10));
+    assert!(cache.is_empty());
+}
@@ -0,0 +1,77 @@
+//! Captures against the in-process compositor of `server`.
+
+mod server;
+
+use libwayshot::{Error, WayshotConnection, WayshotTarget};
+use rustix::fs::{MemfdFlags, memfd_create};
+use wayland_client::protocol::wl_shm::Format;
+use wayland_server::protocol::wl_shm;
+
+use server::Compositor;
+
+/// Capture the first output of `compositor` with screencopy.
+fn capture(compositor: Compositor) -> libwayshot::Result<()> {
+    let (conn, _server) = compositor.connect();
+    let wayshot = WayshotConnection::from_connection(conn).expect("connect to the compositor");
+    let output = &wayshot.get_all_outputs()[0];
+    wayshot.screenshot_single_output(output, false).map(drop)
+}
+
+/// Start a screencast of the first output of `compositor` in `format`.
+fn screencast(compositor: Compositor, format: Format) -> libwayshot::Result<()> {
+    let (conn, _server) = compositor.connect();
+    let wayshot = WayshotConnection::from_connection(conn).expect("connect to the compositor");
+    let target = WayshotTarget::from(wayshot.get_all_outputs()[0].clone());
+    let fd = memfd_create("wayshot-screencast", MemfdFlags::CLOEXEC).expect("memfd");
+    wayshot
+        .create_screencast_with_shm(format, None, target, false, fd)
+        .map(drop)
+}
+
+fn no_formats() -> Compositor {
+    Compositor {
+        buffer_formats: Vec::new(),
+        ..Compositor::default()
+    }
+}
+
+fn unsupported_formats() -> Compositor {
+    Compositor {
+        buffer_formats: vec![wl_shm::Format::Yuyv, wl_shm::Format::Nv12],
+        ..Compositor::default()
+    }
+}
+
+#[test]
+fn captures_with_an_offered_format() {
+    capture(Compositor::default()).expect("capture");
+    screencast(Compositor::default(), Format::Xrgb8888).expect("screencast");
+}
+
+#[test]
+fn no_offered_formats_is_no_formats_advertised() {
+    for result in [
+        capture(no_formats()),
+        screencast(no_formats(), Format::Xrgb8888),
+    ] {
+        assert!(
+            matches!(result, Err(Error::NoFormatsAdvertised)),
+            "{result:?}"
+        );
+    }
+}
+
+#[test]
+fn only_unsupported_formats_is_no_supported_buffer_format() {
+    for result in [
+        capture(unsupported_formats()),
+        screencast(unsupported_formats(), Format::Xrgb8888),
+    ] {
+        match result {
+            Err(Error::NoSupportedBufferFormat { advertised }) => {
+                assert_eq!(advertised, "Yuyv, Nv12")
+            }
+            result => panic!("expected NoSupportedBufferFormat, got {result:?}"),
+        }
+    }
+}
@@ -0,0 +1,98 @@
+//! Connecting to the in-process compositor of `server`.
+
+mod server;
+
+use std::{env, fs, path::PathBuf};
+
+use libwayshot::{Error, LogicalRegion, WayshotConnection, region::Size};
+use wayland_server::ListeningSocket;
+
+use server::{Compositor, Output};
+
+/// A directory of its own for the sockets of `test`, removed on drop.
+struct SocketDir(PathBuf);
+
+impl SocketDir {
+    fn new(test: &str) -> Self {
+        let dir = env::temp_dir().join(format!("wayshot-{test}-{}", std::process::id()));
+        fs::create_dir_all(&dir).expect("create socket dir");
+        Self(dir)
+    }
+}
+
+impl Drop for SocketDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+#[test]
+fn connect_to_a_socket_path() {
+    let dir = SocketDir::new("connect-to");
+    let socket = dir.0.join("wayland-test");
+    let _server = Compositor::default()
+        .listen(ListeningSocket::bind_absolute(socket.clone()).expect("bind the socket"));
+
+    let display = socket.to_str().expect("UTF-8 socket path");
+    let wayshot = WayshotConnection::connect_to(display).expect("connect to the socket");
+    assert_eq!(wayshot.wayland_display(), Some(display));
+    let names: Vec<&str> = wayshot
+        .get_all_outputs()
+        .iter()
+        .map(|output| output.name.as_str())
+        .collect();
+    assert_eq!(names, ["TEST-1"]);
+}
+
+#[test]
+fn connect_to_a_missing_socket() {
+    let dir = SocketDir::new("connect-to-missing");
+    let socket = dir.0.join("wayland-missing");
+    let display = socket.to_str().expect("UTF-8 socket path");
+    let Err(Error::DisplayConnect { socket: path, .. }) = WayshotConnection::connect_to(display)
+    else {
+        panic!("expected DisplayConnect");
+    };
+    assert_eq!(path, socket);
+}
+
+#[test]
+fn outputs_are_complete_after_connecting() {
+    let outputs = vec![
+        Output::new("TEST-1", 0, 0, 64, 48),
+        Output::new("TEST-2", 64, 0, 32, 48),
+        Output::new("TEST-3", -40, 48, 40, 30),
+    ];
+    let (conn, server) = Compositor {
+        outputs: outputs.clone(),
+        ..Compositor::default()
+    }
+    .connect();
+    let mut wayshot = WayshotConnection::from_connection(conn).expect("connect");
+    // A single xdg_output each, requested while binding the wl_outputs.
+    assert_eq!(server.requests().xdg_outputs, outputs.len());
+
+    for refreshed in [false, true] {
+        if refreshed {
+            wayshot.refresh_outputs().expect("refresh the outputs");
+        }
+        let found = wayshot.get_all_outputs();
+        assert_eq!(found.len(), outputs.len());
+        for (info, output) in found.iter().zip(&outputs) {
+            let (x, y, width, height) = output.logical;
+            assert_eq!(info.name, output.name);
+            assert_eq!(info.description, output.description);
+            assert_eq!(
+                info.physical_size,
+                Size {
+                    width: output.mode.0 as u32,
+                    height: output.mode.1 as u32,
+                }
+            );
+            assert_eq!(
+                info.logical_region,
+                LogicalRegion::new(x, y, width as u32, height as u32)
+            );
+        }
+    }
+}
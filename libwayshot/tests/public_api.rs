@@ -0,0 +1,153 @@
+//! The public API the wayshot binary builds on, listed by hand.
+//!
+//! Every item is coerced to its full signature, so changing one of them fails
+//! to build this test until the list is changed along with it, which puts the
+//! change in front of review. Items added to the crate are not caught, list
+//! them here when they become part of the sanctioned surface.
+
+use std::{
+    io::Cursor,
+    path::{Path, PathBuf},
+    sync::atomic::AtomicBool,
+    time::Duration,
+};
+
+use image::{DynamicImage, ImageFormat, RgbaImage};
+use libwayshot::{
+    CaptureId, ChecksumAlgorithm, ComposeOptions, CompositorInfo, CursorOverlayCheck,
+    EncodingOptions, EnvironmentInfo, Error, FrameCopy, FramePacer, FrameStats, HashingWriter,
+    JpegSubsampling, LogicalRegion, OutputCapture, OutputInfo, OutputResolution, PixelFormat,
+    ProgressEvent, ResizeFit, Result, SeatInfo, SeatSelector, StartupTimings, Tile, TopLevel,
+    VerifyMode, WayshotConnection,
+    reexport::{FailureReason, Transform, WEnum, WlOutput},
+    region::{Position, Region, Size},
+};
+
+#[test]
+fn connection() {
+    let _: fn() -> Result<WayshotConnection> = WayshotConnection::new;
+    let _: fn(&str) -> Result<WayshotConnection> = WayshotConnection::connect_to;
+    let _: fn(&WayshotConnection) -> &[OutputInfo] = WayshotConnection::get_all_outputs;
+    let _: fn(&WayshotConnection) -> &[TopLevel] = WayshotConnection::get_all_toplevels;
+    let _: fn(&WayshotConnection) -> Result<Vec<SeatInfo>> = WayshotConnection::seats;
+    let _: fn(&WayshotConnection) -> &EnvironmentInfo = WayshotConnection::environment_info;
+    let _: fn(&WayshotConnection) -> CompositorInfo = WayshotConnection::compositor_info;
+    let _: fn(&WayshotConnection) -> StartupTimings = WayshotConnection::startup_timings;
+    let _: fn(&WayshotConnection) -> u64 = WayshotConnection::capture_queue_events;
+    let _: fn(&WayshotConnection) -> Option<CaptureId> = WayshotConnection::last_capture_id;
+    let _: fn(&WayshotConnection, &mut Vec<u8>) -> std::io::Result<()> =
+        WayshotConnection::write_displays_info::<Vec<u8>>;
+}
+
+#[test]
+fn connection_settings() {
+    let _: fn(&mut WayshotConnection, bool) = WayshotConnection::set_wait_for_frame;
+    let _: fn(&mut WayshotConnection, bool) = WayshotConnection::set_warn_on_uniform;
+    let _: fn(&mut WayshotConnection, Option<OutputResolution>) =
+        WayshotConnection::set_output_resolution;
+    let _ = |conn: &mut WayshotConnection| conn.set_progress(|_: &ProgressEvent| {});
+}
+
+#[test]
+fn screenshots() {
+    let _: fn(&WayshotConnection, LogicalRegion, bool) -> Result<DynamicImage> =
+        WayshotConnection::screenshot;
+    let _: fn(&WayshotConnection, bool) -> Result<DynamicImage> = WayshotConnection::screenshot_all;
+    let _: fn(&WayshotConnection, bool) -> Result<Vec<OutputCapture>> =
+        WayshotConnection::screenshot_all_separate;
+    let _: fn(&WayshotConnection, bool, u32) -> Result<Vec<OutputCapture>> =
+        WayshotConnection::thumbnail_all_separate;
+    let _: fn(&WayshotConnection, &OutputInfo, bool) -> Result<DynamicImage> =
+        WayshotConnection::screenshot_single_output;
+    let _: fn(&WayshotConnection, &OutputInfo, bool, &mut Vec<u8>) -> Result<()> =
+        WayshotConnection::screenshot_single_output_qoi::<Vec<u8>>;
+    let _: fn(&WayshotConnection, &OutputInfo, Region, bool) -> Result<DynamicImage> =
+        WayshotConnection::screenshot_output_logical_region;
+    let _: fn(&WayshotConnection, Region, bool) -> Result<(DynamicImage, LogicalRegion)> =
+        WayshotConnection::screenshot_focused_output_region;
+    let _: fn(&WayshotConnection, &TopLevel, bool) -> Result<DynamicImage> =
+        WayshotConnection::screenshot_toplevel;
+    type Select = fn(&WayshotConnection) -> Result<LogicalRegion>;
+    let _: fn(&WayshotConnection, Select, bool) -> Result<DynamicImage> =
+        WayshotConnection::screenshot_freeze::<Select>;
+    type OnFrame = fn(FrameCopy) -> Result<()>;
+    let _: fn(&WayshotConnection, &OutputInfo, bool, &AtomicBool, OnFrame) -> Result<()> =
+        WayshotConnection::capture_loop::<OnFrame>;
+}
+
+#[cfg(feature = "interactive")]
+#[test]
+fn interactive_selection() {
+    use libwayshot::SelectionMode;
+    let _: fn(&WayshotConnection, &SelectionMode) -> Result<LogicalRegion> =
+        WayshotConnection::select_region_interactive;
+}
+
+#[test]
+fn checks() {
+    let _: fn(&WayshotConnection, &[OutputInfo]) -> Result<CursorOverlayCheck> =
+        WayshotConnection::check_cursor_overlay;
+    let _: fn(&WayshotConnection, &[OutputInfo], bool) -> Result<u64> =
+        WayshotConnection::estimate_capture_bytes;
+    let _: fn(&WayshotConnection, &OutputInfo) -> Result<FrameStats> =
+        WayshotConnection::output_stats;
+    let _: fn(&WayshotConnection, &[OutputInfo], Duration) -> Result<()> =
+        WayshotConnection::flash_outputs;
+}
+
+#[test]
+fn images() {
+    let _: fn(&mut RgbaImage, (f64, f64), f64) = libwayshot::apply_circle_mask;
+    let _: fn(&mut RgbaImage, f64) = libwayshot::apply_rounded_rect_mask;
+    let _: fn(&DynamicImage, u32, u32) -> Result<Vec<Tile>> = libwayshot::split_tiles;
+    let _: fn(Position, Position) -> LogicalRegion = LogicalRegion::from_corners;
+    let _: fn(&Region, f64) -> Region = Region::to_physical;
+    let _: fn(&OutputInfo) -> f64 = OutputInfo::scale;
+    let _ = ComposeOptions::new().scale(1.0).pack_outputs(true);
+    let _: fn(f64) -> Result<FramePacer> = FramePacer::new;
+    let _: Option<Size> = None;
+    let _: Option<(ResizeFit, SeatSelector, Transform, Option<WlOutput>)> = None;
+}
+
+#[test]
+fn encoding() {
+    let _: fn() -> EncodingOptions = EncodingOptions::new;
+    let _: fn(EncodingOptions, Option<CaptureId>) -> EncodingOptions = EncodingOptions::capture_id;
+    let _: fn(EncodingOptions, bool) -> EncodingOptions = EncodingOptions::deterministic;
+    let _: fn(EncodingOptions, JpegSubsampling) -> EncodingOptions =
+        EncodingOptions::jpeg_subsampling;
+    let _: fn(EncodingOptions, Option<PixelFormat>) -> EncodingOptions =
+        EncodingOptions::pixel_format;
+    type Writer = Cursor<Vec<u8>>;
+    let _: fn(&EncodingOptions, &DynamicImage, ImageFormat, &mut Writer) -> Result<()> =
+        EncodingOptions::encode::<Writer>;
+    let _: fn(&EncodingOptions, &DynamicImage, ImageFormat, &[u8], VerifyMode) -> Result<()> =
+        EncodingOptions::verify;
+    let _: fn(ChecksumAlgorithm, &[u8]) -> String = ChecksumAlgorithm::digest;
+    let _: Option<HashingWriter<Vec<u8>>> = None;
+}
+
+#[test]
+fn state() {
+    let _: fn() -> Option<PathBuf> = libwayshot::state::last_region_path;
+    let _: fn(&Path) -> Result<Option<LogicalRegion>> = libwayshot::state::read_last_region;
+    let _: fn(&Path, LogicalRegion) -> Result<()> = libwayshot::state::write_last_region;
+}
+
+#[test]
+fn errors() {
+    let _ = |error: Error| match error {
+        Error::NoOutputs
+        | Error::SessionLocked
+        | Error::SelectionCancelled
+        | Error::EmptyRegion
+        | Error::FramecopyFailed
+        | Error::NoFormatsAdvertised => {}
+        Error::FramecopyFailedWithReason(WEnum::<FailureReason>::Value(_)) => {}
+        Error::DisplayConnect { socket, source } => drop((socket, source)),
+        Error::VerificationFailed(message) => drop(message),
+        Error::ProtocolNotFound(protocol) => drop(protocol),
+        Error::Io(error) => drop(error),
+        _ => {}
+    };
+}
@@ -0,0 +1,583 @@
+//! An in-process compositor for the tests, serving just the globals a
+//! connection binds and a screencopy that copies nothing into the buffers.
+//!
+//! Outputs only change through [`Server::damage`]. As in wlroots, the first
+//! `copy_with_damage` of an output on a screencopy manager completes right
+//! away and later ones wait until the output was damaged since the previous.
+#![allow(dead_code)]
+
+use std::{
+    collections::HashSet,
+    mem,
+    os::{fd::OwnedFd, unix::net::UnixStream},
+    sync::{
+        Arc, Mutex, PoisonError,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use rustix::{
+    event::{PollFd, PollFlags, Timespec, poll},
+    io::Errno,
+};
+use wayland_client::Connection;
+use wayland_protocols::{
+    ext::foreign_toplevel_list::v1::server::ext_foreign_toplevel_list_v1::{
+        self, ExtForeignToplevelListV1,
+    },
+    xdg::xdg_output::zv1::server::{
+        zxdg_output_manager_v1::{self, ZxdgOutputManagerV1},
+        zxdg_output_v1::{self, ZxdgOutputV1},
+    },
+};
+use wayland_protocols_wlr::screencopy::v1::server::{
+    zwlr_screencopy_frame_v1::{self, ZwlrScreencopyFrameV1},
+    zwlr_screencopy_manager_v1::{self, ZwlrScreencopyManagerV1},
+};
+use wayland_server::{
+    Client, DataInit, Dispatch, Display, DisplayHandle, GlobalDispatch, ListeningSocket, New,
+    Resource,
+    backend::{ClientData, ObjectId},
+    protocol::{
+        wl_buffer::{self, WlBuffer},
+        wl_output::{self, WlOutput},
+        wl_shm::{self, WlShm},
+        wl_shm_pool::{self, WlShmPool},
+    },
+};
+
+/// How long the compositor waits for requests before checking if it should stop.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// An output as the compositor announces it.
+#[derive(Debug, Clone)]
+pub struct Output {
+    pub name: String,
+    pub description: String,
+    /// Size of the current mode, in pixels.
+    pub mode: (i32, i32),
+    /// Position and size in the compositor space, as sent by xdg-output.
+    pub logical: (i32, i32, i32, i32),
+}
+
+impl Output {
+    /// An output with a scale of 1 at `x, y`.
+    pub fn new(name: &str, x: i32, y: i32, width: i32, height: i32) -> Self {
+        Self {
+            name: name.to_string(),
+            description: format!("Test output {name}"),
+            mode: (width, height),
+            logical: (x, y, width, height),
+        }
+    }
+}
+
+/// What the compositor serves.
+#[derive(Debug, Clone)]
+pub struct Compositor {
+    pub outputs: Vec<Output>,
+    /// Formats screencopy offers for the buffer of every frame.
+    pub buffer_formats: Vec<wl_shm::Format>,
+    /// Regions sent with every completed `copy_with_damage`, as
+    /// `x, y, width, height`.
+    pub damage: Vec<(u32, u32, u32, u32)>,
+}
+
+impl Default for Compositor {
+    fn default() -> Self {
+        Self {
+            outputs: vec![Output::new("TEST-1", 0, 0, 64, 48)],
+            buffer_formats: vec![wl_shm::Format::Xrgb8888],
+            damage: Vec::new(),
+        }
+    }
+}
+
+/// The requests a compositor received, counted by kind.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Requests {
+    pub xdg_outputs: usize,
+    pub captures: usize,
+    pub copies: usize,
+    pub copies_with_damage: usize,
+}
+
+/// A compositor running on a thread of its own, stopped on drop.
+pub struct Server {
+    stop: Arc<AtomicBool>,
+    requests: Arc<Mutex<Requests>>,
+    /// Indices of the outputs damaged but not yet handled by the compositor.
+    damage: Arc<Mutex<Vec<usize>>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Compositor {
+    /// Serve a single client, returning the connection of that client.
+    pub fn connect(self) -> (Connection, Server) {
+        let (client, server) = UnixStream::pair().expect("socket pair");
+        let server = self.spawn(move |display| {
+            display
+                .handle()
+                .insert_client(server, Arc::new(ClientState))
+                .expect("insert client");
+            None
+        });
+        let conn = Connection::from_socket(client).expect("connect");
+        (conn, server)
+    }
+
+    /// Serve every client connecting to `socket`.
+    pub fn listen(self, socket: ListeningSocket) -> Server {
+        self.spawn(move |_| Some(socket))
+    }
+
+    fn spawn(
+        self,
+        init: impl FnOnce(&mut Display<State>) -> Option<ListeningSocket> + Send + 'static,
+    ) -> Server {
+        let stop = Arc::new(AtomicBool::new(false));
+        let requests = Arc::new(Mutex::default());
+        let damage = Arc::new(Mutex::default());
+        let mut state = State {
+            compositor: self,
+            requests: Arc::clone(&requests),
+            damage: Arc::clone(&damage),
+            copied: HashSet::new(),
+            damaged: HashSet::new(),
+            waiting: Vec::new(),
+        };
+        let thread = thread::spawn({
+            let stop = Arc::clone(&stop);
+            move || {
+                let mut display = Display::new().expect("create display");
+                state.create_globals(&display.handle());
+                let socket = init(&mut display);
+                state.serve(&mut display, socket.as_ref(), &stop);
+            }
+        });
+        Server {
+            stop,
+            requests,
+            damage,
+            thread: Some(thread),
+        }
+    }
+}
+
+impl Server {
+    /// The requests received so far.
+    pub fn requests(&self) -> Requests {
+        self.requests
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// Damage the output at `index`, returning once the copies waiting for it
+    /// completed and their events were sent.
+    pub fn damage(&self, index: usize) {
+        self.damage
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(index);
+        while !self
+            .damage
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .is_empty()
+        {
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take()
+            && thread.join().is_err()
+            && !thread::panicking()
+        {
+            panic!("the compositor panicked");
+        }
+    }
+}
+
+struct ClientState;
+
+impl ClientData for ClientState {}
+
+/// The screencopy manager and output index of a frame.
+struct FrameData {
+    manager: ObjectId,
+    output: usize,
+}
+
+struct State {
+    compositor: Compositor,
+    requests: Arc<Mutex<Requests>>,
+    damage: Arc<Mutex<Vec<usize>>>,
+    /// Managers and outputs that completed a `copy_with_damage`.
+    copied: HashSet<(ObjectId, usize)>,
+    /// Managers and outputs damaged since their last `copy_with_damage`.
+    damaged: HashSet<(ObjectId, usize)>,
+    /// Frames of `copy_with_damage` waiting for damage.
+    waiting: Vec<ZwlrScreencopyFrameV1>,
+}
+
+impl State {
+    fn create_globals(&self, handle: &DisplayHandle) {
+        for index in 0..self.compositor.outputs.len() {
+            handle.create_global::<Self, WlOutput, usize>(4, index);
+        }
+        handle.create_global::<Self, ZxdgOutputManagerV1, ()>(3, ());
+        handle.create_global::<Self, ZwlrScreencopyManagerV1, ()>(3, ());
+        handle.create_global::<Self, WlShm, ()>(1, ());
+        handle.create_global::<Self, ExtForeignToplevelListV1, ()>(1, ());
+    }
+
+    fn serve(
+        &mut self,
+        display: &mut Display<Self>,
+        socket: Option<&ListeningSocket>,
+        stop: &AtomicBool,
+    ) {
+        let display_fd: OwnedFd = display
+            .backend()
+            .poll_fd()
+            .try_clone_to_owned()
+            .expect("clone the display fd");
+        let timeout = Timespec::try_from(POLL_INTERVAL).expect("poll interval");
+        while !stop.load(Ordering::Relaxed) {
+            if let Some(socket) = socket {
+                while let Some(stream) = socket.accept().expect("accept") {
+                    display
+                        .handle()
+                        .insert_client(stream, Arc::new(ClientState))
+                        .expect("insert client");
+                }
+            }
+            display.dispatch_clients(self).expect("dispatch");
+            // Hold the damage until its events are flushed, see Server::damage.
+            let damage = Arc::clone(&self.damage);
+            let mut damage = damage.lock().unwrap_or_else(PoisonError::into_inner);
+            for output in mem::take(&mut *damage) {
+                self.damage_output(output);
+            }
+            display.flush_clients().expect("flush");
+            drop(damage);
+
+            let mut fds = vec![PollFd::new(&display_fd, PollFlags::IN)];
+            if let Some(socket) = socket {
+                fds.push(PollFd::new(socket, PollFlags::IN));
+            }
+            match poll(&mut fds, Some(&timeout)) {
+                Ok(_) | Err(Errno::INTR) => {}
+                Err(error) => panic!("poll: {error}"),
+            }
+        }
+    }
+
+    fn count(&self, request: impl FnOnce(&mut Requests)) {
+        request(&mut self.requests.lock().unwrap_or_else(PoisonError::into_inner));
+    }
+
+    /// Announce the buffer formats of a new screencopy frame of `output`.
+    fn offer_buffers(&self, frame: &ZwlrScreencopyFrameV1, output: &WlOutput) {
+        let output = &self.compositor.outputs[*output.data::<usize>().expect("output index")];
+        let (width, height) = output.mode;
+        for &format in &self.compositor.buffer_formats {
+            frame.buffer(format, width as u32, height as u32, width as u32 * 4);
+        }
+        frame.buffer_done();
+    }
+
+    fn finish_copy(&self, frame: &ZwlrScreencopyFrameV1) {
+        frame.flags(zwlr_screencopy_frame_v1::Flags::empty());
+        frame.ready(0, 0, 0);
+    }
+
+    /// Complete a `copy_with_damage` if its output was damaged since the
+    /// previous one of its manager, or hold it until then.
+    fn copy_with_damage(&mut self, frame: &ZwlrScreencopyFrameV1) {
+        let data = frame.data::<FrameData>().expect("frame data");
+        let key = (data.manager.clone(), data.output);
+        if self.copied.insert(key.clone()) || self.damaged.remove(&key) {
+            for &(x, y, width, height) in &self.compositor.damage {
+                frame.damage(x, y, width, height);
+            }
+            self.finish_copy(frame);
+        } else {
+            self.waiting.push(frame.clone());
+        }
+    }
+
+    fn damage_output(&mut self, output: usize) {
+        self.damaged.extend(
+            self.copied
+                .iter()
+                .filter(|(_, copied)| *copied == output)
+                .cloned(),
+        );
+        let waiting = mem::take(&mut self.waiting);
+        for frame in waiting {
+            if frame.data::<FrameData>().expect("frame data").output == output {
+                self.copy_with_damage(&frame);
+            } else {
+                self.waiting.push(frame);
+            }
+        }
+    }
+}
+
+impl GlobalDispatch<WlOutput, usize> for State {
+    fn bind(
+        state: &mut Self,
+        _: &DisplayHandle,
+        _: &Client,
+        resource: New<WlOutput>,
+        index: &usize,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        let output = data_init.init(resource, *index);
+        let info = &state.compositor.outputs[*index];
+        let (width, height) = info.mode;
+        output.geometry(
+            0,
+            0,
+            0,
+            0,
+            wl_output::Subpixel::Unknown,
+            "wayshot".to_string(),
+            "test".to_string(),
+            wl_output::Transform::Normal,
+        );
+        output.mode(wl_output::Mode::Current, width, height, 60_000);
+        output.scale(1);
+        output.name(info.name.clone());
+        output.description(info.description.clone());
+        output.done();
+    }
+}
+
+impl Dispatch<WlOutput, usize> for State {
+    fn request(
+        _: &mut Self,
+        _: &Client,
+        _: &WlOutput,
+        _: wl_output::Request,
+        _: &usize,
+        _: &DisplayHandle,
+        _: &mut DataInit<'_, Self>,
+    ) {
+    }
+}
+
+impl GlobalDispatch<ZxdgOutputManagerV1, ()> for State {
+    fn bind(
+        _: &mut Self,
+        _: &DisplayHandle,
+        _: &Client,
+        resource: New<ZxdgOutputManagerV1>,
+        _: &(),
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        data_init.init(resource, ());
+    }
+}
+
+impl Dispatch<ZxdgOutputManagerV1, ()> for State {
+    fn request(
+        state: &mut Self,
+        _: &Client,
+        _: &ZxdgOutputManagerV1,
+        request: zxdg_output_manager_v1::Request,
+        _: &(),
+        _: &DisplayHandle,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        if let zxdg_output_manager_v1::Request::GetXdgOutput { id, output } = request {
+            state.count(|requests| requests.xdg_outputs += 1);
+            let xdg_output = data_init.init(id, ());
+            let info = &state.compositor.outputs[*output.data::<usize>().expect("output index")];
+            let (x, y, width, height) = info.logical;
+            xdg_output.logical_position(x, y);
+            xdg_output.logical_size(width, height);
+            // Since version 3 the xdg-output events are applied by wl_output.done.
+            output.done();
+        }
+    }
+}
+
+impl Dispatch<ZxdgOutputV1, ()> for State {
+    fn request(
+        _: &mut Self,
+        _: &Client,
+        _: &ZxdgOutputV1,
+        _: zxdg_output_v1::Request,
+        _: &(),
+        _: &DisplayHandle,
+        _: &mut DataInit<'_, Self>,
+    ) {
+    }
+}
+
+impl GlobalDispatch<ZwlrScreencopyManagerV1, ()> for State {
+    fn bind(
+        _: &mut Self,
+        _: &DisplayHandle,
+        _: &Client,
+        resource: New<ZwlrScreencopyManagerV1>,
+        _: &(),
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        data_init.init(resource, ());
+    }
+}
+
+impl Dispatch<ZwlrScreencopyManagerV1, ()> for State {
+    fn request(
+        state: &mut Self,
+        _: &Client,
+        manager: &ZwlrScreencopyManagerV1,
+        request: zwlr_screencopy_manager_v1::Request,
+        _: &(),
+        _: &DisplayHandle,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        let (frame, output) = match request {
+            zwlr_screencopy_manager_v1::Request::CaptureOutput { frame, output, .. }
+            | zwlr_screencopy_manager_v1::Request::CaptureOutputRegion { frame, output, .. } => {
+                (frame, output)
+            }
+            _ => return,
+        };
+        state.count(|requests| requests.captures += 1);
+        let frame = data_init.init(
+            frame,
+            FrameData {
+                manager: manager.id(),
+                output: *output.data::<usize>().expect("output index"),
+            },
+        );
+        state.offer_buffers(&frame, &output);
+    }
+}
+
+impl Dispatch<ZwlrScreencopyFrameV1, FrameData> for State {
+    fn request(
+        state: &mut Self,
+        _: &Client,
+        frame: &ZwlrScreencopyFrameV1,
+        request: zwlr_screencopy_frame_v1::Request,
+        _: &FrameData,
+        _: &DisplayHandle,
+        _: &mut DataInit<'_, Self>,
+    ) {
+        match request {
+            zwlr_screencopy_frame_v1::Request::Copy { .. } => {
+                state.count(|requests| requests.copies += 1);
+                state.finish_copy(frame);
+            }
+            zwlr_screencopy_frame_v1::Request::CopyWithDamage { .. } => {
+                state.count(|requests| requests.copies_with_damage += 1);
+                state.copy_with_damage(frame);
+            }
+            zwlr_screencopy_frame_v1::Request::Destroy => {
+                state.waiting.retain(|waiting| waiting != frame);
+            }
+            _ => {}
+        }
+    }
+}
+
+impl GlobalDispatch<WlShm, ()> for State {
+    fn bind(
+        _: &mut Self,
+        _: &DisplayHandle,
+        _: &Client,
+        resource: New<WlShm>,
+        _: &(),
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        let shm = data_init.init(resource, ());
+        shm.format(wl_shm::Format::Argb8888);
+        shm.format(wl_shm::Format::Xrgb8888);
+    }
+}
+
+impl Dispatch<WlShm, ()> for State {
+    fn request(
+        _: &mut Self,
+        _: &Client,
+        _: &WlShm,
+        request: wl_shm::Request,
+        _: &(),
+        _: &DisplayHandle,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        if let wl_shm::Request::CreatePool { id, .. } = request {
+            data_init.init(id, ());
+        }
+    }
+}
+
+impl Dispatch<WlShmPool, ()> for State {
+    fn request(
+        _: &mut Self,
+        _: &Client,
+        _: &WlShmPool,
+        request: wl_shm_pool::Request,
+        _: &(),
+        _: &DisplayHandle,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        if let wl_shm_pool::Request::CreateBuffer { id, .. } = request {
+            data_init.init(id, ());
+        }
+    }
+}
+
+impl Dispatch<WlBuffer, ()> for State {
+    fn request(
+        _: &mut Self,
+        _: &Client,
+        _: &WlBuffer,
+        _: wl_buffer::Request,
+        _: &(),
+        _: &DisplayHandle,
+        _: &mut DataInit<'_, Self>,
+    ) {
+    }
+}
+
+impl GlobalDispatch<ExtForeignToplevelListV1, ()> for State {
+    fn bind(
+        _: &mut Self,
+        _: &DisplayHandle,
+        _: &Client,
+        resource: New<ExtForeignToplevelListV1>,
+        _: &(),
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        data_init.init(resource, ());
+    }
+}
+
+impl Dispatch<ExtForeignToplevelListV1, ()> for State {
+    fn request(
+        _: &mut Self,
+        _: &Client,
+        list: &ExtForeignToplevelListV1,
+        request: ext_foreign_toplevel_list_v1::Request,
+        _: &(),
+        _: &DisplayHandle,
+        _: &mut DataInit<'_, Self>,
+    ) {
+        if let ext_foreign_toplevel_list_v1::Request::Stop = request {
+            list.finished();
+        }
+    }
+}
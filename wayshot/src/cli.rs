@@ -1,15 +1,21 @@
-use std::path::PathBuf;
+use std::{ffi::OsString, path::PathBuf};
 
 use clap::{
-    Parser,
+    Args, CommandFactory, Parser, Subcommand,
     builder::{
         Styles,
         styling::{AnsiColor, Effects},
     },
 };
+use libwayshot::{
+    ChecksumAlgorithm, JpegSubsampling, OutputResolution, PixelFormat, ResizeFit, SeatSelector,
+    VerifyMode,
+    region::{Position, Region, Size},
+};
+use serde::{Deserialize, Serialize};
 use tracing::Level;
 
-use crate::utils::EncodingFormat;
+use crate::utils::{ClipboardTarget, EncodingFormat, MaskShape};
 
 fn get_styles() -> Styles {
     Styles::styled()
@@ -19,7 +25,7 @@ fn get_styles() -> Styles {
         .placeholder(AnsiColor::Green.on_default())
 }
 
-#[derive(Parser)]
+#[derive(Parser, PartialEq)]
 #[command(version, about, styles=get_styles())]
 pub struct Cli {
     /// Custom screenshot file path can be of the following types:
@@ -29,11 +35,18 @@ pub struct Cli {
     #[arg(value_name = "FILE", verbatim_doc_comment)]
     pub file: Option<PathBuf>,
 
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
     /// Copy image to clipboard. Can be used simultaneously with [FILE].
     /// Wayshot persists in the background offering the image till the clipboard is overwritten.
-    #[arg(long, verbatim_doc_comment)]
+    #[arg(long, verbatim_doc_comment, global = true)]
     pub clipboard: bool,
 
+    /// Clipboard to offer the image on when copying: regular, primary (middle-click paste) or both.
+    #[arg(long, value_name = "TARGET", global = true)]
+    pub clipboard_target: Option<ClipboardTarget>,
+
     /// Log level to be used for printing to stderr
     #[arg(long, verbatim_doc_comment)]
     pub log_level: Option<Level>,
@@ -42,15 +55,149 @@ pub struct Cli {
     #[arg(short, long)]
     pub geometry: bool,
 
+    /// Like --geometry, but highlight the window under the pointer and select it on click.
+    /// Needs the window geometry from Sway or Hyprland.
+    #[arg(long, verbatim_doc_comment, conflicts_with_all = ["geometry", "output", "choose_output", "toplevel", "choose_toplevel", "stats", "loop_frames", "separate"])]
+    pub window_pick: bool,
+
+    /// Like --geometry, but select the whole output that is clicked.
+    #[arg(long, conflicts_with_all = ["geometry", "window_pick", "output", "choose_output", "toplevel", "choose_toplevel", "stats", "loop_frames", "separate"])]
+    pub output_pick: bool,
+
+    /// Capture the region of the last successful capture again, as stored in
+    /// `$XDG_STATE_HOME/wayshot/last-region`.
+    #[arg(long, verbatim_doc_comment, conflicts_with_all = ["geometry", "window_pick", "output_pick", "output", "choose_output", "toplevel", "choose_toplevel", "stats", "loop_frames", "separate"])]
+    pub last: bool,
+
+    /// Like --last, but move the region by DX,DY logical pixels first, e.g. `300,0` to
+    /// capture the area right of a 300 pixel wide region.
+    #[arg(long, verbatim_doc_comment, value_name = "DX,DY", allow_hyphen_values = true, value_parser = parse_offset, conflicts_with_all = ["geometry", "window_pick", "output_pick", "output", "choose_output", "toplevel", "choose_toplevel", "stats", "loop_frames", "separate"])]
+    pub last_offset: Option<(i32, i32)>,
+
+    /// Capture X,Y WxH in the logical coordinates of the output given with -o, or of the
+    /// focused output otherwise, e.g. `0,0 400x300` for its top left corner. The focus is
+    /// asked from Sway and Hyprland, elsewhere it is the output the pointer is over.
+    #[arg(long, verbatim_doc_comment, value_name = "X,Y WxH", value_parser = parse_region, conflicts_with_all = ["geometry", "window_pick", "output_pick", "choose_output", "toplevel", "choose_toplevel", "stats", "loop_frames", "separate", "last", "last_offset", "resolution"])]
+    pub relative_region: Option<Region>,
+
     /// Enable cursor in screenshots
     #[arg(short, long)]
     pub cursor: bool,
 
+    /// Check that the compositor leaves the cursor out when asked and warn if it could not
+    /// be verified. Captures every output twice more, so keep the cursor on screen.
+    #[arg(long, verbatim_doc_comment, conflicts_with = "cursor")]
+    pub check_cursor: bool,
+
+    /// Seat whose focus --relative-region follows on multi-seat sessions: a seat name,
+    /// `default` for the first seat with a pointer or `all`. Fails naming the available
+    /// seats if there is no such seat with a pointer. The cursors of all seats are drawn
+    /// with --cursor and every seat can make a selection.
+    #[arg(long, verbatim_doc_comment, value_name = "SEAT")]
+    pub seat: Option<SeatSelector>,
+
     /// Set image encoder, by default uses the file extension from the FILE
     /// positional argument. Otherwise defaults to png.
-    #[arg(long, verbatim_doc_comment, visible_aliases = ["extension", "format", "file-format"], value_name = "FILE_EXTENSION")]
+    #[arg(long, verbatim_doc_comment, visible_aliases = ["extension", "format", "file-format"], value_name = "FILE_EXTENSION", global = true)]
     pub encoding: Option<EncodingFormat>,
 
+    /// Convert the image to rgba8, rgb8, bgra8 or bgr8 before encoding, for tools that
+    /// only accept one layout. The encoding has to support it: alpha needs e.g. png,
+    /// bgr needs a lossless encoding such as png, ppm or qoi.
+    #[arg(long, verbatim_doc_comment, global = true)]
+    pub pixel_format: Option<PixelFormat>,
+
+    /// Chroma subsampling of jpg images: 444 keeps the colour at full resolution, 422
+    /// halves it horizontally and 420 in both directions. 420 makes smaller files but
+    /// smears the edges of coloured text, so 444 is the default.
+    #[arg(long, verbatim_doc_comment, value_name = "SUBSAMPLING", global = true)]
+    pub jpeg_subsampling: Option<JpegSubsampling>,
+
+    /// Save the image as tiles of WIDTH pixels instead, named `<name>-<index>.<ext>`.
+    /// The last tile is aligned to the right edge, see --tile-overlap.
+    #[arg(long, value_name = "WIDTH", value_parser = clap::value_parser!(u32).range(1..), verbatim_doc_comment, conflicts_with = "exec")]
+    pub tile_width: Option<u32>,
+
+    /// Cut the screenshot to a shape after cropping, leaving the rest transparent.
+    /// Needs an encoding with alpha such as png or webp.
+    #[arg(long, verbatim_doc_comment, conflicts_with_all = ["stats", "loop_frames", "separate"])]
+    pub shape: Option<MaskShape>,
+
+    /// Round the corners of the screenshot with RADIUS pixels, leaving them transparent.
+    /// Needs an encoding with alpha such as png or webp.
+    #[arg(long, value_name = "RADIUS", verbatim_doc_comment, conflicts_with_all = ["shape", "stats", "loop_frames", "separate"])]
+    pub radius: Option<u32>,
+
+    /// Save every output to its own file instead, named `<name>-<output>.<ext>`.
+    /// With --sidecar each file gets its own metadata, naming the output's active workspace.
+    #[arg(long, verbatim_doc_comment, conflicts_with_all = ["geometry", "output", "choose_output", "toplevel", "choose_toplevel", "stats", "loop_frames", "tile_width", "clipboard", "preview"])]
+    pub separate: bool,
+
+    /// Number of files to encode at once with --separate. Defaults to the number of CPUs.
+    #[arg(long, value_name = "N", requires = "separate", value_parser = clap::value_parser!(u32).range(1..))]
+    pub jobs: Option<u32>,
+
+    /// Print how long connecting, capturing and encoding every file took to stderr.
+    #[arg(long, global = true)]
+    pub timings: bool,
+
+    /// Number of columns neighbouring tiles share with --tile-width.
+    #[arg(
+        long,
+        value_name = "PIXELS",
+        default_value_t = 0,
+        requires = "tile_width"
+    )]
+    pub tile_overlap: u32,
+
+    /// Print the capture metadata as JSON to stdout. On failure print
+    /// `{"error": {"kind", "output", "message", "hint"}}` instead and exit with
+    /// a code specific to the kind of failure.
+    #[arg(long, verbatim_doc_comment, global = true)]
+    pub json: bool,
+
+    /// Briefly flash the captured outputs white once the capture finished.
+    #[arg(long)]
+    pub flash: bool,
+
+    /// Ring the terminal bell once the capture finished.
+    #[arg(long)]
+    pub bell: bool,
+
+    /// Write capture metadata (time, outputs, region, scale, transform, format, version)
+    /// as JSON next to the saved image, e.g. `shot.json` for `shot.png`.
+    #[arg(long, verbatim_doc_comment, global = true)]
+    pub sidecar: bool,
+
+    /// Write progress events (output captured, converted, composited, encoded,
+    /// written) as JSON lines to the open file descriptor FD, e.g. 2 for stderr.
+    #[arg(long, value_name = "FD", verbatim_doc_comment, global = true)]
+    pub progress_fd: Option<u32>,
+
+    /// Run a shell command once the image is saved, e.g. to upload it. `{}` is replaced
+    /// with the saved file, `{geometry}` with the captured region as `X,Y WxH` and
+    /// `{output}` with the captured outputs. The values are shell quoted, the command
+    /// runs with `sh -c` and fails wayshot when it exits with a non-zero status.
+    #[arg(long, verbatim_doc_comment, value_name = "COMMAND", global = true)]
+    pub exec: Option<String>,
+
+    /// Encode deterministically so identical pixels always produce identical bytes:
+    /// no metadata or timestamps, fixed PNG filter and compression level, fixed JPEG quality.
+    #[arg(long, verbatim_doc_comment, global = true)]
+    pub reproducible: bool,
+
+    /// Read every written file back, decode it and compare it with the captured image.
+    /// `sampled` (the default) compares a few rows, `full` every row. The image put on
+    /// the clipboard is read back too. Exits with code 9 when a check fails.
+    #[arg(long, verbatim_doc_comment, value_name = "MODE", num_args = 0..=1, require_equals = true, default_missing_value = "sampled", global = true)]
+    pub verify: Option<VerifyMode>,
+
+    /// Write the sha256 or blake3 digest of every saved file next to it, e.g. `shot.png.sha256`
+    /// in the format `sha256sum -c` and `b3sum -c` check. The digest is computed while the file
+    /// is written and included in the --json and --sidecar metadata.
+    #[arg(long, verbatim_doc_comment, value_name = "ALGORITHM", global = true)]
+    pub checksum: Option<ChecksumAlgorithm>,
+
     /// List all valid outputs
     #[arg(short, long, alias = "list-outputs")]
     pub list_outputs: bool,
@@ -59,6 +206,15 @@ pub struct Cli {
     #[arg(long)]
     pub list_outputs_info: bool,
 
+    /// Same as `wayshot check`.
+    #[arg(long)]
+    pub environment_info: bool,
+
+    /// Print the mean, median and 5th/95th percentile luminance and the dominant colour
+    /// of the output (-o, or the first one) without saving an image. Combine with --json.
+    #[arg(long, verbatim_doc_comment, conflicts_with_all = ["geometry", "toplevel", "choose_toplevel", "loop_frames"])]
+    pub stats: bool,
+
     /// List all toplevel windows (applications)
     #[arg(long, alias = "list-windows")]
     pub list_toplevels: bool,
@@ -79,6 +235,38 @@ pub struct Cli {
     #[arg(long, alias = "choose-window", conflicts_with_all = ["geometry", "output", "choose_output", "toplevel"])]
     pub choose_toplevel: bool,
 
+    /// Downscale the screenshot so that its largest side is at most MAX_DIM pixels.
+    #[arg(long, value_name = "MAX_DIM")]
+    pub thumbnail: Option<u32>,
+
+    /// Resize captures of a single output to exactly WIDTHxHEIGHT pixels, e.g. for visual
+    /// regression baselines that must match across machines. Needs -o, --choose-output or --separate.
+    #[arg(long, value_name = "WIDTHxHEIGHT", value_parser = parse_resolution, verbatim_doc_comment, conflicts_with_all = ["geometry", "window_pick", "output_pick", "toplevel", "choose_toplevel", "thumbnail", "loop_frames"])]
+    pub resolution: Option<OutputResolution>,
+
+    /// How --resolution fits outputs of another aspect ratio: letterbox (default) or stretch.
+    #[arg(long, value_name = "FIT", requires = "resolution")]
+    pub resize_fit: Option<ResizeFit>,
+
+    /// Also print a preview of the screenshot to the terminal, using truecolor half blocks
+    /// or grayscale ASCII when NO_COLOR is set. Goes to stderr when the image is written to stdout.
+    #[arg(long, verbatim_doc_comment, global = true)]
+    pub preview: bool,
+
+    /// Print the text recognized in the screenshot to stdout, in the Tesseract languages LANG
+    /// (default eng), e.g. `eng+deu`. Needs wayshot built with the `ocr` feature.
+    #[arg(long, value_name = "LANG", num_args = 0..=1, default_missing_value = "eng", value_parser = parse_ocr_lang, verbatim_doc_comment, conflicts_with_all = ["json", "loop_frames", "separate"])]
+    pub ocr: Option<String>,
+
+    /// Capture the output continuously and write a stream of PPM frames to [FILE],
+    /// e.g. a FIFO read by ffmpeg. Stops when the reader goes away.
+    #[arg(long = "loop", verbatim_doc_comment, requires = "file", conflicts_with_all = ["geometry", "toplevel", "choose_toplevel", "clipboard", "pixel_format", "verify", "checksum", "exec"])]
+    pub loop_frames: bool,
+
+    /// Frames per second to capture at with --loop. Captures as fast as possible when unset.
+    #[arg(long, requires = "loop_frames", value_parser = parse_fps)]
+    pub fps: Option<f64>,
+
     /// Output file name's formatting.
     /// Defaults to config value (`wayshot-%Y_%m_%d-%H_%M_%S`)
     #[arg(long, verbatim_doc_comment)]
@@ -91,4 +279,504 @@ pub struct Cli {
     ///     3. `None` -- if the config isn't found, the `Config::default()` will be used
     #[arg(long, verbatim_doc_comment)]
     pub config: Option<PathBuf>,
+
+    /// Connect to the Wayland display NAME, e.g. `wayland-2` of a nested compositor,
+    /// instead of the one in `WAYLAND_DISPLAY`. Absolute socket paths work as well.
+    #[arg(long, value_name = "NAME", verbatim_doc_comment)]
+    pub display: Option<String>,
+
+    /// Wait up to SECONDS (default 10) for the compositor to configure an output before
+    /// capturing, for scripts started together with the compositor.
+    #[arg(long, value_name = "SECONDS", num_args = 0..=1, default_missing_value = "10", value_parser = parse_seconds, verbatim_doc_comment)]
+    pub wait_for_outputs: Option<f64>,
+
+    /// Wait for the compositor to present a new frame before capturing, so UI changes
+    /// triggered right before are fully drawn. Adds up to a frame of latency.
+    #[arg(long, verbatim_doc_comment)]
+    pub wait_for_frame: bool,
+
+    /// Outline every output in captures of all outputs, to see where they meet in the image.
+    #[arg(long, verbatim_doc_comment)]
+    pub debug_borders: bool,
+
+    /// Warn when a captured output is a single colour, e.g. all black, which is how
+    /// some compositors fail silently. Reads every captured pixel once more.
+    #[arg(long, verbatim_doc_comment)]
+    pub warn_on_uniform: bool,
+
+    /// Warn before capturing all outputs or a region when the capture likely needs more
+    /// than half of the available memory. Asks the compositor for the buffer of every
+    /// output once more for the estimate.
+    #[arg(long, verbatim_doc_comment)]
+    pub warn_on_large: bool,
+
+    /// Run the capture plan of the config file's `[actions.NAME]` table, e.g. from a
+    /// compositor keybinding. CLI options still take precedence over the action.
+    #[arg(long, verbatim_doc_comment, value_name = "NAME")]
+    pub action: Option<String>,
+
+    /// Treat problems in the config file, such as unknown keys, as errors instead of warnings.
+    #[arg(long)]
+    pub strict_config: bool,
+}
+
+impl Default for Cli {
+    /// The options of a plain `wayshot`.
+    fn default() -> Self {
+        Self::parse_from(["wayshot"])
+    }
+}
+
+impl Cli {
+    /// Whether the options leave the captured frame as it is, so it can be
+    /// encoded straight from the compositor's buffer. Only the options below
+    /// do, any other option, including ones added later, changes the pixels
+    /// or needs them decoded.
+    pub fn keeps_frame_as_is(&self) -> bool {
+        let as_is = Self {
+            file: self.file.clone(),
+            clipboard_target: self.clipboard_target,
+            log_level: self.log_level,
+            cursor: self.cursor,
+            encoding: self.encoding,
+            jpeg_subsampling: self.jpeg_subsampling,
+            jobs: self.jobs,
+            timings: self.timings,
+            tile_overlap: self.tile_overlap,
+            flash: self.flash,
+            bell: self.bell,
+            progress_fd: self.progress_fd,
+            exec: self.exec.clone(),
+            reproducible: self.reproducible,
+            output: self.output.clone(),
+            file_name_format: self.file_name_format.clone(),
+            config: self.config.clone(),
+            display: self.display.clone(),
+            wait_for_outputs: self.wait_for_outputs,
+            wait_for_frame: self.wait_for_frame,
+            warn_on_uniform: self.warn_on_uniform,
+            warn_on_large: self.warn_on_large,
+            action: self.action.clone(),
+            strict_config: self.strict_config,
+            ..Self::default()
+        };
+        *self == as_is
+    }
+}
+
+#[derive(Subcommand, PartialEq)]
+pub enum Command {
+    /// Encode raw pixels read from stdin instead of capturing the screen, e.g. frames
+    /// rendered by another tool. The image goes through the same saving, --verify,
+    /// --clipboard, --sidecar and --exec handling as a screenshot.
+    #[command(verbatim_doc_comment)]
+    Encode(EncodeArgs),
+    /// Print the detected compositor, its protocol versions and session details to
+    /// include in bug reports, as JSON with --json.
+    #[command(verbatim_doc_comment)]
+    Check,
+    /// Run the capture plan of the config file's `[actions.ACTION]` table, the same as
+    /// --action ACTION. Meant for compositor keybindings, e.g. `wayshot trigger full`.
+    #[command(verbatim_doc_comment)]
+    Trigger {
+        #[arg(value_name = "ACTION")]
+        action: String,
+    },
+}
+
+#[derive(Args, PartialEq, Serialize, Deserialize)]
+pub struct EncodeArgs {
+    /// Same as the FILE of a capture, `-` writes the encoded image to stdout.
+    #[arg(value_name = "FILE")]
+    pub file: Option<PathBuf>,
+
+    /// Size of the image on stdin.
+    #[arg(long, value_name = "WIDTHxHEIGHT", value_parser = parse_size)]
+    pub size: (u32, u32),
+
+    /// Layout of the tightly packed pixels on stdin: rgba (the default), rgb, bgra or bgr.
+    #[arg(long, value_name = "FORMAT", default_value = "rgba")]
+    #[serde(with = "crate::plan::as_string")]
+    pub input_format: PixelFormat,
+}
+
+fn parse_fps(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(fps) if fps.is_finite() && fps > 0.0 => Ok(fps),
+        _ => Err(format!("'{value}' is not a positive number")),
+    }
+}
+
+fn parse_resolution(value: &str) -> Result<OutputResolution, String> {
+    value
+        .split_once('x')
+        .and_then(|(width, height)| Some((width.parse().ok()?, height.parse().ok()?)))
+        .and_then(|(width, height)| OutputResolution::new(width, height).ok())
+        .ok_or_else(|| format!("'{value}' is not a resolution like 1920x1080"))
+}
+
+fn parse_size(value: &str) -> Result<(u32, u32), String> {
+    value
+        .split_once('x')
+        .and_then(|(width, height)| Some((width.parse().ok()?, height.parse().ok()?)))
+        .filter(|&(width, height)| width > 0 && height > 0)
+        .ok_or_else(|| format!("'{value}' is not a size like 1920x1080"))
+}
+
+fn parse_region(value: &str) -> Result<Region, String> {
+    let error = || format!("'{value}' is not a region like '10,20 300x200'");
+    let (position, size) = value.trim().split_once(' ').ok_or_else(error)?;
+    let (x, y) = parse_offset(position).map_err(|_| error())?;
+    let (width, height) = parse_size(size.trim()).map_err(|_| error())?;
+    Ok(Region {
+        position: Position { x, y },
+        size: Size { width, height },
+    })
+}
+
+fn parse_offset(value: &str) -> Result<(i32, i32), String> {
+    value
+        .split_once(',')
+        .and_then(|(dx, dy)| Some((dx.trim().parse().ok()?, dy.trim().parse().ok()?)))
+        .ok_or_else(|| format!("'{value}' is not an offset like 300,0"))
+}
+
+#[cfg(feature = "ocr")]
+fn parse_ocr_lang(value: &str) -> Result<String, String> {
+    Ok(value.to_string())
+}
+
+#[cfg(not(feature = "ocr"))]
+fn parse_ocr_lang(_value: &str) -> Result<String, String> {
+    Err("this wayshot was built without OCR support, rebuild it with the 'ocr' feature".into())
+}
+
+fn parse_seconds(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(seconds) if seconds.is_finite() && seconds >= 0.0 => Ok(seconds),
+        _ => Err(format!("'{value}' is not a non-negative number of seconds")),
+    }
+}
+
+/// A renamed flag that keeps working for two releases after `since`.
+pub struct DeprecatedFlag {
+    pub name: &'static str,
+    pub replacement: &'static str,
+    pub since: &'static str,
+}
+
+/// Flags that were renamed. Add an entry here instead of breaking the old name,
+/// and remove it two releases after `since`.
+pub const DEPRECATED_FLAGS: &[DeprecatedFlag] = &[];
+
+/// Rewrite deprecated flags (`--old` and `--old=value`) to their replacement,
+/// returning the new arguments and a notice for every rewritten flag.
+///
+/// Arguments after `--` and the values of options are left alone, even if
+/// they look like a deprecated flag.
+pub fn rewrite_deprecated_flags(
+    args: impl IntoIterator<Item = OsString>,
+    deprecated_flags: &[DeprecatedFlag],
+) -> (Vec<OsString>, Vec<String>) {
+    let mut command = Cli::command();
+    command.build();
+    let mut notices = Vec::new();
+    let mut rewritten = Vec::new();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let Some(arg_str) = arg.to_str() else {
+            rewritten.push(arg);
+            continue;
+        };
+        if arg_str == "--" {
+            rewritten.push(arg);
+            rewritten.extend(args);
+            break;
+        }
+        let (flag, value) = match arg_str.split_once('=') {
+            Some((flag, value)) => (flag, Some(value)),
+            None => (arg_str, None),
+        };
+        let deprecated = deprecated_flags
+            .iter()
+            .find(|deprecated| flag.strip_prefix("--") == Some(deprecated.name));
+        let takes_next = value.is_none()
+            && match deprecated {
+                Some(deprecated) => takes_value(&command, &format!("--{}", deprecated.replacement)),
+                None => takes_value(&command, flag),
+            };
+
+        match deprecated {
+            Some(deprecated) => {
+                notices.push(format!(
+                    "`--{}` is deprecated since {} and will be removed, use `--{}` instead",
+                    deprecated.name, deprecated.since, deprecated.replacement
+                ));
+                rewritten.push(match value {
+                    Some(value) => format!("--{}={value}", deprecated.replacement).into(),
+                    None => format!("--{}", deprecated.replacement).into(),
+                });
+            }
+            None => rewritten.push(arg),
+        }
+        if takes_next {
+            rewritten.extend(args.next());
+        }
+    }
+
+    (rewritten, notices)
+}
+
+/// Whether `flag`, such as `--output` or `-o`, is an option of wayshot or one
+/// of its subcommands that takes the next argument as its value.
+fn takes_value(command: &clap::Command, flag: &str) -> bool {
+    let matches = |arg: &clap::Arg| {
+        if let Some(long) = flag.strip_prefix("--") {
+            arg.get_long() == Some(long)
+                || arg
+                    .get_all_aliases()
+                    .is_some_and(|aliases| aliases.contains(&long))
+        } else if let Some(shorts) = flag.strip_prefix('-') {
+            // In a group like `-cf` only the last flag can take the next argument.
+            shorts
+                .chars()
+                .last()
+                .is_some_and(|short| arg.get_short() == Some(short))
+        } else {
+            false
+        }
+    };
+    std::iter::once(command)
+        .chain(command.get_subcommands())
+        .flat_map(clap::Command::get_arguments)
+        .any(|arg| matches(arg) && arg.get_action().takes_values())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FLAGS: &[DeprecatedFlag] = &[DeprecatedFlag {
+        name: "old-name",
+        replacement: "new-name",
+        since: "1.4.3",
+    }];
+
+    fn rewrite(args: &[&str]) -> (Vec<OsString>, Vec<String>) {
+        rewrite_deprecated_flags(args.iter().map(OsString::from), FLAGS)
+    }
+
+    #[test]
+    fn deprecated_flag_is_rewritten_with_notice() {
+        let (args, notices) = rewrite(&["wayshot", "--old-name", "foo"]);
+
+        assert_eq!(args, ["wayshot", "--new-name", "foo"]);
+        assert_eq!(
+            notices,
+            [
+                "`--old-name` is deprecated since 1.4.3 and will be removed, use `--new-name` instead"
+            ]
+        );
+    }
+
+    #[test]
+    fn deprecated_flag_with_value_is_rewritten() {
+        let (args, notices) = rewrite(&["wayshot", "--old-name=foo"]);
+
+        assert_eq!(args, ["wayshot", "--new-name=foo"]);
+        assert_eq!(notices.len(), 1);
+    }
+
+    #[test]
+    fn other_arguments_are_untouched() {
+        let (args, notices) = rewrite(&["wayshot", "--old-name-suffix", "old-name", "-c"]);
+
+        assert_eq!(args, ["wayshot", "--old-name-suffix", "old-name", "-c"]);
+        assert!(notices.is_empty());
+    }
+
+    #[test]
+    fn arguments_after_double_dash_are_untouched() {
+        let (args, notices) = rewrite(&["wayshot", "--", "--old-name"]);
+
+        assert_eq!(args, ["wayshot", "--", "--old-name"]);
+        assert!(notices.is_empty());
+    }
+
+    #[test]
+    fn option_values_are_untouched() {
+        let (args, notices) = rewrite(&["wayshot", "-o", "--old-name", "--old-name"]);
+        assert_eq!(args, ["wayshot", "-o", "--old-name", "--new-name"]);
+        assert_eq!(notices.len(), 1);
+
+        let (args, notices) = rewrite(&["wayshot", "--file-name-format", "--old-name", "-c"]);
+        assert_eq!(args, ["wayshot", "--file-name-format", "--old-name", "-c"]);
+        assert!(notices.is_empty());
+
+        // Flags without a value don't take the next argument.
+        let (args, _) = rewrite(&["wayshot", "-c", "--old-name"]);
+        assert_eq!(args, ["wayshot", "-c", "--new-name"]);
+    }
+
+    #[test]
+    fn deprecated_flags_resolve_to_existing_flags() {
+        let command = Cli::command();
+        for deprecated in DEPRECATED_FLAGS {
+            assert!(
+                command
+                    .get_arguments()
+                    .any(|arg| arg.get_long() == Some(deprecated.replacement)),
+                "replacement for --{} does not exist",
+                deprecated.name
+            );
+        }
+    }
+
+    #[test]
+    fn loop_requires_file_and_positive_fps() {
+        let cli = Cli::try_parse_from(["wayshot", "--loop", "--fps", "30", "/tmp/cap.fifo"])
+            .expect("valid loop arguments");
+        assert!(cli.loop_frames);
+        assert_eq!(cli.fps, Some(30.0));
+
+        assert!(Cli::try_parse_from(["wayshot", "--loop"]).is_err());
+        assert!(Cli::try_parse_from(["wayshot", "--loop", "--fps", "0", "cap.fifo"]).is_err());
+        assert!(Cli::try_parse_from(["wayshot", "--fps", "30", "cap.fifo"]).is_err());
+    }
+
+    #[cfg(not(feature = "ocr"))]
+    #[test]
+    fn ocr_is_rejected_without_the_feature() {
+        assert!(Cli::try_parse_from(["wayshot", "--ocr"]).is_err());
+        assert!(Cli::try_parse_from(["wayshot", "--ocr", "deu"]).is_err());
+    }
+
+    #[test]
+    fn jobs_requires_separate() {
+        let cli = Cli::try_parse_from(["wayshot", "--separate", "--jobs", "4", "shots/"])
+            .expect("valid separate arguments");
+        assert!(cli.separate);
+        assert_eq!(cli.jobs, Some(4));
+
+        assert!(Cli::try_parse_from(["wayshot", "--jobs", "4"]).is_err());
+        assert!(Cli::try_parse_from(["wayshot", "--separate", "--jobs", "0"]).is_err());
+        assert!(Cli::try_parse_from(["wayshot", "--separate", "-o", "DP-1"]).is_err());
+    }
+
+    #[test]
+    fn pick_modes_conflict_with_other_selections() {
+        let cli = Cli::try_parse_from(["wayshot", "--window-pick"]).expect("window pick only");
+        assert!(cli.window_pick);
+
+        assert!(Cli::try_parse_from(["wayshot", "--window-pick", "--output-pick"]).is_err());
+        assert!(Cli::try_parse_from(["wayshot", "--output-pick", "-g"]).is_err());
+        assert!(Cli::try_parse_from(["wayshot", "--output-pick", "-o", "DP-1"]).is_err());
+    }
+
+    #[test]
+    fn resolution_parses_width_and_height() {
+        let cli = Cli::try_parse_from([
+            "wayshot",
+            "-o",
+            "DP-1",
+            "--resolution",
+            "1920x1080",
+            "--resize-fit",
+            "stretch",
+        ])
+        .expect("valid resolution");
+        let resolution = cli.resolution.expect("resolution");
+        assert_eq!(
+            (resolution.size().width, resolution.size().height),
+            (1920, 1080)
+        );
+        assert_eq!(cli.resize_fit, Some(ResizeFit::Stretch));
+
+        assert!(Cli::try_parse_from(["wayshot", "--resolution", "1920"]).is_err());
+        assert!(Cli::try_parse_from(["wayshot", "--resolution", "0x1080"]).is_err());
+        assert!(Cli::try_parse_from(["wayshot", "--resize-fit", "stretch"]).is_err());
+        assert!(Cli::try_parse_from(["wayshot", "-g", "--resolution", "640x480"]).is_err());
+    }
+
+    #[test]
+    fn wait_for_outputs_defaults_to_ten_seconds() {
+        let cli = Cli::try_parse_from(["wayshot", "--wait-for-outputs"]).expect("flag only");
+        assert_eq!(cli.wait_for_outputs, Some(10.0));
+
+        let cli =
+            Cli::try_parse_from(["wayshot", "--wait-for-outputs", "2.5"]).expect("with seconds");
+        assert_eq!(cli.wait_for_outputs, Some(2.5));
+
+        assert!(Cli::try_parse_from(["wayshot", "--wait-for-outputs", "-1"]).is_err());
+    }
+
+    #[test]
+    fn encode_takes_capture_options_after_the_subcommand() {
+        let cli = Cli::try_parse_from([
+            "wayshot",
+            "encode",
+            "--size",
+            "640x480",
+            "--input-format",
+            "bgra",
+            "--clipboard",
+            "--verify",
+            "out.png",
+        ])
+        .expect("valid encode arguments");
+        let Some(Command::Encode(encode)) = cli.command else {
+            panic!("encode subcommand not parsed");
+        };
+        assert_eq!(encode.size, (640, 480));
+        assert_eq!(encode.input_format, PixelFormat::Bgra8);
+        assert_eq!(encode.file, Some(PathBuf::from("out.png")));
+        assert!(cli.clipboard);
+        assert_eq!(cli.verify, Some(VerifyMode::Sampled));
+        assert_eq!(cli.file, None);
+
+        assert!(Cli::try_parse_from(["wayshot", "encode", "out.png"]).is_err());
+        assert!(Cli::try_parse_from(["wayshot", "encode", "--size", "0x480"]).is_err());
+        assert!(Cli::try_parse_from(["wayshot", "encode", "--size", "1x1", "-g"]).is_err());
+    }
+
+    #[test]
+    fn relative_region_parses_position_and_size() {
+        let cli = Cli::try_parse_from(["wayshot", "--relative-region", "10,-20 400x300"])
+            .expect("valid region");
+        assert_eq!(
+            cli.relative_region,
+            Some(Region {
+                position: Position { x: 10, y: -20 },
+                size: Size {
+                    width: 400,
+                    height: 300
+                },
+            })
+        );
+
+        assert!(Cli::try_parse_from(["wayshot", "--relative-region", "400x300"]).is_err());
+        assert!(Cli::try_parse_from(["wayshot", "--relative-region", "0,0 0x300"]).is_err());
+        assert!(
+            Cli::try_parse_from(["wayshot", "-o", "DP-1", "--relative-region", "0,0 4x3"]).is_ok()
+        );
+        assert!(Cli::try_parse_from(["wayshot", "-g", "--relative-region", "0,0 4x3"]).is_err());
+    }
+
+    #[test]
+    fn only_listed_options_keep_the_frame_as_is() {
+        let parse = |args: &[&str]| Cli::parse_from(["wayshot", "-o", "DP-1"].iter().chain(args));
+        assert!(parse(&["--encoding", "qoi", "--cursor", "shot.qoi"]).keeps_frame_as_is());
+        assert!(parse(&["--exec", "true", "--timings"]).keeps_frame_as_is());
+
+        for args in [
+            &["--thumbnail", "64"][..],
+            &["--shape", "circle"],
+            &["--json"],
+            &["--clipboard"],
+            &["--verify", "full"],
+            &["--relative-region", "0,0 4x3"],
+        ] {
+            assert!(!parse(args).keeps_frame_as_is(), "{args:?}");
+        }
+    }
 }
@@ -1,7 +1,12 @@
-use crate::utils::EncodingFormat;
+use crate::utils::{ClipboardTarget, EncodingFormat};
 use jpegxl_rs::encode::EncoderSpeed;
 use serde::{Deserialize, Serialize};
-use std::{env, io::Read, path::PathBuf};
+use std::{
+    collections::BTreeMap,
+    env,
+    io::{self, Read},
+    path::PathBuf,
+};
 use tracing::Level;
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -9,6 +14,7 @@ pub struct Config {
     pub base: Option<Base>,
     pub file: Option<File>,
     pub encoding: Option<Encoding>,
+    pub actions: Option<BTreeMap<String, Action>>,
 }
 
 impl Default for Config {
@@ -17,17 +23,118 @@ impl Default for Config {
             base: Some(Base::default()),
             file: Some(File::default()),
             encoding: Some(Encoding::default()),
+            actions: None,
         }
     }
 }
 
+/// Known keys of every table in the config file, used to warn about typos.
+const CONFIG_KEYS: &[(&str, &[&str])] = &[
+    ("", &["base", "file", "encoding", "actions"]),
+    (
+        "base",
+        &[
+            "output",
+            "cursor",
+            "clipboard",
+            "clipboard_target",
+            "file",
+            "stdout",
+            "log_level",
+            "notifications",
+            "flash",
+            "bell",
+            "warn_on_uniform",
+            "warn_on_large",
+        ],
+    ),
+    ("file", &["path", "name_format", "encoding"]),
+    ("encoding", &["jxl"]),
+    ("encoding.jxl", &["lossless", "distance", "effort"]),
+    // Every `[actions.<name>]` table.
+    (
+        "actions.*",
+        &[
+            "capture",
+            "output",
+            "cursor",
+            "clipboard",
+            "clipboard_target",
+            "file",
+            "stdout",
+            "encoding",
+        ],
+    ),
+];
+
+/// The loaded config together with the problems found in it.
+#[derive(Debug)]
+pub struct LoadedConfig {
+    pub config: Config,
+    pub warnings: Vec<String>,
+}
+
 impl Config {
-    pub fn load(path: &PathBuf) -> Option<Config> {
-        let mut config_file = std::fs::File::open(path).ok()?;
+    /// Load the config at `path`, falling back to the default config if it
+    /// doesn't exist or can't be parsed.
+    ///
+    /// Unknown keys are ignored but reported in [`LoadedConfig::warnings`].
+    pub fn load(path: &PathBuf) -> LoadedConfig {
+        let mut warnings = Vec::new();
+        let config = match Self::read(path) {
+            Ok(Some(config_str)) => Self::parse(&config_str, &mut warnings).unwrap_or_else(|e| {
+                warnings.push(format!("failed to parse {}: {e}", path.display()));
+                Config::default()
+            }),
+            Ok(None) => Config::default(),
+            Err(e) => {
+                warnings.push(format!("failed to read {}: {e}", path.display()));
+                Config::default()
+            }
+        };
+
+        LoadedConfig { config, warnings }
+    }
+
+    fn read(path: &PathBuf) -> io::Result<Option<String>> {
+        let mut config_file = match std::fs::File::open(path) {
+            Ok(config_file) => config_file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
         let mut config_str = String::new();
-        config_file.read_to_string(&mut config_str).ok()?;
+        config_file.read_to_string(&mut config_str)?;
+        Ok(Some(config_str))
+    }
 
-        toml::from_str(&config_str).ok()?
+    fn parse(config_str: &str, warnings: &mut Vec<String>) -> Result<Config, toml::de::Error> {
+        let table: toml::Table = toml::from_str(config_str)?;
+        validate_keys(&table, "", warnings);
+        table.try_into()
+    }
+
+    /// The action named `name`, with the names of all actions in the error if there is none.
+    pub fn action(&self, name: &str) -> Result<&Action, String> {
+        let actions = self.actions.as_ref();
+        if let Some(action) = actions.and_then(|actions| actions.get(name)) {
+            return Ok(action);
+        }
+        let names: Vec<&str> = actions
+            .into_iter()
+            .flat_map(|actions| actions.keys().map(String::as_str))
+            .collect();
+        if names.is_empty() {
+            return Err(format!(
+                "unknown action `{name}`, the config file defines no [actions.<name>] tables"
+            ));
+        }
+        let suggestion = closest_key(name, &names)
+            .map(|known| format!(", did you mean `{known}`?"))
+            .unwrap_or_else(|| ".".to_string());
+        Err(format!(
+            "unknown action `{name}`{suggestion} Known actions: {}",
+            names.join(", ")
+        ))
     }
 
     pub fn get_default_path() -> PathBuf {
@@ -42,10 +149,15 @@ pub struct Base {
     pub output: Option<String>,
     pub cursor: Option<bool>,
     pub clipboard: Option<bool>,
+    pub clipboard_target: Option<ClipboardTarget>,
     pub file: Option<bool>,
     pub stdout: Option<bool>,
     pub log_level: Option<String>,
     pub notifications: Option<bool>,
+    pub flash: Option<bool>,
+    pub bell: Option<bool>,
+    pub warn_on_uniform: Option<bool>,
+    pub warn_on_large: Option<bool>,
 }
 
 impl Default for Base {
@@ -54,10 +166,15 @@ impl Default for Base {
             output: None,
             cursor: Some(false),
             clipboard: Some(false),
+            clipboard_target: Some(ClipboardTarget::Regular),
             file: Some(true),
             stdout: Some(false),
             log_level: Some("info".to_string()),
             notifications: Some(true),
+            flash: Some(false),
+            bell: Some(false),
+            warn_on_uniform: Some(false),
+            warn_on_large: Some(false),
         }
     }
 }
@@ -94,6 +211,48 @@ impl Default for File {
     }
 }
 
+/// What an action captures.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ActionCapture {
+    /// All outputs, or `output` if the action sets it.
+    #[default]
+    Full,
+    /// A region selected like `--geometry`.
+    Region,
+    /// A window selected like `--window-pick`.
+    Window,
+    /// An output selected like `--output-pick`.
+    Output,
+}
+
+/// A named capture plan from an `[actions.<name>]` table, run with `--action <name>`.
+///
+/// Set keys override `[base]` and `[file]`, CLI options still take precedence.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Action {
+    pub capture: Option<ActionCapture>,
+    pub output: Option<String>,
+    pub cursor: Option<bool>,
+    pub clipboard: Option<bool>,
+    pub clipboard_target: Option<ClipboardTarget>,
+    pub file: Option<bool>,
+    pub stdout: Option<bool>,
+    pub encoding: Option<EncodingFormat>,
+}
+
+impl Action {
+    pub fn apply(&self, base: &mut Base, file: &mut File) {
+        base.output = self.output.clone().or(base.output.take());
+        base.cursor = self.cursor.or(base.cursor);
+        base.clipboard = self.clipboard.or(base.clipboard);
+        base.clipboard_target = self.clipboard_target.or(base.clipboard_target);
+        base.file = self.file.or(base.file);
+        base.stdout = self.stdout.or(base.stdout);
+        file.encoding = self.encoding.or(file.encoding);
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Encoding {
     pub jxl: Option<Jxl>,
@@ -153,3 +312,213 @@ impl Jxl {
         }
     }
 }
+
+/// Push a warning for every key in `table` (found at `path`) that wayshot doesn't know about.
+fn validate_keys(table: &toml::Table, path: &str, warnings: &mut Vec<String>) {
+    validate_keys_as(table, path, path, warnings);
+}
+
+/// Like [`validate_keys`], looking up the known keys under `schema_path` since
+/// action tables are named by the user.
+fn validate_keys_as(
+    table: &toml::Table,
+    path: &str,
+    schema_path: &str,
+    warnings: &mut Vec<String>,
+) {
+    if path == "actions" {
+        for (name, value) in table {
+            match value {
+                toml::Value::Table(inner) => {
+                    validate_keys_as(inner, &format!("actions.{name}"), "actions.*", warnings)
+                }
+                _ => warnings.push(format!("config key `actions.{name}` must be a table")),
+            }
+        }
+        return;
+    }
+    let Some((_, known_keys)) = CONFIG_KEYS
+        .iter()
+        .find(|(table_path, _)| *table_path == schema_path)
+    else {
+        return;
+    };
+    for (key, value) in table {
+        let key_path = if path.is_empty() {
+            key.clone()
+        } else {
+            format!("{path}.{key}")
+        };
+        if !known_keys.contains(&key.as_str()) {
+            let suggestion = closest_key(key, known_keys)
+                .map(|known| format!(", did you mean `{known}`?"))
+                .unwrap_or_default();
+            warnings.push(format!("unknown config key `{key_path}`{suggestion}"));
+        } else if let toml::Value::Table(inner) = value
+            && schema_path != "actions.*"
+        {
+            validate_keys(inner, &key_path, warnings);
+        }
+    }
+}
+
+/// The known key closest to `key`, if it is close enough to likely be a typo.
+fn closest_key<'a>(key: &str, known_keys: &[&'a str]) -> Option<&'a str> {
+    known_keys
+        .iter()
+        .map(|known| (levenshtein(key, known), *known))
+        .filter(|(distance, known)| *distance <= 2.max(known.len() / 3))
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, known)| known)
+}
+
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a_char != *b_char);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(config_str: &str) -> (Config, Vec<String>) {
+        let mut warnings = Vec::new();
+        let config = Config::parse(config_str, &mut warnings).expect("valid toml");
+        (config, warnings)
+    }
+
+    #[test]
+    fn levenshtein_counts_edits() {
+        assert_eq!(levenshtein("cursor", "cursor"), 0);
+        assert_eq!(levenshtein("cusor", "cursor"), 1);
+        assert_eq!(levenshtein("kitten", "sitting"), 3);
+        assert_eq!(levenshtein("", "abc"), 3);
+    }
+
+    #[test]
+    fn known_keys_produce_no_warnings() {
+        let (config, warnings) = parse(
+            r#"
+            [base]
+            cursor = true
+            [encoding.jxl]
+            effort = 3
+            "#,
+        );
+
+        assert!(warnings.is_empty(), "{warnings:?}");
+        assert_eq!(config.base.and_then(|base| base.cursor), Some(true));
+    }
+
+    #[test]
+    fn unknown_key_warns_with_suggestion_and_is_ignored() {
+        let (config, warnings) = parse(
+            r#"
+            [base]
+            cusor = true
+            clipboard = true
+            "#,
+        );
+
+        assert_eq!(
+            warnings,
+            ["unknown config key `base.cusor`, did you mean `cursor`?"]
+        );
+        assert_eq!(config.base.and_then(|base| base.clipboard), Some(true));
+    }
+
+    #[test]
+    fn unknown_key_without_close_match_has_no_suggestion() {
+        let (_, warnings) = parse(
+            r#"
+            [encoding.jxl]
+            frobnicate = 1
+            "#,
+        );
+
+        assert_eq!(warnings, ["unknown config key `encoding.jxl.frobnicate`"]);
+    }
+
+    #[test]
+    fn unknown_table_warns() {
+        let (_, warnings) = parse(
+            r#"
+            [fil]
+            path = "/tmp"
+            "#,
+        );
+
+        assert_eq!(warnings, ["unknown config key `fil`, did you mean `file`?"]);
+    }
+
+    #[test]
+    fn actions_are_parsed_and_validated() {
+        let (config, warnings) = parse(
+            r#"
+            [actions.full]
+            clipboard = true
+            [actions.region]
+            capture = "region"
+            encodng = "jpg"
+            "#,
+        );
+
+        assert_eq!(
+            warnings,
+            ["unknown config key `actions.region.encodng`, did you mean `encoding`?"]
+        );
+        let region = config.action("region").unwrap();
+        assert_eq!(region.capture, Some(ActionCapture::Region));
+        assert_eq!(config.action("full").unwrap().clipboard, Some(true));
+    }
+
+    #[test]
+    fn unknown_action_lists_known_actions() {
+        let (config, _) = parse(
+            r#"
+            [actions.full]
+            [actions.region]
+            capture = "region"
+            "#,
+        );
+
+        assert_eq!(
+            config.action("regoin").unwrap_err(),
+            "unknown action `regoin`, did you mean `region`? Known actions: full, region"
+        );
+        assert_eq!(
+            config.action("clipboard").unwrap_err(),
+            "unknown action `clipboard`. Known actions: full, region"
+        );
+        assert!(Config::default().action("full").is_err());
+    }
+
+    #[test]
+    fn action_overrides_config() {
+        let (config, _) = parse(
+            r#"
+            [actions.clip]
+            clipboard = true
+            file = false
+            encoding = "jpg"
+            "#,
+        );
+        let mut base = Base::default();
+        let mut file = File::default();
+        config.action("clip").unwrap().apply(&mut base, &mut file);
+
+        assert_eq!(base.clipboard, Some(true));
+        assert_eq!(base.file, Some(false));
+        assert_eq!(base.cursor, Some(false));
+        assert_eq!(file.encoding, Some(EncodingFormat::Jpg));
+    }
+}
@@ -0,0 +1,31 @@
+//! The wayshot command line tool as a library, for frontends that script
+//! captures without spawning `wayshot`.
+//!
+//! A [`Plan`] is resolved from the command line options and the config file,
+//! and [`run`] carries it out, printing what `wayshot` would to the given
+//! writer:
+//!
+//! ```no_run
+//! use clap::Parser;
+//! use wayshot_core::{Plan, cli::Cli, run};
+//!
+//! let plan = Plan::resolve(Cli::parse_from(["wayshot", "--output", "DP-1", "shot.png"]))?;
+//! let outcome = run(plan, &mut std::io::stdout())?;
+//! println!("{}", serde_json::to_string(&outcome)?);
+//! # Ok::<(), eyre::Report>(())
+//! ```
+
+pub mod cli;
+pub mod config;
+mod metadata;
+mod plan;
+mod preview;
+mod progress;
+pub mod report;
+mod run;
+mod selection;
+mod utils;
+
+pub use plan::{Outcome, Plan};
+pub use run::{diagnostics, run};
+pub use utils::{ClipboardImage, ClipboardTarget, EncodingFormat, ShotResult};
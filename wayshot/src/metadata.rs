@@ -0,0 +1,384 @@
+use std::{
+    fmt,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, Local};
+use eyre::{Result, WrapErr};
+use libwayshot::{
+    CaptureId, ChecksumAlgorithm, CompositorInfo, EnvironmentInfo, FrameStats, LogicalRegion,
+    OutputInfo, WayshotConnection, reexport::Transform,
+};
+use serde::Serialize;
+
+use crate::utils::{EncodingFormat, write_atomically};
+
+/// Description of a capture, written next to the image by `--sidecar`.
+#[derive(Debug, Clone, Serialize)]
+pub struct CaptureMetadata {
+    pub wayshot_version: &'static str,
+    /// RFC 3339 timestamp of the start of the capture.
+    pub captured_at: String,
+    pub format: EncodingFormat,
+    pub width: u32,
+    pub height: u32,
+    /// Captured area in logical coordinates, absent for toplevel captures.
+    pub region: Option<RegionMetadata>,
+    pub outputs: Vec<OutputMetadata>,
+    /// Digests of the saved files with `--checksum`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub checksums: Vec<ChecksumMetadata>,
+    /// Id of the capture as in the log, absent for `wayshot encode`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub capture_id: Option<CaptureId>,
+    /// The compositor and session, only in the `--json` output.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub diagnostics: Option<Diagnostics>,
+}
+
+/// The compositor and session wayshot runs in, for bug reports. Printed by
+/// `wayshot check` and when a screenshot fails.
+#[derive(Debug, Clone, Serialize)]
+pub struct Diagnostics {
+    pub compositor: CompositorInfo,
+    pub environment: EnvironmentInfo,
+}
+
+impl Diagnostics {
+    /// Queries the diagnostics of `conn`, which takes a roundtrip the first time.
+    pub fn of(conn: &WayshotConnection) -> Self {
+        Self {
+            compositor: conn.compositor_info(),
+            environment: conn.environment_info().clone(),
+        }
+    }
+}
+
+impl fmt::Display for Diagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}\n{}", self.compositor, self.environment)
+    }
+}
+
+/// Digest of a saved file, also written next to it by `--checksum`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ChecksumMetadata {
+    pub file: PathBuf,
+    pub algorithm: String,
+    pub digest: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct RegionMetadata {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct OutputMetadata {
+    pub name: String,
+    pub description: String,
+    pub region: RegionMetadata,
+    pub scale: f64,
+    pub transform: &'static str,
+    /// The workspace active on the output, only known for `--separate` captures
+    /// on compositors offering ext-workspace.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub workspace: Option<String>,
+}
+
+impl From<LogicalRegion> for RegionMetadata {
+    fn from(region: LogicalRegion) -> Self {
+        Self {
+            x: region.inner.position.x,
+            y: region.inner.position.y,
+            width: region.inner.size.width,
+            height: region.inner.size.height,
+        }
+    }
+}
+
+impl From<&OutputInfo> for OutputMetadata {
+    fn from(output: &OutputInfo) -> Self {
+        Self {
+            name: output.name.clone(),
+            description: output.description.clone(),
+            region: output.logical_region.into(),
+            scale: output.scale(),
+            transform: transform_name(output.transform),
+            workspace: None,
+        }
+    }
+}
+
+fn transform_name(transform: Transform) -> &'static str {
+    match transform {
+        Transform::Normal => "normal",
+        Transform::_90 => "90",
+        Transform::_180 => "180",
+        Transform::_270 => "270",
+        Transform::Flipped => "flipped",
+        Transform::Flipped90 => "flipped-90",
+        Transform::Flipped180 => "flipped-180",
+        Transform::Flipped270 => "flipped-270",
+        _ => "unknown",
+    }
+}
+
+impl CaptureMetadata {
+    pub fn new(
+        captured_at: DateTime<Local>,
+        format: EncodingFormat,
+        (width, height): (u32, u32),
+        region: Option<LogicalRegion>,
+        outputs: &[&OutputInfo],
+    ) -> Self {
+        Self {
+            wayshot_version: env!("CARGO_PKG_VERSION"),
+            captured_at: captured_at.to_rfc3339(),
+            format,
+            width,
+            height,
+            region: region.map(RegionMetadata::from),
+            outputs: outputs.iter().map(|output| (*output).into()).collect(),
+            checksums: Vec::new(),
+            capture_id: None,
+            diagnostics: None,
+        }
+    }
+}
+
+impl ChecksumMetadata {
+    pub fn new(file: &Path, algorithm: ChecksumAlgorithm, digest: String) -> Self {
+        Self {
+            file: file.to_owned(),
+            algorithm: algorithm.to_string(),
+            digest,
+        }
+    }
+
+    /// Path of the checksum file, the saved file with the algorithm appended,
+    /// e.g. `shot.png.sha256`.
+    pub fn path(&self) -> PathBuf {
+        let mut path = self.file.clone().into_os_string();
+        path.push(".");
+        path.push(&self.algorithm);
+        path.into()
+    }
+}
+
+/// Luminance and colour statistics of an output, printed by `--stats`.
+#[derive(Debug, Clone, Serialize)]
+pub struct StatsReport {
+    pub output: String,
+    pub mean_luminance: f64,
+    pub median_luminance: u8,
+    pub p5_luminance: u8,
+    pub p95_luminance: u8,
+    /// Dominant colour as `#rrggbb`.
+    pub dominant_color: String,
+}
+
+impl StatsReport {
+    pub fn new(output: &str, stats: &FrameStats) -> Self {
+        let [r, g, b] = stats.dominant_color;
+        Self {
+            output: output.to_string(),
+            mean_luminance: stats.mean_luminance,
+            median_luminance: stats.median_luminance(),
+            p5_luminance: stats.luminance_percentile(5.0),
+            p95_luminance: stats.luminance_percentile(95.0),
+            dominant_color: format!("#{r:02x}{g:02x}{b:02x}"),
+        }
+    }
+}
+
+impl fmt::Display for StatsReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "output: {}", self.output)?;
+        writeln!(f, "mean luminance: {:.1}", self.mean_luminance)?;
+        writeln!(f, "median luminance: {}", self.median_luminance)?;
+        writeln!(
+            f,
+            "5th/95th percentile luminance: {}/{}",
+            self.p5_luminance, self.p95_luminance
+        )?;
+        write!(f, "dominant color: {}", self.dominant_color)
+    }
+}
+
+/// Path of the sidecar file belonging to `image_path`.
+pub fn sidecar_path(image_path: &Path) -> PathBuf {
+    image_path.with_extension("json")
+}
+
+/// Write `metadata` as JSON next to `image_path`, see [`write_atomically`].
+pub fn write_sidecar(image_path: &Path, metadata: &CaptureMetadata) -> Result<PathBuf> {
+    let path = sidecar_path(image_path);
+    write_atomically(&path, |file| {
+        serde_json::to_writer_pretty(&mut *file, metadata)?;
+        file.write_all(b"\n")?;
+        Ok(())
+    })
+    .wrap_err_with(|| format!("failed to write sidecar {}", path.display()))?;
+    Ok(path)
+}
+
+/// Write `checksum` next to its file in the format of `sha256sum` and
+/// `b3sum`, so `sha256sum -c shot.png.sha256` checks it from its directory.
+pub fn write_checksum(checksum: &ChecksumMetadata) -> Result<PathBuf> {
+    let path = checksum.path();
+    let file_name = checksum.file.file_name().unwrap_or_default();
+    write_atomically(&path, |file| {
+        writeln!(file, "{}  {}", checksum.digest, file_name.to_string_lossy())?;
+        Ok(())
+    })
+    .wrap_err_with(|| format!("failed to write checksum {}", path.display()))?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use std::{env, fs};
+
+    fn metadata() -> CaptureMetadata {
+        let captured_at = Local.with_ymd_and_hms(2024, 5, 6, 7, 8, 9).unwrap();
+        CaptureMetadata::new(
+            captured_at,
+            EncodingFormat::Png,
+            (200, 100),
+            Some(LogicalRegion::new(10, 20, 200, 100)),
+            &[],
+        )
+    }
+
+    #[test]
+    fn sidecar_path_replaces_extension() {
+        assert_eq!(
+            sidecar_path(Path::new("/tmp/shot.png")),
+            PathBuf::from("/tmp/shot.json")
+        );
+    }
+
+    #[test]
+    fn metadata_serializes_expected_fields() {
+        let json = serde_json::to_value(metadata()).expect("serialize");
+
+        assert_eq!(json["wayshot_version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(json["format"], "png");
+        assert_eq!(json["width"], 200);
+        assert_eq!(
+            json["region"],
+            serde_json::json!({"x": 10, "y": 20, "width": 200, "height": 100})
+        );
+        assert_eq!(json["outputs"], serde_json::json!([]));
+        assert!(
+            json["captured_at"]
+                .as_str()
+                .unwrap()
+                .starts_with("2024-05-06T07:08:09")
+        );
+        assert!(json.get("diagnostics").is_none());
+    }
+
+    #[test]
+    fn json_metadata_carries_the_diagnostics() {
+        let mut metadata = metadata();
+        metadata.diagnostics = Some(Diagnostics {
+            compositor: CompositorInfo {
+                name: Some("sway".to_string()),
+                screencopy_version: Some(3),
+                ..CompositorInfo::default()
+            },
+            environment: EnvironmentInfo {
+                wl_shm_version: Some(1),
+                ..EnvironmentInfo::default()
+            },
+        });
+        let json = serde_json::to_value(metadata).expect("serialize");
+        assert_eq!(json["diagnostics"]["compositor"]["name"], "sway");
+        assert_eq!(json["diagnostics"]["compositor"]["screencopy_version"], 3);
+        assert_eq!(json["diagnostics"]["environment"]["wl_shm_version"], 1);
+        assert!(json["diagnostics"]["environment"]["seat_name"].is_null());
+    }
+
+    #[test]
+    fn workspaces_are_only_listed_when_known() {
+        let mut output = OutputMetadata {
+            name: "DP-1".to_string(),
+            description: String::new(),
+            region: LogicalRegion::new(0, 0, 1920, 1080).into(),
+            scale: 1.0,
+            transform: "normal",
+            workspace: None,
+        };
+        assert!(
+            serde_json::to_value(&output)
+                .unwrap()
+                .get("workspace")
+                .is_none()
+        );
+
+        output.workspace = Some("2: web".to_string());
+        assert_eq!(
+            serde_json::to_value(&output).unwrap()["workspace"],
+            "2: web"
+        );
+    }
+
+    #[test]
+    fn checksum_files_are_checkable_by_sha256sum() {
+        let dir = env::temp_dir().join(format!("wayshot-checksum-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let image_path = dir.join("shot.png");
+        let mut file = fs::File::create(&image_path).unwrap();
+        let digest =
+            crate::utils::write_hashed(&mut file, Some(ChecksumAlgorithm::Sha256), |file| {
+                Ok(file.write_all(b"abc")?)
+            })
+            .expect("write")
+            .expect("digest");
+        assert_eq!(fs::read(&image_path).unwrap(), b"abc");
+
+        let checksum = ChecksumMetadata::new(&image_path, ChecksumAlgorithm::Sha256, digest);
+        let path = write_checksum(&checksum).expect("write checksum");
+        assert_eq!(path, dir.join("shot.png.sha256"));
+        // The FIPS 180-2 digest of "abc", relative to the checksum file.
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad  shot.png\n"
+        );
+
+        let mut metadata = metadata();
+        assert!(
+            serde_json::to_value(&metadata)
+                .unwrap()
+                .get("checksums")
+                .is_none()
+        );
+        metadata.checksums.push(checksum);
+        let json = serde_json::to_value(&metadata).unwrap();
+        assert_eq!(json["checksums"][0]["algorithm"], "sha256");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn write_sidecar_leaves_no_temporary_file() {
+        let dir = env::temp_dir().join(format!("wayshot-sidecar-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let image_path = dir.join("shot.png");
+
+        let path = write_sidecar(&image_path, &metadata()).expect("write sidecar");
+
+        let written: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(written["height"], 100);
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,406 @@
+use std::{
+    env,
+    path::{Path, PathBuf},
+};
+
+use eyre::{Result, bail};
+use libwayshot::{CaptureId, LogicalRegion};
+use serde::{Deserialize, Serialize};
+use tracing::Level;
+
+use crate::{
+    cli::{Cli, Command, EncodeArgs},
+    config::{ActionCapture, Config, Jxl, LoadedConfig},
+    utils::{self, ClipboardImage, ClipboardTarget, EncodingFormat, ShotResult},
+};
+
+/// What a wayshot run does, resolved from the command line and the config
+/// file, see [`Plan::resolve`] and [`crate::run`].
+///
+/// The settings the config file can change are resolved into fields of their
+/// own. Everything else is taken from [`Self::options`] as given.
+///
+/// Only the resolved fields are serialized. A deserialized plan runs with the
+/// [`Self::options`] of a plain `wayshot`, so frontends that pass it around
+/// only keep what the config file can change.
+#[derive(Serialize, Deserialize)]
+pub struct Plan {
+    /// The command line, with the selection of an `--action` applied.
+    #[serde(skip)]
+    pub options: Cli,
+    /// Raw pixels to encode instead of capturing, see `wayshot encode`.
+    pub encode: Option<EncodeArgs>,
+    /// Print the diagnostics instead of capturing, see `wayshot check`.
+    pub check: bool,
+    /// The `--display` to capture, if it isn't the one of this session.
+    pub foreign_display: Option<String>,
+    pub config_path: PathBuf,
+    /// Problems found in the config file, see `--strict-config`.
+    pub config_warnings: Vec<String>,
+    #[serde(with = "as_string")]
+    pub log_level: Level,
+    pub cursor: bool,
+    pub clipboard: bool,
+    pub clipboard_target: ClipboardTarget,
+    pub encoding: EncodingFormat,
+    pub jxl: Jxl,
+    /// Where the image is saved, with the name format expanded.
+    pub file: Option<PathBuf>,
+    /// Whether the encoded image is written to stdout.
+    pub stdout: bool,
+    pub output: Option<String>,
+    pub notifications: bool,
+    pub flash: bool,
+    pub bell: bool,
+    pub warn_on_uniform: bool,
+    pub warn_on_large: bool,
+}
+
+/// What a [`crate::run`] did.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Outcome {
+    /// What was captured, none for the listings, `--stats`, `--loop` and
+    /// `wayshot encode`.
+    pub shot: Option<ShotResult>,
+    /// The selected region, or the one of the captured outputs.
+    pub region: Option<LogicalRegion>,
+    /// Id of the last frame captured, as in the log and the metadata.
+    pub capture_id: Option<CaptureId>,
+    /// Every file an image was saved to, tiles and `--separate` outputs included.
+    pub files: Vec<PathBuf>,
+    /// The image to offer on the clipboard, which the caller has to keep
+    /// serving, see [`crate::run`].
+    #[serde(skip)]
+    pub clipboard: Option<ClipboardImage>,
+    /// What `--timings` measured, a line each, for the caller to print.
+    pub timings: Vec<String>,
+}
+
+impl Plan {
+    /// Merge `cli` with the config file it names, or the default one, and the
+    /// `--action` it selects. The command line takes precedence.
+    pub fn resolve(mut cli: Cli) -> Result<Self> {
+        // `wayshot encode` saves its image like a capture, to its own FILE.
+        let (encode, check) = match cli.command.take() {
+            Some(Command::Encode(mut encode)) => {
+                cli.file = encode.file.take().or(cli.file.take());
+                (Some(encode), false)
+            }
+            Some(Command::Check) => (None, true),
+            Some(Command::Trigger { action }) => {
+                cli.action = Some(action);
+                (None, cli.environment_info)
+            }
+            None => (None, cli.environment_info),
+        };
+        // The session environment (notifications, window IPC) belongs to WAYLAND_DISPLAY,
+        // not to a display chosen with --display.
+        let session_display = env::var("WAYLAND_DISPLAY").ok();
+        let runtime_dir = env::var_os("XDG_RUNTIME_DIR").map(PathBuf::from);
+        let foreign_display = cli.display.clone().filter(|display| {
+            !is_session_display(display, session_display.as_deref(), runtime_dir.as_deref())
+        });
+        let config_path = cli.config.clone().unwrap_or(Config::get_default_path());
+        let LoadedConfig {
+            config,
+            warnings: config_warnings,
+        } = Config::load(&config_path);
+        let mut base = config.base.clone().unwrap_or_default();
+        let mut file = config.file.clone().unwrap_or_default();
+        let encoding_config = config.encoding.clone().unwrap_or_default();
+
+        if let Some(name) = &cli.action {
+            let action = config.action(name).map_err(|e| eyre::eyre!(e))?;
+            action.apply(&mut base, &mut file);
+            // A selection given on the command line replaces the action's.
+            let selection_given = cli.geometry
+                || cli.window_pick
+                || cli.output_pick
+                || cli.output.is_some()
+                || cli.choose_output
+                || cli.toplevel.is_some()
+                || cli.choose_toplevel
+                || cli.relative_region.is_some();
+            if !selection_given {
+                match action.capture.unwrap_or_default() {
+                    ActionCapture::Full => {}
+                    ActionCapture::Region => cli.geometry = true,
+                    ActionCapture::Window => cli.window_pick = true,
+                    ActionCapture::Output => cli.output_pick = true,
+                }
+            }
+        }
+
+        let encoding = cli
+            .encoding
+            .or(input_encoding(&cli))
+            .unwrap_or(file.encoding.unwrap_or_default());
+
+        let file_name_format = cli.file_name_format.clone().unwrap_or(
+            file.name_format
+                .unwrap_or("wayshot-%Y_%m_%d-%H_%M_%S".to_string()),
+        );
+        if cli.ocr.is_some() && cli.file.as_deref() == Some(Path::new("-")) {
+            bail!(
+                "--ocr prints the recognized text to stdout, the image can't be written there too"
+            );
+        }
+        // With --ocr stdout is for the text and the image is only kept when FILE asks for it,
+        // even when the config writes images to stdout or a file.
+        let mut stdout = base.stdout.unwrap_or_default() && cli.ocr.is_none();
+        let file = cli
+            .file
+            .as_ref()
+            .and_then(|pathbuf| {
+                if pathbuf.to_string_lossy() == "-" {
+                    stdout = true;
+                    None
+                } else {
+                    Some(utils::get_full_file_name(
+                        pathbuf,
+                        &file_name_format,
+                        encoding,
+                    ))
+                }
+            })
+            .or_else(|| {
+                if base.file.unwrap_or_default() && cli.ocr.is_none() {
+                    let dir = file
+                        .path
+                        .unwrap_or_else(|| env::current_dir().unwrap_or_default());
+                    Some(utils::get_full_file_name(&dir, &file_name_format, encoding))
+                } else {
+                    None
+                }
+            });
+
+        // Feedback enabled in the config stays out of pipelines, only the flags force it there.
+        let pipeline = stdout || cli.json;
+        if pipeline && cli.progress_fd == Some(1) {
+            bail!(
+                "--progress-fd 1 would mix progress lines into the output on stdout, use another descriptor such as 2"
+            );
+        }
+        Ok(Self {
+            log_level: cli.log_level.unwrap_or(base.get_log_level()),
+            cursor: cli.cursor || base.cursor.unwrap_or_default(),
+            clipboard: cli.clipboard || base.clipboard.unwrap_or_default(),
+            clipboard_target: cli
+                .clipboard_target
+                .or(base.clipboard_target)
+                .unwrap_or_default(),
+            encoding,
+            jxl: encoding_config.jxl.unwrap_or_default(),
+            file,
+            stdout,
+            output: cli.output.clone().or(base.output),
+            // Notifications would show up in the session of WAYLAND_DISPLAY instead.
+            notifications: base.notifications.unwrap_or(true) && foreign_display.is_none(),
+            flash: cli.flash || (base.flash.unwrap_or_default() && !pipeline),
+            bell: cli.bell || (base.bell.unwrap_or_default() && !pipeline),
+            warn_on_uniform: cli.warn_on_uniform || base.warn_on_uniform.unwrap_or_default(),
+            warn_on_large: cli.warn_on_large || base.warn_on_large.unwrap_or_default(),
+            foreign_display,
+            config_path,
+            config_warnings,
+            options: cli,
+            encode,
+            check,
+        })
+    }
+}
+
+/// The encoding of the FILE given on the command line, by its extension.
+pub(crate) fn input_encoding(cli: &Cli) -> Option<EncodingFormat> {
+    cli.file
+        .as_ref()
+        .and_then(|pathbuf| pathbuf.try_into().ok())
+}
+
+/// Whether `display` is the socket of the session's `WAYLAND_DISPLAY`. Both are
+/// resolved in `runtime_dir` like [`libwayshot::WayshotConnection::connect_to`]
+/// resolves them, so a name and the path of its socket are the same display.
+fn is_session_display(display: &str, session: Option<&str>, runtime_dir: Option<&Path>) -> bool {
+    let socket = |display: &str| {
+        let path = match runtime_dir {
+            // Joining an absolute path replaces the directory.
+            Some(runtime_dir) => runtime_dir.join(display),
+            None => PathBuf::from(display),
+        };
+        path.canonicalize().unwrap_or(path)
+    };
+    session.is_some_and(|session| socket(session) == socket(display))
+}
+
+/// Serde for values written as on the command line, such as the log level.
+pub(crate) mod as_string {
+    use std::{fmt::Display, str::FromStr};
+
+    use serde::{Deserialize, Deserializer, Serializer, de::Error};
+
+    pub fn serialize<T: Display, S: Serializer>(
+        value: &T,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(value)
+    }
+
+    pub fn deserialize<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+    where
+        T: FromStr,
+        T::Err: Display,
+        D: Deserializer<'de>,
+    {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(D::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use clap::Parser;
+
+    use super::*;
+
+    fn resolve(config: &str, args: &[&str]) -> Plan {
+        resolve_with(config, args, |_| {})
+    }
+
+    /// Like [`resolve`], with `adjust` applied to the parsed options, e.g. to set
+    /// options of features this build doesn't have.
+    fn resolve_with(config: &str, args: &[&str], adjust: impl FnOnce(&mut Cli)) -> Plan {
+        try_resolve_with(config, args, adjust).expect("resolve")
+    }
+
+    /// Like [`resolve`] for plans that may be invalid.
+    fn try_resolve(config: &str, args: &[&str]) -> Result<Plan> {
+        try_resolve_with(config, args, |_| {})
+    }
+
+    /// Resolve `args` with `config` as the config file, so the tests don't
+    /// depend on the config of the user running them.
+    fn try_resolve_with(
+        config: &str,
+        args: &[&str],
+        adjust: impl FnOnce(&mut Cli),
+    ) -> Result<Plan> {
+        let dir = env::temp_dir().join(format!("wayshot-plan-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        // Tests run in parallel, each resolve gets its own config file.
+        static CONFIGS: AtomicUsize = AtomicUsize::new(0);
+        let config_path = dir.join(format!("{}.toml", CONFIGS.fetch_add(1, Ordering::Relaxed)));
+        std::fs::write(&config_path, config).unwrap();
+        let config_arg = format!("--config={}", config_path.display());
+        let mut cli = Cli::parse_from(["wayshot", &config_arg].iter().chain(args));
+        adjust(&mut cli);
+        let plan = Plan::resolve(cli);
+        std::fs::remove_file(config_path).unwrap();
+        plan
+    }
+
+    #[test]
+    fn command_line_overrides_the_config_and_action() {
+        let config = r#"
+            [base]
+            cursor = true
+            clipboard = false
+            flash = true
+            output = "DP-1"
+
+            [file]
+            encoding = "jpg"
+
+            [actions.quick]
+            capture = "region"
+            clipboard = true
+            output = "eDP-1"
+        "#;
+
+        let plan = resolve(config, &[]);
+        assert!(plan.cursor && !plan.clipboard && plan.flash);
+        assert_eq!(plan.output.as_deref(), Some("DP-1"));
+        assert_eq!(plan.encoding, EncodingFormat::Jpg);
+        assert!(plan.config_warnings.is_empty());
+
+        let plan = resolve(config, &["--action", "quick", "--encoding", "png"]);
+        assert!(plan.clipboard && plan.options.geometry);
+        let triggered = resolve(config, &["trigger", "quick"]);
+        assert!(triggered.clipboard && triggered.options.geometry);
+        assert_eq!(triggered.options.action.as_deref(), Some("quick"));
+        assert_eq!(plan.output.as_deref(), Some("eDP-1"));
+        assert_eq!(plan.encoding, EncodingFormat::Png);
+
+        // Writing to stdout keeps the configured flash out of the pipeline.
+        let plan = resolve(config, &["-", "--output", "HDMI-A-1"]);
+        assert!(plan.stdout && plan.file.is_none() && !plan.flash);
+        assert_eq!(plan.output.as_deref(), Some("HDMI-A-1"));
+
+        assert!(try_resolve(config, &["--action", "slow"]).is_err());
+    }
+
+    #[test]
+    fn ocr_only_saves_the_image_to_an_explicit_file() {
+        let config = "[base]\nfile = true\nstdout = true";
+        let ocr = |cli: &mut Cli| cli.ocr = Some("eng".to_string());
+        let plan = resolve_with(config, &[], ocr);
+        assert!(plan.file.is_none());
+        assert!(!plan.stdout);
+        assert!(resolve_with(config, &["shot.png"], ocr).file.is_some());
+    }
+
+    #[test]
+    fn large_capture_warning_is_opt_in() {
+        assert!(!resolve("", &[]).warn_on_large);
+        assert!(resolve("", &["--warn-on-large"]).warn_on_large);
+        assert!(resolve("[base]\nwarn_on_large = true", &[]).warn_on_large);
+    }
+
+    #[test]
+    fn plan_round_trips_through_serde() {
+        let plan = resolve(
+            "[base]\ncursor = true",
+            &["--log-level", "debug", "shot.png"],
+        );
+        let json = serde_json::to_string(&plan).unwrap();
+        let plan: Plan = serde_json::from_str(&json).unwrap();
+        assert!(plan.cursor);
+        assert_eq!(plan.log_level, Level::DEBUG);
+        assert!(plan.file.is_some());
+        assert_eq!(serde_json::to_string(&plan).unwrap(), json);
+    }
+
+    #[test]
+    fn the_session_display_is_compared_by_its_socket() {
+        let runtime_dir = Some(Path::new("/run/user/1000"));
+        let session = Some("wayland-1");
+        assert!(is_session_display("wayland-1", session, runtime_dir));
+        assert!(is_session_display(
+            "/run/user/1000/wayland-1",
+            session,
+            runtime_dir
+        ));
+        assert!(is_session_display(
+            "wayland-1",
+            Some("/run/user/1000/wayland-1"),
+            runtime_dir
+        ));
+        assert!(!is_session_display("wayland-2", session, runtime_dir));
+        assert!(!is_session_display(
+            "/tmp/nested/wayland-1",
+            session,
+            runtime_dir
+        ));
+        assert!(!is_session_display("wayland-1", None, runtime_dir));
+    }
+
+    #[test]
+    fn progress_is_kept_off_the_stdout_of_the_image() {
+        assert!(try_resolve("", &["-", "--progress-fd", "1"]).is_err());
+
+        let plan = resolve("", &["-", "--progress-fd", "2"]);
+        assert!(plan.stdout);
+    }
+}
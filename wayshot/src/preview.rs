@@ -0,0 +1,180 @@
+use std::{env, fmt::Write, os::fd::AsFd};
+
+use image::{DynamicImage, GenericImageView, Rgba, imageops::FilterType};
+use rustix::termios;
+
+/// Width used when the terminal size can't be determined.
+const DEFAULT_COLUMNS: u32 = 80;
+
+/// Characters from dark to bright used when colour is disabled.
+const ASCII_RAMP: &[u8] = b" .:-=+*#%@";
+
+/// How the preview is drawn.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PreviewStyle {
+    /// Truecolor upper half blocks, two pixels per character cell.
+    TrueColor,
+    /// Grayscale ASCII characters, for terminals or users without colour.
+    Ascii,
+}
+
+impl PreviewStyle {
+    /// ASCII if `NO_COLOR` is set to a non-empty value, see <https://no-color.org>.
+    pub fn from_env() -> Self {
+        match env::var_os("NO_COLOR") {
+            Some(value) if !value.is_empty() => Self::Ascii,
+            _ => Self::TrueColor,
+        }
+    }
+}
+
+/// Size of the terminal `fd` is attached to in character cells, falling back
+/// to `COLUMNS` and `LINES` and then 80 columns without a line limit.
+pub fn terminal_size(fd: impl AsFd) -> (u32, Option<u32>) {
+    if let Ok(size) = termios::tcgetwinsize(fd)
+        && size.ws_col > 0
+    {
+        return (
+            size.ws_col as u32,
+            Some(size.ws_row as u32).filter(|rows| *rows > 0),
+        );
+    }
+    let from_env = |name| env::var(name).ok().and_then(|value| value.parse().ok());
+    (
+        from_env("COLUMNS").unwrap_or(DEFAULT_COLUMNS),
+        from_env("LINES"),
+    )
+}
+
+/// Render `image` to fit `columns` character cells wide and, if given, `rows` high.
+pub fn render(
+    image: &DynamicImage,
+    columns: u32,
+    rows: Option<u32>,
+    style: PreviewStyle,
+) -> String {
+    let (width, height) = image.dimensions();
+    if width == 0 || height == 0 || columns == 0 {
+        return String::new();
+    }
+    // Every cell is about twice as high as it is wide and shows two pixels vertically,
+    // so one pixel per column and two per row keeps the aspect ratio.
+    let mut preview_width = columns.min(width);
+    let mut preview_height = ((height as u64 * preview_width as u64 / width as u64) as u32).max(1);
+    if let Some(rows) = rows {
+        // Leave a line for the prompt.
+        let max_height = rows.saturating_sub(1).max(1) * 2;
+        if preview_height > max_height {
+            preview_width =
+                ((width as u64 * max_height as u64 / height as u64) as u32).clamp(1, columns);
+            preview_height = max_height;
+        }
+    }
+    let preview = image
+        .resize_exact(preview_width, preview_height, FilterType::Triangle)
+        .into_rgba8();
+
+    let mut out = String::new();
+    for y in (0..preview_height).step_by(2) {
+        for x in 0..preview_width {
+            let top = over_black(*preview.get_pixel(x, y));
+            let bottom = (y + 1 < preview_height).then(|| over_black(*preview.get_pixel(x, y + 1)));
+            match style {
+                PreviewStyle::TrueColor => {
+                    let [r, g, b] = top;
+                    let _ = write!(out, "\x1b[38;2;{r};{g};{b}m");
+                    match bottom {
+                        Some([r, g, b]) => {
+                            let _ = write!(out, "\x1b[48;2;{r};{g};{b}m");
+                        }
+                        None => out.push_str("\x1b[49m"),
+                    }
+                    out.push('▀');
+                }
+                PreviewStyle::Ascii => {
+                    let luminance = match bottom {
+                        Some(bottom) => (luminance(top) + luminance(bottom)) / 2,
+                        None => luminance(top),
+                    };
+                    let index = luminance as usize * (ASCII_RAMP.len() - 1) / 255;
+                    out.push(ASCII_RAMP[index] as char);
+                }
+            }
+        }
+        if style == PreviewStyle::TrueColor {
+            out.push_str("\x1b[0m");
+        }
+        out.push('\n');
+    }
+    out
+}
+
+/// Render `image` sized to the terminal `fd` is attached to, in the style `NO_COLOR` asks for.
+pub fn render_for_terminal(image: &DynamicImage, fd: impl AsFd) -> String {
+    let (columns, rows) = terminal_size(fd);
+    render(image, columns, rows, PreviewStyle::from_env())
+}
+
+/// Composite a possibly transparent pixel over a black terminal background.
+fn over_black(Rgba([r, g, b, a]): Rgba<u8>) -> [u8; 3] {
+    [r, g, b].map(|channel| (channel as u32 * a as u32 / 255) as u8)
+}
+
+fn luminance([r, g, b]: [u8; 3]) -> u32 {
+    (54 * r as u32 + 183 * g as u32 + 19 * b as u32) >> 8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::RgbaImage;
+
+    fn two_tone(width: u32, height: u32) -> DynamicImage {
+        RgbaImage::from_fn(width, height, |_, y| {
+            if y < height / 2 {
+                Rgba([255, 0, 0, 255])
+            } else {
+                Rgba([0, 0, 255, 255])
+            }
+        })
+        .into()
+    }
+
+    #[test]
+    fn truecolor_uses_half_blocks_for_two_rows_per_line() {
+        let preview = render(&two_tone(4, 2), 4, None, PreviewStyle::TrueColor);
+
+        let lines: Vec<_> = preview.lines().collect();
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0].matches('▀').count(), 4);
+        assert!(lines[0].starts_with("\x1b[38;2;255;0;0m\x1b[48;2;0;0;255m▀"));
+        assert!(lines[0].ends_with("\x1b[0m"));
+    }
+
+    #[test]
+    fn preview_fits_columns_and_rows() {
+        let image = two_tone(400, 200);
+
+        let wide = render(&image, 40, None, PreviewStyle::Ascii);
+        assert_eq!(wide.lines().count(), 10);
+        assert!(wide.lines().all(|line| line.len() == 40));
+
+        let short = render(&image, 40, Some(6), PreviewStyle::Ascii);
+        assert_eq!(short.lines().count(), 5);
+        assert!(short.lines().all(|line| line.len() == 20));
+    }
+
+    #[test]
+    fn ascii_maps_brightness_to_ramp() {
+        let image: DynamicImage = RgbaImage::from_fn(2, 2, |x, _| {
+            if x == 0 {
+                Rgba([0, 0, 0, 255])
+            } else {
+                Rgba([255, 255, 255, 255])
+            }
+        })
+        .into();
+
+        assert_eq!(render(&image, 2, None, PreviewStyle::Ascii), " @\n");
+    }
+}
@@ -0,0 +1,211 @@
+//! Progress events of `--progress-fd`, written as one JSON object per line
+//! for frontends that show the progress of a capture.
+
+use std::{
+    fs::File,
+    io::Write,
+    os::fd::{BorrowedFd, RawFd},
+    path::Path,
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use eyre::{Result, WrapErr};
+use libwayshot::{CaptureId, ProgressEvent};
+use rustix::io::{fcntl_dupfd_cloexec, fcntl_getfd};
+use serde::Serialize;
+
+use crate::utils::EncodingFormat;
+
+/// A step of a run, the `event` field of a progress line. The fields of the
+/// variants are part of the documented format, see wayshot(1).
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Progress<'a> {
+    OutputCaptured {
+        output: &'a str,
+        capture_id: CaptureId,
+    },
+    Converted {
+        output: &'a str,
+    },
+    Composited {
+        outputs: usize,
+        width: u32,
+        height: u32,
+    },
+    Encoded {
+        format: EncodingFormat,
+    },
+    /// `path` is `-` for stdout.
+    Written {
+        path: &'a Path,
+    },
+}
+
+impl<'a> Progress<'a> {
+    /// The line for an event of the library, none for events it added since.
+    pub fn from_library(event: &'a ProgressEvent) -> Option<Self> {
+        Some(match event {
+            ProgressEvent::OutputCaptured { output, capture_id } => Self::OutputCaptured {
+                output,
+                capture_id: *capture_id,
+            },
+            ProgressEvent::FrameConverted { output } => Self::Converted { output },
+            ProgressEvent::Composited {
+                outputs,
+                width,
+                height,
+            } => Self::Composited {
+                outputs: *outputs,
+                width: *width,
+                height: *height,
+            },
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Serialize)]
+struct Line<'a> {
+    /// Milliseconds since the Unix epoch.
+    time_ms: u64,
+    #[serde(flatten)]
+    progress: &'a Progress<'a>,
+}
+
+/// Writes progress lines to a file descriptor, flushing every line.
+#[derive(Debug)]
+pub struct ProgressWriter {
+    file: Mutex<File>,
+}
+
+impl ProgressWriter {
+    /// Writes to a duplicate of the file descriptor `fd` wayshot was started
+    /// with, e.g. a pipe or socket of the frontend or 2 for stderr.
+    pub fn open(fd: u32) -> Result<Self> {
+        let raw_fd = RawFd::try_from(fd).wrap_err_with(|| format!("Invalid --progress-fd {fd}"))?;
+        // SAFETY: the descriptor is inherited and nothing closes it while it is
+        // borrowed. If it isn't open, `F_GETFD` fails with `EBADF`.
+        let borrowed = unsafe { BorrowedFd::borrow_raw(raw_fd) };
+        fcntl_getfd(borrowed).wrap_err_with(|| format!("--progress-fd {fd} is not open"))?;
+        let owned = fcntl_dupfd_cloexec(borrowed, 3)
+            .wrap_err_with(|| format!("Failed to duplicate --progress-fd {fd}"))?;
+        Ok(Self::new(File::from(owned)))
+    }
+
+    fn new(file: File) -> Self {
+        Self {
+            file: Mutex::new(file),
+        }
+    }
+
+    /// Write the line of `progress`. A frontend that stopped reading doesn't
+    /// fail the capture, failures are only logged.
+    pub fn emit(&self, progress: &Progress) {
+        let time_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        let mut line = match serde_json::to_vec(&Line { time_ms, progress }) {
+            Ok(line) => line,
+            Err(e) => {
+                tracing::debug!("Failed to serialize progress {progress:?}: {e}");
+                return;
+            }
+        };
+        line.push(b'\n');
+        let mut file = self.file.lock().unwrap_or_else(|e| e.into_inner());
+        // One write per line, so lines of concurrent encoders don't interleave.
+        if let Err(e) = file.write_all(&line).and_then(|_| file.flush()) {
+            tracing::debug!("Failed to write progress: {e}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, os::fd::AsRawFd};
+
+    use serde_json::Value;
+
+    use super::*;
+
+    #[test]
+    fn progress_is_written_as_json_lines() {
+        let path = std::env::temp_dir().join(format!("wayshot-progress-{}", std::process::id()));
+        let writer = ProgressWriter::new(File::create(&path).unwrap());
+        let capture_id = CaptureId {
+            process: 4242,
+            generation: 1,
+        };
+        let captured = ProgressEvent::OutputCaptured {
+            output: "DP-1".to_string(),
+            capture_id,
+        };
+        writer.emit(&Progress::from_library(&captured).unwrap());
+        writer.emit(&Progress::Encoded {
+            format: EncodingFormat::Png,
+        });
+        writer.emit(&Progress::Written {
+            path: Path::new("/tmp/shot.png"),
+        });
+
+        let lines: Vec<Value> = fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        fs::remove_file(path).unwrap();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0]["event"], "output_captured");
+        assert_eq!(lines[0]["output"], "DP-1");
+        assert_eq!(lines[0]["capture_id"], "4242-1");
+        assert_eq!(lines[1]["event"], "encoded");
+        assert_eq!(lines[1]["format"], "png");
+        assert_eq!(lines[2]["event"], "written");
+        assert_eq!(lines[2]["path"], "/tmp/shot.png");
+        assert!(
+            lines
+                .iter()
+                .all(|line| line["time_ms"].as_u64().unwrap() > 0)
+        );
+        assert!(lines[0]["time_ms"].as_u64() <= lines[2]["time_ms"].as_u64());
+    }
+
+    #[test]
+    fn progress_is_written_to_a_duplicate_of_the_descriptor() {
+        let path = std::env::temp_dir().join(format!("wayshot-progress-fd-{}", std::process::id()));
+        let file = File::create(&path).unwrap();
+        let writer = ProgressWriter::open(file.as_raw_fd() as u32).unwrap();
+        drop(file);
+        writer.emit(&Progress::Encoded {
+            format: EncodingFormat::Png,
+        });
+
+        let written = fs::read_to_string(&path).unwrap();
+        fs::remove_file(path).unwrap();
+        assert!(written.contains(r#""event":"encoded""#));
+        assert!(ProgressWriter::open(u32::MAX >> 2).is_err());
+    }
+
+    #[test]
+    fn library_events_keep_their_fields() {
+        let composited = ProgressEvent::Composited {
+            outputs: 2,
+            width: 3840,
+            height: 1080,
+        };
+        let line = serde_json::to_value(Progress::from_library(&composited).unwrap()).unwrap();
+        assert_eq!(
+            line,
+            serde_json::json!({"event": "composited", "outputs": 2, "width": 3840, "height": 1080})
+        );
+        let converted = ProgressEvent::FrameConverted {
+            output: "eDP-1".to_string(),
+        };
+        let line = serde_json::to_value(Progress::from_library(&converted).unwrap()).unwrap();
+        assert_eq!(line["event"], "converted");
+        assert_eq!(line["output"], "eDP-1");
+    }
+}
@@ -0,0 +1,593 @@
+use serde::Serialize;
+
+/// Machine readable counterpart of the `libwayshot::Error` variants, printed by `--json`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    NoOutputs,
+    OutputWaitTimeout,
+    BufferTooSmall,
+    FrameTooLarge,
+    BufferSizeMismatch,
+    InvalidColor,
+    Io,
+    Dispatch,
+    Bind,
+    Global,
+    ConnectionLost,
+    StreamClosed,
+    Connect,
+    FramecopyFailed,
+    SessionLocked,
+    NoFormatsAdvertised,
+    NoSupportedBufferFormat,
+    ProtocolNotFound,
+    FreezeCallback,
+    SelectionCancelled,
+    EmptyRegion,
+    RegionNotVisible,
+    NoDmaState,
+    DrmDevice,
+    DmabufDeviceMismatch,
+    UnrecognizedColorCode,
+    Egl,
+    EglImageToTexProcNotFound,
+    CaptureFailed,
+    OutputConfiguration,
+    SeatNotFound,
+    SeatWithoutPointer,
+    FocusedOutputUnknown,
+    Unsupported,
+    InvalidFd,
+    RegionOutOfBounds,
+    OutputNotFound,
+    InvalidStateFile,
+    InvalidTileLayout,
+    Encoding,
+    RawImageSize,
+    VerificationFailed,
+    UnsupportedPixelFormat,
+    /// Errors raised by wayshot itself rather than libwayshot.
+    Other,
+}
+
+/// Exit codes used with `--json`, one per class of failure.
+pub mod exit_code {
+    pub const OTHER: i32 = 1;
+    pub const NO_OUTPUTS: i32 = 3;
+    pub const UNSUPPORTED: i32 = 4;
+    pub const CAPTURE_FAILED: i32 = 5;
+    pub const CONNECTION: i32 = 6;
+    pub const CANCELLED: i32 = 7;
+    pub const SESSION_LOCKED: i32 = 8;
+    pub const VERIFY_FAILED: i32 = 9;
+}
+
+impl From<&libwayshot::Error> for ErrorKind {
+    fn from(error: &libwayshot::Error) -> Self {
+        use libwayshot::Error;
+        match error {
+            Error::NoOutputs => Self::NoOutputs,
+            Error::OutputWaitTimeout { .. } => Self::OutputWaitTimeout,
+            Error::BufferTooSmall => Self::BufferTooSmall,
+            Error::FrameTooLarge { .. } => Self::FrameTooLarge,
+            Error::BufferSizeMismatch { .. } => Self::BufferSizeMismatch,
+            Error::InvalidColor => Self::InvalidColor,
+            Error::Io(_) => Self::Io,
+            Error::Dispatch(_) => Self::Dispatch,
+            Error::Bind(_) => Self::Bind,
+            Error::Global(_) => Self::Global,
+            Error::ConnectionLost(_) => Self::ConnectionLost,
+            Error::StreamClosed(_) => Self::StreamClosed,
+            Error::Connect(_) | Error::DisplayConnect { .. } => Self::Connect,
+            Error::FramecopyFailed | Error::FramecopyFailedWithReason(_) => Self::FramecopyFailed,
+            Error::SessionLocked => Self::SessionLocked,
+            Error::NoFormatsAdvertised => Self::NoFormatsAdvertised,
+            Error::NoSupportedBufferFormat { .. } => Self::NoSupportedBufferFormat,
+            Error::ProtocolNotFound(_) => Self::ProtocolNotFound,
+            Error::FreezeCallbackError(_) => Self::FreezeCallback,
+            Error::SelectionCancelled => Self::SelectionCancelled,
+            Error::EmptyRegion => Self::EmptyRegion,
+            Error::RegionNotVisible(_) => Self::RegionNotVisible,
+            Error::NoDMAStateError => Self::NoDmaState,
+            Error::DrmDevice { .. } => Self::DrmDevice,
+            Error::DmabufDeviceMismatch { .. } => Self::DmabufDeviceMismatch,
+            Error::UnrecognizedColorCode(_) => Self::UnrecognizedColorCode,
+            Error::EGLError(_) => Self::Egl,
+            Error::EGLImageToTexProcNotFoundError => Self::EglImageToTexProcNotFound,
+            Error::CaptureFailed(_) => Self::CaptureFailed,
+            Error::OutputConfiguration(_) => Self::OutputConfiguration,
+            Error::SeatNotFound { .. } => Self::SeatNotFound,
+            Error::SeatWithoutPointer { .. } => Self::SeatWithoutPointer,
+            Error::FocusedOutputUnknown => Self::FocusedOutputUnknown,
+            Error::Unsupported(_) => Self::Unsupported,
+            Error::InvalidFd(_) => Self::InvalidFd,
+            Error::RegionOutOfBounds { .. } => Self::RegionOutOfBounds,
+            Error::OutputNotFound(_) => Self::OutputNotFound,
+            Error::InvalidStateFile { .. } => Self::InvalidStateFile,
+            Error::InvalidTileLayout(_) => Self::InvalidTileLayout,
+            Error::Encoding(_) => Self::Encoding,
+            Error::RawImageSize { .. } => Self::RawImageSize,
+            Error::VerificationFailed(_) => Self::VerificationFailed,
+            Error::UnsupportedPixelFormat { .. } => Self::UnsupportedPixelFormat,
+            // The error is non-exhaustive to other crates, so the compiler
+            // can't check this match. `every_error_has_a_kind` does instead.
+            _ => Self::Other,
+        }
+    }
+}
+
+impl ErrorKind {
+    /// Kind of the libwayshot error behind `report`, [`Self::Other`] for any other error.
+    pub fn of(report: &eyre::Report) -> Self {
+        report
+            .downcast_ref::<libwayshot::Error>()
+            .map_or(Self::Other, Self::from)
+    }
+
+    pub fn exit_code(self) -> i32 {
+        match self {
+            Self::NoOutputs
+            | Self::OutputWaitTimeout
+            | Self::OutputNotFound
+            | Self::FocusedOutputUnknown => exit_code::NO_OUTPUTS,
+            Self::ProtocolNotFound
+            | Self::NoSupportedBufferFormat
+            | Self::Unsupported
+            | Self::NoDmaState
+            | Self::EglImageToTexProcNotFound
+            | Self::UnsupportedPixelFormat => exit_code::UNSUPPORTED,
+            Self::FramecopyFailed
+            | Self::CaptureFailed
+            | Self::NoFormatsAdvertised
+            | Self::BufferTooSmall
+            | Self::FrameTooLarge
+            | Self::BufferSizeMismatch
+            | Self::OutputConfiguration
+            | Self::DmabufDeviceMismatch => exit_code::CAPTURE_FAILED,
+            Self::ConnectionLost | Self::Connect | Self::Dispatch | Self::StreamClosed => {
+                exit_code::CONNECTION
+            }
+            Self::SelectionCancelled | Self::EmptyRegion | Self::RegionNotVisible => {
+                exit_code::CANCELLED
+            }
+            Self::SessionLocked => exit_code::SESSION_LOCKED,
+            Self::VerificationFailed => exit_code::VERIFY_FAILED,
+            _ => exit_code::OTHER,
+        }
+    }
+
+    fn hint(self) -> Option<&'static str> {
+        Some(match self {
+            Self::NoOutputs | Self::OutputWaitTimeout => {
+                "no output is ready, check that one is enabled or use --wait-for-outputs"
+            }
+            Self::ProtocolNotFound | Self::Unsupported => {
+                "the compositor lacks a protocol wayshot needs, run `wayshot check` for details"
+            }
+            Self::FramecopyFailed | Self::CaptureFailed => {
+                "the compositor refused the capture, retry or check that the output is enabled"
+            }
+            Self::NoFormatsAdvertised => {
+                "check that the output is enabled, otherwise the compositor may be buggy"
+            }
+            Self::SessionLocked => "unlock the session and capture again",
+            Self::DmabufDeviceMismatch => {
+                "allocate DMA-BUFs on the render node of the GPU the compositor renders on"
+            }
+            Self::ConnectionLost | Self::Connect => {
+                "could not talk to the compositor, check that WAYLAND_DISPLAY is set"
+            }
+            Self::StreamClosed => "the reader of the stream closed it",
+            Self::FrameTooLarge => "the output is too large for a shm buffer, lower its resolution",
+            Self::BufferSizeMismatch => {
+                "the shm buffer was truncated during the capture, retry the capture"
+            }
+            Self::OutputConfiguration => {
+                "the compositor did not enable the output, enable it and capture again"
+            }
+            Self::OutputNotFound => "list the available outputs with --list-outputs",
+            Self::FocusedOutputUnknown => "move the pointer over an output and capture again",
+            Self::SeatNotFound | Self::SeatWithoutPointer => {
+                "pick one of the available seats with --seat, or leave it out"
+            }
+            Self::InvalidStateFile => {
+                "capture a region once without --last to write the state file again"
+            }
+            Self::RawImageSize => "check that --size and --input-format match the piped image",
+            Self::UnsupportedPixelFormat => {
+                "choose a --pixel-format the encoding can hold, or another encoding"
+            }
+            _ => return None,
+        })
+    }
+}
+
+/// The `{"error": {...}}` object printed to stdout by `--json` when wayshot fails.
+#[derive(Debug, Serialize)]
+pub struct ErrorReport {
+    pub error: ErrorDetails,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ErrorDetails {
+    pub kind: ErrorKind,
+    /// Name of the output that was being captured, if any.
+    pub output: Option<String>,
+    pub message: String,
+    pub hint: Option<&'static str>,
+}
+
+impl ErrorReport {
+    pub fn new(report: &eyre::Report, output: Option<String>) -> Self {
+        let kind = ErrorKind::of(report);
+        Self {
+            error: ErrorDetails {
+                kind,
+                output,
+                message: format!("{report:#}"),
+                hint: kind.hint(),
+            },
+        }
+    }
+
+    pub fn exit_code(&self) -> i32 {
+        self.error.kind.exit_code()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libwayshot::{
+        Error, LogicalRegion, PixelFormat,
+        reexport::{FailureReason, WEnum},
+        region::{Position, Region, Size},
+    };
+    use serde_json::json;
+    use std::{collections::BTreeSet, io};
+    use wayland_client::{
+        ConnectError, DispatchError,
+        backend::{InvalidId, ObjectId},
+        globals::{BindError, GlobalError},
+    };
+
+    fn report_json(error: libwayshot::Error, output: Option<&str>) -> serde_json::Value {
+        let report = ErrorReport::new(&error.into(), output.map(str::to_string));
+        serde_json::to_value(report).expect("serialize")
+    }
+
+    #[test]
+    fn no_outputs_report() {
+        assert_eq!(
+            report_json(libwayshot::Error::NoOutputs, None),
+            json!({"error": {
+                "kind": "no_outputs",
+                "output": null,
+                "message": "no outputs supplied",
+                "hint": "no output is ready, check that one is enabled or use --wait-for-outputs",
+            }})
+        );
+    }
+
+    #[test]
+    fn protocol_not_found_report() {
+        let json = report_json(
+            libwayshot::Error::ProtocolNotFound("zwlr_screencopy_manager_v1".to_string()),
+            Some("DP-1"),
+        );
+
+        assert_eq!(json["error"]["kind"], "protocol_not_found");
+        assert_eq!(json["error"]["output"], "DP-1");
+        assert_eq!(
+            json["error"]["message"],
+            "Cannot find required wayland protocol"
+        );
+        assert!(json["error"]["hint"].is_string());
+    }
+
+    #[test]
+    fn selection_cancelled_report() {
+        assert_eq!(
+            report_json(libwayshot::Error::SelectionCancelled, None),
+            json!({"error": {
+                "kind": "selection_cancelled",
+                "output": null,
+                "message": "selection cancelled",
+                "hint": null,
+            }})
+        );
+    }
+
+    #[test]
+    fn framecopy_failed_report() {
+        let json = report_json(libwayshot::Error::FramecopyFailed, Some("HDMI-A-1"));
+
+        assert_eq!(json["error"]["kind"], "framecopy_failed");
+        assert_eq!(json["error"]["output"], "HDMI-A-1");
+        assert_eq!(json["error"]["message"], "framecopy failed");
+    }
+
+    #[test]
+    fn exit_codes_follow_error_class() {
+        let exit_code =
+            |error: libwayshot::Error| ErrorReport::new(&error.into(), None).exit_code();
+
+        assert_eq!(
+            exit_code(libwayshot::Error::NoOutputs),
+            exit_code::NO_OUTPUTS
+        );
+        assert_eq!(
+            exit_code(libwayshot::Error::ProtocolNotFound(String::new())),
+            exit_code::UNSUPPORTED
+        );
+        assert_eq!(
+            exit_code(libwayshot::Error::SelectionCancelled),
+            exit_code::CANCELLED
+        );
+        assert_eq!(
+            exit_code(libwayshot::Error::EmptyRegion),
+            exit_code::CANCELLED
+        );
+        assert_eq!(
+            exit_code(libwayshot::Error::SessionLocked),
+            exit_code::SESSION_LOCKED
+        );
+        assert_eq!(
+            exit_code(libwayshot::Error::FramecopyFailed),
+            exit_code::CAPTURE_FAILED
+        );
+        assert_eq!(
+            exit_code(libwayshot::Error::NoFormatsAdvertised),
+            exit_code::CAPTURE_FAILED
+        );
+        assert_eq!(
+            exit_code(libwayshot::Error::VerificationFailed(String::new())),
+            exit_code::VERIFY_FAILED
+        );
+        assert_eq!(
+            exit_code(libwayshot::Error::DisplayConnect {
+                socket: "/run/user/1000/wayland-9".into(),
+                source: std::io::ErrorKind::ConnectionRefused.into(),
+            }),
+            exit_code::CONNECTION
+        );
+    }
+
+    #[test]
+    fn wrapped_verification_failures_keep_their_kind() {
+        let report = eyre::Report::from(libwayshot::Error::VerificationFailed(
+            "row 3 of the decoded image differs".to_string(),
+        ))
+        .wrap_err("shot.png");
+
+        assert_eq!(ErrorKind::of(&report), ErrorKind::VerificationFailed);
+    }
+
+    #[test]
+    fn wayshot_errors_are_other() {
+        let report = ErrorReport::new(&eyre::eyre!("No output found!"), None);
+
+        assert_eq!(report.error.kind, ErrorKind::Other);
+        assert_eq!(report.exit_code(), exit_code::OTHER);
+    }
+
+    /// An error of every variant of `libwayshot::Error` with its kind.
+    fn every_error() -> Vec<(Error, ErrorKind)> {
+        let size = Size {
+            width: 1920,
+            height: 1080,
+        };
+        let io_error = || io::Error::from(io::ErrorKind::BrokenPipe);
+        vec![
+            (Error::NoOutputs, ErrorKind::NoOutputs),
+            (
+                Error::OutputWaitTimeout {
+                    expected: 2,
+                    found: 1,
+                },
+                ErrorKind::OutputWaitTimeout,
+            ),
+            (Error::BufferTooSmall, ErrorKind::BufferTooSmall),
+            (
+                Error::FrameTooLarge {
+                    size,
+                    stride: 1 << 20,
+                },
+                ErrorKind::FrameTooLarge,
+            ),
+            (
+                Error::BufferSizeMismatch {
+                    expected: 8,
+                    got: 4,
+                },
+                ErrorKind::BufferSizeMismatch,
+            ),
+            (Error::InvalidColor, ErrorKind::InvalidColor),
+            (Error::Io(io_error()), ErrorKind::Io),
+            (
+                Error::Dispatch(DispatchError::BadMessage {
+                    sender_id: ObjectId::null(),
+                    interface: "wl_output",
+                    opcode: 0,
+                }),
+                ErrorKind::Dispatch,
+            ),
+            (Error::Bind(BindError::NotPresent), ErrorKind::Bind),
+            (
+                Error::Global(GlobalError::InvalidId(InvalidId)),
+                ErrorKind::Global,
+            ),
+            (Error::ConnectionLost(io_error()), ErrorKind::ConnectionLost),
+            (Error::StreamClosed(io_error()), ErrorKind::StreamClosed),
+            (
+                Error::Connect(ConnectError::NoCompositor),
+                ErrorKind::Connect,
+            ),
+            (Error::FramecopyFailed, ErrorKind::FramecopyFailed),
+            (
+                Error::FramecopyFailedWithReason(WEnum::Value(FailureReason::Stopped)),
+                ErrorKind::FramecopyFailed,
+            ),
+            (Error::NoFormatsAdvertised, ErrorKind::NoFormatsAdvertised),
+            (
+                Error::NoSupportedBufferFormat {
+                    advertised: "Yuyv".to_string(),
+                },
+                ErrorKind::NoSupportedBufferFormat,
+            ),
+            (
+                Error::ProtocolNotFound(String::new()),
+                ErrorKind::ProtocolNotFound,
+            ),
+            (
+                Error::FreezeCallbackError(String::new()),
+                ErrorKind::FreezeCallback,
+            ),
+            (Error::SessionLocked, ErrorKind::SessionLocked),
+            (Error::SelectionCancelled, ErrorKind::SelectionCancelled),
+            (Error::EmptyRegion, ErrorKind::EmptyRegion),
+            (
+                Error::RegionNotVisible(LogicalRegion::new(-100, 0, 50, 20)),
+                ErrorKind::RegionNotVisible,
+            ),
+            (Error::NoDMAStateError, ErrorKind::NoDmaState),
+            (
+                Error::DrmDevice {
+                    device: "/dev/dri/renderD128".into(),
+                    source: io_error(),
+                },
+                ErrorKind::DrmDevice,
+            ),
+            (
+                Error::DisplayConnect {
+                    socket: "wayland-9".into(),
+                    source: io_error(),
+                },
+                ErrorKind::Connect,
+            ),
+            (
+                Error::DmabufDeviceMismatch {
+                    device: "/dev/dri/renderD129".into(),
+                    compositor_device: "/dev/dri/renderD128".into(),
+                    source: Box::new(Error::FramecopyFailed),
+                },
+                ErrorKind::DmabufDeviceMismatch,
+            ),
+            (
+                Error::UnrecognizedColorCode(drm::buffer::UnrecognizedFourcc(42)),
+                ErrorKind::UnrecognizedColorCode,
+            ),
+            (
+                Error::EGLError(khronos_egl::Error::ContextLost),
+                ErrorKind::Egl,
+            ),
+            (
+                Error::EGLImageToTexProcNotFoundError,
+                ErrorKind::EglImageToTexProcNotFound,
+            ),
+            (
+                Error::CaptureFailed(String::new()),
+                ErrorKind::CaptureFailed,
+            ),
+            (
+                Error::OutputConfiguration(String::new()),
+                ErrorKind::OutputConfiguration,
+            ),
+            (
+                Error::SeatNotFound {
+                    name: "seat1".to_string(),
+                    available: "seat0".to_string(),
+                },
+                ErrorKind::SeatNotFound,
+            ),
+            (
+                Error::SeatWithoutPointer {
+                    selector: "default".to_string(),
+                    available: "seat0".to_string(),
+                },
+                ErrorKind::SeatWithoutPointer,
+            ),
+            (Error::FocusedOutputUnknown, ErrorKind::FocusedOutputUnknown),
+            (Error::Unsupported(String::new()), ErrorKind::Unsupported),
+            (Error::InvalidFd(gbm::InvalidFdError), ErrorKind::InvalidFd),
+            (
+                Error::RegionOutOfBounds {
+                    region: Region {
+                        position: Position { x: 1900, y: 0 },
+                        size,
+                    },
+                    bounds: size,
+                },
+                ErrorKind::RegionOutOfBounds,
+            ),
+            (
+                Error::OutputNotFound("DP-9".to_string()),
+                ErrorKind::OutputNotFound,
+            ),
+            (
+                Error::InvalidStateFile {
+                    path: "last-region".into(),
+                    reason: String::new(),
+                },
+                ErrorKind::InvalidStateFile,
+            ),
+            (
+                Error::InvalidTileLayout(String::new()),
+                ErrorKind::InvalidTileLayout,
+            ),
+            (
+                Error::Encoding(image::ImageError::IoError(io_error())),
+                ErrorKind::Encoding,
+            ),
+            (
+                Error::RawImageSize {
+                    width: 2,
+                    height: 2,
+                    expected: 16,
+                    got: 12,
+                },
+                ErrorKind::RawImageSize,
+            ),
+            (
+                Error::VerificationFailed(String::new()),
+                ErrorKind::VerificationFailed,
+            ),
+            (
+                Error::UnsupportedPixelFormat {
+                    pixel_format: PixelFormat::Bgra8,
+                    format: image::ImageFormat::Jpeg,
+                },
+                ErrorKind::UnsupportedPixelFormat,
+            ),
+        ]
+    }
+
+    #[test]
+    fn every_error_has_a_kind() {
+        // The variants as listed by libwayshot, which checks the list against
+        // the error.
+        let variants: BTreeSet<&str> = include_str!("../../libwayshot/error-variants.txt")
+            .lines()
+            .collect();
+        let errors = every_error();
+        let names: Vec<String> = errors
+            .iter()
+            .map(|(error, _)| {
+                format!("{error:?}")
+                    .chars()
+                    .take_while(char::is_ascii_alphanumeric)
+                    .collect()
+            })
+            .collect();
+        assert_eq!(
+            names.iter().map(String::as_str).collect::<BTreeSet<_>>(),
+            variants,
+            "add new libwayshot::Error variants to ErrorKind and every_error"
+        );
+
+        for (error, kind) in &errors {
+            assert_eq!(ErrorKind::from(error), *kind, "{error:?}");
+        }
+    }
+}
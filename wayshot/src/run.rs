@@ -0,0 +1,1169 @@
+use std::{
+    cell::{Cell, RefCell},
+    env,
+    fs::{self, OpenOptions},
+    io::{self, BufWriter, Cursor, Read, Write},
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    rc::Rc,
+    slice,
+    sync::{Arc, OnceLock, atomic::AtomicBool},
+    thread,
+    time::{Duration, Instant},
+};
+
+use eyre::{Result, WrapErr, bail};
+use libwayshot::{
+    CaptureId, ComposeOptions, CursorOverlayCheck, EncodingOptions, FramePacer, LogicalRegion,
+    OutputCapture, OutputInfo, SeatSelector, SelectionMode, WayshotConnection, region::Position,
+};
+
+use dialoguer::{FuzzySelect, theme::ColorfulTheme};
+
+use crate::{
+    Outcome, Plan, metadata, preview,
+    progress::{Progress, ProgressWriter},
+    report::ErrorKind,
+    selection,
+    utils::{
+        self, ClipboardBackend, ClipboardImage, EncodingFormat, MaskShape, ShotResult, Timings,
+        send_notification,
+    },
+};
+
+/// Outline `--debug-borders` draws around every output, in physical pixels.
+const DEBUG_BORDER_COLOR: image::Rgba<u8> = image::Rgba([255, 0, 0, 255]);
+const DEBUG_BORDER_THICKNESS: u32 = 2;
+/// How long `--flash` takes to fade out.
+const FLASH_DURATION: Duration = Duration::from_millis(150);
+
+fn select_output<T>(outputs: &[T]) -> Option<usize>
+where
+    T: ToString + std::fmt::Display,
+{
+    let Ok(selection) = FuzzySelect::with_theme(&ColorfulTheme::default())
+        .with_prompt("Choose Screen")
+        .default(0)
+        .items(outputs)
+        .interact()
+    else {
+        return None;
+    };
+    Some(selection)
+}
+
+/// Carry out `plan`: capture, or encode stdin for `wayshot encode`, and save
+/// and print the image as planned. What `wayshot` prints to stdout, the
+/// listings and the image of `-` included, is written to `writer`. Nothing is
+/// printed to stderr: the `--timings` are returned in [`Outcome::timings`],
+/// and the [`diagnostics`] that `wayshot` prints along with errors are up to
+/// the caller.
+///
+/// Over data-control the clipboard is only served while a process offers it,
+/// so the image to copy is returned in [`Outcome::clipboard`] for the caller
+/// to offer. Without data-control, or for another `--display`, it is handed
+/// to `wl-copy` right away.
+pub fn run(plan: Plan, writer: &mut dyn Write) -> Result<Outcome> {
+    let timings = Timings::default();
+    let outcome = run_timed(plan, writer, &timings)?;
+    Ok(Outcome {
+        timings: timings.into_lines(),
+        ..outcome
+    })
+}
+
+/// [`run`], recording the `--timings` in `timings`.
+fn run_timed(plan: Plan, writer: &mut dyn Write, timings: &Timings) -> Result<Outcome> {
+    let Plan {
+        options: cli,
+        encode,
+        check,
+        foreign_display,
+        config_path,
+        config_warnings,
+        log_level: _,
+        cursor,
+        clipboard,
+        clipboard_target,
+        encoding,
+        jxl: jxl_config,
+        file,
+        stdout: stdout_print,
+        output,
+        notifications: notifications_enabled,
+        flash,
+        bell,
+        warn_on_uniform,
+        warn_on_large,
+    } = plan;
+
+    if cli.strict_config && !config_warnings.is_empty() {
+        for warning in &config_warnings {
+            tracing::error!("{warning}");
+        }
+        bail!(
+            "{} problem(s) found in config file {}",
+            config_warnings.len(),
+            config_path.display()
+        );
+    }
+    for warning in &config_warnings {
+        tracing::warn!("{warning}");
+    }
+    let progress = cli
+        .progress_fd
+        .map(ProgressWriter::open)
+        .transpose()?
+        .map(Arc::new);
+    let emit_progress = |event: Progress| {
+        if let Some(progress) = &progress {
+            progress.emit(&event);
+        }
+    };
+
+    if let Some(ie) = crate::plan::input_encoding(&cli)
+        && ie != encoding
+    {
+        tracing::warn!(
+            "The encoding requested '{encoding}' does not match the output file's encoding '{ie}'. Still using the requested encoding however.",
+        );
+    }
+
+    if (cli.shape.is_some() || cli.radius.is_some()) && !encoding.supports_alpha() {
+        bail!(
+            "--shape and --radius need an encoding with alpha such as png or webp, got '{encoding}'"
+        );
+    }
+    if let Some(pixel_format) = cli.pixel_format {
+        if !encoding.supports_pixel_format(pixel_format) {
+            bail!("--pixel-format {pixel_format} is not supported by the '{encoding}' encoding");
+        }
+        if (cli.shape.is_some() || cli.radius.is_some()) && !pixel_format.has_alpha() {
+            bail!("--shape and --radius need a --pixel-format with alpha, got '{pixel_format}'");
+        }
+    }
+
+    if cli.jpeg_subsampling.is_some() && encoding != EncodingFormat::Jpg {
+        tracing::warn!("--jpeg-subsampling only applies to the jpg encoding, not '{encoding}'");
+    }
+    let encoding_options = EncodingOptions::new()
+        .deterministic(cli.reproducible)
+        .pixel_format(cli.pixel_format)
+        .jpeg_subsampling(cli.jpeg_subsampling.unwrap_or_default());
+    // The capture id goes into PNG files along with the sidecar, but not into
+    // reproducible ones.
+    let embed_capture_id = cli.sidecar && !cli.reproducible;
+
+    let loop_path = cli.file.clone().filter(|_| cli.loop_frames);
+    let output_resolution = cli
+        .resolution
+        .map(|resolution| resolution.fit(cli.resize_fit.unwrap_or_default()));
+    // Actions may select regions that clap can't reject.
+    if output_resolution.is_some()
+        && (cli.geometry
+            || cli.window_pick
+            || cli.output_pick
+            || !(output.is_some() || cli.choose_output || cli.separate))
+    {
+        bail!(
+            "--resolution only resizes single outputs, use it with -o, --choose-output or --separate"
+        );
+    }
+
+    let encode_to_buffer = |image: &image::DynamicImage,
+                            capture_id: Option<CaptureId>|
+     -> Result<Cursor<Vec<u8>>> {
+        let encoding_options = encoding_options.capture_id(capture_id.filter(|_| embed_capture_id));
+        Ok(match encoding {
+            EncodingFormat::Jxl => Cursor::new(
+                utils::encode_to_jxl_bytes(
+                    image,
+                    jxl_config.get_lossless(),
+                    jxl_config.get_distance(),
+                    jxl_config.get_encoder_speed(),
+                )
+                .map_err(|e| eyre::eyre!("Failed to encode JXL: {}", e))?,
+            ),
+            EncodingFormat::Svg => Cursor::new(utils::encode_to_svg(image, &encoding_options)?),
+            EncodingFormat::Dds => Cursor::new(utils::encode_to_dds(image)?),
+            _ => {
+                let mut buffer = Cursor::new(Vec::new());
+                encoding_options.encode(image, encoding.into(), &mut buffer)?;
+                buffer
+            }
+        })
+    };
+    // Reads `path` back after it was written with `encoded` for `image`.
+    let verify_file =
+        |image: &image::DynamicImage, path: &Path, encoded: &[u8], mode| -> Result<()> {
+            let read_back = fs::read(path)?;
+            if read_back != encoded {
+                return Err(libwayshot::Error::VerificationFailed(format!(
+                    "read back {} bytes, wrote {}",
+                    read_back.len(),
+                    encoded.len()
+                ))
+                .into());
+            }
+            // JXL, SVG and DDS aren't decoded by the image crate, the bytes have to do.
+            let format = image::ImageFormat::from(encoding);
+            if !matches!(
+                encoding,
+                EncodingFormat::Jxl | EncodingFormat::Svg | EncodingFormat::Dds
+            ) && format.reading_enabled()
+            {
+                encoding_options.verify(image, format, &read_back, mode)?;
+            }
+            Ok(())
+        };
+    // Encodes `image` straight into `file`.
+    let save_encoded = |image: &image::DynamicImage,
+                        capture_id: Option<CaptureId>,
+                        file: &mut dyn utils::WriteSeek|
+     -> Result<()> {
+        let encoding_options = encoding_options.capture_id(capture_id.filter(|_| embed_capture_id));
+        if encoding == EncodingFormat::Jxl {
+            utils::encode_to_jxl(
+                image,
+                file,
+                jxl_config.get_lossless(),
+                jxl_config.get_distance(),
+                jxl_config.get_encoder_speed(),
+            )
+            .map_err(|e| eyre::eyre!("Failed to encode to JXL: {}", e))?;
+        } else if encoding == EncodingFormat::Svg {
+            file.write_all(&utils::encode_to_svg(image, &encoding_options)?)?;
+        } else if encoding == EncodingFormat::Dds {
+            file.write_all(&utils::encode_to_dds(image)?)?;
+        } else {
+            let mut file_writer = BufWriter::new(file);
+            encoding_options.encode(image, encoding.into(), &mut file_writer)?;
+            file_writer.flush()?;
+        }
+        Ok(())
+    };
+    // Writes the checksum file for `path` with --checksum, once the image is saved.
+    let write_checksum =
+        |path: &Path, digest: Option<String>| -> Result<Option<metadata::ChecksumMetadata>> {
+            let (Some(algorithm), Some(digest)) = (cli.checksum, digest) else {
+                return Ok(None);
+            };
+            let checksum = metadata::ChecksumMetadata::new(path, algorithm, digest);
+            let checksum_path = metadata::write_checksum(&checksum)?;
+            tracing::debug!("Wrote checksum {}", checksum_path.display());
+            Ok(Some(checksum))
+        };
+    // Saves `image` at `path`, returning the digest of the file with --checksum.
+    let save_image = |image: &image::DynamicImage,
+                      capture_id: Option<CaptureId>,
+                      path: &Path|
+     -> Result<Option<metadata::ChecksumMetadata>> {
+        let encode_start = Instant::now();
+        let mut digest = None;
+        if let Some(mode) = cli.verify {
+            let encoded = encode_to_buffer(image, capture_id)?.into_inner();
+            utils::write_atomically(path, |file| {
+                digest = utils::write_hashed(file, cli.checksum, |writer| {
+                    Ok(writer.write_all(&encoded)?)
+                })?;
+                Ok(())
+            })?;
+            emit_progress(Progress::Encoded { format: encoding });
+            emit_progress(Progress::Written { path });
+            verify_file(image, path, &encoded, mode)
+                .wrap_err_with(|| format!("Failed to verify {}", path.display()))?;
+            if cli.timings {
+                timings.record(
+                    format!("encode and verify {}", path.display()),
+                    encode_start.elapsed(),
+                );
+            }
+            return write_checksum(path, digest);
+        }
+        utils::write_atomically(path, |file| {
+            digest = utils::write_hashed(file, cli.checksum, |file| {
+                save_encoded(image, capture_id, file)
+            })?;
+            Ok(())
+        })?;
+        // The image is encoded straight into the file, both are done by now.
+        emit_progress(Progress::Encoded { format: encoding });
+        emit_progress(Progress::Written { path });
+        if cli.timings {
+            timings.record(format!("encode {}", path.display()), encode_start.elapsed());
+        }
+        write_checksum(path, digest)
+    };
+    // Filled once connected with --json, `wayshot encode` has no compositor to describe.
+    let json_diagnostics = OnceLock::new();
+    // The last image copied over data-control, for the caller to offer.
+    let clipboard_image = RefCell::new(None);
+    // Everything done with the image once it is captured, or read by `wayshot encode`.
+    let deliver = |image: &image::DynamicImage,
+                   captured_at: chrono::DateTime<chrono::Local>,
+                   region: Option<LogicalRegion>,
+                   outputs: &[&OutputInfo],
+                   capture_id: Option<CaptureId>,
+                   writer: &mut dyn Write|
+     -> Result<Vec<PathBuf>> {
+        let mut image_buf: Option<Cursor<Vec<u8>>> = None;
+        let mut files = Vec::new();
+
+        if cli.sidecar && file.is_none() {
+            tracing::warn!("--sidecar needs the image to be saved to a file, skipping it");
+        }
+        if cli.exec.is_some() && file.is_none() {
+            tracing::warn!("--exec needs the image to be saved to a file, skipping it");
+        }
+        if cli.checksum.is_some() && file.is_none() {
+            tracing::warn!("--checksum needs the image to be saved to a file, skipping it");
+        }
+        let mut checksums = Vec::new();
+        let capture_metadata = |checksums: &[metadata::ChecksumMetadata]| {
+            let mut metadata = metadata::CaptureMetadata::new(
+                captured_at,
+                encoding,
+                (image.width(), image.height()),
+                region,
+                outputs,
+            );
+            metadata.checksums = checksums.to_vec();
+            metadata.capture_id = capture_id;
+            metadata
+        };
+        if let Some(f) = &file {
+            match cli.tile_width {
+                Some(tile_width) => {
+                    let tiles = libwayshot::split_tiles(image, tile_width, cli.tile_overlap)?;
+                    for (index, tile) in tiles.iter().enumerate() {
+                        let tile_path = utils::get_tile_file_name(f, index);
+                        tracing::debug!(
+                            "Saving tile at x offset {} to {}",
+                            tile.x_offset,
+                            tile_path.display()
+                        );
+                        checksums.extend(save_image(&tile.image, capture_id, &tile_path)?);
+                        files.push(tile_path);
+                    }
+                }
+                None => {
+                    checksums.extend(save_image(image, capture_id, f)?);
+                    files.push(f.clone());
+                }
+            }
+            if cli.sidecar {
+                let sidecar = metadata::write_sidecar(f, &capture_metadata(&checksums))?;
+                tracing::debug!("Wrote sidecar {}", sidecar.display());
+            }
+            if let Some(template) = &cli.exec {
+                let outputs: Vec<&str> =
+                    outputs.iter().map(|output| output.name.as_str()).collect();
+                utils::run_exec(&utils::expand_exec_template(template, f, region, &outputs))?;
+            }
+        }
+
+        if cli.json {
+            if stdout_print {
+                tracing::warn!("The image is written to stdout, not printing --json metadata");
+            } else {
+                let mut metadata = capture_metadata(&checksums);
+                metadata.diagnostics = json_diagnostics.get().cloned();
+                serde_json::to_writer(&mut *writer, &metadata)?;
+                writeln!(writer)?;
+                writer.flush()?;
+            }
+        }
+
+        if stdout_print {
+            let buffer = encode_to_buffer(image, capture_id)?;
+            emit_progress(Progress::Encoded { format: encoding });
+            writer.write_all(buffer.get_ref())?;
+            writer.flush()?;
+            emit_progress(Progress::Written {
+                path: Path::new("-"),
+            });
+            image_buf = Some(buffer);
+        }
+
+        if cli.preview {
+            if stdout_print {
+                let stderr = io::stderr();
+                let preview = preview::render_for_terminal(image, &stderr);
+                stderr.lock().write_all(preview.as_bytes())?;
+            } else {
+                let preview = preview::render_for_terminal(image, io::stdout());
+                writer.write_all(preview.as_bytes())?;
+                writer.flush()?;
+            }
+        }
+
+        if let Some(lang) = &cli.ocr {
+            let text = utils::recognize_text(image, lang)?;
+            writer.write_all(text.as_bytes())?;
+            writer.flush()?;
+        }
+
+        if clipboard {
+            let buffer = match image_buf {
+                Some(buf) => buf,
+                None => {
+                    let buffer = encode_to_buffer(image, capture_id)?;
+                    emit_progress(Progress::Encoded { format: encoding });
+                    buffer
+                }
+            };
+            // wl-clipboard-rs only connects to the display in the environment,
+            // wl-copy is pointed at another one.
+            let backend = utils::resolve_clipboard_backend(
+                foreign_display.is_none() && utils::has_data_control(),
+                env::var_os("PATH").as_deref(),
+            )?;
+            tracing::debug!("Copying to the clipboard with {backend}");
+            match backend {
+                ClipboardBackend::DataControl => {
+                    clipboard_image.replace(Some(ClipboardImage {
+                        bytes: buffer.into_inner(),
+                        target: clipboard_target,
+                        verify: cli.verify.is_some(),
+                    }));
+                }
+                ClipboardBackend::WlCopy(wl_copy) => {
+                    if cli.verify.is_some() {
+                        tracing::warn!(
+                            "Can't read the clipboard back without data-control, not verifying it"
+                        );
+                    }
+                    utils::copy_with_wl_copy(
+                        &wl_copy,
+                        foreign_display.as_deref(),
+                        buffer.get_ref(),
+                        encoding.mime_type(),
+                        clipboard_target,
+                    )?;
+                }
+            }
+        }
+
+        Ok(files)
+    };
+
+    if let Some(encode) = encode {
+        let captured_at = chrono::Local::now();
+        let mut raw = Vec::new();
+        io::stdin().lock().read_to_end(&mut raw)?;
+        let (width, height) = encode.size;
+        let image = encode
+            .input_format
+            .image_from_raw(width, height, raw)
+            .wrap_err("Failed to read the image from stdin")?;
+        let files = deliver(&image, captured_at, None, &[], None, writer)?;
+        return Ok(Outcome {
+            files,
+            clipboard: clipboard_image.into_inner(),
+            ..Outcome::default()
+        });
+    }
+
+    let display = cli.display.as_deref();
+    let mut wayshot_conn = match cli.wait_for_outputs {
+        Some(seconds) => connect_waiting_for_outputs(Duration::from_secs_f64(seconds), display)?,
+        None => connect(display)?,
+    };
+    if cli.timings {
+        for (phase, duration) in wayshot_conn.startup_timings().phases() {
+            timings.record(phase, duration);
+        }
+    }
+    wayshot_conn.set_wait_for_frame(cli.wait_for_frame);
+    wayshot_conn.set_output_resolution(output_resolution);
+    wayshot_conn.set_warn_on_uniform(warn_on_uniform);
+    if let Some(progress) = &progress {
+        let progress = Arc::clone(progress);
+        wayshot_conn.set_progress(move |event| {
+            if let Some(event) = Progress::from_library(event) {
+                progress.emit(&event);
+            }
+        });
+    }
+
+    if cli.json {
+        json_diagnostics.get_or_init(|| metadata::Diagnostics::of(&wayshot_conn));
+    }
+    if cli.list_outputs {
+        let valid_outputs = wayshot_conn.get_all_outputs();
+        for output in valid_outputs {
+            writeln!(writer, "{}", output.name)?;
+        }
+
+        writer.flush()?;
+
+        return Ok(Outcome::default());
+    }
+
+    if cli.list_outputs_info {
+        wayshot_conn.write_displays_info(writer)?;
+        writer.flush()?;
+        return Ok(Outcome::default());
+    }
+
+    if check {
+        let diagnostics = metadata::Diagnostics::of(&wayshot_conn);
+        if cli.json {
+            serde_json::to_writer(&mut *writer, &diagnostics)?;
+            writeln!(writer)?;
+        } else {
+            writeln!(writer, "{diagnostics}")?;
+        }
+        writer.flush()?;
+        return Ok(Outcome::default());
+    }
+
+    if cli.list_toplevels {
+        let toplevels = wayshot_conn.get_all_toplevels();
+        for tl in toplevels.iter().filter(|t| t.active) {
+            writeln!(writer, "{}", tl.id_and_title())?;
+        }
+        writer.flush()?;
+        return Ok(Outcome::default());
+    }
+
+    let single_output = || -> Result<&OutputInfo> {
+        let outputs = wayshot_conn.get_all_outputs();
+        let output_info = if let Some(output_name) = &output {
+            outputs.iter().find(|output| &output.name == output_name)
+        } else if cli.choose_output {
+            let output_names: Vec<&str> = outputs
+                .iter()
+                .map(|display| display.name.as_str())
+                .collect();
+            select_output(&output_names).map(|index| &outputs[index])
+        } else {
+            outputs.first()
+        };
+        let Some(output_info) = output_info else {
+            bail!("No output found!");
+        };
+        Ok(output_info)
+    };
+
+    if cli.stats {
+        let output_info = single_output()?;
+        let stats = wayshot_conn.output_stats(output_info)?;
+        let report = metadata::StatsReport::new(&output_info.name, &stats);
+        if cli.json {
+            serde_json::to_writer(&mut *writer, &report)?;
+            writeln!(writer)?;
+        } else {
+            writeln!(writer, "{report}")?;
+        }
+        writer.flush()?;
+        return Ok(Outcome::default());
+    }
+
+    let seat = cli.seat.clone().unwrap_or_default();
+    if cli.seat.is_some() {
+        let seats = wayshot_conn.seats()?;
+        let selected = seat.resolve(&seats)?;
+        if cursor && selected.len() < seats.iter().filter(|seat| seat.pointer).count() {
+            tracing::warn!(
+                "The compositor draws the cursor of every seat, the ones of seats other than \
+                 {seat} can't be left out"
+            );
+        }
+    }
+
+    if cli.check_cursor && !cursor {
+        match wayshot_conn.check_cursor_overlay(wayshot_conn.get_all_outputs())? {
+            CursorOverlayCheck::Honored => tracing::info!("The compositor hides the cursor"),
+            CursorOverlayCheck::Unverified => tracing::warn!(
+                "Could not verify that the compositor hides the cursor: captures with and \
+                 without it are identical. If it shows in the screenshot, the compositor \
+                 ignores the request to hide it and wayshot can't remove it."
+            ),
+        }
+    }
+
+    if let Some(path) = loop_path {
+        if encoding != EncodingFormat::Ppm {
+            bail!("--loop only supports the ppm encoding, got '{encoding}'");
+        }
+        let output_info = single_output()?;
+        stream_ppm_frames(
+            &wayshot_conn,
+            output_info,
+            cursor,
+            &path,
+            cli.fps,
+            cli.timings.then_some(timings),
+        )?;
+        return Ok(Outcome::default());
+    }
+
+    let screenshot_output = |output: &OutputInfo| match cli.thumbnail {
+        Some(max_dim) => wayshot_conn
+            .thumbnail_output(output, cursor, max_dim)
+            .map(image::DynamicImage::from),
+        None => wayshot_conn.screenshot_single_output(output, cursor),
+    };
+
+    // The frames are copied by now, so the feedback can't end up in the image.
+    let capture_feedback = |outputs: &[OutputInfo]| {
+        if bell {
+            utils::ring_bell();
+        }
+        if flash && let Err(e) = wayshot_conn.flash_outputs(outputs, FLASH_DURATION) {
+            tracing::warn!("Failed to flash the captured outputs: {e}");
+        }
+    };
+
+    if cli.separate {
+        let Some(path) = &file else {
+            bail!("--separate needs a file or directory to save the outputs to");
+        };
+        let outputs = wayshot_conn.get_all_outputs();
+        let captured_at = chrono::Local::now();
+        let capture_start = Instant::now();
+        let captures = match cli.thumbnail {
+            Some(max_dim) => wayshot_conn.thumbnail_all_separate(cursor, max_dim)?,
+            None => wayshot_conn.screenshot_all_separate(cursor)?,
+        };
+        if cli.timings {
+            timings.record("capture", capture_start.elapsed());
+        }
+        capture_feedback(outputs);
+
+        let jobs = cli.jobs.map_or_else(
+            || thread::available_parallelism().map_or(1, NonZeroUsize::get),
+            |jobs| jobs as usize,
+        );
+        let results = utils::run_parallel(&captures, jobs, |capture| {
+            let OutputCapture {
+                output,
+                image,
+                capture_id,
+                workspace,
+                ..
+            } = capture;
+            let output_path = utils::get_output_file_name(path, &output.name);
+            let result = save_image(image, *capture_id, &output_path).and_then(|checksum| {
+                if !cli.sidecar {
+                    return Ok(());
+                }
+                let mut metadata = metadata::CaptureMetadata::new(
+                    captured_at,
+                    encoding,
+                    (image.width(), image.height()),
+                    Some(output.logical_region),
+                    &[output],
+                );
+                metadata.checksums = checksum.into_iter().collect();
+                metadata.capture_id = *capture_id;
+                metadata.outputs[0].workspace = workspace.clone();
+                metadata::write_sidecar(&output_path, &metadata)?;
+                Ok(())
+            });
+            (output_path, result)
+        });
+        let mut failed = 0;
+        let mut unverified = 0;
+        for (output_path, result) in &results {
+            if let Err(e) = result {
+                tracing::error!("Failed to save {}: {e:#}", output_path.display());
+                failed += 1;
+                if ErrorKind::of(e) == ErrorKind::VerificationFailed {
+                    unverified += 1;
+                }
+            }
+        }
+        if failed > 0 && failed == unverified {
+            return Err(libwayshot::Error::VerificationFailed(format!(
+                "{failed} of {} files don't hold the image",
+                results.len()
+            ))
+            .into());
+        }
+        if failed > 0 {
+            bail!("{failed} of {} files could not be saved", results.len());
+        }
+        if let Some(template) = &cli.exec {
+            for (capture, (output_path, _)) in captures.iter().zip(&results) {
+                utils::run_exec(&utils::expand_exec_template(
+                    template,
+                    output_path,
+                    Some(capture.output.logical_region),
+                    &[&capture.output.name],
+                ))?;
+            }
+        }
+        if notifications_enabled {
+            send_notification(Ok(ShotResult::All));
+        }
+        return Ok(Outcome {
+            shot: Some(ShotResult::All),
+            region: outputs.try_into().ok(),
+            capture_id: wayshot_conn.last_capture_id(),
+            files: results
+                .into_iter()
+                .map(|(output_path, _)| output_path)
+                .collect(),
+            ..Outcome::default()
+        });
+    }
+
+    // A single output saved as QOI is encoded straight from the captured frame,
+    // unless the image is needed for anything else.
+    let direct_qoi = encoding == EncodingFormat::Qoi
+        && cli.keeps_frame_as_is()
+        && output_resolution.is_none()
+        && !(clipboard || stdout_print);
+    if direct_qoi && let (Some(path), Some(output_name)) = (&file, &output) {
+        let outputs = wayshot_conn.get_all_outputs();
+        let Some(output_info) = outputs.iter().find(|output| output.name == *output_name) else {
+            bail!("No output found!");
+        };
+        let capture_start = Instant::now();
+        utils::write_atomically(path, |file| {
+            let mut file_writer = BufWriter::new(file);
+            wayshot_conn.screenshot_single_output_qoi(output_info, cursor, &mut file_writer)?;
+            Ok(file_writer.flush()?)
+        })?;
+        emit_progress(Progress::Encoded { format: encoding });
+        emit_progress(Progress::Written { path });
+        if cli.timings {
+            timings.record("capture and encode", capture_start.elapsed());
+        }
+        capture_feedback(slice::from_ref(output_info));
+        remember_last_region(output_info.logical_region);
+        if let Some(template) = &cli.exec {
+            utils::run_exec(&utils::expand_exec_template(
+                template,
+                path,
+                Some(output_info.logical_region),
+                &[&output_info.name],
+            ))?;
+        }
+        let shot = ShotResult::Output {
+            name: output_name.clone(),
+        };
+        if notifications_enabled {
+            send_notification(Ok(shot.clone()));
+        }
+        return Ok(Outcome {
+            shot: Some(shot),
+            region: Some(output_info.logical_region),
+            capture_id: wayshot_conn.last_capture_id(),
+            files: vec![path.clone()],
+            ..Outcome::default()
+        });
+    }
+
+    let captured_at = chrono::Local::now();
+    let selected_region = Rc::new(Cell::new(None));
+    let capture_start = Instant::now();
+    let result = (|| -> Result<(image::DynamicImage, ShotResult)> {
+        if cli.last || cli.last_offset.is_some() {
+            let Some(state_path) = libwayshot::state::last_region_path() else {
+                bail!("--last needs XDG_STATE_HOME or HOME to find the last region");
+            };
+            let Some(last_region) = libwayshot::state::read_last_region(&state_path)? else {
+                bail!(
+                    "No region was captured yet, {} is missing",
+                    state_path.display()
+                );
+            };
+            let (dx, dy) = cli.last_offset.unwrap_or_default();
+            let region = last_region
+                .translated(dx, dy)
+                .check_selection(wayshot_conn.get_all_outputs())?;
+            selected_region.set(Some(region));
+            return Ok((wayshot_conn.screenshot(region, cursor)?, ShotResult::Area));
+        }
+        if let Some(region) = cli.relative_region {
+            // Sway and Hyprland know the focus of each seat, elsewhere the pointer tells.
+            let output_name = match &output {
+                Some(output_name) => Some(output_name.clone()),
+                None if foreign_display.is_none() && selection::has_compositor_ipc() => {
+                    Some(selection::focused_output(&seat)?)
+                }
+                None => None,
+            };
+            let (image, region) = match output_name {
+                Some(output_name) => {
+                    let outputs = wayshot_conn.get_all_outputs();
+                    let Some(output_info) =
+                        outputs.iter().find(|output| output.name == output_name)
+                    else {
+                        bail!("No output named '{output_name}'");
+                    };
+                    let image =
+                        wayshot_conn.screenshot_output_logical_region(output_info, region, cursor)?;
+                    let Position { x, y } = output_info.logical_region.inner.position;
+                    (image, LogicalRegion { inner: region }.translated(x, y))
+                }
+                None => {
+                    if seat != SeatSelector::Default {
+                        bail!("--seat {seat} picks whose focus to follow, which needs Sway or Hyprland");
+                    }
+                    wayshot_conn.screenshot_focused_output_region(region, cursor)?
+                }
+            };
+            selected_region.set(Some(region));
+            return Ok((image, ShotResult::Area));
+        }
+        let selection_mode = if cli.geometry {
+            Some(SelectionMode::Rectangle)
+        } else if cli.window_pick {
+            if let Some(display) = &foreign_display {
+                bail!(
+                    "--window-pick asks the compositor of this session for the window geometry, \
+                     which is not the one on --display {display}"
+                );
+            }
+            // Ask before freezing so a missing IPC fails without an overlay.
+            Some(SelectionMode::Window(selection::visible_windows()?))
+        } else if cli.output_pick {
+            Some(SelectionMode::Output)
+        } else {
+            None
+        };
+        if let Some(selection_mode) = selection_mode {
+            if warn_on_large {
+                warn_if_capture_is_large(&wayshot_conn, cursor);
+            }
+            let selected_region = selected_region.clone();
+            Ok((
+                wayshot_conn.screenshot_freeze(
+                    move |w_conn| {
+                        let region = w_conn.select_region_interactive(&selection_mode)?;
+                        selected_region.set(Some(region));
+                        Ok(region)
+                    },
+                    cursor,
+                )?,
+                ShotResult::Area,
+            ))
+        } else if let Some(ref name) = cli.toplevel {
+            let toplevels = wayshot_conn.get_all_toplevels();
+            let maybe = toplevels
+                .iter()
+                .filter(|t| t.active)
+                .find(|t| t.id_and_title() == *name);
+            if let Some(toplevel) = maybe {
+                Ok((
+                    wayshot_conn.screenshot_toplevel(toplevel, cursor)?,
+                    ShotResult::Toplevel { name: name.clone() },
+                ))
+            } else {
+                bail!("No toplevel window matched '{name}'")
+            }
+        } else if cli.choose_toplevel {
+            let toplevels = wayshot_conn.get_all_toplevels();
+            let active: Vec<_> = toplevels.iter().filter(|t| t.active).collect();
+            if active.is_empty() {
+                bail!("No active toplevel windows found!");
+            }
+            let names: Vec<String> = active.iter().map(|t| t.id_and_title()).collect();
+            if let Some(idx) = select_output(&names) {
+                Ok((
+                    wayshot_conn.screenshot_toplevel(active[idx], cursor)?,
+                    ShotResult::Toplevel {
+                        name: names[idx].clone(),
+                    },
+                ))
+            } else {
+                bail!("No toplevel window selected!");
+            }
+        } else if let Some(output_name) = output {
+            let outputs = wayshot_conn.get_all_outputs();
+            if let Some(output) = outputs.iter().find(|output| output.name == output_name) {
+                Ok((
+                    screenshot_output(output)?,
+                    ShotResult::Output {
+                        name: output_name.clone(),
+                    },
+                ))
+            } else {
+                bail!("No output found!");
+            }
+        } else if cli.choose_output {
+            let outputs = wayshot_conn.get_all_outputs();
+            let output_names: Vec<&str> = outputs
+                .iter()
+                .map(|display| display.name.as_str())
+                .collect();
+            if let Some(index) = select_output(&output_names) {
+                Ok((
+                    screenshot_output(&outputs[index])?,
+                    ShotResult::Output {
+                        name: output_names[index].to_string(),
+                    },
+                ))
+            } else {
+                bail!("No output found!");
+            }
+        } else {
+            if warn_on_large {
+                warn_if_capture_is_large(&wayshot_conn, cursor);
+            }
+            let image = if cli.debug_borders {
+                let options = ComposeOptions::new().draw_output_borders(
+                    DEBUG_BORDER_COLOR,
+                    DEBUG_BORDER_THICKNESS,
+                    true,
+                );
+                wayshot_conn
+                    .screenshot_outputs_with_layout(
+                        wayshot_conn.get_all_outputs(),
+                        cursor,
+                        options,
+                    )?
+                    .image
+                    .into()
+            } else {
+                wayshot_conn.screenshot_all(cursor)?
+            };
+            Ok((image, ShotResult::All))
+        }
+    })()
+    .map(
+        |(image_buffer, shot_result)| match (cli.thumbnail, &shot_result) {
+            // Single outputs are already downscaled while capturing.
+            (Some(max_dim), ShotResult::Area | ShotResult::All | ShotResult::Toplevel { .. }) => {
+                (image_buffer.thumbnail(max_dim, max_dim), shot_result)
+            }
+            _ => (image_buffer, shot_result),
+        },
+    )
+    .map(|(image_buffer, shot_result)| {
+        (mask_image(image_buffer, cli.shape, cli.radius), shot_result)
+    });
+
+    match result {
+        Ok((image_buffer, shot_result)) => {
+            if cli.timings {
+                timings.record("capture", capture_start.elapsed());
+                timings.note(format!(
+                    "capture queue events: {}",
+                    wayshot_conn.capture_queue_events()
+                ));
+            }
+            let (region, outputs) = shot_region_and_outputs(
+                &shot_result,
+                selected_region.get(),
+                wayshot_conn.get_all_outputs(),
+            );
+            if bell || flash {
+                let outputs: Vec<OutputInfo> = outputs.iter().copied().cloned().collect();
+                capture_feedback(&outputs);
+            }
+            if let Some(region) = region {
+                remember_last_region(region);
+            }
+
+            let capture_id = wayshot_conn.last_capture_id();
+            let files = deliver(
+                &image_buffer,
+                captured_at,
+                region,
+                &outputs,
+                capture_id,
+                writer,
+            )?;
+
+            if notifications_enabled {
+                send_notification(Ok(shot_result.clone()));
+            }
+
+            Ok(Outcome {
+                shot: Some(shot_result),
+                region,
+                capture_id,
+                files,
+                clipboard: clipboard_image.into_inner(),
+                ..Outcome::default()
+            })
+        }
+        Err(e) => {
+            if notifications_enabled {
+                send_notification(Err(&e));
+            }
+            Err(e)
+        }
+    }
+}
+
+/// Cut `image` to `shape` or round its corners by `radius`, if either is given.
+fn mask_image(
+    image: image::DynamicImage,
+    shape: Option<MaskShape>,
+    radius: Option<u32>,
+) -> image::DynamicImage {
+    if shape.is_none() && radius.is_none() {
+        return image;
+    }
+    let mut image = image.into_rgba8();
+    if shape == Some(MaskShape::Circle) {
+        let (width, height) = (image.width() as f64, image.height() as f64);
+        libwayshot::apply_circle_mask(
+            &mut image,
+            (width / 2.0, height / 2.0),
+            width.min(height) / 2.0,
+        );
+    }
+    if let Some(radius) = radius {
+        libwayshot::apply_rounded_rect_mask(&mut image, radius as f64);
+    }
+    image.into()
+}
+
+/// The logical region of a finished shot and the outputs it was taken from.
+fn shot_region_and_outputs<'a>(
+    shot_result: &ShotResult,
+    selected_region: Option<LogicalRegion>,
+    outputs: &'a [OutputInfo],
+) -> (Option<LogicalRegion>, Vec<&'a OutputInfo>) {
+    match shot_result {
+        ShotResult::Output { name } => {
+            let output = outputs.iter().find(|output| output.name == *name);
+            (
+                output.map(|output| output.logical_region),
+                output.into_iter().collect(),
+            )
+        }
+        ShotResult::All => (outputs.try_into().ok(), outputs.iter().collect()),
+        ShotResult::Area => (
+            selected_region,
+            outputs
+                .iter()
+                .filter(|output| {
+                    selected_region.is_some_and(|region| region.overlaps(&output.logical_region))
+                })
+                .collect(),
+        ),
+        ShotResult::Toplevel { .. } => (None, Vec::new()),
+    }
+}
+
+/// Store `region` for `--last`. Failing to is not worth failing the capture over.
+fn remember_last_region(region: LogicalRegion) {
+    let Some(path) = libwayshot::state::last_region_path() else {
+        return;
+    };
+    if let Err(e) = libwayshot::state::write_last_region(&path, region) {
+        tracing::warn!(
+            "Failed to store the region for --last in {}: {e}",
+            path.display()
+        );
+    }
+}
+
+/// Warn when capturing all outputs likely needs more than half of the available
+/// memory, e.g. on small devices driving large displays. With `--warn-on-large`
+/// only, as the estimate negotiates a buffer for every output.
+fn warn_if_capture_is_large(wayshot_conn: &WayshotConnection, cursor: bool) {
+    let Some(available) = utils::available_memory() else {
+        return;
+    };
+    match wayshot_conn.estimate_capture_bytes(wayshot_conn.get_all_outputs(), cursor) {
+        Ok(bytes) if bytes > available / 2 => tracing::warn!(
+            "The capture needs about {} MiB, {} MiB of memory are available",
+            bytes / (1024 * 1024),
+            available / (1024 * 1024)
+        ),
+        Ok(bytes) => tracing::debug!("The capture needs about {bytes} bytes"),
+        Err(e) => tracing::debug!("Failed to estimate the capture size: {e}"),
+    }
+}
+
+/// The diagnostics of `display`, or of the display in the environment, as
+/// printed along with errors, over a connection of their own. For panic
+/// hooks, as the panicking thread may hold the connection of [`run`].
+pub fn diagnostics(display: Option<&str>) -> Result<String> {
+    Ok(metadata::Diagnostics::of(&connect(display)?).to_string())
+}
+
+/// Connect to `display`, or to the display in the environment without one.
+fn connect(display: Option<&str>) -> libwayshot::Result<WayshotConnection> {
+    match display {
+        Some(display) => WayshotConnection::connect_to(display),
+        None => WayshotConnection::new(),
+    }
+}
+
+/// Connect to the compositor and wait up to `timeout` for at least one configured output.
+fn connect_waiting_for_outputs(
+    timeout: Duration,
+    display: Option<&str>,
+) -> Result<WayshotConnection> {
+    const RECONNECT_INTERVAL: Duration = Duration::from_millis(100);
+
+    let deadline = Instant::now() + timeout;
+    loop {
+        match connect(display) {
+            Ok(mut wayshot_conn) => {
+                wayshot_conn
+                    .wait_for_outputs(1, deadline.saturating_duration_since(Instant::now()))?;
+                return Ok(wayshot_conn);
+            }
+            // Without any wl_output global there is nothing to wait on yet, so reconnect.
+            Err(libwayshot::Error::NoOutputs) if Instant::now() < deadline => {
+                thread::sleep(
+                    RECONNECT_INTERVAL.min(deadline.saturating_duration_since(Instant::now())),
+                );
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+/// Continuously capture `output_info` and write each frame as PPM to `path`,
+/// paced to `fps` when given. Returns once the reader closes the file. The
+/// achieved frame rate is recorded in `timings`, if given.
+fn stream_ppm_frames(
+    wayshot_conn: &WayshotConnection,
+    output_info: &OutputInfo,
+    cursor: bool,
+    path: &Path,
+    fps: Option<f64>,
+    timings: Option<&Timings>,
+) -> Result<()> {
+    // Opening a FIFO for writing blocks until a reader shows up.
+    let mut writer = BufWriter::new(
+        OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?,
+    );
+    let mut pacer = fps.map(FramePacer::new).transpose()?;
+    let stop = AtomicBool::new(false);
+
+    // The loop only ends when the reader goes away, so pace it here to keep
+    // the stats when it does.
+    let result = wayshot_conn.capture_loop(output_info, cursor, &stop, |frame| {
+        frame.write_ppm(&mut writer)?;
+        writer.flush()?;
+        if let Some(pacer) = &mut pacer {
+            pacer.wait();
+        }
+        Ok(())
+    });
+    if let Some(stats) = pacer.map(|pacer| pacer.stats()) {
+        tracing::debug!("Loop stats: {stats:?}");
+        if let Some(timings) = timings {
+            timings.note(format!(
+                "{} frames at {:.1} fps, {} skipped",
+                stats.frames,
+                stats.achieved_fps(),
+                stats.skipped
+            ));
+        }
+    }
+    match result {
+        Err(libwayshot::Error::Io(e)) if e.kind() == io::ErrorKind::BrokenPipe => {
+            tracing::info!("Reader of {} disconnected, stopping", path.display());
+            Ok(())
+        }
+        result => Ok(result?),
+    }
+}
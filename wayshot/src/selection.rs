@@ -0,0 +1,341 @@
+use std::{
+    env,
+    io::{Read, Write},
+    os::unix::net::UnixStream,
+    path::PathBuf,
+};
+
+use eyre::{Context, Result, bail, eyre};
+use libwayshot::{
+    SeatSelector,
+    region::{LogicalRegion, Position, Region, Size},
+};
+use serde_json::Value;
+
+/// Geometry of the visible windows, topmost first, asked from the compositor
+/// over its IPC since Wayland does not expose window positions.
+pub fn visible_windows() -> Result<Vec<LogicalRegion>> {
+    if let Some(socket) = env::var_os("SWAYSOCK") {
+        let tree = sway_request(&PathBuf::from(socket), SWAY_GET_TREE)?;
+        return Ok(sway_visible_windows(&tree));
+    }
+    if let Some(signature) = env::var_os("HYPRLAND_INSTANCE_SIGNATURE") {
+        let socket = hyprland_socket(&PathBuf::from(signature))?;
+        let clients = hyprland_request(&socket, "j/clients")?;
+        let monitors = hyprland_request(&socket, "j/monitors")?;
+        return Ok(hyprland_visible_windows(&clients, &monitors));
+    }
+    bail!("picking a window needs the window geometry from Sway or Hyprland")
+}
+
+/// Whether the IPC of Sway or Hyprland is there to ask for the windows and
+/// the focus.
+pub fn has_compositor_ipc() -> bool {
+    env::var_os("SWAYSOCK").is_some() || env::var_os("HYPRLAND_INSTANCE_SIGNATURE").is_some()
+}
+
+/// Name of the output that has the focus of `seat`, asked from the compositor
+/// over its IPC since Wayland does not tell clients where the focus is.
+///
+/// Only Sway tracks the focus per seat, Hyprland has a single seat.
+pub fn focused_output(seat: &SeatSelector) -> Result<String> {
+    let focused = if let Some(socket) = env::var_os("SWAYSOCK") {
+        let socket = PathBuf::from(socket);
+        match seat {
+            SeatSelector::Default => focused_name(&sway_request(&socket, SWAY_GET_OUTPUTS)?),
+            SeatSelector::Named(name) => sway_seat_focus(
+                &sway_request(&socket, SWAY_GET_SEATS)?,
+                &sway_request(&socket, SWAY_GET_TREE)?,
+                name,
+            ),
+            SeatSelector::All => bail!("every seat has its own focus, pick one with --seat"),
+        }
+    } else if let Some(signature) = env::var_os("HYPRLAND_INSTANCE_SIGNATURE") {
+        let socket = hyprland_socket(&PathBuf::from(signature))?;
+        focused_name(&hyprland_request(&socket, "j/monitors")?)
+    } else {
+        bail!("finding the focused output needs Sway or Hyprland")
+    };
+    focused.ok_or_else(|| eyre!("the compositor reported no focused output"))
+}
+
+/// The focused output of a Sway `GET_OUTPUTS` or Hyprland `monitors` reply,
+/// which both flag it with `"focused": true`.
+fn focused_name(outputs: &Value) -> Option<String> {
+    outputs
+        .as_array()?
+        .iter()
+        .find(|output| output["focused"].as_bool() == Some(true))
+        .and_then(|output| Some(output["name"].as_str()?.to_string()))
+}
+
+const SWAY_MAGIC: &[u8] = b"i3-ipc";
+const SWAY_GET_OUTPUTS: u32 = 3;
+const SWAY_GET_TREE: u32 = 4;
+const SWAY_GET_SEATS: u32 = 101;
+
+/// The output holding the node focused by the seat `name`, out of Sway
+/// `GET_SEATS` and `GET_TREE` replies.
+fn sway_seat_focus(seats: &Value, tree: &Value, name: &str) -> Option<String> {
+    fn find(node: &Value, focus: u64, output: Option<&str>) -> Option<String> {
+        let output = match node["type"].as_str() {
+            Some("output") => node["name"].as_str(),
+            _ => output,
+        };
+        if node["id"].as_u64() == Some(focus) {
+            return output.map(str::to_string);
+        }
+        ["nodes", "floating_nodes"]
+            .iter()
+            .filter_map(|key| node[key].as_array())
+            .flatten()
+            .find_map(|child| find(child, focus, output))
+    }
+
+    let focus = seats
+        .as_array()?
+        .iter()
+        .find(|seat| seat["name"].as_str() == Some(name))?["focus"]
+        .as_u64()?;
+    find(tree, focus, None)
+}
+
+fn sway_request(socket: &PathBuf, message_type: u32) -> Result<Value> {
+    let mut stream = UnixStream::connect(socket)
+        .wrap_err_with(|| format!("failed to connect to Sway at {}", socket.display()))?;
+    let mut request = SWAY_MAGIC.to_vec();
+    request.extend(0u32.to_ne_bytes());
+    request.extend(message_type.to_ne_bytes());
+    stream.write_all(&request)?;
+
+    let mut header = [0; 14];
+    stream.read_exact(&mut header)?;
+    if &header[..6] != SWAY_MAGIC {
+        bail!("unexpected reply from Sway");
+    }
+    let length = u32::from_ne_bytes(header[6..10].try_into()?);
+    let mut payload = vec![0; length as usize];
+    stream.read_exact(&mut payload)?;
+    Ok(serde_json::from_slice(&payload)?)
+}
+
+/// Windows in a Sway `GET_TREE` reply, floating ones first as they are on top.
+fn sway_visible_windows(tree: &Value) -> Vec<LogicalRegion> {
+    fn collect(node: &Value, floating: bool, windows: &mut Vec<(bool, LogicalRegion)>) {
+        let is_window = node.get("pid").is_some_and(|pid| !pid.is_null());
+        if is_window && node["visible"].as_bool() == Some(true) {
+            let rect = &node["rect"];
+            if let Some(region) = region(&rect["x"], &rect["y"], &rect["width"], &rect["height"]) {
+                windows.push((floating, region));
+            }
+        }
+        for (key, floating) in [("floating_nodes", true), ("nodes", floating)] {
+            for child in node[key].as_array().into_iter().flatten() {
+                collect(child, floating, windows);
+            }
+        }
+    }
+
+    let mut windows = Vec::new();
+    collect(tree, false, &mut windows);
+    // Stable, so the tree order is kept otherwise.
+    windows.sort_by_key(|(floating, _)| !floating);
+    windows.into_iter().map(|(_, region)| region).collect()
+}
+
+fn hyprland_socket(signature: &PathBuf) -> Result<PathBuf> {
+    let runtime_dir = env::var_os("XDG_RUNTIME_DIR").map(PathBuf::from);
+    // Hyprland moved its sockets from /tmp to the runtime directory in 0.40.
+    runtime_dir
+        .map(|runtime_dir| runtime_dir.join("hypr"))
+        .into_iter()
+        .chain([PathBuf::from("/tmp/hypr")])
+        .map(|dir| dir.join(signature).join(".socket.sock"))
+        .find(|socket| socket.exists())
+        .ok_or_else(|| eyre!("Hyprland socket not found"))
+}
+
+fn hyprland_request(socket: &PathBuf, request: &str) -> Result<Value> {
+    let mut stream = UnixStream::connect(socket)
+        .wrap_err_with(|| format!("failed to connect to Hyprland at {}", socket.display()))?;
+    stream.write_all(request.as_bytes())?;
+    let mut reply = Vec::new();
+    stream.read_to_end(&mut reply)?;
+    Ok(serde_json::from_slice(&reply)?)
+}
+
+/// Mapped windows on the active workspaces of a Hyprland `clients` reply,
+/// floating ones first and otherwise most recently focused first.
+fn hyprland_visible_windows(clients: &Value, monitors: &Value) -> Vec<LogicalRegion> {
+    let active_workspaces: Vec<&Value> = monitors
+        .as_array()
+        .into_iter()
+        .flatten()
+        .flat_map(|monitor| {
+            [
+                &monitor["activeWorkspace"]["id"],
+                &monitor["specialWorkspace"]["id"],
+            ]
+        })
+        .filter(|id| !id.is_null())
+        .collect();
+
+    let mut windows: Vec<_> = clients
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter(|client| {
+            client["mapped"].as_bool() == Some(true)
+                && client["hidden"].as_bool() != Some(true)
+                && active_workspaces.contains(&&client["workspace"]["id"])
+        })
+        .filter_map(|client| {
+            let region = region(
+                &client["at"][0],
+                &client["at"][1],
+                &client["size"][0],
+                &client["size"][1],
+            )?;
+            let floating = client["floating"].as_bool() == Some(true);
+            let focus_history = client["focusHistoryID"].as_i64().unwrap_or(i64::MAX);
+            Some(((!floating, focus_history), region))
+        })
+        .collect();
+    windows.sort_by_key(|(order, _)| *order);
+    windows.into_iter().map(|(_, region)| region).collect()
+}
+
+fn region(x: &Value, y: &Value, width: &Value, height: &Value) -> Option<LogicalRegion> {
+    let size = Size {
+        width: width.as_u64()?.try_into().ok()?,
+        height: height.as_u64()?.try_into().ok()?,
+    };
+    if size.width == 0 || size.height == 0 {
+        return None;
+    }
+    Some(LogicalRegion {
+        inner: Region {
+            position: Position {
+                x: x.as_i64()?.try_into().ok()?,
+                y: y.as_i64()?.try_into().ok()?,
+            },
+            size,
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn sway_windows_are_visible_leaves_with_floating_first() {
+        let tree = json!({
+            "type": "root",
+            "nodes": [{
+                "type": "workspace",
+                "nodes": [
+                    {"pid": 10, "visible": true, "rect": {"x": 0, "y": 0, "width": 960, "height": 1080}},
+                    {"pid": 11, "visible": false, "rect": {"x": 960, "y": 0, "width": 960, "height": 1080}},
+                    {"pid": null, "visible": true, "rect": {"x": 0, "y": 0, "width": 1920, "height": 1080}},
+                ],
+                "floating_nodes": [
+                    {"pid": 12, "visible": true, "rect": {"x": 100, "y": 200, "width": 300, "height": 400}},
+                ],
+            }],
+        });
+
+        assert_eq!(
+            sway_visible_windows(&tree),
+            [
+                LogicalRegion::new(100, 200, 300, 400),
+                LogicalRegion::new(0, 0, 960, 1080),
+            ]
+        );
+    }
+
+    #[test]
+    fn hyprland_windows_on_active_workspaces_in_stacking_order() {
+        let monitors = json!([
+            {"activeWorkspace": {"id": 1}, "specialWorkspace": {"id": 0, "name": ""}},
+            {"activeWorkspace": {"id": 2}},
+        ]);
+        let client = |at: [i32; 2], size: [u32; 2], workspace: i32, floating: bool, focus: i32| {
+            json!({
+                "mapped": true,
+                "hidden": false,
+                "at": at,
+                "size": size,
+                "workspace": {"id": workspace},
+                "floating": floating,
+                "focusHistoryID": focus,
+            })
+        };
+        let clients = json!([
+            client([0, 0], [960, 1080], 1, false, 2),
+            client([960, 0], [960, 1080], 1, false, 1),
+            client([1920, 0], [2560, 1440], 2, false, 3),
+            client([0, 0], [500, 500], 3, false, 0),
+            client([50, 60], [400, 300], 1, true, 4),
+        ]);
+
+        assert_eq!(
+            hyprland_visible_windows(&clients, &monitors),
+            [
+                LogicalRegion::new(50, 60, 400, 300),
+                LogicalRegion::new(960, 0, 960, 1080),
+                LogicalRegion::new(0, 0, 960, 1080),
+                LogicalRegion::new(1920, 0, 2560, 1440),
+            ]
+        );
+    }
+
+    #[test]
+    fn focused_output_is_flagged_in_the_reply() {
+        let sway = json!([
+            {"name": "eDP-1", "focused": false, "active": true},
+            {"name": "DP-1", "focused": true, "active": true},
+        ]);
+        assert_eq!(focused_name(&sway).as_deref(), Some("DP-1"));
+
+        let hyprland = json!([{"id": 0, "name": "HDMI-A-1", "focused": true}]);
+        assert_eq!(focused_name(&hyprland).as_deref(), Some("HDMI-A-1"));
+
+        assert_eq!(
+            focused_name(&json!([{"name": "DP-1", "focused": false}])),
+            None
+        );
+        assert_eq!(focused_name(&json!({"error": "unknown request"})), None);
+    }
+
+    #[test]
+    fn sway_seat_focus_is_found_in_the_tree() {
+        let seats = json!([
+            {"name": "seat0", "focus": 7},
+            {"name": "seat1", "focus": 12},
+            {"name": "seat2", "focus": 0},
+        ]);
+        let tree = json!({"id": 1, "type": "root", "nodes": [
+            {"id": 3, "type": "output", "name": "eDP-1", "nodes": [
+                {"id": 5, "type": "workspace", "nodes": [{"id": 7, "type": "con"}]},
+            ]},
+            {"id": 4, "type": "output", "name": "DP-1", "nodes": [
+                {"id": 6, "type": "workspace", "nodes": [], "floating_nodes": [
+                    {"id": 12, "type": "floating_con"},
+                ]},
+            ]},
+        ]});
+        assert_eq!(
+            sway_seat_focus(&seats, &tree, "seat0").as_deref(),
+            Some("eDP-1")
+        );
+        assert_eq!(
+            sway_seat_focus(&seats, &tree, "seat1").as_deref(),
+            Some("DP-1")
+        );
+        // Nothing focused, or no such seat.
+        assert_eq!(sway_seat_focus(&seats, &tree, "seat2"), None);
+        assert_eq!(sway_seat_focus(&seats, &tree, "seat3"), None);
+    }
+}
@@ -1,44 +1,40 @@
 use clap::ValueEnum;
-use eyre::{ContextCompat, Error, bail};
+use eyre::{ContextCompat, Error, WrapErr, bail};
 use notify_rust::Notification;
 
-use image::DynamicImage;
+use image::{DynamicImage, GrayImage, imageops};
 use jpegxl_rs::encode::{EncoderResult, EncoderSpeed};
 use serde::{Deserialize, Serialize};
 use std::{
     env,
+    ffi::{OsStr, OsString},
     fmt::Display,
-    fs::File,
-    io::Write,
+    fs::{self, File, OpenOptions},
+    io::{Cursor, ErrorKind, Read, Seek, Write},
+    os::unix::fs::PermissionsExt,
     path::{Path, PathBuf},
+    process::{Command, Stdio},
     str::FromStr,
+    sync::{
+        Mutex, PoisonError,
+        atomic::{AtomicUsize, Ordering},
+    },
+    thread,
+    time::Duration,
 };
 
 use chrono::Local;
-use libwayshot::Result;
-use libwayshot::region::{LogicalRegion, Position, Region, Size};
-
-pub fn waysip_to_region(
-    size: libwaysip::Size,
-    position: libwaysip::Position,
-) -> Result<LogicalRegion> {
-    let size: Size = Size {
-        width: size.width.try_into().map_err(|_| {
-            libwayshot::Error::FreezeCallbackError("width cannot be negative".to_string())
-        })?,
-        height: size.height.try_into().map_err(|_| {
-            libwayshot::Error::FreezeCallbackError("height cannot be negative".to_string())
-        })?,
-    };
-    let position: Position = Position {
-        x: position.x,
-        y: position.y,
-    };
+use libwayshot::region::{LogicalRegion, Region};
+use libwayshot::{ChecksumAlgorithm, EncodingOptions, HashingWriter, PixelFormat, Result};
+use wl_clipboard_rs::{
+    copy::{MimeType, Options, Source},
+    paste,
+};
 
-    Ok(LogicalRegion {
-        inner: Region { position, size },
-    })
-}
+/// How often [`ClipboardImage::verify`] reads the clipboard back before giving up, and how
+/// long it waits before each try.
+const CLIPBOARD_VERIFY_ATTEMPTS: u32 = 20;
+const CLIPBOARD_VERIFY_INTERVAL: Duration = Duration::from_millis(50);
 
 /// Supported image encoding formats.
 #[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum, Serialize, Deserialize, Default)]
@@ -59,6 +55,241 @@ pub enum EncodingFormat {
     Avif,
     /// JPEG-XL encoder,
     Jxl,
+    /// SVG embedding the image as PNG, at 1:1 pixel size.
+    Svg,
+    /// DDS texture, uncompressed RGBA. Needs the `dds` feature.
+    Dds,
+}
+
+impl EncodingFormat {
+    /// MIME type of the encoded image.
+    pub fn mime_type(self) -> &'static str {
+        match self {
+            Self::Jpg => "image/jpeg",
+            Self::Png => "image/png",
+            Self::Ppm => "image/x-portable-pixmap",
+            Self::Qoi => "image/qoi",
+            Self::Webp => "image/webp",
+            Self::Avif => "image/avif",
+            Self::Jxl => "image/jxl",
+            Self::Svg => "image/svg+xml",
+            Self::Dds => "image/vnd-ms.dds",
+        }
+    }
+
+    /// Whether the encoded image keeps the alpha channel.
+    pub fn supports_alpha(self) -> bool {
+        matches!(
+            self,
+            Self::Png | Self::Qoi | Self::Webp | Self::Avif | Self::Svg | Self::Dds
+        )
+    }
+
+    /// Whether images converted to `pixel_format` can be encoded.
+    pub fn supports_pixel_format(self, pixel_format: PixelFormat) -> bool {
+        match self {
+            // The JXL encoder always takes RGB.
+            Self::Jxl => pixel_format == PixelFormat::Rgb8,
+            Self::Svg => pixel_format.is_supported_by(image::ImageFormat::Png),
+            // DDS textures are always written as RGBA.
+            Self::Dds => pixel_format == PixelFormat::Rgba8,
+            _ => pixel_format.is_supported_by(self.into()),
+        }
+    }
+}
+
+/// Shape the screenshot is cut to with `--shape`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum MaskShape {
+    /// The largest circle centered on the image.
+    Circle,
+}
+
+/// Clipboard(s) the screenshot is offered on.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ClipboardTarget {
+    /// Regular clipboard (Ctrl+V).
+    #[default]
+    Regular,
+    /// Primary selection (middle-click paste).
+    Primary,
+    /// Both the regular clipboard and the primary selection.
+    Both,
+}
+
+impl From<ClipboardTarget> for wl_clipboard_rs::copy::ClipboardType {
+    fn from(target: ClipboardTarget) -> Self {
+        match target {
+            ClipboardTarget::Regular => Self::Regular,
+            ClipboardTarget::Primary => Self::Primary,
+            ClipboardTarget::Both => Self::Both,
+        }
+    }
+}
+
+/// An encoded image to offer on the clipboard over data-control, see
+/// [`crate::Outcome::clipboard`].
+///
+/// The clipboard only serves it while a process offers it, which `wayshot` leaves to a
+/// child it forks once the run is done.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClipboardImage {
+    pub bytes: Vec<u8>,
+    pub target: ClipboardTarget,
+    /// Whether `--verify` asks to read the clipboard back once it is offered.
+    pub verify: bool,
+}
+
+impl ClipboardImage {
+    /// Offer the image until something else is copied, returning only then.
+    pub fn serve(self) -> eyre::Result<()> {
+        let mut opts = Options::new();
+        opts.clipboard(self.target.into()).foreground(true);
+        opts.copy(Source::Bytes(self.bytes.into()), MimeType::Autodetect)?;
+        Ok(())
+    }
+
+    /// Offer the image from a thread of this process, for as long as it runs.
+    pub fn offer(&self) -> eyre::Result<()> {
+        let mut opts = Options::new();
+        opts.clipboard(self.target.into());
+        opts.copy(
+            Source::Bytes(self.bytes.clone().into()),
+            MimeType::Autodetect,
+        )?;
+        Ok(())
+    }
+
+    /// Read the clipboard until it serves the image, giving the process that offers it
+    /// [`CLIPBOARD_VERIFY_ATTEMPTS`] tries to take over the selection.
+    pub fn verify(&self) -> eyre::Result<()> {
+        // Both selections get the same bytes, checking the regular one is enough.
+        let clipboard = match self.target {
+            ClipboardTarget::Primary => paste::ClipboardType::Primary,
+            ClipboardTarget::Regular | ClipboardTarget::Both => paste::ClipboardType::Regular,
+        };
+        let mut served = None;
+        for _ in 0..CLIPBOARD_VERIFY_ATTEMPTS {
+            thread::sleep(CLIPBOARD_VERIFY_INTERVAL);
+            let Ok((mut pipe, _)) =
+                paste::get_contents(clipboard, paste::Seat::Unspecified, paste::MimeType::Any)
+            else {
+                continue;
+            };
+            let mut bytes = Vec::new();
+            pipe.read_to_end(&mut bytes)?;
+            if bytes == self.bytes {
+                return Ok(());
+            }
+            served = Some(bytes.len());
+        }
+        Err(libwayshot::Error::VerificationFailed(match served {
+            Some(len) => format!(
+                "the clipboard serves {len} bytes instead of the {} byte image",
+                self.bytes.len()
+            ),
+            None => "the clipboard is empty".to_string(),
+        })
+        .into())
+    }
+}
+
+/// How `--clipboard` offers the image.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClipboardBackend {
+    /// Wayshot serves it itself over ext-data-control or wlr-data-control.
+    DataControl,
+    /// The `wl-copy` at this path serves it, for compositors without data-control like GNOME.
+    WlCopy(PathBuf),
+}
+
+impl Display for ClipboardBackend {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::DataControl => write!(f, "data-control"),
+            Self::WlCopy(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+/// Whether the compositor has a data-control protocol for wayshot to serve the clipboard
+/// with. When that can't be told, e.g. without a seat, it is assumed to, so copying reports
+/// what is actually wrong.
+pub fn has_data_control() -> bool {
+    !matches!(
+        wl_clipboard_rs::utils::is_primary_selection_supported(),
+        Err(wl_clipboard_rs::utils::PrimarySelectionCheckError::MissingProtocol)
+    )
+}
+
+/// Pick how to offer the clipboard: over data-control when the compositor has it, otherwise
+/// with `wl-copy` from the directories in `path`, the value of `PATH`.
+pub fn resolve_clipboard_backend(
+    data_control: bool,
+    path: Option<&OsStr>,
+) -> eyre::Result<ClipboardBackend> {
+    if data_control {
+        return Ok(ClipboardBackend::DataControl);
+    }
+    let wl_copy = path.and_then(|path| {
+        env::split_paths(path)
+            .map(|dir| dir.join("wl-copy"))
+            .find(|candidate| {
+                fs::metadata(candidate).is_ok_and(|metadata| {
+                    metadata.is_file() && metadata.permissions().mode() & 0o111 != 0
+                })
+            })
+    });
+    match wl_copy {
+        Some(wl_copy) => Ok(ClipboardBackend::WlCopy(wl_copy)),
+        None => bail!(
+            "Can't copy to the clipboard: the compositor supports neither ext-data-control nor \
+             wlr-data-control, and wl-copy isn't on PATH. Install wl-clipboard to copy with wl-copy"
+        ),
+    }
+}
+
+/// Offer `bytes` of `mime_type` on `target` of `display`, or of the display in the
+/// environment, with the `wl-copy` at `wl_copy`, which keeps serving them in the background.
+pub fn copy_with_wl_copy(
+    wl_copy: &Path,
+    display: Option<&str>,
+    bytes: &[u8],
+    mime_type: &str,
+    target: ClipboardTarget,
+) -> eyre::Result<()> {
+    let primary: &[bool] = match target {
+        ClipboardTarget::Regular => &[false],
+        ClipboardTarget::Primary => &[true],
+        ClipboardTarget::Both => &[false, true],
+    };
+    for &primary in primary {
+        let mut command = Command::new(wl_copy);
+        command.arg("--type").arg(mime_type);
+        if primary {
+            command.arg("--primary");
+        }
+        if let Some(display) = display {
+            command.env("WAYLAND_DISPLAY", display);
+        }
+        // The background wl-copy must not hold on to our stdout, e.g. a pipe.
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .spawn()
+            .wrap_err_with(|| format!("Failed to run {}", wl_copy.display()))?;
+        child
+            .stdin
+            .take()
+            .wrap_err("wl-copy has no stdin")?
+            .write_all(bytes)?;
+        let status = child.wait()?;
+        if !status.success() {
+            bail!("{} failed with {status}", wl_copy.display());
+        }
+    }
+    Ok(())
 }
 
 impl From<EncodingFormat> for image::ImageFormat {
@@ -73,6 +304,10 @@ impl From<EncodingFormat> for image::ImageFormat {
             // Note: JXL is handled separately via encode_to_jxl_bytes since image-rs doesn't support it yet
             // This fallback is only used if the code path somehow reaches here (shouldn't happen)
             EncodingFormat::Jxl => image::ImageFormat::Png,
+            // SVG wraps a PNG, see encode_to_svg.
+            EncodingFormat::Svg => image::ImageFormat::Png,
+            // The image crate only reads DDS, see encode_to_dds.
+            EncodingFormat::Dds => image::ImageFormat::Dds,
         }
     }
 }
@@ -114,6 +349,8 @@ impl From<EncodingFormat> for &str {
             EncodingFormat::Webp => "webp",
             EncodingFormat::Avif => "avif",
             EncodingFormat::Jxl => "jxl",
+            EncodingFormat::Svg => "svg",
+            EncodingFormat::Dds => "dds",
         }
     }
 }
@@ -130,6 +367,8 @@ impl FromStr for EncodingFormat {
             "webp" => Self::Webp,
             "avif" => Self::Avif,
             "jxl" => Self::Jxl,
+            "svg" => Self::Svg,
+            "dds" => Self::Dds,
             _ => bail!("unsupported extension '{s}'"),
         })
     }
@@ -177,6 +416,164 @@ pub fn get_full_file_name(path: &Path, filename_format: &str, encoding: Encoding
     }
 }
 
+/// File name of tile `index` of the image at `path`, e.g. `shot-0.png` for `shot.png`.
+pub fn get_tile_file_name(path: &Path, index: usize) -> PathBuf {
+    with_stem_suffix(path, index)
+}
+
+/// File name of the output `output_name` saved separately from the image at `path`,
+/// e.g. `shot-DP-1.png` for `shot.png`.
+pub fn get_output_file_name(path: &Path, output_name: &str) -> PathBuf {
+    with_stem_suffix(path, output_name)
+}
+
+fn with_stem_suffix(path: &Path, suffix: impl Display) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let file_name = match path.extension() {
+        Some(extension) => format!("{stem}-{suffix}.{}", extension.to_string_lossy()),
+        None => format!("{stem}-{suffix}"),
+    };
+    path.with_file_name(file_name)
+}
+
+/// Quote `value` for `sh`, so it stays a single word whatever it contains.
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+/// Fill in the `--exec` command `template`: `{}` becomes the saved file, `{geometry}`
+/// the captured region as `X,Y WxH` and `{output}` the comma separated outputs. Each
+/// value is shell quoted, other braces are left alone.
+pub fn expand_exec_template(
+    template: &str,
+    path: &Path,
+    region: Option<LogicalRegion>,
+    outputs: &[&str],
+) -> String {
+    let geometry = region.map_or_else(String::new, |region| {
+        let Region { position, size } = region.inner;
+        format!(
+            "{},{} {}x{}",
+            position.x, position.y, size.width, size.height
+        )
+    });
+    let placeholders = [
+        ("{}", path.to_string_lossy().into_owned()),
+        ("{geometry}", geometry),
+        ("{output}", outputs.join(",")),
+    ];
+
+    let mut command = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        command.push_str(&rest[..start]);
+        rest = &rest[start..];
+        match placeholders
+            .iter()
+            .find(|(placeholder, _)| rest.starts_with(placeholder))
+        {
+            Some((placeholder, value)) => {
+                command.push_str(&shell_quote(value));
+                rest = &rest[placeholder.len()..];
+            }
+            None => {
+                command.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+    command.push_str(rest);
+    command
+}
+
+/// Run `command` with `sh -c` and fail unless it exits successfully.
+pub fn run_exec(command: &str) -> eyre::Result<()> {
+    tracing::debug!("Running --exec command: {command}");
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .status()
+        .wrap_err("Failed to run the --exec command")?;
+    if !status.success() {
+        bail!("The --exec command `{command}` failed with {status}");
+    }
+    Ok(())
+}
+
+/// Run `job` for every item on at most `jobs` threads, returning the results in
+/// the order of `items`. A failing item does not stop the others.
+pub fn run_parallel<T, R, F>(items: &[T], jobs: usize, job: F) -> Vec<R>
+where
+    T: Sync,
+    R: Send,
+    F: Fn(&T) -> R + Sync,
+{
+    let next = AtomicUsize::new(0);
+    let mut results: Vec<(usize, R)> = thread::scope(|scope| {
+        let workers: Vec<_> = (0..jobs.clamp(1, items.len().max(1)))
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut results = Vec::new();
+                    loop {
+                        let index = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(item) = items.get(index) else {
+                            return results;
+                        };
+                        results.push((index, job(item)));
+                    }
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().expect("worker thread panicked"))
+            .collect()
+    });
+    results.sort_unstable_by_key(|(index, _)| *index);
+    results.into_iter().map(|(_, result)| result).collect()
+}
+
+/// The lines `--timings` prints, collected during a run for the caller to
+/// print, see [`crate::Outcome::timings`].
+#[derive(Debug, Default)]
+pub struct Timings(Mutex<Vec<String>>);
+
+impl Timings {
+    /// Record how long `label` took.
+    pub fn record(&self, label: impl Display, duration: Duration) {
+        self.note(format!("{label}: {:.1}ms", duration.as_secs_f64() * 1000.0));
+    }
+
+    /// Record a line that isn't a duration, such as a frame rate.
+    pub fn note(&self, line: String) {
+        self.0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(line);
+    }
+
+    pub fn into_lines(self) -> Vec<String> {
+        self.0.into_inner().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Memory available to new allocations in bytes, from `MemAvailable` in `/proc/meminfo`.
+pub fn available_memory() -> Option<u64> {
+    parse_mem_available(&std::fs::read_to_string("/proc/meminfo").ok()?)
+}
+
+fn parse_mem_available(meminfo: &str) -> Option<u64> {
+    let kib = meminfo
+        .lines()
+        .find_map(|line| line.strip_prefix("MemAvailable:"))?
+        .trim()
+        .strip_suffix("kB")?
+        .trim()
+        .parse::<u64>()
+        .ok()?;
+    Some(kib * 1024)
+}
+
 pub fn encode_to_jxl_bytes(
     image_buffer: &DynamicImage,
     lossless: bool,
@@ -201,15 +598,229 @@ pub fn encode_to_jxl_bytes(
     Ok(data.to_vec())
 }
 
+/// Encode `image` as PNG with `options` and wrap it in an SVG of the same
+/// size, so one image pixel is one SVG user unit.
+pub fn encode_to_svg(image: &DynamicImage, options: &EncodingOptions) -> eyre::Result<Vec<u8>> {
+    let (width, height) = (image.width(), image.height());
+    if width == 0 || height == 0 {
+        bail!("can't encode an empty {width}x{height} image as svg");
+    }
+    let mut png = Cursor::new(Vec::new());
+    options.encode(image, image::ImageFormat::Png, &mut png)?;
+
+    let mut svg = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <svg xmlns=\"http://www.w3.org/2000/svg\" xmlns:xlink=\"http://www.w3.org/1999/xlink\" \
+         width=\"{width}\" height=\"{height}\" viewBox=\"0 0 {width} {height}\">\n\
+         <image width=\"{width}\" height=\"{height}\" xlink:href=\"data:image/png;base64,"
+    );
+    push_base64(&mut svg, png.get_ref());
+    svg.push_str("\"/>\n</svg>\n");
+    Ok(svg.into_bytes())
+}
+
+/// Largest width and height of a Direct3D 11 2D texture, the most loaders
+/// accept.
+const DDS_MAX_DIMENSION: u32 = 16384;
+
+/// Encode `image` as an uncompressed DDS texture.
+///
+/// The texture has a DX10 header with `DXGI_FORMAT_R8G8B8A8_UNORM`: one mip
+/// level of tightly packed RGBA rows, top row first, with straight alpha.
+pub fn encode_to_dds(image: &DynamicImage) -> eyre::Result<Vec<u8>> {
+    let (width, height) = (image.width(), image.height());
+    if width == 0 || height == 0 || width > DDS_MAX_DIMENSION || height > DDS_MAX_DIMENSION {
+        bail!(
+            "can't encode a {width}x{height} image as dds, textures are 1 to \
+             {DDS_MAX_DIMENSION} pixels wide and high"
+        );
+    }
+    write_dds(width, height, image.to_rgba8().into_raw())
+}
+
+#[cfg(feature = "dds")]
+fn write_dds(width: u32, height: u32, pixels: Vec<u8>) -> eyre::Result<Vec<u8>> {
+    use ddsfile::{AlphaMode, D3D10ResourceDimension, Dds, DxgiFormat, NewDxgiParams};
+
+    let mut dds = Dds::new_dxgi(NewDxgiParams {
+        height,
+        width,
+        depth: None,
+        format: DxgiFormat::R8G8B8A8_UNorm,
+        mipmap_levels: None,
+        array_layers: None,
+        caps2: None,
+        is_cubemap: false,
+        resource_dimension: D3D10ResourceDimension::Texture2D,
+        alpha_mode: AlphaMode::Straight,
+    })
+    .wrap_err("Failed to create the dds texture")?;
+    dds.data = pixels;
+    let mut buffer = Vec::new();
+    dds.write(&mut buffer)
+        .wrap_err("Failed to encode the dds texture")?;
+    Ok(buffer)
+}
+
+#[cfg(not(feature = "dds"))]
+fn write_dds(_width: u32, _height: u32, _pixels: Vec<u8>) -> eyre::Result<Vec<u8>> {
+    bail!("this wayshot was built without dds support, rebuild it with the 'dds' feature")
+}
+
+/// Resolution Tesseract is told the image passed to it has. Screens are
+/// around 96 DPI and [`ocr_input`] doubles that.
+#[cfg(feature = "ocr")]
+const OCR_DPI: i32 = 192;
+
+/// `image` prepared for Tesseract, which reads dark text on a light
+/// background at print resolutions best: grayscale, inverted when it is
+/// mostly dark like a dark theme, and upscaled twice.
+pub fn ocr_input(image: &DynamicImage) -> GrayImage {
+    let mut gray = image.to_luma8();
+    let pixels = gray.as_raw();
+    let mean = pixels.iter().map(|&luma| luma as u64).sum::<u64>() / pixels.len().max(1) as u64;
+    if mean < 128 {
+        imageops::invert(&mut gray);
+    }
+    imageops::resize(
+        &gray,
+        gray.width() * 2,
+        gray.height() * 2,
+        imageops::FilterType::Triangle,
+    )
+}
+
+/// Recognize the text in `image` with Tesseract, in the languages `lang`
+/// like `eng+deu`.
+pub fn recognize_text(image: &DynamicImage, lang: &str) -> eyre::Result<String> {
+    let mut png = Cursor::new(Vec::new());
+    ocr_input(image).write_to(&mut png, image::ImageFormat::Png)?;
+    run_tesseract(png.get_ref(), lang)
+}
+
+#[cfg(feature = "ocr")]
+fn run_tesseract(png: &[u8], lang: &str) -> eyre::Result<String> {
+    let mut tesseract = leptess::LepTess::new(None, lang).wrap_err_with(|| {
+        format!(
+            "Failed to start Tesseract, is the data for the '{lang}' language installed? \
+             Set TESSDATA_PREFIX if it is in a non-standard place"
+        )
+    })?;
+    tesseract
+        .set_image_from_mem(png)
+        .wrap_err("Failed to pass the screenshot to Tesseract")?;
+    tesseract.set_source_resolution(OCR_DPI);
+    tesseract
+        .get_utf8_text()
+        .wrap_err("Tesseract recognized text that isn't valid UTF-8")
+}
+
+#[cfg(not(feature = "ocr"))]
+fn run_tesseract(_png: &[u8], _lang: &str) -> eyre::Result<String> {
+    bail!("this wayshot was built without OCR support, rebuild it with the 'ocr' feature")
+}
+
+/// Append `bytes` to `out` in standard, padded base64.
+fn push_base64(out: &mut String, bytes: &[u8]) {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    out.reserve(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let group = chunk.iter().enumerate().fold(0u32, |group, (i, byte)| {
+            group | (*byte as u32) << (16 - 8 * i)
+        });
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(group >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+}
+
+/// A seekable writer, what the image encoders write to.
+pub trait WriteSeek: Write + Seek {}
+
+impl<W: Write + Seek> WriteSeek for W {}
+
+/// Write `file` through `write`, hashing the written bytes with `algorithm`
+/// on the way and returning their hex digest.
+pub fn write_hashed(
+    file: &mut File,
+    algorithm: Option<ChecksumAlgorithm>,
+    write: impl FnOnce(&mut dyn WriteSeek) -> eyre::Result<()>,
+) -> eyre::Result<Option<String>> {
+    let Some(algorithm) = algorithm else {
+        write(file)?;
+        return Ok(None);
+    };
+    let mut writer = HashingWriter::new(file, algorithm);
+    write(&mut writer)?;
+    Ok(Some(writer.finish().1))
+}
+
+/// Write `path` through `write`, into a hidden temporary file in the same
+/// directory that is renamed over `path` once complete, so readers such as
+/// directory watchers never see a partially written file.
+///
+/// Writes `path` directly instead if it is not a regular file, e.g. a FIFO or
+/// `/dev/stdout`, and with a warning if the temporary file can't be created.
+/// A temporary file that can't be renamed into place because it is on another
+/// filesystem is copied over `path`.
+pub fn write_atomically(
+    path: &Path,
+    write: impl FnOnce(&mut File) -> eyre::Result<()>,
+) -> eyre::Result<()> {
+    let mut tmp_name = OsString::from(".");
+    tmp_name.push(path.file_name().unwrap_or_default());
+    tmp_name.push(format!(".{}.tmp", std::process::id()));
+    let tmp_path = path.with_file_name(tmp_name);
+    let tmp_file = if fs::metadata(path).is_ok_and(|metadata| !metadata.is_file()) {
+        None
+    } else {
+        File::create(&tmp_path)
+            .inspect_err(|e| {
+                tracing::warn!(
+                    "Can't create a temporary file next to {} ({e}), writing it directly",
+                    path.display()
+                );
+            })
+            .ok()
+    };
+    let Some(mut file) = tmp_file else {
+        return write(&mut File::create(path)?);
+    };
+
+    let result = (|| {
+        write(&mut file)?;
+        file.sync_all()?;
+        match fs::rename(&tmp_path, path) {
+            Err(e) if e.kind() == ErrorKind::CrossesDevices => {
+                tracing::warn!(
+                    "Can't rename the temporary file to {} across filesystems, copying it",
+                    path.display()
+                );
+                fs::copy(&tmp_path, path)?;
+                fs::remove_file(&tmp_path)?;
+                Ok(())
+            }
+            result => Ok(result?),
+        }
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    result
+}
+
 pub fn encode_to_jxl(
     image_buffer: &DynamicImage,
-    path: &PathBuf,
+    file: &mut (impl Write + ?Sized),
     lossless: bool,
     distance: f32,
     speed: EncoderSpeed,
 ) -> Result<(), Box<dyn std::error::Error>> {
     let data = encode_to_jxl_bytes(image_buffer, lossless, distance, speed)?;
-    let mut file = File::create(path)?;
     file.write_all(&data)?;
 
     Ok(())
@@ -217,7 +828,9 @@ pub fn encode_to_jxl(
 
 const TIMEOUT: i32 = 5000;
 
-#[derive(Debug, Clone)]
+/// What a shot captured, see [`crate::Outcome`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum ShotResult {
     Output { name: String },
     Toplevel { name: String },
@@ -225,6 +838,16 @@ pub enum ShotResult {
     All,
 }
 
+/// Write a BEL to the controlling terminal, if there is one.
+pub fn ring_bell() {
+    match OpenOptions::new().write(true).open("/dev/tty") {
+        Ok(mut tty) => {
+            let _ = tty.write_all(b"\x07");
+        }
+        Err(e) => tracing::debug!("No controlling terminal to ring the bell on: {e}"),
+    }
+}
+
 pub fn send_notification(shot_result: Result<ShotResult, &Error>) {
     match shot_result {
         Ok(result) => {
@@ -253,3 +876,215 @@ pub fn send_notification(shot_result: Result<ShotResult, &Error>) {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mem_available_is_read_in_bytes() {
+        let meminfo = "MemTotal:        1012345 kB\nMemFree:          100000 kB\nMemAvailable:     524288 kB\n";
+        assert_eq!(parse_mem_available(meminfo), Some(512 * 1024 * 1024));
+        assert_eq!(parse_mem_available("MemTotal: 1 kB\n"), None);
+    }
+
+    #[test]
+    fn write_atomically_replaces_the_file_without_leftovers() {
+        let dir = env::temp_dir().join(format!("wayshot-atomic-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("shot.png");
+        fs::write(&path, "old").unwrap();
+
+        let failed = write_atomically(&path, |file| {
+            file.write_all(b"partial")?;
+            Err(eyre::eyre!("encoding failed"))
+        });
+        assert!(failed.is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "old");
+
+        write_atomically(&path, |file| Ok(file.write_all(b"new")?)).expect("write");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn pixel_format_support_follows_the_encoding() {
+        assert!(EncodingFormat::Png.supports_pixel_format(PixelFormat::Bgra8));
+        assert!(EncodingFormat::Ppm.supports_pixel_format(PixelFormat::Bgr8));
+        assert!(!EncodingFormat::Jpg.supports_pixel_format(PixelFormat::Rgba8));
+        assert!(EncodingFormat::Jxl.supports_pixel_format(PixelFormat::Rgb8));
+        assert!(!EncodingFormat::Jxl.supports_pixel_format(PixelFormat::Rgba8));
+    }
+
+    #[test]
+    fn base64_is_padded() {
+        for (bytes, encoded) in [
+            (&b""[..], ""),
+            (b"f", "Zg=="),
+            (b"fo", "Zm8="),
+            (b"foo", "Zm9v"),
+            (b"foob", "Zm9vYg=="),
+            (b"\xff\xfe\xfd", "//79"),
+        ] {
+            let mut out = String::new();
+            push_base64(&mut out, bytes);
+            assert_eq!(out, encoded);
+        }
+    }
+
+    #[test]
+    fn svg_embeds_png_at_pixel_size() {
+        let image = DynamicImage::new_rgba8(3, 2);
+        let svg =
+            String::from_utf8(encode_to_svg(&image, &EncodingOptions::new()).unwrap()).unwrap();
+
+        assert!(svg.contains(r#"width="3" height="2" viewBox="0 0 3 2""#));
+        assert!(svg.contains(
+            r#"<image width="3" height="2" xlink:href="data:image/png;base64,iVBORw0KGgo"#
+        ));
+        assert!(svg.ends_with("\"/>\n</svg>\n"));
+        assert!(encode_to_svg(&DynamicImage::new_rgba8(0, 2), &EncodingOptions::new()).is_err());
+    }
+
+    #[test]
+    fn dds_rejects_sizes_textures_cannot_have() {
+        assert!(encode_to_dds(&DynamicImage::new_rgba8(0, 2)).is_err());
+        assert!(encode_to_dds(&DynamicImage::new_rgba8(DDS_MAX_DIMENSION + 1, 1)).is_err());
+        assert!(EncodingFormat::Dds.supports_pixel_format(PixelFormat::Rgba8));
+        assert!(!EncodingFormat::Dds.supports_pixel_format(PixelFormat::Bgra8));
+    }
+
+    #[test]
+    fn clipboard_falls_back_to_wl_copy_on_path() {
+        let dir = env::temp_dir().join(format!("wayshot-clipboard-{}", std::process::id()));
+        let (empty, bin) = (dir.join("empty"), dir.join("bin"));
+        fs::create_dir_all(&empty).unwrap();
+        fs::create_dir_all(&bin).unwrap();
+        let wl_copy = bin.join("wl-copy");
+        fs::write(&wl_copy, "#!/bin/sh\n").unwrap();
+        let path = env::join_paths([&empty, &bin]).unwrap();
+
+        // Data-control wins when the compositor has it.
+        assert_eq!(
+            resolve_clipboard_backend(true, Some(&path)).unwrap(),
+            ClipboardBackend::DataControl
+        );
+        // A wl-copy that can't be executed doesn't count.
+        assert!(resolve_clipboard_backend(false, Some(&path)).is_err());
+        fs::set_permissions(&wl_copy, fs::Permissions::from_mode(0o755)).unwrap();
+        assert_eq!(
+            resolve_clipboard_backend(false, Some(&path)).unwrap(),
+            ClipboardBackend::WlCopy(wl_copy)
+        );
+        // Without either the error names both.
+        let error = resolve_clipboard_backend(false, Some(empty.as_os_str()))
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("data-control") && error.contains("wl-copy"));
+        assert!(resolve_clipboard_backend(false, None).is_err());
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn ocr_input_is_dark_on_light_and_upscaled() {
+        use image::{Luma, Rgba, RgbaImage};
+
+        // Light text on a dark theme background.
+        let mut image = RgbaImage::from_pixel(3, 2, Rgba([20, 20, 20, 255]));
+        image.put_pixel(0, 0, Rgba([250, 250, 250, 255]));
+        let input = ocr_input(&DynamicImage::ImageRgba8(image));
+        assert_eq!(input.dimensions(), (6, 4));
+        assert!(input.get_pixel(0, 0)[0] < 128);
+        assert_eq!(*input.get_pixel(5, 3), Luma([235]));
+
+        // Dark on light stays as it is.
+        let light = RgbaImage::from_pixel(1, 1, Rgba([200, 200, 200, 255]));
+        let input = ocr_input(&DynamicImage::ImageRgba8(light));
+        assert_eq!(*input.get_pixel(1, 1), Luma([200]));
+    }
+
+    #[cfg(feature = "dds")]
+    #[test]
+    fn dds_stores_rgba_rows_top_first() {
+        use ddsfile::{Dds, DxgiFormat};
+
+        let image = image::RgbaImage::from_fn(3, 2, |x, y| {
+            image::Rgba([x as u8 * 80, y as u8 * 120, 7, 128])
+        });
+        let encoded = encode_to_dds(&DynamicImage::ImageRgba8(image.clone())).unwrap();
+
+        let dds = Dds::read(&mut encoded.as_slice()).unwrap();
+        assert_eq!((dds.get_width(), dds.get_height()), (3, 2));
+        assert_eq!(dds.get_dxgi_format(), Some(DxgiFormat::R8G8B8A8_UNorm));
+        assert_eq!(dds.data, image.into_raw());
+    }
+
+    #[test]
+    fn output_file_name_is_suffixed_with_output() {
+        assert_eq!(
+            get_output_file_name(Path::new("/tmp/shot.png"), "DP-1"),
+            Path::new("/tmp/shot-DP-1.png")
+        );
+        assert_eq!(
+            get_output_file_name(Path::new("shot"), "HDMI-A-1"),
+            Path::new("shot-HDMI-A-1")
+        );
+    }
+
+    #[test]
+    fn run_parallel_keeps_order_and_runs_every_item() {
+        let items: Vec<u32> = (0..20).collect();
+        let running = AtomicUsize::new(0);
+        let max_running = AtomicUsize::new(0);
+
+        let results = run_parallel(&items, 3, |item| {
+            let now_running = running.fetch_add(1, Ordering::SeqCst) + 1;
+            max_running.fetch_max(now_running, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(1));
+            running.fetch_sub(1, Ordering::SeqCst);
+            if item % 7 == 3 {
+                Err(format!("item {item} failed"))
+            } else {
+                Ok(item * 2)
+            }
+        });
+
+        assert_eq!(results.len(), items.len());
+        assert_eq!(results[1], Ok(2));
+        assert_eq!(results[3], Err("item 3 failed".to_string()));
+        assert_eq!(results[19], Ok(38));
+        assert!(max_running.load(Ordering::SeqCst) <= 3);
+    }
+
+    #[test]
+    fn exec_template_substitutes_quoted_placeholders() {
+        let region = LogicalRegion::new(-10, 20, 300, 200);
+
+        assert_eq!(
+            expand_exec_template(
+                "upload {} --geometry {geometry} --on {output} {unknown}",
+                Path::new("/tmp/my shot.png"),
+                Some(region),
+                &["DP-1", "HDMI-A-1"],
+            ),
+            "upload '/tmp/my shot.png' --geometry '-10,20 300x200' --on 'DP-1,HDMI-A-1' {unknown}"
+        );
+        // Substituted values are not expanded again and can't break out of the quotes.
+        assert_eq!(
+            expand_exec_template("cat {}", Path::new("it's {output}.png"), None, &[]),
+            r"cat 'it'\''s {output}.png'"
+        );
+        assert_eq!(
+            expand_exec_template("echo {geometry}", Path::new("a.png"), None, &[]),
+            "echo ''"
+        );
+    }
+
+    #[test]
+    fn exec_surfaces_the_exit_status() {
+        run_exec("true").expect("true succeeds");
+        let error = run_exec("exit 3").expect_err("exit 3 fails");
+        assert!(error.to_string().contains("exit status: 3"), "{error}");
+    }
+}
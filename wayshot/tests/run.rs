@@ -0,0 +1,36 @@
+//! Runs of plans that end before anything is captured, so they pass without a
+//! compositor.
+
+use std::io;
+
+use clap::Parser;
+use wayshot_core::{
+    Plan,
+    cli::Cli,
+    report::{ErrorKind, exit_code},
+    run,
+};
+
+fn plan(args: &[&str]) -> Plan {
+    let config = std::env::temp_dir().join("wayshot-run-test-missing.toml");
+    let config_arg = format!("--config={}", config.display());
+    let cli = Cli::parse_from(["wayshot", &config_arg].iter().chain(args));
+    Plan::resolve(cli).expect("resolve")
+}
+
+#[test]
+fn invalid_plans_fail_before_connecting() {
+    let error = run(plan(&["--shape", "circle", "shot.jpg"]), &mut io::sink()).unwrap_err();
+    assert!(error.to_string().contains("--shape"), "{error}");
+}
+
+#[test]
+fn missing_displays_fail_to_connect() {
+    let error = run(
+        plan(&["--display", "/nonexistent/wayshot-test-0"]),
+        &mut io::sink(),
+    )
+    .unwrap_err();
+    assert_eq!(ErrorKind::of(&error), ErrorKind::Connect, "{error:?}");
+    assert_eq!(ErrorKind::of(&error).exit_code(), exit_code::CONNECTION);
+}